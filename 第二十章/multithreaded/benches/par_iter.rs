@@ -0,0 +1,111 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 16:48:05
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 16:48:05
+ * @Description: par_map / par_chunks 与第十三章的顺序迭代器写法的对比
+ * 运行：cargo bench --bench par_iter
+ * 只有一个 CPU 时并行版本不会更快，这时的比值反映的是切分、调度和拼接结果的开销。
+ */
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use multithreaded::ThreadPool;
+
+const WORKERS: usize = 4;
+const SAMPLES: usize = 2_000_000;
+const SHOES: usize = 4_000_000;
+const ROUNDS: usize = 3;
+
+//第十三章 performance 中音频解码器的线性预测：用前 12 个样本和 12 个系数预测下一个样本
+const COEFFICIENTS: [i64; 12] = [3, -1, 4, 1, -5, 9, 2, -6, 5, 3, -5, 8];
+const QLP_SHIFT: i16 = 4;
+
+fn prediction(buffer: &[i32], i: usize) -> i32 {
+    (COEFFICIENTS
+        .iter()
+        .zip(&buffer[i - 12..i])
+        .map(|(&c, &s)| c * s as i64)
+        .sum::<i64>()
+        >> QLP_SHIFT) as i32
+}
+
+//第十三章 iterators 中的 Shoe 和按尺码过滤
+#[derive(Clone)]
+struct Shoe {
+    size: u32,
+    style: &'static str,
+}
+
+fn best(mut run: impl FnMut() -> Duration) -> Duration {
+    (0..ROUNDS).map(|_| run()).min().unwrap()
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> Duration {
+    let start = Instant::now();
+    black_box(f());
+    start.elapsed()
+}
+
+fn report(name: &str, sequential: Duration, parallel: Duration) {
+    println!(
+        "{:<12} sequential {:>10.2?}   pool {:>10.2?}   x{:.2}",
+        name,
+        sequential,
+        parallel,
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
+
+fn main() {
+    let pool = ThreadPool::new(WORKERS);
+    println!("{} workers, best of {} rounds", WORKERS, ROUNDS);
+
+    let buffer: Vec<i32> = (0..SAMPLES as i32)
+        .map(|i| i.wrapping_mul(7919) % 1000)
+        .collect();
+    let sequential = best(|| {
+        time(|| {
+            (12..buffer.len())
+                .map(|i| prediction(&buffer, i))
+                .collect::<Vec<i32>>()
+        })
+    });
+    let parallel = best(|| time(|| pool.par_map(12..buffer.len(), |i| prediction(&buffer, i))));
+    assert_eq!(
+        pool.par_map(12..buffer.len(), |i| prediction(&buffer, i)),
+        (12..buffer.len())
+            .map(|i| prediction(&buffer, i))
+            .collect::<Vec<i32>>()
+    );
+    report("par_map", sequential, parallel);
+
+    let shoes: Vec<Shoe> = (0..SHOES)
+        .map(|i| Shoe {
+            size: (i % 7) as u32 + 7,
+            style: if i % 2 == 0 { "sneaker" } else { "boot" },
+        })
+        .collect();
+    let shoe_size = 10;
+    let sequential = best(|| {
+        time(|| {
+            shoes
+                .iter()
+                .filter(|shoe| shoe.size == shoe_size && shoe.style == "boot")
+                .count()
+        })
+    });
+    let parallel = best(|| {
+        time(|| {
+            pool.par_chunks(&shoes, 64 * 1024, |chunk| {
+                chunk
+                    .iter()
+                    .filter(|shoe| shoe.size == shoe_size && shoe.style == "boot")
+                    .count()
+            })
+            .into_iter()
+            .sum::<usize>()
+        })
+    });
+    report("par_chunks", sequential, parallel);
+}
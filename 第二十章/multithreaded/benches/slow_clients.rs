@@ -0,0 +1,132 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 21:36:52
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 21:36:52
+ * @Description: 大量慢客户端下，线程模式与事件循环模式处理正常请求的延迟和吞吐量对比
+ * 运行：cargo bench --bench slow_clients
+ */
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use multithreaded::http::{Mode, Response, Router, Server, ServerConfig, StatusCode};
+use multithreaded::ThreadPool;
+
+const WORKERS: usize = 4;
+const SLOW_CLIENTS: usize = 64;
+//慢客户端每隔多久发送请求的一个字节
+const DRIP: Duration = Duration::from_millis(100);
+const FAST_REQUESTS: usize = 200;
+const FAST_CLIENTS: usize = 4;
+
+fn start(mode: Mode) -> SocketAddr {
+    let router = Router::new().get("/", |_| Response::text(StatusCode::OK, "hello"));
+    let config = ServerConfig {
+        mode,
+        ..ServerConfig::default()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        //慢客户端结束时断开连接，不打印这些错误
+        let server = Server::builder(router)
+            .config(config)
+            .pool(ThreadPool::new(WORKERS))
+            .on_error(|_, _| {})
+            .build();
+        server.serve(&listener);
+    });
+    addr
+}
+
+//慢客户端：一个字节一个字节地发送请求，收到响应后再来一遍，直到 done
+fn slow_client(addr: SocketAddr, done: Arc<AtomicBool>) {
+    let request = b"GET / HTTP/1.1\r\nHost: bench\r\n\r\n";
+    let Ok(mut stream) = TcpStream::connect(addr) else {
+        return;
+    };
+    while !done.load(Ordering::Relaxed) {
+        for byte in request {
+            if stream.write_all(&[*byte]).is_err() {
+                return;
+            }
+            thread::sleep(DRIP);
+            if done.load(Ordering::Relaxed) {
+                return;
+            }
+        }
+        if stream.read(&mut [0; 256]).is_err() {
+            return;
+        }
+    }
+}
+
+//每个快客户端用自己的持久连接发送请求，返回每个请求的延迟
+fn fast_client(addr: SocketAddr, requests: usize) -> Vec<Duration> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut buf = [0; 256];
+    (0..requests)
+        .map(|_| {
+            let start = Instant::now();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: bench\r\n\r\n")
+                .unwrap();
+            let mut response = Vec::new();
+            while !response.ends_with(b"hello") {
+                let n = stream.read(&mut buf).unwrap();
+                assert!(n > 0, "connection closed");
+                response.extend_from_slice(&buf[..n]);
+            }
+            start.elapsed()
+        })
+        .collect()
+}
+
+fn run(mode: Mode) {
+    let addr = start(mode);
+    let done = Arc::new(AtomicBool::new(false));
+    let slow: Vec<_> = (0..SLOW_CLIENTS)
+        .map(|_| {
+            let done = Arc::clone(&done);
+            thread::spawn(move || slow_client(addr, done))
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(200));
+
+    let start = Instant::now();
+    let fast: Vec<_> = (0..FAST_CLIENTS)
+        .map(|_| thread::spawn(move || fast_client(addr, FAST_REQUESTS / FAST_CLIENTS)))
+        .collect();
+    let mut latencies: Vec<Duration> = fast
+        .into_iter()
+        .flat_map(|client| client.join().unwrap())
+        .collect();
+    let elapsed = start.elapsed();
+    done.store(true, Ordering::Relaxed);
+    for client in slow {
+        client.join().unwrap();
+    }
+
+    latencies.sort();
+    println!(
+        "{:<10} p50 {:>10.2?}  p99 {:>10.2?}  max {:>10.2?}  {:>8.0} req/s",
+        format!("{:?}", mode),
+        latencies[latencies.len() / 2],
+        latencies[latencies.len() * 99 / 100],
+        latencies[latencies.len() - 1],
+        FAST_REQUESTS as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    println!(
+        "{} workers, {} slow clients sending one byte every {:?}, {} fast requests",
+        WORKERS, SLOW_CLIENTS, DRIP, FAST_REQUESTS
+    );
+    run(Mode::Threads);
+    run(Mode::EventLoop);
+}
@@ -0,0 +1,173 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 09:40:03
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 09:40:03
+ * @Description: 大量很小的任务下，工作窃取线程池与最初的 Arc<Mutex<mpsc::Receiver>> 线程池的吞吐量对比
+ * 运行：cargo bench --bench throughput
+ */
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use multithreaded::ThreadPool;
+
+const WORKERS: usize = 4;
+const TINY_JOBS: usize = 200_000;
+const PARENTS: usize = 64;
+const CHILDREN: usize = 2_000;
+const ROUNDS: usize = 3;
+
+//最初版本的线程池（去掉了每个任务的 println!），作为对比的基准
+mod mutex_receiver {
+    use super::*;
+
+    type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    enum Message {
+        NewJob(Job),
+        Terminate,
+    }
+
+    pub struct ThreadPool {
+        workers: Vec<Option<thread::JoinHandle<()>>>,
+        sender: mpsc::Sender<Message>,
+    }
+
+    impl ThreadPool {
+        pub fn new(size: usize) -> ThreadPool {
+            let (sender, receiver) = mpsc::channel();
+            let receiver = Arc::new(Mutex::new(receiver));
+            let workers = (0..size)
+                .map(|_| {
+                    let receiver: Arc<Mutex<mpsc::Receiver<Message>>> = Arc::clone(&receiver);
+                    Some(thread::spawn(move || loop {
+                        let message = receiver.lock().unwrap().recv().unwrap();
+                        match message {
+                            Message::NewJob(job) => job(),
+                            Message::Terminate => break,
+                        }
+                    }))
+                })
+                .collect();
+            ThreadPool { workers, sender }
+        }
+
+        pub fn execute<F>(&self, f: F)
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.sender.send(Message::NewJob(Box::new(f))).unwrap();
+        }
+    }
+
+    impl Drop for ThreadPool {
+        fn drop(&mut self) {
+            for _ in &self.workers {
+                self.sender.send(Message::Terminate).unwrap();
+            }
+            for worker in &mut self.workers {
+                if let Some(thread) = worker.take() {
+                    thread.join().unwrap();
+                }
+            }
+        }
+    }
+}
+
+//两种线程池只需要 execute 这一个共同的接口
+trait Pool: Send + Sync + 'static {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>);
+}
+
+impl Pool for ThreadPool {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(job);
+    }
+}
+
+impl Pool for mutex_receiver::ThreadPool {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(job);
+    }
+}
+
+//从线程池外部提交大量只做一次原子加法的任务
+fn tiny_jobs<P: Pool>(pool: Arc<P>) -> Duration {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    for _ in 0..TINY_JOBS {
+        let counter = Arc::clone(&counter);
+        pool.spawn(Box::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }));
+    }
+    wait_for(&counter, TINY_JOBS);
+    start.elapsed()
+}
+
+//每个父任务在 worker 内部再提交很多子任务，工作窃取的本地队列主要针对这种场景
+fn nested_jobs<P: Pool>(pool: Arc<P>) -> Duration {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    for _ in 0..PARENTS {
+        let counter = Arc::clone(&counter);
+        let inner = Arc::clone(&pool);
+        pool.spawn(Box::new(move || {
+            for _ in 0..CHILDREN {
+                let counter = Arc::clone(&counter);
+                inner.spawn(Box::new(move || {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }));
+            }
+        }));
+    }
+    wait_for(&counter, PARENTS * CHILDREN);
+    start.elapsed()
+}
+
+fn wait_for(counter: &AtomicUsize, expected: usize) {
+    while counter.load(Ordering::Relaxed) < expected {
+        thread::yield_now();
+    }
+}
+
+//重复几轮取最好成绩，减少调度噪声的影响
+fn best<P: Pool>(new: impl Fn() -> P, scenario: fn(Arc<P>) -> Duration) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let pool = Arc::new(new());
+            let elapsed = scenario(Arc::clone(&pool));
+            //等所有父任务释放它们持有的 Arc，保证线程池在主线程里被 drop
+            while Arc::strong_count(&pool) > 1 {
+                thread::yield_now();
+            }
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, jobs: usize, before: Duration, after: Duration) {
+    let rate = |d: Duration| jobs as f64 / d.as_secs_f64();
+    println!(
+        "{:<12} mutex-receiver {:>10.0} jobs/s   work-stealing {:>10.0} jobs/s   x{:.2}",
+        name,
+        rate(before),
+        rate(after),
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+}
+
+fn main() {
+    println!("{} workers, best of {} rounds", WORKERS, ROUNDS);
+
+    let before = best(|| mutex_receiver::ThreadPool::new(WORKERS), tiny_jobs);
+    let after = best(|| ThreadPool::new(WORKERS), tiny_jobs);
+    report("tiny", TINY_JOBS, before, after);
+
+    let before = best(|| mutex_receiver::ThreadPool::new(WORKERS), nested_jobs);
+    let after = best(|| ThreadPool::new(WORKERS), nested_jobs);
+    report("nested", PARENTS * CHILDREN, before, after);
+}
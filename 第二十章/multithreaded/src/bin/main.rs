@@ -2,18 +2,24 @@
  * @Author: wlj
  * @Date: 2022-12-26 15:41:15
  * @LastEditors: wlj
- * @LastEditTime: 2022-12-27 08:56:26
+ * @LastEditTime: 2026-10-20 05:37:41
  * @Description: 将单线程 server 变为多线程 server
  * @see:https://kaisery.github.io/trpl-zh-cn/ch20-02-multithreaded.html
  */
 
-use std::fs;
-use std::io::prelude::*;
+use multithreaded::http::{
+    self, AccessLog, Config, ConfigError, Context, Proxy, RequestId, Response, Router, Scoped,
+    Server, StaticFiles, StatusCode, Templates, TlsConfig,
+};
+use multithreaded::ThreadPool;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::env;
 use std::net::TcpListener;
-use std::net::TcpStream;
+use std::path::Path;
+use std::process;
 use std::thread;
 use std::time::Duration;
-use multithreaded::ThreadPool;
 
 //目前server会依次处理每一请求，意味着它在完成第一个连接的处理之前不会处理第二个连接。如果server正接收越来越多的请求，这类串行操作会时性能越来越差。
 //如果一个请求花费很长实际来处理，随后而来的请求则不等不等待这个长请求结束，即使这些请求可以很快就处理完。我们需要修复这种情况，不过首先让我们实际尝试一下这个问题。
@@ -39,16 +45,86 @@ fn main() {
 
     //为有限数量的线程创建一个类似的接口
     //我们期望线程池以类似且熟悉的方式工作，以便从线程切换到线程池并不会对使用该 API 的代码做出较大的修改。
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4); //创建一个新的线程池，它有一个课配置的线程数参数，在这里是4
-    for stream in listener.incoming().take(2){//take 方法定义于 Iterator trait，这里限制循环最多头 2 次。ThreadPool 会在 main 的结尾离开作用域，而且还会看到 drop 实现的运行。
-        let stream = stream.unwrap();
+    //监听地址、线程数等设置来自命令行参数和配置文件，见 http/config.rs
+    let config = match Config::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            print!("{}", http::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, http::USAGE);
+            process::exit(2);
+        }
+    };
+    let listener = TcpListener::bind(&config.bind).unwrap_or_else(|err| {
+        eprintln!("cannot listen on {}: {}", config.bind, err);
+        process::exit(1);
+    });
+    //创建一个新的线程池，它有一个课配置的线程数参数，最初是4
+    let pool = ThreadPool::new(config.workers);
+    //最初这里对每个连接调用 pool.execute(|| handle_connection(stream))，每个连接只处理一个请求。
+    //现在连接交给 Server：同一个连接上可以处理多个请求，空闲的连接不会占用 worker，见 http/server.rs
+    //pool.execute有着类似thread::spawn的接口，它获取一个线程池运行于每一个流的闭包。
+    //pool.execute 需要实现为获取闭包并传递给池中的线程运行。这段代码还不能编译，不过通过尝试编译器会指导我们如何修复它。
+    //每个响应都带上 X-Request-Id，认证、CORS 和限流等中间件见 http/middleware.rs
+    let mut server = Server::builder(routes(&config.docroot, config.server.compress_min_size))
+        .config(config.server.clone())
+        .pool(pool)
+        .middleware(RequestId::new());
+    //给了 --upstream 就把 /api/ 下的请求转发给它，见 http/proxy.rs
+    if let Some(upstream) = &config.upstream {
+        server = server.middleware(Scoped::new(
+            "/api",
+            Proxy::new(upstream.as_str()).timeout(config.upstream_timeout),
+        ));
+    }
+    //访问日志由单独的线程写入，不拖慢处理请求的 worker，见 http/log.rs
+    match &config.access_log {
+        Some(path) if path.as_os_str() == "-" => {
+            server = server.access_log(AccessLog::stdout(config.log_format))
+        }
+        Some(path) => match AccessLog::open(path, config.log_format, config.log_rotation) {
+            Ok(log) => server = server.access_log(log),
+            Err(err) => {
+                eprintln!("cannot open access log {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        },
+        None => {}
+    }
+    //给了证书和私钥就提供 HTTPS，见 http/tls.rs
+    let scheme = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => match TlsConfig::from_pem_files(cert, key) {
+            Ok(tls) => {
+                server = server.tls(tls);
+                "https"
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        },
+        _ => "http",
+    };
+    let server = server.build();
 
-        pool.execute(|| {
-            //pool.execute有着类似thread::spawn的接口，它获取一个线程池运行于每一个流的闭包。
-            //pool.execute 需要实现为获取闭包并传递给池中的线程运行。这段代码还不能编译，不过通过尝试编译器会指导我们如何修复它。
-            handle_connection(stream)
-        })
+    //最初这里是 listener.incoming().take(2)：take 方法定义于 Iterator trait，限制循环最多头 2 次，
+    //ThreadPool 会在 main 的结尾离开作用域，从而看到 drop 实现的运行。
+    //现在一直接受连接，直到收到 SIGINT（ctrl-c）或 SIGTERM：停止接受新连接，等正在处理的请求结束，再关闭线程池。
+    let stop = server.shutdown_handle();
+    let mut signals = Signals::new([SIGINT, SIGTERM]).expect("failed to register signal handlers");
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            println!("received signal {}, shutting down", signal);
+            stop.shutdown();
+        }
+    });
+    println!("listening on {}://{}", scheme, config.bind);
+    server.serve(&listener);
+    if let Err(err) = server.shutdown(config.shutdown_timeout) {
+        eprintln!("{}", err);
+        process::exit(1);
     }
     //报错，报错告诉我们需要一个 ThreadPool 类型或模块，所以我们将构建一个。
     //ThreadPool 的实现会与 web server 的特定工作相独立，所以让我们从 hello crate 切换到存放 ThreadPool 实现的新库 crate。
@@ -59,50 +135,91 @@ fn main() {
     //use hello::ThreadPool;下一步是为 ThreadPool 创建一个叫做 new 的关联函数。
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    //在当前 server 实现中模拟慢请求
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
-
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else if buffer.starts_with(sleep) {
-        //当接收到这个请求时，在渲染成功 HTML 页面之前会先休眠五秒。
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
-    };
+//最初这里是 match (&request.method, request.path()) 的分支，每个分支再自己拼状态行、读文件。现在由 Router 分发，见 http/router.rs
+fn routes(docroot: &Path, compress_min_size: Option<usize>) -> Router {
+    //文档根目录（默认是 public）下的文件通过 /static/ 访问，见 http/static_files.rs。
+    //和其他响应一样按 --compress-min-size 压缩
+    let files = StaticFiles::new(docroot).compress_min_size(compress_min_size);
+    //页面模板也放在文档根目录下，读过一次就缓存起来，见 http/template.rs
+    let templates = Templates::new(docroot);
+    Router::new()
+        .get("/", |_| page(StatusCode::OK, "hello.html"))
+        //sleep 和读文件都会阻塞，--mode event-loop 时交给线程池，不占用事件循环线程，见 http/event_loop.rs
+        .blocking()
+        .get("/sleep", |_| {
+            //在当前 server 实现中模拟慢请求。当接收到这个请求时，在渲染成功 HTML 页面之前会先休眠五秒。
+            thread::sleep(Duration::from_secs(5));
+            page(StatusCode::OK, "hello.html")
+        })
+        .blocking()
+        .get("/static/*path", move |request| {
+            files.serve(request, request.param("path").unwrap_or(""))
+        })
+        .blocking()
+        //WebSocket 回声：原样发回收到的每一条消息，见 http/websocket.rs
+        .websocket("/ws/echo", |_, mut ws| {
+            while let Ok(Some(message)) = ws.recv() {
+                if ws.send(&message).is_err() {
+                    break;
+                }
+            }
+        })
+        //最初 404 页面是固定的 404.html，现在用模板显示请求的路径，路径中的 HTML 会被转义
+        .not_found(move |request| {
+            let link =
+                |href: &str, title: &str| Context::new().with("href", href).with("title", title);
+            let context = Context::new()
+                .with("title", "Not Found")
+                .with("path", request.path())
+                .with("query", request.query())
+                .with(
+                    "links",
+                    vec![link("/", "Home"), link("/static/", "Static files")],
+                );
+            render(
+                &templates,
+                StatusCode::NOT_FOUND,
+                "templates/404.html",
+                &context,
+            )
+        })
+        //第一次渲染时要读模板文件
+        .blocking_not_found()
+}
 
-    let contents = fs::read_to_string(filename).unwrap();
+//和 page 一样，模板有错误时记下错误并回复内置的 500 页面
+fn render(templates: &Templates, status: StatusCode, name: &str, context: &Context) -> Response {
+    match templates.render(name, context) {
+        Ok(html) => Response::html(status, html),
+        Err(err) => {
+            eprintln!("cannot render {}: {}", name, err);
+            Response::error_page(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
 
-    let response = format!(
-        "{}\r\nContent-Length: {}\r\n\r\n{}",
-        status_line,
-        contents.len(),
-        contents
-    );
+//页面文件不存在或者读不出来时不再 unwrap 让 worker panic，而是记下错误并回复内置的 500 页面
+fn page(status: StatusCode, filename: &str) -> Response {
+    Response::html_file(status, filename).unwrap_or_else(|err| {
+        eprintln!("cannot read {}: {}", filename, err);
+        Response::error_page(StatusCode::INTERNAL_SERVER_ERROR)
+    })
+}
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
-    //使用 cargo run 启动 server，并接着打开两个浏览器窗口：一个请求 http://127.0.0.1:7878/ 而另一个请求 http://127.0.0.1:7878/sleep
-    //如果像之前一样多次请求 /，会发现响应的比较快速。不过如果请求 /sleep 之后在请求 /，就会看到 / 会等待直到 sleep 休眠完五秒之后才出现。
-    //这里有多种办法来改变我们的 web server 使其避免所有请求都排在慢请求之后；我们将要实现的一个便是线程池。
+//使用 cargo run 启动 server，并接着打开两个浏览器窗口：一个请求 http://127.0.0.1:7878/ 而另一个请求 http://127.0.0.1:7878/sleep
+//如果像之前一样多次请求 /，会发现响应的比较快速。不过如果请求 /sleep 之后在请求 /，就会看到 / 会等待直到 sleep 休眠完五秒之后才出现。
+//这里有多种办法来改变我们的 web server 使其避免所有请求都排在慢请求之后；我们将要实现的一个便是线程池。
 
-    //使用线程池改善吞吐量
-    //线程池（thread pool）是一组预先分配的等待或准备处理任务的线程。当程序收到一个新任务，线程池中的一个线程会被分配任务，这个线程会离开并处理任务。
-    //其余的线程则可用于处理在第一个线程处理任务的同时处理其他接收到的任务。当第一个线程处理完任务时，它会返回空线程池中等待处理新任务。线程池允许我们并发处理连接，增加server的吞吐量
-    //我们会将池中线程限制为较少的数量，以防被拒绝服务（Denial of Service，Dos）攻击；如果程序为每一个接收的请求都新建一个线程，某人向server发起千万级的请求时会耗尽服务器的资源并
-    //导致所有请求的处理都被终止。
-    //不同于分配无限的线程，线程池中将有固定数量的等待线程。当新进请求时，将有固定数量的等待线程。当新进请求时，将请求发送到线程池 中做处理。
-    //线程池会维护一个接收请求的队列。每一个线程会从队列中取出一个请求，处理请求，接着向队列索取另一个请求。通过这种设计，则可以并发处理N个请求，其中N为线程数。如果每一个线程都在
-    //响应慢请求，之后的请求仍会阻塞队列，不过相比之前增加了能处理的慢请求的数量。
-    //这个设计仅仅是多种改善 web server 吞吐量的方法之一。其他可供探索的方法有 fork/join 模型 和 单线程异步I/O 模型。如果你对这个主题感兴趣，则可以阅读更多关于其他解决方案的内容
-    //并尝试用Rust实现他们；对于一个像 Rust 这样的底层语言，所有这些方法都是可能的。
-    //在开始之前，让我们讨论一下线程池应用看起来怎样。当尝试设计代码时，首先编写客户端接口确实有助于指导代码设计。以期望的调用方式来构建API代码的结构，接着在这个结构之内实现功能，
-    //而不是先实现功能再设计公有API。
-    //类似第十二章项目中使用的测试驱动开发。这里将要使用编译器驱动开发（compiler-driven development)。我们将编写调用所期望的函数的代码，接着观察编译器错误告诉我们接下来需要修改什么使得代码可以工作。
-}
+//使用线程池改善吞吐量
+//线程池（thread pool）是一组预先分配的等待或准备处理任务的线程。当程序收到一个新任务，线程池中的一个线程会被分配任务，这个线程会离开并处理任务。
+//其余的线程则可用于处理在第一个线程处理任务的同时处理其他接收到的任务。当第一个线程处理完任务时，它会返回空线程池中等待处理新任务。线程池允许我们并发处理连接，增加server的吞吐量
+//我们会将池中线程限制为较少的数量，以防被拒绝服务（Denial of Service，Dos）攻击；如果程序为每一个接收的请求都新建一个线程，某人向server发起千万级的请求时会耗尽服务器的资源并
+//导致所有请求的处理都被终止。
+//不同于分配无限的线程，线程池中将有固定数量的等待线程。当新进请求时，将有固定数量的等待线程。当新进请求时，将请求发送到线程池 中做处理。
+//线程池会维护一个接收请求的队列。每一个线程会从队列中取出一个请求，处理请求，接着向队列索取另一个请求。通过这种设计，则可以并发处理N个请求，其中N为线程数。如果每一个线程都在
+//响应慢请求，之后的请求仍会阻塞队列，不过相比之前增加了能处理的慢请求的数量。
+//这个设计仅仅是多种改善 web server 吞吐量的方法之一。其他可供探索的方法有 fork/join 模型 和 单线程异步I/O 模型。如果你对这个主题感兴趣，则可以阅读更多关于其他解决方案的内容
+//并尝试用Rust实现他们；对于一个像 Rust 这样的底层语言，所有这些方法都是可能的。
+//在开始之前，让我们讨论一下线程池应用看起来怎样。当尝试设计代码时，首先编写客户端接口确实有助于指导代码设计。以期望的调用方式来构建API代码的结构，接着在这个结构之内实现功能，
+//而不是先实现功能再设计公有API。
+//类似第十二章项目中使用的测试驱动开发。这里将要使用编译器驱动开发（compiler-driven development)。我们将编写调用所期望的函数的代码，接着观察编译器错误告诉我们接下来需要修改什么使得代码可以工作。
@@ -0,0 +1,686 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 01:31:45
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 01:31:45
+ * @Description: 阻塞的 HTTP/1.1 客户端：构建请求、读取分块或定长的响应体、复用持久连接，以及超时
+ */
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{Headers, Method, StatusCode, Version};
+
+//测试 server 的时候一直是手写 "GET / HTTP/1.1\r\n..." 这样的字节，再从读回来的字符串里找状态行和响应体，
+//持久连接、分块编码这些情况写起来既啰嗦又容易出错。Client 是一个很小的阻塞客户端：
+// 1. client.get(url).header(..).body(..).send() 构建并发送请求，只支持 http:// 地址；
+// 2. 响应体按 Content-Length、分块编码或者读到连接关闭为止读完，跳过 100 Continue 之类的临时响应；
+// 3. 能继续使用的连接按 host:port 放进连接池，下一个发往同一地址的请求直接复用。池里的连接可能已经被 server 关闭，
+//    复用的连接还没收到任何响应就断开时，幂等的请求换一个新连接重试一次；
+// 4. 连接和每次读写都有超时。
+//
+//读取响应的部分也被反向代理用来读取上游的响应，见 http/proxy.rs。
+
+//默认的连接超时
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+//默认的读写超时
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+//连接池里最多保留多少个空闲连接
+const DEFAULT_MAX_IDLE: usize = 8;
+
+//空闲连接保留多久。比这个 server 默认的 keep-alive（5 秒）短，免得拿到 server 正要关闭的连接
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(4);
+
+//响应体的默认大小上限
+const DEFAULT_MAX_BODY: usize = 64 * 1024 * 1024;
+
+//状态行和头部的大小上限
+const MAX_HEAD: usize = 64 * 1024;
+
+/// 阻塞的 HTTP/1.1 客户端，可以在多个线程之间共用
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use multithreaded::http::Client;
+///
+/// let client = Client::new().timeout(Duration::from_secs(5));
+/// let response = client
+///     .post("http://127.0.0.1:7878/echo")
+///     .header("Content-Type", "text/plain")
+///     .body("hello")
+///     .send()
+///     .unwrap();
+/// println!("{} {}", response.status.as_u16(), response.text());
+/// ```
+pub struct Client {
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_idle: usize,
+    idle_timeout: Duration,
+    max_body: usize,
+    idle: Mutex<Vec<Idle>>,
+}
+
+//连接池里的一个空闲连接
+struct Idle {
+    //host:port
+    authority: String,
+    conn: BufReader<TcpStream>,
+    since: Instant,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("connect_timeout", &self.connect_timeout)
+            .field("timeout", &self.timeout)
+            .field("idle", &self.idle.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            max_idle: DEFAULT_MAX_IDLE,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_body: DEFAULT_MAX_BODY,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 建立连接的超时
+    pub fn connect_timeout(mut self, timeout: Duration) -> Client {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 每次读写的超时：这么长时间内没有任何进展时返回 [`ClientError::Timeout`]
+    pub fn timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 连接池里最多保留多少个空闲连接，0 表示不复用连接
+    pub fn max_idle(mut self, max: usize) -> Client {
+        self.max_idle = max;
+        self
+    }
+
+    /// 空闲连接保留多久
+    pub fn idle_timeout(mut self, timeout: Duration) -> Client {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// 响应体的大小上限，超过时返回 [`ClientError::TooLarge`]
+    pub fn max_body(mut self, max: usize) -> Client {
+        self.max_body = max;
+        self
+    }
+
+    /// 构建一个发往 `url` 的请求，`url` 形如 `http://host:port/path?query`
+    pub fn request(&self, method: Method, url: &str) -> ClientRequest<'_> {
+        ClientRequest {
+            client: self,
+            method,
+            url: url.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+            chunked: false,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> ClientRequest<'_> {
+        self.request(Method::Get, url)
+    }
+
+    pub fn head(&self, url: &str) -> ClientRequest<'_> {
+        self.request(Method::Head, url)
+    }
+
+    pub fn post(&self, url: &str) -> ClientRequest<'_> {
+        self.request(Method::Post, url)
+    }
+
+    pub fn put(&self, url: &str) -> ClientRequest<'_> {
+        self.request(Method::Put, url)
+    }
+
+    pub fn delete(&self, url: &str) -> ClientRequest<'_> {
+        self.request(Method::Delete, url)
+    }
+
+    /// 连接池里现有的空闲连接数
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    fn send(&self, request: ClientRequest) -> Result<ClientResponse, ClientError> {
+        let (authority, target) = parse_url(&request.url)?;
+        let data = request.encode(authority, target);
+        let idempotent = matches!(
+            request.method,
+            Method::Get
+                | Method::Head
+                | Method::Put
+                | Method::Delete
+                | Method::Options
+                | Method::Trace
+        );
+        loop {
+            let (mut conn, reused) = match self.checkout(authority) {
+                Some(conn) => (conn, true),
+                None => (self.connect(authority)?, false),
+            };
+            match self.exchange(&mut conn, &data, request.method == Method::Head) {
+                Ok((response, reusable)) => {
+                    if reusable {
+                        self.checkin(authority, conn);
+                    }
+                    return Ok(response);
+                }
+                //池里的连接已经被 server 关闭了，换一个新连接
+                Err(ClientError::Closed) if reused && idempotent => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn connect(&self, authority: &str) -> Result<BufReader<TcpStream>, ClientError> {
+        //没有端口时用 80
+        let addrs = match authority.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => authority.to_socket_addrs(),
+            _ => (authority, 80).to_socket_addrs(),
+        }
+        .map_err(ClientError::Connect)?;
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "host has no addresses");
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true).map_err(ClientError::Connect)?;
+                    return Ok(BufReader::new(stream));
+                }
+                Err(err) if is_timeout(&err) => return Err(ClientError::Timeout),
+                Err(err) => last = err,
+            }
+        }
+        Err(ClientError::Connect(last))
+    }
+
+    //从连接池里取一个发往 `authority` 的、还能用的空闲连接
+    fn checkout(&self, authority: &str) -> Option<BufReader<TcpStream>> {
+        let mut idle = self.idle.lock().unwrap();
+        idle.retain(|idle| idle.since.elapsed() < self.idle_timeout);
+        //后放回的连接在后面，先取出来
+        while let Some(index) = idle.iter().rposition(|idle| idle.authority == authority) {
+            let Idle { conn, .. } = idle.remove(index);
+            if is_open(conn.get_ref()) {
+                return Some(conn);
+            }
+        }
+        None
+    }
+
+    fn checkin(&self, authority: &str, conn: BufReader<TcpStream>) {
+        let mut idle = self.idle.lock().unwrap();
+        idle.retain(|idle| idle.since.elapsed() < self.idle_timeout);
+        if idle.len() < self.max_idle {
+            idle.push(Idle {
+                authority: authority.to_string(),
+                conn,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    //在一个连接上发送请求、读回响应。返回的 bool 表示连接能不能放回连接池
+    fn exchange(
+        &self,
+        conn: &mut BufReader<TcpStream>,
+        data: &[u8],
+        head: bool,
+    ) -> Result<(ClientResponse, bool), ClientError> {
+        let stream = conn.get_mut();
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|()| stream.set_write_timeout(Some(self.timeout)))
+            .map_err(ClientError::Io)?;
+        stream
+            .write_all(data)
+            .and_then(|()| stream.flush())
+            .map_err(write_error)?;
+        read_response(conn, head, self.max_body)
+    }
+}
+
+/// 一个还没有发送的请求，由 [`Client::request`]、[`Client::get`] 等方法得到
+#[derive(Debug)]
+pub struct ClientRequest<'a> {
+    client: &'a Client,
+    method: Method,
+    url: String,
+    headers: Headers,
+    body: Vec<u8>,
+    chunked: bool,
+}
+
+impl ClientRequest<'_> {
+    /// 添加一个头部。没有设置 `Host` 时使用 URL 中的 `host:port`
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// 设置请求体。默认带上 `Content-Length`
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// 是否用分块编码发送请求体，而不是 `Content-Length`
+    pub fn chunked(mut self, enabled: bool) -> Self {
+        self.chunked = enabled;
+        self
+    }
+
+    /// 发送请求并读回完整的响应
+    pub fn send(self) -> Result<ClientResponse, ClientError> {
+        self.client.send(self)
+    }
+
+    //请求行、头部和请求体
+    fn encode(&self, authority: &str, target: &str) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, target);
+        if !self.headers.contains("host") {
+            head.push_str(&format!("Host: {}\r\n", authority));
+        }
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        let has_body = !self.body.is_empty()
+            || matches!(self.method, Method::Post | Method::Put | Method::Patch);
+        if self.chunked {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        } else if has_body && !self.headers.contains("content-length") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        let mut data = head.into_bytes();
+        if self.chunked {
+            if !self.body.is_empty() {
+                data.extend_from_slice(format!("{:x}\r\n", self.body.len()).as_bytes());
+                data.extend_from_slice(&self.body);
+                data.extend_from_slice(b"\r\n");
+            }
+            data.extend_from_slice(b"0\r\n\r\n");
+        } else {
+            data.extend_from_slice(&self.body);
+        }
+        data
+    }
+}
+
+/// 读回的完整响应。分块编码的响应体已经解码
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientResponse {
+    pub status: StatusCode,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl ClientResponse {
+    /// 把响应体当作 UTF-8 文本，不合法的字节替换成 U+FFFD
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+}
+
+/// 请求失败的原因
+#[derive(Debug)]
+pub enum ClientError {
+    /// URL 不是 `http://host[:port][/path]` 的形式
+    InvalidUrl(String),
+    /// 连接失败
+    Connect(io::Error),
+    /// 连接在收到任何响应之前就断开了
+    Closed,
+    /// 连接或者读写超时
+    Timeout,
+    Io(io::Error),
+    /// 响应不是合法的 HTTP/1.x 响应
+    InvalidResponse(&'static str),
+    /// 响应体超过了 [`Client::max_body`]
+    TooLarge,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl(url) => write!(f, "invalid URL {:?}", url),
+            ClientError::Connect(err) => write!(f, "cannot connect: {}", err),
+            ClientError::Closed => f.write_str("connection closed before a response"),
+            ClientError::Timeout => f.write_str("timed out"),
+            ClientError::Io(err) => write!(f, "I/O error: {}", err),
+            ClientError::InvalidResponse(message) => write!(f, "invalid response: {}", message),
+            ClientError::TooLarge => f.write_str("response too large"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Connect(err) | ClientError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+//把 URL 拆成 host:port 和请求目标
+fn parse_url(url: &str) -> Result<(&str, &str), ClientError> {
+    let invalid = || ClientError::InvalidUrl(url.to_string());
+    let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, target) = rest.split_at(end);
+    if authority.is_empty() || authority.contains(['@', ' ']) || target.contains([' ', '#']) {
+        return Err(invalid());
+    }
+    //没有路径时请求目标是 /；有查询串却没有路径的 URL 不接受
+    match target {
+        "" => Ok((authority, "/")),
+        _ if target.starts_with('?') => Err(invalid()),
+        _ => Ok((authority, target)),
+    }
+}
+
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn read_error(err: io::Error) -> ClientError {
+    if is_timeout(&err) {
+        ClientError::Timeout
+    } else {
+        ClientError::Io(err)
+    }
+}
+
+//写请求失败。复用的连接已经被对方关闭时算作 Closed
+pub(crate) fn write_error(err: io::Error) -> ClientError {
+    match err.kind() {
+        _ if is_timeout(&err) => ClientError::Timeout,
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset => ClientError::Closed,
+        _ => ClientError::Io(err),
+    }
+}
+
+//不阻塞地看一眼空闲连接：对方关闭了连接，或者发来了不该有的数据，就不能再用了
+pub(crate) fn is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let result = stream.peek(&mut [0; 1]);
+    if stream.set_nonblocking(false).is_err() {
+        return false;
+    }
+    matches!(result, Err(err) if err.kind() == io::ErrorKind::WouldBlock)
+}
+
+/// 读一个完整的响应。`head` 表示请求是 HEAD，这时响应没有响应体。
+/// 返回的 bool 表示连接上的下一个响应可以接着读，也就是连接可以复用
+pub(crate) fn read_response(
+    conn: &mut BufReader<TcpStream>,
+    head: bool,
+    max_body: usize,
+) -> Result<(ClientResponse, bool), ClientError> {
+    //跳过 100 Continue 之类的临时响应
+    let (version, status, headers) = loop {
+        let (version, status, headers) = read_head(conn)?;
+        if status.as_u16() >= 200 {
+            break (version, status, headers);
+        }
+        if status == StatusCode::SWITCHING_PROTOCOLS {
+            return Err(ClientError::InvalidResponse("unexpected protocol switch"));
+        }
+    };
+
+    let no_body = head || !status.allows_body() || status == StatusCode::NO_CONTENT;
+    let chunked = headers.has_token("transfer-encoding", "chunked");
+    let length = match headers.get("content-length") {
+        Some(length) => Some(
+            length
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| ClientError::InvalidResponse("bad Content-Length"))?,
+        ),
+        None => None,
+    };
+    let (body, delimited) = if no_body {
+        (Vec::new(), true)
+    } else if chunked {
+        (read_chunked(conn, max_body)?, true)
+    } else if let Some(length) = length {
+        if length > max_body {
+            return Err(ClientError::TooLarge);
+        }
+        let mut body = vec![0; length];
+        conn.read_exact(&mut body).map_err(read_error)?;
+        (body, true)
+    } else {
+        //既没有长度也不是分块编码：响应体一直到连接关闭
+        let mut body = Vec::new();
+        let read = conn
+            .take(max_body as u64 + 1)
+            .read_to_end(&mut body)
+            .map_err(read_error)?;
+        if read > max_body {
+            return Err(ClientError::TooLarge);
+        }
+        (body, false)
+    };
+    let reusable = delimited
+        && version == Version::Http11
+        && !headers.has_token("connection", "close")
+        && conn.buffer().is_empty();
+    let response = ClientResponse {
+        status,
+        version,
+        headers,
+        body,
+    };
+    Ok((response, reusable))
+}
+
+//读一行，去掉行尾的 CRLF。`first` 表示这是响应的第一行，这时连接直接关闭算作 Closed
+fn read_line(
+    conn: &mut impl BufRead,
+    limit: &mut usize,
+    first: bool,
+) -> Result<String, ClientError> {
+    let mut line = Vec::new();
+    let read = conn
+        .take(*limit as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::ConnectionReset if first => ClientError::Closed,
+            _ => read_error(err),
+        })?;
+    if read == 0 && first {
+        return Err(ClientError::Closed);
+    }
+    if !line.ends_with(b"\n") {
+        return Err(if read == *limit {
+            ClientError::InvalidResponse("response head too large")
+        } else {
+            ClientError::Io(io::ErrorKind::UnexpectedEof.into())
+        });
+    }
+    *limit -= read;
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| ClientError::InvalidResponse("response head is not UTF-8"))
+}
+
+//读状态行和头部
+fn read_head(conn: &mut impl BufRead) -> Result<(Version, StatusCode, Headers), ClientError> {
+    let mut limit = MAX_HEAD;
+    let line = read_line(conn, &mut limit, true)?;
+    let mut parts = line.splitn(3, ' ');
+    let version = match parts.next() {
+        Some("HTTP/1.1") => Version::Http11,
+        Some("HTTP/1.0") => Version::Http10,
+        _ => return Err(ClientError::InvalidResponse("bad status line")),
+    };
+    let status = parts
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .filter(|code| (100..600).contains(code))
+        .ok_or(ClientError::InvalidResponse("bad status code"))?;
+    let mut headers = Headers::new();
+    loop {
+        let line = read_line(conn, &mut limit, false)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(ClientError::InvalidResponse("bad header line"))?;
+        headers.append(name.trim(), value.trim());
+    }
+    Ok((version, StatusCode::new(status), headers))
+}
+
+//读分块编码的响应体，丢掉块扩展和尾部头部
+fn read_chunked(conn: &mut impl BufRead, max: usize) -> Result<Vec<u8>, ClientError> {
+    let mut body = Vec::new();
+    loop {
+        let mut limit = MAX_HEAD;
+        let line = read_line(conn, &mut limit, false)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| ClientError::InvalidResponse("bad chunk size"))?;
+        if size == 0 {
+            while !read_line(conn, &mut limit, false)?.is_empty() {}
+            return Ok(body);
+        }
+        if body.len() + size > max {
+            return Err(ClientError::TooLarge);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        conn.read_exact(&mut body[start..]).map_err(read_error)?;
+        if !read_line(conn, &mut limit, false)?.is_empty() {
+            return Err(ClientError::InvalidResponse("missing CRLF after chunk"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_chunked_bodies_and_skips_trailers() {
+        let mut data: &[u8] = b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\nrest";
+        assert_eq!(read_chunked(&mut data, 100).unwrap(), b"Wikipedia");
+        assert_eq!(data, b"rest");
+
+        let mut data: &[u8] = b"zz\r\n";
+        assert!(matches!(
+            read_chunked(&mut data, 100),
+            Err(ClientError::InvalidResponse(_))
+        ));
+        let mut data: &[u8] = b"10\r\n0123456789abcdef\r\n0\r\n\r\n";
+        assert!(matches!(
+            read_chunked(&mut data, 8),
+            Err(ClientError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn parses_status_lines_and_headers() {
+        let mut data: &[u8] = b"HTTP/1.1 418 I'm a teapot\r\nX-A: 1\r\nX-A: 2\r\n\r\n";
+        let (version, status, headers) = read_head(&mut data).unwrap();
+        assert_eq!(version, Version::Http11);
+        assert_eq!(status.as_u16(), 418);
+        assert_eq!(headers.get_all("x-a").collect::<Vec<_>>(), ["1", "2"]);
+
+        let mut empty: &[u8] = b"";
+        assert!(matches!(read_head(&mut empty), Err(ClientError::Closed)));
+        let mut garbage: &[u8] = b"SSH-2.0-OpenSSH\r\n\r\n";
+        assert!(matches!(
+            read_head(&mut garbage),
+            Err(ClientError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(
+            parse_url("http://127.0.0.1:7878/a/b?c=d").unwrap(),
+            ("127.0.0.1:7878", "/a/b?c=d")
+        );
+        assert_eq!(parse_url("http://localhost").unwrap(), ("localhost", "/"));
+        for url in [
+            "https://localhost/",
+            "localhost:80/",
+            "http:///x",
+            "http://h/a b",
+        ] {
+            assert!(
+                matches!(parse_url(url), Err(ClientError::InvalidUrl(_))),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn encodes_requests() {
+        let client = Client::new();
+        let request = client
+            .post("http://localhost:8080/upload")
+            .header("X-Test", "1")
+            .body("hello");
+        assert_eq!(
+            request.encode("localhost:8080", "/upload"),
+            b"POST /upload HTTP/1.1\r\nHost: localhost:8080\r\nX-Test: 1\r\nContent-Length: 5\r\n\r\nhello"
+        );
+        let request = client
+            .put("http://localhost/")
+            .header("Host", "example.com")
+            .body("hello")
+            .chunked(true);
+        assert_eq!(
+            request.encode("localhost", "/"),
+            b"PUT / HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"
+        );
+        //GET 没有请求体时不带 Content-Length
+        let request = client.get("http://localhost/");
+        assert_eq!(
+            request.encode("localhost", "/"),
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,498 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 22:41:26
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 05:26:09
+ * @Description: 响应压缩：根据 Accept-Encoding 选择 gzip 或 brotli，缓存静态文件压缩后的副本
+ */
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use flate2::write::GzEncoder;
+
+use super::{Request, Response, StatusCode};
+
+//文本（HTML、CSS、JS、JSON）压缩之后通常只剩原来的四分之一左右。server 写出响应之前：
+// 1. 响应体已经压缩过（有 Content-Encoding）、是区间响应，或者 Content-Type 是图片、视频、压缩包这类本身就压缩过的格式，不处理；
+//    已经带有 `Vary: Accept-Encoding` 的响应说明生成它的一方（StaticFiles、代理的上游）已经按 Accept-Encoding 选过了，也不处理；
+// 2. 可以压缩的响应都加上 `Vary: Accept-Encoding`，告诉缓存同一个地址的内容随请求的 Accept-Encoding 变化；
+// 3. 太小的响应不压缩，省下的几个字节抵不上压缩的开销；压缩之后没有变小的也发送原文；
+// 4. 压缩后的内容与原文逐字节不同，强 ETag 改成弱 ETag。
+//处理函数生成的响应每次都要重新压缩，用较快的级别；静态文件压缩后的副本按路径缓存，用最高的级别，文件变化（ETag 变化）时重新压缩。
+
+/// 小于这个大小的响应默认不压缩
+pub(crate) const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// 静态文件压缩副本的缓存默认最多占用的字节数
+const CACHE_CAPACITY: usize = 32 * 1024 * 1024;
+
+/// 支持的内容编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// `Content-Encoding` 中的名字
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    /// 压缩 `data`。`best` 为 true 时用最高的压缩级别，适合压缩一次、发送多次的内容
+    pub fn compress(self, data: &[u8], best: bool) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let level = if best {
+                    flate2::Compression::best()
+                } else {
+                    flate2::Compression::default()
+                };
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Brotli => {
+                let quality = if best { 11 } else { 5 };
+                let mut out = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, quality, 22);
+                    encoder.write_all(data)?;
+                    encoder.flush()?;
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 根据 `Accept-Encoding` 的值选择编码，客户端不接受任何支持的编码时返回 `None`（发送原文）。
+///
+/// 选 q 值最大的编码，q 值相同时优先 brotli；没有单独列出的编码使用 `*` 的 q 值。
+///
+/// ```
+/// use multithreaded::http::{negotiate_encoding, Encoding};
+///
+/// assert_eq!(negotiate_encoding("gzip, deflate, br"), Some(Encoding::Brotli));
+/// assert_eq!(negotiate_encoding("br;q=0.5, gzip"), Some(Encoding::Gzip));
+/// assert_eq!(negotiate_encoding("identity"), None);
+/// ```
+pub fn negotiate_encoding(accept: &str) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut brotli = None;
+    let mut any = None;
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let mut q = 1.0;
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    //不合法的 q 值当作 0，宁可不压缩
+                    q = value.trim().parse::<f32>().unwrap_or(0.0);
+                }
+            }
+        }
+        match coding.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(q),
+            "br" => brotli = Some(q),
+            "*" => any = Some(q),
+            _ => {}
+        }
+    }
+    let brotli = brotli.or(any).unwrap_or(0.0);
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    if brotli <= 0.0 && gzip <= 0.0 {
+        None
+    } else if brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else {
+        Some(Encoding::Gzip)
+    }
+}
+
+/// 这种 `Content-Type` 的内容是否值得压缩。图片、音视频、字体和压缩包本身就是压缩过的，再压缩没有意义。
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/x-icon"
+        )
+}
+
+//请求中所有 Accept-Encoding 头部的值，多个同名头部等价于用逗号连起来
+fn accept_encoding(request: &Request) -> String {
+    request
+        .headers
+        .get_all("accept-encoding")
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+//可以压缩的响应都加上 Vary，返回是否应该尝试压缩
+fn prepare(response: &mut Response) -> bool {
+    if !response.status.allows_body()
+        || response.status == StatusCode::PARTIAL_CONTENT
+        || response.headers.contains("content-encoding")
+        || response.headers.contains("content-range")
+    {
+        return false;
+    }
+    let compressible = response
+        .headers
+        .get("content-type")
+        .is_some_and(is_compressible);
+    if compressible && !response.headers.has_token("vary", "*") {
+        response.headers.append_token("Vary", "Accept-Encoding");
+    }
+    compressible
+}
+
+//换上压缩后的响应体
+fn encode(response: &mut Response, encoding: Encoding, body: Vec<u8>) {
+    response
+        .headers
+        .insert("Content-Encoding", encoding.as_str());
+    response.headers.remove("content-length");
+    if let Some(etag) = response.headers.get("etag") {
+        if !etag.starts_with("W/") {
+            let weak = format!("W/{}", etag);
+            response.headers.insert("ETag", weak);
+        }
+    }
+    response.body = body;
+}
+
+/// 按请求的 `Accept-Encoding` 压缩处理函数生成的响应。小于 `min_size` 的响应不压缩。
+pub(crate) fn compress_response(request: &Request, response: &mut Response, min_size: usize) {
+    //StaticFiles 缓存过“压缩之后没有变小”的文件时发送的原文就带着它，不能每次都再压缩一遍
+    if response.headers.has_token("vary", "accept-encoding") {
+        return;
+    }
+    if !prepare(response) || response.body.len() < min_size {
+        return;
+    }
+    let Some(encoding) = negotiate_encoding(&accept_encoding(request)) else {
+        return;
+    };
+    match encoding.compress(&response.body, false) {
+        Ok(body) if body.len() < response.body.len() => encode(response, encoding, body),
+        _ => {}
+    }
+}
+
+/// 静态文件压缩后的副本，按路径和编码缓存；文件的 ETag 变化时重新压缩。
+/// 缓存占用的字节数超过上限时随便丢掉一些旧的副本。
+pub(crate) struct Cache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<(PathBuf, Encoding), Cached>,
+    size: usize,
+}
+
+struct Cached {
+    etag: String,
+    //None 表示压缩之后没有变小，直接发送原文
+    body: Option<Vec<u8>>,
+}
+
+impl Cached {
+    fn size(&self) -> usize {
+        self.body.as_ref().map_or(0, Vec::len)
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new(CACHE_CAPACITY)
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = self.entries.lock().unwrap();
+        f.debug_struct("Cache")
+            .field("files", &entries.map.len())
+            .field("size", &entries.size)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl Cache {
+    pub(crate) fn new(capacity: usize) -> Cache {
+        Cache {
+            entries: Mutex::new(Entries::default()),
+            capacity,
+        }
+    }
+
+    /// 回复长度为 `len` 的整个静态文件，按请求压缩，小于 `min_size` 的文件不压缩。
+    /// `response` 已经设置了 `Content-Type` 和 `ETag`，还没有响应体。
+    pub(crate) fn serve(
+        &self,
+        request: &Request,
+        mut response: Response,
+        path: &Path,
+        len: u64,
+        min_size: usize,
+    ) -> io::Result<Response> {
+        let encoding = if prepare(&mut response) && len >= min_size as u64 {
+            negotiate_encoding(&accept_encoding(request))
+        } else {
+            None
+        };
+        let Some(encoding) = encoding else {
+            return fs::read(path).map(|body| response.with_body(body));
+        };
+        let etag = response.headers.get("etag").unwrap_or("").to_string();
+        let compressed = match self.get(path, encoding, &etag) {
+            Some(compressed) => compressed,
+            None => {
+                let original = fs::read(path)?;
+                let compressed = encoding
+                    .compress(&original, true)
+                    .ok()
+                    .filter(|body| body.len() < original.len());
+                self.insert(path, encoding, etag, compressed.clone());
+                if compressed.is_none() {
+                    return Ok(response.with_body(original));
+                }
+                compressed
+            }
+        };
+        match compressed {
+            Some(body) => {
+                encode(&mut response, encoding, body);
+                Ok(response)
+            }
+            None => fs::read(path).map(|body| response.with_body(body)),
+        }
+    }
+
+    //缓存中的副本，外层的 None 表示没有缓存或者已经过时
+    fn get(&self, path: &Path, encoding: Encoding, etag: &str) -> Option<Option<Vec<u8>>> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .map
+            .get(&(path.to_path_buf(), encoding))
+            .filter(|cached| cached.etag == etag)
+            .map(|cached| cached.body.clone())
+    }
+
+    //压缩是在锁外面进行的，两个线程可能同时压缩同一个文件，后插入的覆盖先插入的
+    fn insert(&self, path: &Path, encoding: Encoding, etag: String, body: Option<Vec<u8>>) {
+        let cached = Cached { etag, body };
+        let size = cached.size();
+        if size > self.capacity {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let key = (path.to_path_buf(), encoding);
+        if let Some(old) = entries.map.remove(&key) {
+            entries.size -= old.size();
+        }
+        while entries.size + size > self.capacity {
+            let Some(victim) = entries.map.keys().next().cloned() else {
+                break;
+            };
+            let old = entries.map.remove(&victim).unwrap();
+            entries.size -= old.size();
+        }
+        entries.size += size;
+        entries.map.insert(key, cached);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Method, Version};
+    use std::io::Read;
+
+    fn request(accept: Option<&str>) -> Request {
+        let mut request = Request {
+            method: Method::Get,
+            target: "/".to_string(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: Vec::new(),
+        };
+        if let Some(accept) = accept {
+            request.headers.append("Accept-Encoding", accept);
+        }
+        request
+    }
+
+    fn decode(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        match encoding {
+            "gzip" => flate2::read::GzDecoder::new(body)
+                .read_to_end(&mut out)
+                .unwrap(),
+            "br" => brotli::Decompressor::new(body, 4096)
+                .read_to_end(&mut out)
+                .unwrap(),
+            _ => panic!("unknown encoding {}", encoding),
+        };
+        out
+    }
+
+    #[test]
+    fn negotiates_by_quality() {
+        assert_eq!(negotiate_encoding(""), None);
+        assert_eq!(negotiate_encoding("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("GZIP;q=0.8, br"), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate_encoding("br;q=0, gzip;q=0.1"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate_encoding("*"), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate_encoding("*;q=0.5, br;q=0.1"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate_encoding("gzip;q=0, br;q=0"), None);
+        assert_eq!(negotiate_encoding("deflate, identity"), None);
+        assert_eq!(negotiate_encoding("gzip;q=oops"), None);
+    }
+
+    #[test]
+    fn only_text_is_compressible() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/gzip"));
+        assert!(!is_compressible("font/woff2"));
+    }
+
+    #[test]
+    fn compresses_large_text_responses() {
+        let text = "hello, compression! ".repeat(200);
+        for (accept, name) in [("gzip", "gzip"), ("br, gzip", "br")] {
+            let mut response =
+                Response::text(StatusCode::OK, text.clone()).with_header("ETag", "\"v1\"");
+            compress_response(&request(Some(accept)), &mut response, DEFAULT_MIN_SIZE);
+            assert_eq!(response.headers.get("content-encoding"), Some(name));
+            assert_eq!(response.headers.get("vary"), Some("Accept-Encoding"));
+            assert_eq!(response.headers.get("etag"), Some("W/\"v1\""));
+            assert!(response.body.len() < text.len() / 10);
+            assert_eq!(decode(name, &response.body), text.as_bytes());
+        }
+    }
+
+    #[test]
+    fn skips_what_should_not_be_compressed() {
+        let text = "x".repeat(4096);
+        //客户端不支持
+        let mut response = Response::text(StatusCode::OK, text.clone());
+        compress_response(&request(None), &mut response, DEFAULT_MIN_SIZE);
+        assert!(!response.headers.contains("content-encoding"));
+        assert_eq!(response.headers.get("vary"), Some("Accept-Encoding"));
+        //太小
+        let mut response = Response::text(StatusCode::OK, "tiny").with_header("Vary", "Origin");
+        compress_response(&request(Some("gzip")), &mut response, DEFAULT_MIN_SIZE);
+        assert_eq!(response.body, b"tiny");
+        assert_eq!(
+            response.headers.get("vary"),
+            Some("Origin, Accept-Encoding")
+        );
+        //本身就是压缩过的格式
+        let mut response = Response::new(StatusCode::OK)
+            .with_header("Content-Type", "image/png")
+            .with_body(text.clone());
+        compress_response(&request(Some("gzip")), &mut response, DEFAULT_MIN_SIZE);
+        assert_eq!(response.body, text.as_bytes());
+        assert!(!response.headers.contains("vary"));
+        //处理函数自己压缩过
+        let mut response =
+            Response::text(StatusCode::OK, text.clone()).with_header("Content-Encoding", "gzip");
+        compress_response(&request(Some("br")), &mut response, DEFAULT_MIN_SIZE);
+        assert_eq!(response.headers.get("content-encoding"), Some("gzip"));
+        assert_eq!(response.body, text.as_bytes());
+        //已经按 Accept-Encoding 选过了，比如缓存里压缩之后没有变小的静态文件
+        let mut response =
+            Response::text(StatusCode::OK, text.clone()).with_header("Vary", "accept-encoding");
+        compress_response(&request(Some("gzip")), &mut response, DEFAULT_MIN_SIZE);
+        assert!(!response.headers.contains("content-encoding"));
+        assert_eq!(response.body, text.as_bytes());
+    }
+
+    #[test]
+    fn random_data_is_sent_as_is() {
+        //伪随机的字节压缩不了，压缩后反而更大
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let mut response = Response::new(StatusCode::OK)
+            .with_header("Content-Type", "text/plain")
+            .with_body(noise.clone());
+        compress_response(&request(Some("gzip")), &mut response, DEFAULT_MIN_SIZE);
+        assert!(!response.headers.contains("content-encoding"));
+        assert_eq!(response.body, noise);
+    }
+
+    #[test]
+    fn cache_evicts_to_stay_under_capacity() {
+        let cache = Cache::new(100);
+        cache.insert(
+            Path::new("a"),
+            Encoding::Gzip,
+            "1".into(),
+            Some(vec![0; 60]),
+        );
+        cache.insert(
+            Path::new("b"),
+            Encoding::Gzip,
+            "1".into(),
+            Some(vec![0; 60]),
+        );
+        assert!(cache.entries.lock().unwrap().size <= 100);
+        assert!(cache.get(Path::new("b"), Encoding::Gzip, "1").is_some());
+        assert!(cache.get(Path::new("b"), Encoding::Gzip, "2").is_none());
+        cache.insert(
+            Path::new("c"),
+            Encoding::Gzip,
+            "1".into(),
+            Some(vec![0; 200]),
+        );
+        assert!(cache.get(Path::new("c"), Encoding::Gzip, "1").is_none());
+    }
+}
@@ -0,0 +1,432 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 19:47:31
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 05:48:20
+ * @Description: server 的配置：TOML 配置文件和命令行参数
+ */
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{LogFormat, Rotation, ServerConfig, DEFAULT_UPSTREAM_TIMEOUT};
+
+//最初监听地址 127.0.0.1:7878 和 4 个线程都写死在 main 里，改一下就要重新编译。
+//现在这些设置可以写在 TOML 配置文件里（--config 指定），也可以通过命令行参数给出，命令行参数覆盖配置文件。
+//配置文件的键与命令行参数同名，只是把 `-` 换成 `_`，例如：
+//
+//    bind = "0.0.0.0:8080"
+//    workers = 8
+//    docroot = "public"
+//    keep_alive = 5          # 秒，可以是小数
+//    max_connections = 1024
+//    access_log = "access.log"   # "-" 表示标准输出
+//    log_format = "json"
+//    compress_min_size = "off"   # 或者字节数
+//    upstream = "127.0.0.1:9000" # /api/ 下的请求转发到这里
+
+/// 命令行的用法说明
+pub const USAGE: &str = "\
+usage: main [options]
+
+options:
+  --config <file>            read settings from a TOML file; flags override it
+  --bind <addr>              address to listen on (default 127.0.0.1:7878)
+  --workers <n>              number of worker threads (default 4)
+  --docroot <dir>            directory served under /static/ (default public)
+  --keep-alive <secs>        close idle keep-alive connections after this long (default 5)
+  --request-timeout <secs>   give up on a request that stalls this long (default 10)
+  --max-requests <n>         requests served per connection (default 100)
+  --max-connections <n>      connections open at once (default 1024)
+  --mode <mode>              threads, or event-loop to multiplex connections with epoll (default threads)
+  --compress-min-size <n>    gzip/brotli text responses of at least n bytes, or off (default 1024)
+  --upstream <addr>          proxy /api/* to the HTTP server at host:port (default off)
+  --upstream-timeout <secs>  give up on an upstream that stalls this long (default 30)
+  --shutdown-timeout <secs>  time allowed for in-flight requests on shutdown (default 30)
+  --tls-cert <file>          serve HTTPS with this PEM certificate chain (needs --tls-key)
+  --tls-key <file>           PEM private key for --tls-cert
+  --access-log <file>        write an access log to this file, or - for stdout (default off)
+  --log-format <format>      common, combined or json (default combined)
+  --log-max-size <bytes>     rotate the access log at this size, 0 to never rotate (default 10485760)
+  --log-keep <n>             rotated access logs to keep (default 5)
+  -h, --help                 print this help
+";
+
+/// 启动 server 所需的全部配置
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: String,
+    pub workers: usize,
+    pub docroot: PathBuf,
+    /// 收到 SIGINT 或 SIGTERM 后，等待正在处理的请求多久
+    pub shutdown_timeout: Duration,
+    pub server: ServerConfig,
+    /// PEM 格式的证书链和私钥，两个都有时提供 HTTPS
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// 访问日志写到哪里，`-` 表示标准输出，`None` 表示不记录
+    pub access_log: Option<PathBuf>,
+    pub log_format: LogFormat,
+    pub log_rotation: Rotation,
+    /// `/api/` 下的请求转发到的上游（`host:port`），`None` 表示不代理
+    pub upstream: Option<String>,
+    /// 读写上游的超时
+    pub upstream_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "127.0.0.1:7878".to_string(),
+            workers: 4,
+            docroot: PathBuf::from("public"),
+            shutdown_timeout: Duration::from_secs(30),
+            server: ServerConfig::default(),
+            tls_cert: None,
+            tls_key: None,
+            access_log: None,
+            log_format: LogFormat::default(),
+            log_rotation: Rotation::default(),
+            upstream: None,
+            upstream_timeout: DEFAULT_UPSTREAM_TIMEOUT,
+        }
+    }
+}
+
+/// 读取配置时的错误
+#[derive(Debug)]
+pub enum ConfigError {
+    /// 命令行中有 `-h` 或 `--help`，调用者应当打印 [`USAGE`]
+    Help,
+    /// 读取配置文件失败
+    Io(PathBuf, io::Error),
+    /// 配置文件不是合法的 TOML
+    Toml(PathBuf, String),
+    /// 未知的选项，或者选项的值不合法
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Help => f.write_str("help requested"),
+            ConfigError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            ConfigError::Toml(path, err) => {
+                write!(f, "invalid TOML in {}: {}", path.display(), err)
+            }
+            ConfigError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    /// 从命令行参数（不包括程序名）读取配置。先读 `--config` 指定的文件，再应用其余的参数。
+    ///
+    /// ```
+    /// use multithreaded::http::Config;
+    ///
+    /// let config = Config::from_args(["--workers", "8", "--keep-alive=2.5"]).unwrap();
+    /// assert_eq!(config.workers, 8);
+    /// assert_eq!(config.server.keep_alive.as_millis(), 2500);
+    /// ```
+    pub fn from_args<I, S>(args: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut flags = Vec::new();
+        let mut file = None;
+        let mut args = args.into_iter().map(Into::into);
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(ConfigError::Help);
+            }
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(ConfigError::Invalid(format!(
+                    "unexpected argument {:?}",
+                    arg
+                )));
+            };
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => match args.next() {
+                    Some(value) => (flag.to_string(), value),
+                    None => return Err(ConfigError::Invalid(format!("--{} needs a value", flag))),
+                },
+            };
+            if key == "config" {
+                file = Some(PathBuf::from(value));
+            } else {
+                flags.push((key.replace('-', "_"), value));
+            }
+        }
+
+        let mut config = match file {
+            Some(path) => Config::read_file(path)?,
+            None => Config::default(),
+        };
+        for (key, value) in flags {
+            config.set(&key, &value).map_err(|err| {
+                ConfigError::Invalid(format!("--{}: {}", key.replace('_', "-"), err))
+            })?;
+        }
+        config.check()?;
+        Ok(config)
+    }
+
+    /// 读取 TOML 配置文件，没有出现的键使用默认值
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Config, ConfigError> {
+        let config = Config::read_file(path.into())?;
+        config.check()?;
+        Ok(config)
+    }
+
+    //读取配置文件但不检查组合，命令行参数还可能补上缺少的项
+    fn read_file(path: PathBuf) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(&path).map_err(|err| ConfigError::Io(path.clone(), err))?;
+        let table: toml::Table = text.parse().map_err(|err: toml::de::Error| {
+            ConfigError::Toml(path.clone(), err.message().to_string())
+        })?;
+        let mut config = Config::default();
+        for (key, value) in &table {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                _ => {
+                    return Err(ConfigError::Invalid(format!(
+                        "{}: {} must be a string or a number",
+                        path.display(),
+                        key
+                    )))
+                }
+            };
+            config.set(key, &value).map_err(|err| {
+                ConfigError::Invalid(format!("{}: {}: {}", path.display(), key, err))
+            })?;
+        }
+        Ok(config)
+    }
+
+    //各项单独都合法，但是组合起来不合法的情况
+    fn check(&self) -> Result<(), ConfigError> {
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(ConfigError::Invalid(
+                "--tls-cert and --tls-key must be given together".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    //设置一项配置，配置文件和命令行共用
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "bind" => self.bind = value.to_string(),
+            "workers" => self.workers = positive(value)?,
+            "docroot" => self.docroot = PathBuf::from(value),
+            "keep_alive" => self.server.keep_alive = seconds(value)?,
+            "request_timeout" => self.server.request_timeout = seconds(value)?,
+            "max_requests" => self.server.max_requests = positive(value)?,
+            "max_connections" => self.server.max_connections = positive(value)?,
+            "mode" => self.server.mode = value.parse()?,
+            "compress_min_size" => {
+                self.server.compress_min_size = match value {
+                    "off" => None,
+                    _ => Some(count(value)?),
+                }
+            }
+            "upstream" => self.upstream = Some(upstream(value)?),
+            "upstream_timeout" => self.upstream_timeout = seconds(value)?,
+            "shutdown_timeout" => self.shutdown_timeout = seconds(value)?,
+            "tls_cert" => self.tls_cert = Some(PathBuf::from(value)),
+            "tls_key" => self.tls_key = Some(PathBuf::from(value)),
+            "access_log" => self.access_log = Some(PathBuf::from(value)),
+            "log_format" => self.log_format = value.parse()?,
+            "log_max_size" => self.log_rotation.max_bytes = count(value)? as u64,
+            "log_keep" => self.log_rotation.keep = count(value)?,
+            _ => return Err(format!("unknown option {:?}", key)),
+        }
+        Ok(())
+    }
+}
+
+fn positive(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("expected a positive integer, got {:?}", value)),
+    }
+}
+
+fn count(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("expected a non-negative integer, got {:?}", value))
+}
+
+//Proxy 只支持明文的上游，遇到 https:// 会 panic，所以启动之前就拒绝
+fn upstream(value: &str) -> Result<String, String> {
+    if value.starts_with("https://") {
+        return Err(format!("HTTPS upstream {:?} is not supported", value));
+    }
+    let addr = value.strip_prefix("http://").unwrap_or(value);
+    if addr.contains("://") || addr.trim_end_matches('/').is_empty() {
+        return Err(format!(
+            "expected host:port or http://host:port, got {:?}",
+            value
+        ));
+    }
+    Ok(value.to_string())
+}
+
+//各种超时都不会需要超过一天；太大的值加到 Instant 上会溢出
+const MAX_SECONDS: u64 = 24 * 60 * 60;
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .filter(|duration| !duration.is_zero() && *duration <= Duration::from_secs(MAX_SECONDS))
+        .ok_or_else(|| {
+            format!(
+                "expected a positive number of seconds up to {}, got {:?}",
+                MAX_SECONDS, value
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Mode;
+
+    #[test]
+    fn defaults_without_arguments() {
+        let config = Config::from_args(Vec::<String>::new()).unwrap();
+        assert_eq!(config.bind, "127.0.0.1:7878");
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.server.max_connections, 1024);
+    }
+
+    #[test]
+    fn flags_accept_both_forms() {
+        let config = Config::from_args([
+            "--bind=0.0.0.0:8080",
+            "--max-connections",
+            "10",
+            "--request-timeout",
+            "0.5",
+            "--mode=event-loop",
+            "--compress-min-size=off",
+        ])
+        .unwrap();
+        assert_eq!(config.bind, "0.0.0.0:8080");
+        assert_eq!(config.server.max_connections, 10);
+        assert_eq!(config.server.request_timeout, Duration::from_millis(500));
+        assert_eq!(config.server.mode, Mode::EventLoop);
+        assert_eq!(config.server.compress_min_size, None);
+        let config = Config::from_args(["--compress-min-size", "256"]).unwrap();
+        assert_eq!(config.server.compress_min_size, Some(256));
+        let config =
+            Config::from_args(["--upstream", "127.0.0.1:9000", "--upstream-timeout=2"]).unwrap();
+        assert_eq!(config.upstream.as_deref(), Some("127.0.0.1:9000"));
+        assert_eq!(config.upstream_timeout, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn access_log_options() {
+        let config = Config::from_args(Vec::<String>::new()).unwrap();
+        assert_eq!(config.access_log, None);
+        assert_eq!(config.log_format, LogFormat::Combined);
+
+        let config = Config::from_args([
+            "--access-log=-",
+            "--log-format",
+            "json",
+            "--log-max-size",
+            "0",
+            "--log-keep=2",
+        ])
+        .unwrap();
+        assert_eq!(config.access_log, Some(PathBuf::from("-")));
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(
+            config.log_rotation,
+            Rotation {
+                max_bytes: 0,
+                keep: 2
+            }
+        );
+        assert!(Config::from_args(["--log-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_flags() {
+        for args in [
+            &["--workers", "0"][..],
+            &["--keep-alive", "-1"],
+            &["--keep-alive", "18446744073709551615"],
+            &["--request-timeout=1e30"],
+            &["--port", "80"],
+            &["--bind"],
+            &["--mode", "async"],
+            &["--tls-cert", "cert.pem"],
+            &["--upstream", "https://127.0.0.1:9000"],
+            &["--upstream", "ftp://127.0.0.1:9000"],
+            &["--upstream", "http://"],
+            &["serve"],
+        ] {
+            assert!(
+                matches!(
+                    Config::from_args(args.iter().copied()),
+                    Err(ConfigError::Invalid(_))
+                ),
+                "{:?}",
+                args
+            );
+        }
+        assert!(matches!(Config::from_args(["-h"]), Err(ConfigError::Help)));
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.toml");
+        fs::write(
+            &path,
+            "bind = \"0.0.0.0:9000\"\nworkers = 16\nkeep_alive = 1.5\ndocroot = \"www\"\n",
+        )
+        .unwrap();
+        let config =
+            Config::from_args(["--config", path.to_str().unwrap(), "--workers", "2"]).unwrap();
+        assert_eq!(config.bind, "0.0.0.0:9000");
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.docroot, PathBuf::from("www"));
+        assert_eq!(config.server.keep_alive, Duration::from_millis(1500));
+
+        fs::write(&path, "workers = [1, 2]\n").unwrap();
+        assert!(matches!(
+            Config::from_file(&path),
+            Err(ConfigError::Invalid(_))
+        ));
+        fs::write(&path, "workers = \n").unwrap();
+        assert!(matches!(
+            Config::from_file(&path),
+            Err(ConfigError::Toml(..))
+        ));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(Config::from_file(&path), Err(ConfigError::Io(..))));
+    }
+}
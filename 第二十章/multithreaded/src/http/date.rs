@@ -0,0 +1,146 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 18:40:52
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 18:40:52
+ * @Description: HTTP 日期（IMF-fixdate）的格式化与解析
+ */
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+//UTC 的日历时间。标准库没有日历相关的功能，这里用 Howard Hinnant 的 civil_from_days 算法自己换算。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DateTime {
+    pub(crate) year: i64,
+    /// 1 到 12
+    pub(crate) month: u32,
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+    /// 0 表示星期日
+    pub(crate) weekday: u32,
+}
+
+impl DateTime {
+    //早于 1970 年的时间按 1970-01-01 00:00:00 处理
+    pub(crate) fn from_system_time(time: SystemTime) -> DateTime {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()) as i64;
+        let days = secs.div_euclid(86400);
+        let rem = secs.rem_euclid(86400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: rem / 3600,
+            minute: rem % 3600 / 60,
+            second: rem % 60,
+            weekday: (days + 4).rem_euclid(7) as u32,
+        }
+    }
+
+    pub(crate) fn month_name(&self) -> &'static str {
+        MONTHS[self.month as usize - 1]
+    }
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// 格式化成 `Sun, 06 Nov 1994 08:49:37 GMT` 的形式，用于 `Date`、`Last-Modified` 等头部
+pub fn format_http_date(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[t.weekday as usize],
+        t.day,
+        t.month_name(),
+        t.year,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+/// 解析 IMF-fixdate 格式的日期。RFC 9110 要求接收方也认识两种过时的格式，
+/// 这里不支持，遇到时返回 `None`，调用方把它当作没有这个头部。
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let rest = value.trim().split_once(", ")?.1;
+    let mut parts = rest.split(' ');
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|&name| name == month)? as u32 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut clock = parts
+        .next()?
+        .split(':')
+        .map(|part| part.parse::<u32>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if parts.next()? != "GMT" || parts.next().is_some() || clock.next().is_some() {
+        return None;
+    }
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 || year < 1970 {
+        return None;
+    }
+    let secs =
+        days_from_civil(year, month, day) * 86400 + i64::from(hour * 3600 + minute * 60 + second);
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_the_rfc_example() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            format_http_date(UNIX_EPOCH),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn parsing_round_trips() {
+        for secs in [0, 784111777, 951782400, 1709164800, 4102444799] {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(parse_http_date(&format_http_date(time)), Some(time));
+        }
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 25:00:00 GMT"), None);
+    }
+}
@@ -0,0 +1,72 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 20:58:16
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:52:36
+ * @Description: 处理连接时的错误
+ */
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use super::ParseError;
+
+//最初的 handle_connection 对 stream.read、fs::read_to_string、stream.write 和 flush 都直接 unwrap：
+//客户端在响应写到一半时断开，或者 404.html 不存在，worker 就会 panic。
+//现在处理一个连接时的每一种失败都变成一个 ConnectionError，交给 server 的错误回调（默认打印到标准错误，客户端断开连接除外）记录，
+//然后关闭这个连接，worker 继续处理别的连接。能回复的错误先回复：请求不合法回复 400 等，请求超时回复 408，
+//处理函数 panic 回复 500。
+
+/// 处理一个连接时的错误，由 [`ServerBuilder::on_error`](super::ServerBuilder::on_error) 设置的回调接收
+#[derive(Debug)]
+pub enum ConnectionError {
+    /// 读取请求失败，例如对方重置了连接
+    Read(io::Error),
+    /// 请求不合法或者超过了上限，已经回复了对应的状态码
+    Parse(ParseError),
+    /// 请求在 `request_timeout` 之内没有完整到达，已经回复了 408
+    Timeout,
+    /// 写响应失败，最常见的是客户端已经断开（broken pipe）
+    Write(io::Error),
+    /// 处理函数 panic，已经回复了 500，连接继续使用
+    Handler(String),
+}
+
+impl ConnectionError {
+    /// 是不是客户端断开连接引起的。这类错误很常见，也不是 server 的问题。
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            ConnectionError::Read(err) | ConnectionError::Write(err) => matches!(
+                err.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Read(err) => write!(f, "failed to read request: {}", err),
+            ConnectionError::Parse(err) => write!(f, "invalid request: {}", err),
+            ConnectionError::Timeout => f.write_str("request timed out"),
+            ConnectionError::Write(err) => write!(f, "failed to write response: {}", err),
+            ConnectionError::Handler(message) => write!(f, "handler panicked: {}", message),
+        }
+    }
+}
+
+impl Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConnectionError::Read(err) | ConnectionError::Write(err) => Some(err),
+            ConnectionError::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
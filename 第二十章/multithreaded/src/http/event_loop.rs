@@ -0,0 +1,541 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 21:36:52
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 06:24:10
+ * @Description: 事件循环模式：一个线程用 epoll 读写所有连接，只把阻塞的处理函数交给线程池
+ */
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{self, SocketAddr, TcpListener};
+use std::os::fd::AsFd;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mio::net::{TcpListener as MioListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+
+use super::parser::{BodyReader, Next};
+use super::server::{handle_request, log_response, respond, run_websocket, service_unavailable};
+use super::server::{Counted, ServerShared};
+use super::tls::Stream;
+use super::{ConnectionError, Method, Parser, Request, Response, StatusCode};
+use crate::ThreadPool;
+
+//线程模式下，一个连接从读请求到写完响应都占着一个 worker，读写都是阻塞的。
+//5 个慢吞吞地发送请求的客户端就能占满 ThreadPool::new(4) 的全部 worker，后面的请求只能排队，/sleep 也是一样。
+//事件循环模式只用一个线程读写所有连接：
+// 1. 套接字都是非阻塞的，注册到 epoll（通过 mio）上，哪个连接可读、可写就处理哪个，没有数据时不占用任何线程；
+// 2. 读到完整的请求后，普通的处理函数直接在事件循环线程上运行；用 Router::blocking、Router::blocking_not_found 标记的处理函数，
+//    以及会阻塞的中间件（例如 Proxy）要处理的请求交给线程池，处理完通过信道把响应送回来，再用 Waker 唤醒事件循环；
+// 3. 一个连接同一时刻只处理一个请求，流水线请求的响应顺序与请求的顺序一致；
+// 4. 响应先写进连接的输出缓冲区，套接字写不下时等它再次可写，没写完之前不处理这个连接的下一个请求；
+// 5. 配置了 TLS 时套接字包装在 rustls 会话里，握手和加解密也是非阻塞地在事件循环线程上进行；
+// 6. 超时（request_timeout、keep_alive）、max_requests、max_connections、访问日志和优雅关闭都与线程模式相同；
+// 7. WebSocket 握手的 101 写完之后，连接从 epoll 上注销并改回阻塞模式，整个会话交给线程池运行；
+// 8. Router::streaming 的路由也一样：头部解析完就把连接交给线程池，处理函数阻塞地读请求体，写完响应后关闭连接。
+//
+//mio 在 Linux 上是边沿触发的，所以每次都要读（写、accept）到 WouldBlock 为止，否则不会再收到通知。
+
+const LISTENER: Token = Token(0);
+const WAKER: Token = Token(1);
+//连接的 token 从这里开始递增，不会重复使用，线程池送回的响应不会交给后来的连接
+const FIRST_CONNECTION: usize = 2;
+
+struct Conn {
+    stream: Stream<TcpStream>,
+    peer: Option<SocketAddr>,
+    parser: Parser,
+    served: usize,
+    //待写出的响应，written 之前的部分已经写出
+    out: Vec<u8>,
+    written: usize,
+    //线程池正在处理这个连接的请求
+    busy: bool,
+    //写完输出缓冲区之后关闭连接
+    closing: bool,
+    //对方已经关闭了连接的写端
+    eof: bool,
+    //最后一次读写有进展的时间，超时从这里算起
+    last_active: Instant,
+    //握手成功的 WebSocket 请求，写完 101 之后升级
+    upgrade: Option<Request>,
+    //Router::streaming 的路由的请求，请求体由线程池上的处理函数自己读
+    streaming: Option<Request>,
+    _counted: Counted,
+}
+
+impl Conn {
+    fn writing(&self) -> bool {
+        self.written < self.out.len()
+    }
+
+    fn idle(&self) -> bool {
+        !self.busy && !self.writing() && !self.parser.has_partial()
+    }
+}
+
+//线程池处理完的请求
+struct Done {
+    token: Token,
+    request: Request,
+    response: Response,
+    keep_alive: bool,
+    started: Instant,
+}
+
+struct EventLoop<'a> {
+    shared: &'a Arc<ServerShared>,
+    pool: &'a ThreadPool,
+    poll: Poll,
+    waker: Arc<Waker>,
+    done_tx: Sender<Done>,
+    done_rx: Receiver<Done>,
+    conns: HashMap<Token, Conn>,
+    next_token: usize,
+}
+
+/// 在当前线程上运行事件循环，直到 server 停止而且所有连接都处理完
+pub(super) fn run(
+    shared: &Arc<ServerShared>,
+    pool: &ThreadPool,
+    listener: &TcpListener,
+) -> io::Result<()> {
+    let listener = listener.try_clone()?;
+    listener.set_nonblocking(true)?;
+    let mut listener = MioListener::from_std(listener);
+    let poll = Poll::new()?;
+    poll.registry()
+        .register(&mut listener, LISTENER, Interest::READABLE)?;
+    let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut event_loop = EventLoop {
+        shared,
+        pool,
+        poll,
+        waker,
+        done_tx,
+        done_rx,
+        conns: HashMap::new(),
+        next_token: FIRST_CONNECTION,
+    };
+
+    let mut events = Events::with_capacity(1024);
+    let mut accepting = true;
+    loop {
+        //停止之后不再接受新连接，空闲的连接直接关闭，等其余的连接写完当前的响应
+        if shared.stop.stopped.load(Ordering::SeqCst) {
+            if accepting {
+                event_loop.poll.registry().deregister(&mut listener)?;
+                accepting = false;
+            }
+            event_loop.conns.retain(|_, conn| !conn.idle());
+            if event_loop.conns.is_empty() {
+                return Ok(());
+            }
+        }
+
+        let timeout = event_loop
+            .next_deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if let Err(err) = event_loop.poll.poll(&mut events, timeout) {
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        for event in events.iter() {
+            match event.token() {
+                LISTENER => event_loop.accept(&listener),
+                WAKER => {}
+                token => event_loop.drive(token),
+            }
+        }
+        while let Ok(done) = event_loop.done_rx.try_recv() {
+            event_loop.complete(done);
+        }
+        event_loop.expire();
+    }
+}
+
+impl EventLoop<'_> {
+    fn accept(&mut self, listener: &MioListener) {
+        loop {
+            let (stream, peer) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                //WouldBlock 表示已经没有等待接受的连接；其他错误（例如文件描述符用完）时跳过这一轮
+                Err(_) => return,
+            };
+            let connections = &self.shared.connections;
+            if connections.fetch_add(1, Ordering::SeqCst) >= self.shared.config.max_connections {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                //响应很短，新连接的发送缓冲区一定放得下。TLS 连接要先握手，直接关闭
+                if self.shared.tls.is_none() {
+                    let mut stream = stream;
+                    if let Err(err) =
+                        respond(self.shared, &mut stream, Some(peer), &service_unavailable())
+                    {
+                        (self.shared.on_error)(Some(peer), &err);
+                    }
+                }
+                continue;
+            }
+            let counted = Counted(Arc::clone(connections));
+            let mut stream = match Stream::new(stream, self.shared.tls.as_ref()) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    (self.shared.on_error)(Some(peer), &ConnectionError::Read(err));
+                    continue;
+                }
+            };
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            if let Err(err) = self.poll.registry().register(
+                stream.get_mut(),
+                token,
+                Interest::READABLE | Interest::WRITABLE,
+            ) {
+                (self.shared.on_error)(Some(peer), &ConnectionError::Read(err));
+                continue;
+            }
+            //注册时已经可读的连接也会收到一次事件，不需要在这里读
+            self.conns.insert(
+                token,
+                Conn {
+                    stream,
+                    peer: Some(peer),
+                    parser: Parser::new(self.shared.config.limits),
+                    served: 0,
+                    out: Vec::new(),
+                    written: 0,
+                    busy: false,
+                    closing: false,
+                    eof: false,
+                    last_active: Instant::now(),
+                    upgrade: None,
+                    streaming: None,
+                    _counted: counted,
+                },
+            );
+        }
+    }
+
+    //连接可读或者可写了，或者线程池送回了响应：尽可能地推进这个连接，需要关闭时移除它（drop 即关闭）
+    fn drive(&mut self, token: Token) {
+        let Some(conn) = self.conns.get_mut(&token) else {
+            return;
+        };
+        let result = drive(
+            self.shared,
+            self.pool,
+            &self.done_tx,
+            &self.waker,
+            token,
+            conn,
+        );
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                let conn = self.conns.remove(&token).unwrap();
+                if conn.upgrade.is_some() {
+                    self.upgrade(conn);
+                } else if conn.streaming.is_some() {
+                    self.stream_body(conn);
+                }
+            }
+            Err(err) => {
+                (self.shared.on_error)(conn.peer, &err);
+                self.conns.remove(&token);
+            }
+        }
+    }
+
+    //把写完 101 的连接从 epoll 上拿下来，改回阻塞模式，在线程池上运行 WebSocket 会话
+    fn upgrade(&mut self, conn: Conn) {
+        let Conn {
+            mut stream,
+            peer,
+            parser,
+            upgrade,
+            _counted,
+            ..
+        } = conn;
+        let request = upgrade.unwrap();
+        let control = match self.detach(&mut stream) {
+            Ok(control) => control,
+            Err(err) => return (self.shared.on_error)(peer, &ConnectionError::Read(err)),
+        };
+        let shared = Arc::clone(self.shared);
+        self.pool.execute(move || {
+            let _counted = _counted;
+            let buffered = parser.into_buffered();
+            run_websocket(&shared, peer, &request, Box::new(stream), control, buffered);
+        });
+    }
+
+    //在线程池上阻塞地运行流式请求体的处理函数。读到哪里不好交还给事件循环，写完响应就关闭连接
+    fn stream_body(&mut self, conn: Conn) {
+        let Conn {
+            mut stream,
+            peer,
+            mut parser,
+            served,
+            streaming,
+            _counted,
+            ..
+        } = conn;
+        let mut request = streaming.unwrap();
+        let timeout = self.shared.config.request_timeout;
+        let control = self.detach(&mut stream).and_then(|control| {
+            control.set_read_timeout(Some(timeout))?;
+            control.set_write_timeout(Some(timeout))
+        });
+        if let Err(err) = control {
+            return (self.shared.on_error)(peer, &ConnectionError::Read(err));
+        }
+        let shared = Arc::clone(self.shared);
+        self.pool.execute(move || {
+            let _counted = _counted;
+            let started = Instant::now();
+            let mut body = BodyReader::new(&mut stream, &mut parser);
+            let (mut response, _) =
+                handle_request(&shared, peer, &mut request, served, Some(&mut body));
+            response.headers.insert("Connection", "close");
+            let include_body = request.method != Method::Head;
+            if let Err(err) = response.write_to(&mut stream, include_body) {
+                return (shared.on_error)(peer, &ConnectionError::Write(err));
+            }
+            log_response(
+                &shared,
+                peer,
+                Some(&request),
+                &response,
+                include_body,
+                started.elapsed(),
+            );
+        });
+    }
+
+    //把连接从 epoll 上拿下来，改回阻塞模式。
+    //返回复制的一份文件描述符，用来设置阻塞模式和读写超时，它们对两份描述符都生效
+    fn detach(&self, stream: &mut Stream<TcpStream>) -> io::Result<net::TcpStream> {
+        self.poll.registry().deregister(stream.get_mut())?;
+        let control = net::TcpStream::from(stream.get_ref().as_fd().try_clone_to_owned()?);
+        control.set_nonblocking(false)?;
+        Ok(control)
+    }
+
+    fn complete(&mut self, done: Done) {
+        //连接可能已经超时或者出错关闭了
+        let Some(conn) = self.conns.get_mut(&done.token) else {
+            return;
+        };
+        conn.busy = false;
+        queue(
+            self.shared,
+            conn,
+            done.request,
+            &done.response,
+            done.keep_alive,
+            done.started,
+        );
+        self.drive(done.token);
+    }
+
+    //连接下一次超时的时间。线程池正在处理的连接不会超时，超时时间大到超出 Instant 的范围时也不会
+    fn deadline(&self, conn: &Conn) -> Option<Instant> {
+        let config = &self.shared.config;
+        if conn.busy {
+            None
+        } else if conn.writing() || conn.parser.has_partial() {
+            conn.last_active.checked_add(config.request_timeout)
+        } else {
+            conn.last_active.checked_add(config.keep_alive)
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.conns
+            .values()
+            .filter_map(|conn| self.deadline(conn))
+            .min()
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<Token> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| self.deadline(conn).is_some_and(|deadline| deadline <= now))
+            .map(|(token, _)| *token)
+            .collect();
+        for token in expired {
+            let conn = self.conns.get_mut(&token).unwrap();
+            if conn.writing() {
+                //客户端一直不读响应
+                let err = ConnectionError::Write(io::ErrorKind::TimedOut.into());
+                (self.shared.on_error)(conn.peer, &err);
+                self.conns.remove(&token);
+            } else if conn.parser.has_partial() && !conn.closing {
+                let response = Response::text(StatusCode::REQUEST_TIMEOUT, "408 Request Timeout\n")
+                    .with_header("Connection", "close");
+                let _ = response.write_to(&mut conn.out, true);
+                log_response(
+                    self.shared,
+                    conn.peer,
+                    None,
+                    &response,
+                    true,
+                    Duration::ZERO,
+                );
+                conn.closing = true;
+                (self.shared.on_error)(conn.peer, &ConnectionError::Timeout);
+                self.drive(token);
+            } else {
+                //超过 keep_alive 没有新请求
+                self.conns.remove(&token);
+            }
+        }
+    }
+}
+
+//推进一个连接：写出缓冲的响应，处理已经读到的请求，再读更多的数据，直到需要等待。
+//返回 Ok(false) 表示连接应当关闭
+fn drive(
+    shared: &Arc<ServerShared>,
+    pool: &ThreadPool,
+    done: &Sender<Done>,
+    waker: &Arc<Waker>,
+    token: Token,
+    conn: &mut Conn,
+) -> Result<bool, ConnectionError> {
+    let mut chunk = [0; 4096];
+    loop {
+        while conn.writing() {
+            match conn.stream.write(&conn.out[conn.written..]) {
+                Ok(0) => return Err(ConnectionError::Write(io::ErrorKind::WriteZero.into())),
+                Ok(n) => {
+                    conn.written += n;
+                    conn.last_active = Instant::now();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(ConnectionError::Write(err)),
+            }
+        }
+        //TLS 会话里可能还有没写进套接字的密文
+        match conn.stream.flush() {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+            Err(err) => return Err(ConnectionError::Write(err)),
+        }
+        conn.out.clear();
+        conn.written = 0;
+        if conn.closing {
+            return Ok(false);
+        }
+        if conn.busy {
+            return Ok(true);
+        }
+
+        match conn
+            .parser
+            .next(|request| shared.router.streams_body(request))
+        {
+            Ok(Some(Next::Head(request))) => {
+                conn.served += 1;
+                conn.streaming = Some(request);
+                return Ok(false);
+            }
+            Ok(Some(Next::Request(mut request))) => {
+                conn.served += 1;
+                let started = Instant::now();
+                if shared.router.is_blocking(&request) || shared.middleware.is_blocking(&request) {
+                    conn.busy = true;
+                    let (shared, done, waker) =
+                        (Arc::clone(shared), done.clone(), Arc::clone(waker));
+                    let (peer, served) = (conn.peer, conn.served);
+                    pool.execute(move || {
+                        let (response, keep_alive) =
+                            handle_request(&shared, peer, &mut request, served, None);
+                        let _ = done.send(Done {
+                            token,
+                            request,
+                            response,
+                            keep_alive,
+                            started,
+                        });
+                        let _ = waker.wake();
+                    });
+                } else {
+                    let (response, keep_alive) =
+                        handle_request(shared, conn.peer, &mut request, conn.served, None);
+                    queue(shared, conn, request, &response, keep_alive, started);
+                }
+                continue;
+            }
+            Ok(None) => {
+                if conn.parser.take_continue() {
+                    conn.out.extend_from_slice(b"HTTP/1.1 100 Continue\r\n\r\n");
+                    continue;
+                }
+            }
+            //请求不合法或者超过了解析器的上限时，回复对应的状态码并在写完后关闭连接
+            Err(err) => {
+                let response = err.to_response();
+                let _ = response.write_to(&mut conn.out, true);
+                log_response(shared, conn.peer, None, &response, true, Duration::ZERO);
+                conn.closing = true;
+                (shared.on_error)(conn.peer, &ConnectionError::Parse(err));
+                continue;
+            }
+        }
+
+        if conn.eof {
+            if conn.parser.has_partial() {
+                return Err(ConnectionError::Read(io::ErrorKind::UnexpectedEof.into()));
+            }
+            return Ok(false);
+        }
+        match conn.stream.read(&mut chunk) {
+            Ok(0) => conn.eof = true,
+            Ok(n) => {
+                conn.parser.feed(&chunk[..n]);
+                conn.last_active = Instant::now();
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(ConnectionError::Read(err)),
+        }
+    }
+}
+
+//把响应放进连接的输出缓冲区，并记入访问日志。WebSocket 握手成功时留下请求，写完 101 之后升级
+fn queue(
+    shared: &ServerShared,
+    conn: &mut Conn,
+    request: Request,
+    response: &Response,
+    keep_alive: bool,
+    started: Instant,
+) {
+    let include_body = request.method != Method::Head;
+    //写进 Vec 不会失败
+    let _ = response.write_to(&mut conn.out, include_body);
+    log_response(
+        shared,
+        conn.peer,
+        Some(&request),
+        response,
+        include_body,
+        started.elapsed(),
+    );
+    conn.closing |= !keep_alive;
+    conn.last_active = Instant::now();
+    if response.status == StatusCode::SWITCHING_PROTOCOLS {
+        conn.upgrade = Some(request);
+    }
+}
@@ -0,0 +1,859 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 02:05:26
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 06:24:10
+ * @Description: 解析 application/x-www-form-urlencoded 和 multipart/form-data 表单，上传的文件写进临时目录
+ */
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use tempfile::NamedTempFile;
+
+use super::url::percent_decode;
+use super::{Request, Response, StatusCode};
+
+//POST 请求能读到请求体了，处理函数还得自己解析表单。浏览器提交表单时请求体有两种格式：
+// 1. application/x-www-form-urlencoded：name=value&name=value，`+` 表示空格，其余特殊字符用百分号编码；
+// 2. multipart/form-data：用 Content-Type 中的 boundary 分隔成多个部分，每个部分有自己的头部，
+//    Content-Disposition 给出字段名，上传文件时还有文件名。文件的内容写进临时目录里的文件，Form 里只有路径。
+//
+//普通路由的处理函数拿到的 Request 里，请求体已经整个读进了内存，能上传多大由 ServerConfig::limits 的 max_body
+//（默认 8 MiB）决定，超过的请求在调用处理函数之前就回复了 413。
+//上传大文件要用 Router::streaming 注册路由，处理函数拿到连接上的请求体（一个 Read），交给 parse_stream：
+//parse_multipart 是流式的，内存里只保留一小段数据，文件的内容边读边写进临时文件，大小只受 FormParser 的上限限制。
+//
+//字段个数、字段值、单个文件和整个表单的大小都有上限，超过时返回 413；boundary 不合法、缺少结束的分隔行、
+//部分的头部格式错误等都返回 400；两种格式都不是时返回 415。错误都可以用 FormError::to_response 直接回复。
+//临时文件在 UploadedFile 被 drop 时删除，需要保留时用 UploadedFile::persist 移到别的地方。
+
+//每次从请求体读取的字节数
+const READ_CHUNK: usize = 8 * 1024;
+
+//每个部分的头部的大小上限
+const MAX_PART_HEADERS: usize = 8 * 1024;
+
+//RFC 2046 规定 boundary 最长 70 个字符
+const MAX_BOUNDARY: usize = 70;
+
+/// 解析表单的错误。每种错误对应一个应当回复给客户端的状态码
+#[derive(Debug)]
+pub enum FormError {
+    /// 415：请求体既不是 urlencoded 也不是 multipart 表单
+    UnsupportedMediaType,
+    /// 400：multipart 表单缺少 boundary，或者 boundary 不合法
+    InvalidBoundary,
+    /// 400：表单的格式错误，附带具体的原因
+    Malformed(&'static str),
+    /// 413：超过了某个上限，附带是哪一个
+    TooLarge(&'static str),
+    /// 500：写临时文件失败，或者读取请求体失败
+    Io(io::Error),
+}
+
+impl FormError {
+    /// 应当回复的状态码
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FormError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormError::InvalidBoundary | FormError::Malformed(_) => StatusCode::BAD_REQUEST,
+            FormError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            FormError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// 回复给客户端的错误响应。服务端的 I/O 错误不会把细节告诉客户端
+    pub fn to_response(&self) -> Response {
+        let status = self.status();
+        let text = match self {
+            FormError::Io(_) => format!("{} {}\n", status.as_u16(), status.reason()),
+            _ => format!("{}\n", self),
+        };
+        Response::text(status, text)
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = self.status();
+        write!(f, "{} {}: ", status.as_u16(), status.reason())?;
+        match self {
+            FormError::UnsupportedMediaType => f.write_str("expected a form"),
+            FormError::InvalidBoundary => f.write_str("missing or invalid multipart boundary"),
+            FormError::Malformed(detail) | FormError::TooLarge(detail) => f.write_str(detail),
+            FormError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for FormError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FormError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// 解析好的表单：普通字段和上传的文件，都按在请求体中出现的顺序排列
+#[derive(Debug, Default)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+}
+
+impl Form {
+    /// 名为 `name` 的第一个字段的值
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// 名为 `name` 的所有字段的值，例如多选框
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// 所有的普通字段
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// 字段名为 `name` 的第一个文件
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    /// 取出所有的文件，例如要把它们 [`persist`](UploadedFile::persist) 到别的地方
+    pub fn into_files(self) -> Vec<UploadedFile> {
+        self.files
+    }
+
+    fn len(&self) -> usize {
+        self.fields.len() + self.files.len()
+    }
+}
+
+/// 上传的文件，内容在临时目录里的一个文件中。drop 时删除这个临时文件
+#[derive(Debug)]
+pub struct UploadedFile {
+    /// 表单中的字段名
+    pub name: String,
+    /// 客户端给出的文件名，已经去掉了目录部分。它来自客户端，不要直接拼成服务端的路径
+    pub filename: String,
+    /// 这个部分的 Content-Type，没有给出时是 `application/octet-stream`
+    pub content_type: String,
+    pub size: u64,
+    file: NamedTempFile,
+}
+
+impl UploadedFile {
+    /// 临时文件的路径
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// 打开临时文件读取上传的内容
+    pub fn open(&self) -> io::Result<File> {
+        self.file.reopen()
+    }
+
+    /// 把临时文件移到 `path`，之后不再删除。临时目录和 `path` 不在同一个文件系统上时改为复制
+    pub fn persist(self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        match self.file.persist(path) {
+            Ok(_) => Ok(()),
+            Err(err) => fs::copy(err.file.path(), path).map(drop),
+        }
+    }
+}
+
+/// 表单的解析器，设置临时目录和各种大小上限。
+/// 允许上传超过 [`Limits::max_body`](super::Limits::max_body) 的文件时，见 [`Router::streaming`](super::Router::streaming)
+///
+/// ```no_run
+/// use multithreaded::http::{FormParser, Response, Router, StatusCode};
+///
+/// let forms = FormParser::new().temp_dir("uploads").max_file_size(1024 * 1024);
+/// let router = Router::new().post("/upload", move |request| match forms.parse(request) {
+///     Ok(form) => {
+///         let title = form.get("title").unwrap_or("untitled");
+///         let size = form.file("photo").map_or(0, |file| file.size);
+///         Response::text(StatusCode::OK, format!("{}: {} bytes\n", title, size))
+///     }
+///     Err(err) => err.to_response(),
+/// });
+/// # let _ = router;
+/// ```
+#[derive(Debug, Clone)]
+pub struct FormParser {
+    temp_dir: PathBuf,
+    max_fields: usize,
+    max_field_size: usize,
+    max_file_size: u64,
+    max_total_size: u64,
+}
+
+impl Default for FormParser {
+    fn default() -> FormParser {
+        FormParser::new()
+    }
+}
+
+impl FormParser {
+    /// 上传的文件放在系统的临时目录下。默认最多 1000 个字段，每个字段值最多 64 KiB，
+    /// 单个文件和整个表单最多 8 MiB（和 [`Limits::max_body`](super::Limits::max_body) 默认的上限一样）
+    pub fn new() -> FormParser {
+        FormParser {
+            temp_dir: env::temp_dir(),
+            max_fields: 1000,
+            max_field_size: 64 * 1024,
+            max_file_size: 8 * 1024 * 1024,
+            max_total_size: 8 * 1024 * 1024,
+        }
+    }
+
+    /// 上传的文件写到哪个目录，目录需要已经存在
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> FormParser {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// 字段（包括文件）的最大个数
+    pub fn max_fields(mut self, max: usize) -> FormParser {
+        self.max_fields = max;
+        self
+    }
+
+    /// 普通字段的值的最大字节数
+    pub fn max_field_size(mut self, max: usize) -> FormParser {
+        self.max_field_size = max;
+        self
+    }
+
+    /// 单个文件的最大字节数
+    pub fn max_file_size(mut self, max: u64) -> FormParser {
+        self.max_file_size = max;
+        self
+    }
+
+    /// 整个请求体的最大字节数
+    pub fn max_total_size(mut self, max: u64) -> FormParser {
+        self.max_total_size = max;
+        self
+    }
+
+    /// 按 `Content-Type` 解析请求体中的表单
+    ///
+    /// 请求体此时已经在内存里，它的大小先受 [`Limits::max_body`](super::Limits::max_body) 限制。
+    /// 更大的上传用 [`FormParser::parse_stream`]
+    pub fn parse(&self, request: &Request) -> Result<Form, FormError> {
+        match form_kind(request)? {
+            None => self.parse_urlencoded(&request.body),
+            Some(boundary) => self.parse_multipart(request.body.as_slice(), &boundary),
+        }
+    }
+
+    /// 和 [`FormParser::parse`] 一样，但请求体从 `body` 中读取，通常是
+    /// [`Router::streaming`](super::Router::streaming) 的处理函数拿到的请求体，不受 `max_body` 限制。
+    /// multipart 表单边读边解析；urlencoded 表单最多读 `max_total_size` 字节
+    pub fn parse_stream(&self, request: &Request, body: impl Read) -> Result<Form, FormError> {
+        match form_kind(request)? {
+            None => {
+                let mut data = Vec::new();
+                body.take(self.max_total_size.saturating_add(1))
+                    .read_to_end(&mut data)
+                    .map_err(read_error)?;
+                self.parse_urlencoded(&data)
+            }
+            Some(boundary) => self.parse_multipart(body, &boundary),
+        }
+    }
+
+    /// 解析 `application/x-www-form-urlencoded` 的请求体，查询串也是这个格式
+    pub fn parse_urlencoded(&self, body: &[u8]) -> Result<Form, FormError> {
+        if body.len() as u64 > self.max_total_size {
+            return Err(FormError::TooLarge("form too large"));
+        }
+        let body = str::from_utf8(body).map_err(|_| FormError::Malformed("form is not UTF-8"))?;
+        let decode = |text: &str| {
+            percent_decode(&text.replace('+', " "))
+                .ok_or(FormError::Malformed("invalid percent-encoding"))
+        };
+        let mut form = Form::default();
+        for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+            if form.len() >= self.max_fields {
+                return Err(FormError::TooLarge("too many fields"));
+            }
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode(value)?;
+            if value.len() > self.max_field_size {
+                return Err(FormError::TooLarge("field value too large"));
+            }
+            form.fields.push((decode(name)?, value));
+        }
+        Ok(form)
+    }
+
+    /// 从 `body` 中流式地解析 `multipart/form-data`，`boundary` 是 `Content-Type` 中的参数。
+    /// 每次只从 `body` 读取一小段，文件的内容边读边写进临时文件
+    pub fn parse_multipart(&self, body: impl Read, boundary: &str) -> Result<Form, FormError> {
+        if !is_valid_boundary(boundary) {
+            return Err(FormError::InvalidBoundary);
+        }
+        let delimiter = format!("\r\n--{}", boundary).into_bytes();
+        //在开头补一个 CRLF，第一个分隔行就和其余的分隔行一样以 CRLF 开头
+        let mut stream = Stream {
+            reader: body,
+            buf: b"\r\n".to_vec(),
+            total: 0,
+            max_total: self.max_total_size,
+        };
+        //第一个分隔行之前的前言（preamble）没有意义，丢掉
+        let mut preamble = 0;
+        stream.copy_until(&delimiter, |data| {
+            preamble += data.len();
+            match preamble > self.max_field_size {
+                true => Err(FormError::Malformed("missing multipart boundary")),
+                false => Ok(()),
+            }
+        })?;
+
+        let mut form = Form::default();
+        loop {
+            //最后一个分隔行以 `--` 结尾，之后的结语（epilogue）同样丢掉
+            if stream.consume_prefix(b"--")? {
+                return Ok(form);
+            }
+            //分隔行后面只能有空白，然后是 CRLF
+            let padding = stream.read_line(MAX_PART_HEADERS)?;
+            if !padding.iter().all(|&b| b == b' ' || b == b'\t') {
+                return Err(FormError::Malformed("invalid multipart delimiter line"));
+            }
+            if form.len() >= self.max_fields {
+                return Err(FormError::TooLarge("too many fields"));
+            }
+            let part = stream.read_part_headers()?;
+            match part.filename {
+                None => {
+                    let mut value = Vec::new();
+                    stream.copy_until(&delimiter, |data| {
+                        if value.len() + data.len() > self.max_field_size {
+                            return Err(FormError::TooLarge("field value too large"));
+                        }
+                        value.extend_from_slice(data);
+                        Ok(())
+                    })?;
+                    let value = String::from_utf8(value)
+                        .map_err(|_| FormError::Malformed("field value is not UTF-8"))?;
+                    form.fields.push((part.name, value));
+                }
+                Some(filename) => {
+                    let mut file = tempfile::Builder::new()
+                        .prefix("upload-")
+                        .tempfile_in(&self.temp_dir)
+                        .map_err(FormError::Io)?;
+                    let mut size = 0;
+                    stream.copy_until(&delimiter, |data| {
+                        size += data.len() as u64;
+                        if size > self.max_file_size {
+                            return Err(FormError::TooLarge("file too large"));
+                        }
+                        file.write_all(data).map_err(FormError::Io)
+                    })?;
+                    file.flush().map_err(FormError::Io)?;
+                    form.files.push(UploadedFile {
+                        name: part.name,
+                        filename,
+                        content_type: part
+                            .content_type
+                            .unwrap_or_else(|| "application/octet-stream".to_string()),
+                        size,
+                        file,
+                    });
+                }
+            }
+        }
+    }
+}
+
+//一个部分的头部里我们关心的内容
+struct PartHeaders {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+}
+
+//在请求体上的一个小缓冲区，保证内存里只有一小段数据
+struct Stream<R> {
+    reader: R,
+    buf: Vec<u8>,
+    total: u64,
+    max_total: u64,
+}
+
+impl<R: Read> Stream<R> {
+    //再读一些数据，读到结尾时返回 false
+    fn fill(&mut self) -> Result<bool, FormError> {
+        let mut chunk = [0; READ_CHUNK];
+        let read = loop {
+            match self.reader.read(&mut chunk) {
+                Ok(read) => break read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(read_error(err)),
+            }
+        };
+        self.total += read as u64;
+        if self.total > self.max_total {
+            return Err(FormError::TooLarge("form too large"));
+        }
+        self.buf.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+
+    fn unexpected_end() -> FormError {
+        FormError::Malformed("body ends before the closing boundary")
+    }
+
+    //把分隔符之前的数据交给 sink，并跳过分隔符。
+    //缓冲区末尾可能是半个分隔符，这部分留到读了更多数据之后再判断
+    fn copy_until(
+        &mut self,
+        delimiter: &[u8],
+        mut sink: impl FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<(), FormError> {
+        loop {
+            if let Some(index) = find(&self.buf, delimiter) {
+                sink(&self.buf[..index])?;
+                self.buf.drain(..index + delimiter.len());
+                return Ok(());
+            }
+            let keep = delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let ready = self.buf.len() - keep;
+                sink(&self.buf[..ready])?;
+                self.buf.drain(..ready);
+            }
+            if !self.fill()? {
+                return Err(Self::unexpected_end());
+            }
+        }
+    }
+
+    //接下来的数据以 `prefix` 开头时跳过它并返回 true
+    fn consume_prefix(&mut self, prefix: &[u8]) -> Result<bool, FormError> {
+        while self.buf.len() < prefix.len() {
+            if !self.fill()? {
+                return Err(Self::unexpected_end());
+            }
+        }
+        let matches = self.buf.starts_with(prefix);
+        if matches {
+            self.buf.drain(..prefix.len());
+        }
+        Ok(matches)
+    }
+
+    //读一行，不包括行尾的 CRLF
+    fn read_line(&mut self, limit: usize) -> Result<Vec<u8>, FormError> {
+        loop {
+            if let Some(index) = find(&self.buf, b"\r\n") {
+                let line = self.buf[..index].to_vec();
+                self.buf.drain(..index + 2);
+                return Ok(line);
+            }
+            if self.buf.len() > limit {
+                return Err(FormError::TooLarge("part headers too large"));
+            }
+            if !self.fill()? {
+                return Err(Self::unexpected_end());
+            }
+        }
+    }
+
+    fn read_part_headers(&mut self) -> Result<PartHeaders, FormError> {
+        let mut disposition = None;
+        let mut content_type = None;
+        let mut remaining = MAX_PART_HEADERS;
+        loop {
+            let line = self.read_line(remaining)?;
+            remaining = remaining
+                .checked_sub(line.len() + 2)
+                .ok_or(FormError::TooLarge("part headers too large"))?;
+            if line.is_empty() {
+                break;
+            }
+            let line = str::from_utf8(&line)
+                .map_err(|_| FormError::Malformed("part header is not UTF-8"))?;
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(FormError::Malformed("invalid part header"))?;
+            let value = value.trim().to_string();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-disposition" => disposition = Some(value),
+                "content-type" => content_type = Some(value),
+                _ => {}
+            }
+        }
+        let disposition =
+            disposition.ok_or(FormError::Malformed("part without Content-Disposition"))?;
+        let (kind, params) = disposition.split_once(';').unwrap_or((&disposition, ""));
+        if !kind.trim().eq_ignore_ascii_case("form-data") {
+            return Err(FormError::Malformed("part is not form-data"));
+        }
+        let params = parameters(params);
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+        };
+        let name = param("name").ok_or(FormError::Malformed("part without a field name"))?;
+        //有的浏览器会带上完整的路径，只保留最后一段
+        let filename = param("filename").map(|filename| {
+            let base = filename.rsplit(['/', '\\']).next().unwrap_or("");
+            base.to_string()
+        });
+        Ok(PartHeaders {
+            name,
+            filename,
+            content_type,
+        })
+    }
+}
+
+//RFC 2046：1 到 70 个字符，只能是字母、数字和 '()+_,-./:=? 以及空格，空格不能在最后
+//按 Content-Type 判断表单的格式：urlencoded 返回 None，multipart 返回 boundary
+fn form_kind(request: &Request) -> Result<Option<String>, FormError> {
+    let content_type = request
+        .headers
+        .get("content-type")
+        .ok_or(FormError::UnsupportedMediaType)?;
+    let (media_type, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+    let media_type = media_type.trim();
+    if media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+        Ok(None)
+    } else if media_type.eq_ignore_ascii_case("multipart/form-data") {
+        let boundary = parameters(params)
+            .into_iter()
+            .find(|(name, _)| name == "boundary")
+            .ok_or(FormError::InvalidBoundary)?
+            .1;
+        Ok(Some(boundary))
+    } else {
+        Err(FormError::UnsupportedMediaType)
+    }
+}
+
+//读取请求体失败。连接上的请求体格式错误（例如分块编码不合法）是客户端的问题，回复 400
+fn read_error(err: io::Error) -> FormError {
+    match err.kind() {
+        io::ErrorKind::InvalidData => FormError::Malformed("malformed request body"),
+        _ => FormError::Io(err),
+    }
+}
+
+fn is_valid_boundary(boundary: &str) -> bool {
+    (1..=MAX_BOUNDARY).contains(&boundary.len())
+        && !boundary.ends_with(' ')
+        && boundary
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(&b))
+}
+
+//`; name=value; name="quoted value"` 形式的参数，参数名转成小写
+fn parameters(text: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+        if rest.is_empty() {
+            return params;
+        }
+        let end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let name = rest[..end].trim().to_ascii_lowercase();
+        rest = &rest[end..];
+        let Some(value) = rest.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let (value, after) = match value.strip_prefix('"') {
+            //引号里的 \" 和 \\ 是转义
+            Some(quoted) => {
+                let mut unquoted = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '\\' => unquoted.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        }
+                        c => unquoted.push(c),
+                    }
+                }
+                (unquoted, &quoted[end..])
+            }
+            None => {
+                let end = value.find(';').unwrap_or(value.len());
+                (value[..end].trim().to_string(), &value[end..])
+            }
+        };
+        params.push((name, value));
+        rest = after;
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Method, Version};
+
+    //每次最多读出 `step` 个字节，检查跨越读取边界的分隔符
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    const BODY: &str = "preamble to ignore\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Hello, 世界\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"tag\"\r\n\
+        \r\n\
+        a\r\n\
+        --XyZ \r\n\
+        Content-Disposition: form-data; name=\"tag\"\r\n\
+        \r\n\
+        b\r\n\
+        --XyZ\r\n\
+        content-disposition: form-data; name=\"photo\"; filename=\"C:\\\\Users\\\\me\\\\a \\\"cat\\\".gif\"\r\n\
+        Content-Type: image/gif\r\n\
+        \r\n\
+        GIF89a\r\n--XyNot a boundary\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\
+        \r\n\
+        \r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    fn request(content_type: &str, body: &[u8]) -> Request {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", content_type);
+        Request {
+            method: Method::Post,
+            target: "/upload".to_string(),
+            version: Version::Http11,
+            headers,
+            body: body.to_vec(),
+            params: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn decodes_urlencoded_forms() {
+        let form = FormParser::new()
+            .parse(&request(
+                "application/x-www-form-urlencoded",
+                b"name=J%C3%B6rg+M&tag=a&&tag=b&flag&empty=&a%26b=1%2B1",
+            ))
+            .unwrap();
+        assert_eq!(form.get("name"), Some("Jörg M"));
+        assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("empty"), Some(""));
+        assert_eq!(form.get("a&b"), Some("1+1"));
+        assert_eq!(form.fields().count(), 6);
+
+        let parser = FormParser::new();
+        assert!(matches!(
+            parser.parse_urlencoded(b"a=%zz"),
+            Err(FormError::Malformed(_))
+        ));
+        let parser = FormParser::new().max_fields(2).max_field_size(3);
+        assert!(matches!(
+            parser.parse_urlencoded(b"a=1&b=2&c=3"),
+            Err(FormError::TooLarge("too many fields"))
+        ));
+        assert!(matches!(
+            parser.parse_urlencoded(b"a=1234"),
+            Err(FormError::TooLarge("field value too large"))
+        ));
+    }
+
+    #[test]
+    fn streams_multipart_fields_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let parser = FormParser::new().temp_dir(dir.path());
+        for step in [1, 3, 7, 8192] {
+            let body = Trickle {
+                data: BODY.as_bytes(),
+                step,
+            };
+            let form = parser.parse_multipart(body, "XyZ").unwrap();
+            assert_eq!(form.get("title"), Some("Hello, 世界"));
+            assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["a", "b"]);
+            let photo = form.file("photo").unwrap();
+            assert_eq!(photo.filename, "a \"cat\".gif");
+            assert_eq!(photo.content_type, "image/gif");
+            assert_eq!(photo.size, 26);
+            assert!(photo.path().starts_with(dir.path()));
+            assert_eq!(
+                fs::read(photo.path()).unwrap(),
+                b"GIF89a\r\n--XyNot a boundary"
+            );
+            let empty = form.file("empty").unwrap();
+            assert_eq!((empty.filename.as_str(), empty.size), ("", 0));
+            assert_eq!(empty.content_type, "application/octet-stream");
+        }
+        //drop 之后临时文件都删掉了
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn persisted_files_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let form = FormParser::new()
+            .temp_dir(dir.path())
+            .parse(&request(
+                "multipart/form-data; boundary=\"b b\"",
+                b"--b b\r\nContent-Disposition: form-data; name=f; filename=x.txt\r\n\r\nkeep me\r\n--b b--",
+            ))
+            .unwrap();
+        let target = dir.path().join("kept.txt");
+        let mut files = form.into_files();
+        files.pop().unwrap().persist(&target).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"keep me");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn rejects_malformed_boundaries_and_bodies() {
+        let parser = FormParser::new();
+        let parse =
+            |content_type: &str, body: &str| parser.parse(&request(content_type, body.as_bytes()));
+        for content_type in [
+            "multipart/form-data",
+            "multipart/form-data; boundary=",
+            "multipart/form-data; boundary=\"ends with space \"",
+            "multipart/form-data; boundary=bad{chars}",
+            &format!("multipart/form-data; boundary={}", "x".repeat(71)),
+        ] {
+            let err = parse(content_type, "--x--").unwrap_err();
+            assert!(
+                matches!(err, FormError::InvalidBoundary),
+                "{}",
+                content_type
+            );
+            assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let multipart = "multipart/form-data; boundary=XyZ";
+        let part = "Content-Disposition: form-data; name=a\r\n\r\n1";
+        let cases = [
+            //请求体里根本没有这个 boundary
+            "--other\r\n\r\n--other--".to_string(),
+            //缺少结束的分隔行
+            format!("--XyZ\r\n{}", part),
+            format!("--XyZ\r\n{}\r\n--XyZ", part),
+            //分隔行后面跟着别的字符
+            format!("--XyZgarbage\r\n{}\r\n--XyZ--", part),
+            "--XyZ\r\nContent-Type: text/plain\r\n\r\n1\r\n--XyZ--".to_string(),
+            "--XyZ\r\nContent-Disposition: attachment; name=a\r\n\r\n1\r\n--XyZ--".to_string(),
+            "--XyZ\r\nContent-Disposition: form-data\r\n\r\n1\r\n--XyZ--".to_string(),
+            "--XyZ\r\nno colon\r\n\r\n1\r\n--XyZ--".to_string(),
+        ];
+        for body in &cases {
+            let err = parse(multipart, body).unwrap_err();
+            assert!(
+                matches!(err, FormError::Malformed(_)),
+                "{:?}: {:?}",
+                body,
+                err
+            );
+            assert!(err.to_response().status == StatusCode::BAD_REQUEST);
+        }
+
+        assert!(matches!(
+            parse("text/plain", "a=1"),
+            Err(FormError::UnsupportedMediaType)
+        ));
+        let response = parse("application/json", "{}").unwrap_err().to_response();
+        assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn enforces_size_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let body = |value: &str, filename: &str| {
+            format!(
+                "--XyZ\r\nContent-Disposition: form-data; name=a{}\r\n\r\n{}\r\n--XyZ--",
+                filename, value
+            )
+        };
+        let parser = FormParser::new()
+            .temp_dir(dir.path())
+            .max_field_size(4)
+            .max_file_size(8);
+        let parse = |body: &str| parser.parse_multipart(body.as_bytes(), "XyZ");
+        assert!(parse(&body("1234", "")).is_ok());
+        assert!(matches!(
+            parse(&body("12345", "")),
+            Err(FormError::TooLarge("field value too large"))
+        ));
+        assert!(parse(&body("12345678", "; filename=f")).is_ok());
+        let err = parse(&body("123456789", "; filename=f")).unwrap_err();
+        assert!(matches!(err, FormError::TooLarge("file too large")));
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        //超过上限的文件同样被删掉
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let parser = FormParser::new().max_total_size(64);
+        let err = parser
+            .parse_multipart(body(&"x".repeat(100), "").as_bytes(), "XyZ")
+            .unwrap_err();
+        assert!(matches!(err, FormError::TooLarge("form too large")));
+        let parser = FormParser::new().max_fields(1);
+        let two = "--XyZ\r\nContent-Disposition: form-data; name=a\r\n\r\n1\r\n\
+                   --XyZ\r\nContent-Disposition: form-data; name=b\r\n\r\n2\r\n--XyZ--";
+        assert!(matches!(
+            parser.parse_multipart(two.as_bytes(), "XyZ"),
+            Err(FormError::TooLarge("too many fields"))
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 17:20:12
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 17:20:12
+ * @Description: HTTP 头部：保留顺序、允许重复、名字不区分大小写
+ */
+use std::fmt;
+
+/// HTTP 头部的集合。
+///
+/// 头部的名字不区分大小写，同一个名字可以出现多次（例如 `Set-Cookie`），遍历时保持插入的顺序。
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// 第一个名为 `name` 的头部的值
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// 所有名为 `name` 的头部的值，按出现的顺序
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// 设置头部，替换掉所有同名的旧值
+    pub fn insert(&mut self, name: &str, value: impl Into<String>) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// 添加头部，保留同名的旧值
+    pub fn append(&mut self, name: &str, value: impl Into<String>) {
+        self.entries.push((name.to_string(), value.into()));
+    }
+
+    /// 删除所有名为 `name` 的头部
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// 逗号分隔的头部（例如 `Connection: keep-alive, Upgrade`）中是否包含 `token`，不区分大小写
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// 在逗号分隔的头部（例如 `Vary`）末尾加上 `token`，已经有这个 token 时不变。没有这个头部时新建一个。
+    pub fn append_token(&mut self, name: &str, token: &str) {
+        if self.has_token(name, token) {
+            return;
+        }
+        let value = match self.get(name) {
+            Some(value) if !value.trim().is_empty() => format!("{}, {}", value, token),
+            _ => token.to_string(),
+        };
+        self.insert(name, value);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Debug for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        headers.insert("CONTENT-TYPE", "text/plain");
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.get("Content-Type"), Some("text/plain"));
+    }
+
+    #[test]
+    fn repeated_headers_keep_their_order() {
+        let mut headers = Headers::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Set-Cookie", "b=2");
+        assert_eq!(
+            headers.get_all("set-cookie").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        headers.remove("set-cookie");
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn finds_tokens_in_comma_separated_values() {
+        let mut headers = Headers::new();
+        headers.append("Connection", "keep-alive, Upgrade");
+        assert!(headers.has_token("connection", "upgrade"));
+        assert!(!headers.has_token("connection", "close"));
+        headers.append_token("Connection", "close");
+        headers.append_token("connection", "UPGRADE");
+        assert_eq!(
+            headers.get("connection"),
+            Some("keep-alive, Upgrade, close")
+        );
+    }
+}
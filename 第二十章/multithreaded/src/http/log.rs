@@ -0,0 +1,475 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 20:21:44
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 03:06:40
+ * @Description: 访问日志：Common、Combined 和 JSON 三种格式，按大小滚动，由单独的线程写入
+ */
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::date::DateTime;
+
+//处理请求的 worker 不应该因为写日志而变慢，更不能因为磁盘慢而阻塞。
+//所以 worker 只把一条 LogEntry 放进有界信道，格式化、写文件和滚动都在日志线程里做；
+//信道满了（日志线程跟不上）时丢弃这条日志并计数，而不是让 worker 等待。
+
+//信道中最多积压多少条日志
+const CAPACITY: usize = 8192;
+
+/// 日志的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Common Log Format：`127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`
+    Common,
+    /// Combined Log Format，在 Common 的基础上加上 Referer、User-Agent，最后是以微秒计的处理时间（Apache 的 `%D`）
+    #[default]
+    Combined,
+    /// 每行一个 JSON 对象
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "common" | "clf" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("expected common, combined or json, got {:?}", s)),
+        }
+    }
+}
+
+/// 一条访问日志
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// 收到请求的时间
+    pub time: SystemTime,
+    pub client: Option<SocketAddr>,
+    /// 请求无法解析时为空
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub status: u16,
+    /// 响应体的字节数
+    pub bytes: u64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    /// 从收到完整的请求到写完响应的时间
+    pub latency: Duration,
+}
+
+impl LogEntry {
+    /// 按 `format` 格式化成一行，不包括换行符
+    pub fn format(&self, format: LogFormat) -> String {
+        let client = self
+            .client
+            .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+        let t = DateTime::from_system_time(self.time);
+        let mut line = String::new();
+        if format == LogFormat::Json {
+            line.push('{');
+            let _ = write!(
+                line,
+                "\"time\":\"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z\",",
+                t.year, t.month, t.day, t.hour, t.minute, t.second
+            );
+            let _ = write!(line, "\"client\":{},", json_string(Some(&client)));
+            let _ = write!(line, "\"method\":{},", json_string(Some(&self.method)));
+            let _ = write!(line, "\"path\":{},", json_string(Some(&self.target)));
+            let _ = write!(line, "\"version\":{},", json_string(Some(&self.version)));
+            let _ = write!(line, "\"status\":{},\"bytes\":{},", self.status, self.bytes);
+            let _ = write!(
+                line,
+                "\"referer\":{},",
+                json_string(self.referer.as_deref())
+            );
+            let _ = write!(
+                line,
+                "\"user_agent\":{},",
+                json_string(self.user_agent.as_deref())
+            );
+            let _ = write!(line, "\"latency_us\":{}}}", self.latency.as_micros());
+            return line;
+        }
+
+        let request = if self.method.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{} {} {}", self.method, self.target, self.version)
+        };
+        let bytes = match self.bytes {
+            0 => "-".to_string(),
+            bytes => bytes.to_string(),
+        };
+        let _ = write!(
+            line,
+            "{} - - [{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000] {} {} {}",
+            client,
+            t.day,
+            t.month_name(),
+            t.year,
+            t.hour,
+            t.minute,
+            t.second,
+            quoted(&request),
+            self.status,
+            bytes
+        );
+        if format == LogFormat::Combined {
+            let _ = write!(
+                line,
+                " {} {} {}",
+                quoted(self.referer.as_deref().unwrap_or("-")),
+                quoted(self.user_agent.as_deref().unwrap_or("-")),
+                self.latency.as_micros()
+            );
+        }
+        line
+    }
+}
+
+//放进双引号里，与 Apache 一样把引号、反斜杠和控制字符转义，防止客户端伪造日志行
+fn quoted(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_string(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "null".to_string();
+    };
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// 日志文件按大小滚动的规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// 日志文件超过这个大小时滚动，0 表示不滚动
+    pub max_bytes: u64,
+    /// 保留多少个旧文件：`access.log.1` 是最近的一个，`access.log.{keep}` 是最旧的一个
+    pub keep: usize,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation {
+            max_bytes: 10 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, rotation: Rotation) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            rotation,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.rotation.max_bytes > 0 && self.size > 0 && self.size + len > self.rotation.max_bytes
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    //access.log.{n} 依次改名为 access.log.{n+1}，最旧的一个被覆盖，access.log 改名为 access.log.1，再重新打开 access.log
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for n in (1..self.rotation.keep).rev() {
+            match fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        if self.rotation.keep > 0 {
+            fs::rename(&self.path, numbered(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        *self = RotatingFile::open(self.path.clone(), self.rotation)?;
+        Ok(())
+    }
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+enum Sink {
+    File(RotatingFile),
+    Writer(Box<dyn Write + Send>),
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.write_line(line),
+            Sink::Writer(writer) => writeln!(writer, "{}", line),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.file.flush(),
+            Sink::Writer(writer) => writer.flush(),
+        }
+    }
+}
+
+/// 访问日志。drop 时等日志线程把积压的日志都写完。
+pub struct AccessLog {
+    tx: Option<SyncSender<LogEntry>>,
+    thread: Option<thread::JoinHandle<()>>,
+    dropped: AtomicU64,
+}
+
+impl AccessLog {
+    /// 写到 `path`，按 `rotation` 滚动
+    pub fn open(
+        path: impl Into<PathBuf>,
+        format: LogFormat,
+        rotation: Rotation,
+    ) -> io::Result<AccessLog> {
+        let file = RotatingFile::open(path.into(), rotation)?;
+        Ok(AccessLog::start(Sink::File(file), format))
+    }
+
+    /// 写到标准输出
+    pub fn stdout(format: LogFormat) -> AccessLog {
+        AccessLog::to_writer(io::stdout(), format)
+    }
+
+    /// 写到任意的 `writer`，不滚动
+    pub fn to_writer<W: Write + Send + 'static>(writer: W, format: LogFormat) -> AccessLog {
+        AccessLog::start(Sink::Writer(Box::new(writer)), format)
+    }
+
+    fn start(sink: Sink, format: LogFormat) -> AccessLog {
+        let (tx, rx) = mpsc::sync_channel(CAPACITY);
+        let thread = thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || write_entries(rx, sink, format))
+            .expect("failed to spawn the access log thread");
+        AccessLog {
+            tx: Some(tx),
+            thread: Some(thread),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// 记录一条日志，不会阻塞
+    pub fn log(&self, entry: LogEntry) {
+        if let Some(tx) = &self.tx {
+            if let Err(TrySendError::Full(_)) = tx.try_send(entry) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 因为日志线程跟不上而丢弃的日志条数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        //信道断开之后日志线程写完剩下的日志就退出
+        self.tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+//日志线程：一有日志就写，信道暂时空了再 flush，这样既能成批写入，日志也不会在缓冲区里停留太久
+fn write_entries(rx: Receiver<LogEntry>, mut sink: Sink, format: LogFormat) {
+    let report = |result: io::Result<()>| {
+        if let Err(err) = result {
+            eprintln!("access log: {}", err);
+        }
+    };
+    while let Ok(entry) = rx.recv() {
+        report(sink.write_line(&entry.format(format)));
+        for entry in rx.try_iter() {
+            report(sink.write_line(&entry.format(format)));
+        }
+        report(sink.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::UNIX_EPOCH;
+
+    fn entry() -> LogEntry {
+        LogEntry {
+            time: UNIX_EPOCH + Duration::from_secs(971186136),
+            client: Some("127.0.0.1:51234".parse().unwrap()),
+            method: "GET".to_string(),
+            target: "/apache_pb.gif".to_string(),
+            version: "HTTP/1.0".to_string(),
+            status: 200,
+            bytes: 2326,
+            referer: Some("http://www.example.com/start.html".to_string()),
+            user_agent: Some("Mozilla/4.08 [en] (Win98; I ;Nav)".to_string()),
+            latency: Duration::from_micros(1534),
+        }
+    }
+
+    #[test]
+    fn common_and_combined_formats() {
+        assert_eq!(
+            entry().format(LogFormat::Common),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326"
+        );
+        assert_eq!(
+            entry().format(LogFormat::Combined),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 \
+             \"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav)\" 1534"
+        );
+    }
+
+    #[test]
+    fn json_format_escapes_strings() {
+        let mut entry = entry();
+        entry.user_agent = Some("evil\"\n".to_string());
+        entry.referer = None;
+        assert_eq!(
+            entry.format(LogFormat::Json),
+            "{\"time\":\"2000-10-10T13:55:36Z\",\"client\":\"127.0.0.1\",\"method\":\"GET\",\
+             \"path\":\"/apache_pb.gif\",\"version\":\"HTTP/1.0\",\"status\":200,\"bytes\":2326,\
+             \"referer\":null,\"user_agent\":\"evil\\\"\\n\",\"latency_us\":1534}"
+        );
+    }
+
+    #[test]
+    fn unparsed_requests_and_empty_bodies_use_dashes() {
+        let mut entry = entry();
+        entry.method.clear();
+        entry.status = 400;
+        entry.bytes = 0;
+        entry.client = None;
+        entry.user_agent = Some("a\"b".to_string());
+        assert_eq!(
+            entry.format(LogFormat::Combined),
+            "- - - [10/Oct/2000:13:55:36 +0000] \"-\" 400 - \
+             \"http://www.example.com/start.html\" \"a\\\"b\" 1534"
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn entries_are_written_by_the_log_thread() {
+        let out = Shared::default();
+        let log = AccessLog::to_writer(out.clone(), LogFormat::Common);
+        for _ in 0..3 {
+            log.log(entry());
+        }
+        drop(log);
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text.lines().count(), 3);
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let rotation = Rotation {
+            max_bytes: 300,
+            keep: 2,
+        };
+        let log = AccessLog::open(&path, LogFormat::Common, rotation).unwrap();
+        for _ in 0..20 {
+            log.log(entry());
+        }
+        drop(log);
+        for file in [path.clone(), numbered(&path, 1), numbered(&path, 2)] {
+            let len = fs::metadata(&file).unwrap().len();
+            assert!(len > 0 && len <= 300, "{}: {}", file.display(), len);
+        }
+        assert!(!numbered(&path, 3).exists());
+    }
+
+    #[test]
+    fn parses_format_names() {
+        assert_eq!("clf".parse(), Ok(LogFormat::Common));
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
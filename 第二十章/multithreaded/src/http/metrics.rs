@@ -0,0 +1,278 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 00:34:18
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 00:34:18
+ * @Description: 内置的 /healthz、/readyz 和 Prometheus 文本格式的 /metrics
+ */
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::server::ServerShared;
+use super::{Method, Request, Response, StatusCode};
+use crate::{Histogram, HistogramSnapshot, PoolStats};
+
+//server 跑起来之后，只能从访问日志里推测它的状况：有多少请求出错、响应慢不慢、线程池是不是已经忙不过来了。
+//现在 server 自己统计这些数字，并直接回复三个路径（路由表里有同一路径的路由时让给路由）：
+// 1. /healthz：进程还活着就回复 200，给负载均衡或者容器编排做存活检查；
+// 2. /readyz：可以接受新请求时回复 200，正在关闭或者连接数已经达到 max_connections 时回复 503，
+//    负载均衡据此暂时把流量切走；
+// 3. /metrics：Prometheus 的文本格式（0.0.4）。包括按状态码统计的响应数、按状态码类别的延迟直方图、
+//    打开的连接数，以及线程池的 worker 数、忙碌的 worker 数、利用率和队列深度。
+//
+//每个写出的响应（包括 400、408、503 这些没有对应处理函数的响应）都在写访问日志的地方计数，两种模式都一样。
+//直方图复用线程池的 Histogram：以 2 的幂微秒为边界，记录时不加锁。
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+//统计的状态码范围是 100 到 599
+const FIRST_STATUS: u16 = 100;
+const STATUSES: usize = 500;
+
+/// server 的请求统计
+pub(crate) struct Metrics {
+    started: Instant,
+    //第 i 个计数器是状态码 FIRST_STATUS + i 的响应数
+    statuses: Box<[AtomicU64]>,
+    //按状态码类别（1xx 到 5xx）的延迟
+    latency: [Histogram; 5],
+    bytes: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics {
+            started: Instant::now(),
+            statuses: (0..STATUSES).map(|_| AtomicU64::new(0)).collect(),
+            latency: std::array::from_fn(|_| Histogram::new()),
+            bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    /// 记录一个写出的响应，`bytes` 是响应体的字节数
+    pub(crate) fn record(&self, status: StatusCode, bytes: u64, latency: Duration) {
+        let index = usize::from(status.as_u16().saturating_sub(FIRST_STATUS));
+        if let Some(counter) = self.statuses.get(index) {
+            counter.fetch_add(1, Ordering::Relaxed);
+            self.latency[index / 100].record(latency);
+        }
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Prometheus 文本格式的全部指标。`connections` 是打开的连接数
+    pub(crate) fn render(&self, connections: usize, pool: &PoolStats) -> String {
+        let mut out = String::new();
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Responses written, by status code.",
+        );
+        for (index, counter) in self.statuses.iter().enumerate() {
+            let count = counter.load(Ordering::Relaxed);
+            if count > 0 {
+                let status = FIRST_STATUS as usize + index;
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{status=\"{}\"}} {}",
+                    status, count
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time from reading a request to writing its response, by status class.",
+        );
+        for (class, histogram) in self.latency.iter().enumerate() {
+            let snapshot = histogram.snapshot();
+            if snapshot.count > 0 {
+                let label = format!("class=\"{}xx\"", class + 1);
+                histogram_lines(&mut out, "http_request_duration_seconds", &label, &snapshot);
+            }
+        }
+
+        let busy = pool
+            .workers
+            .current_workers
+            .saturating_sub(pool.workers.idle_workers);
+        let utilisation = if pool.workers.current_workers == 0 {
+            0.0
+        } else {
+            busy as f64 / pool.workers.current_workers as f64
+        };
+        let samples: [(&str, &str, &str, String); 8] = [
+            (
+                "http_response_bytes_total",
+                "counter",
+                "Response body bytes written.",
+                self.bytes.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "http_connections_open",
+                "gauge",
+                "Connections currently open, including idle keep-alive connections.",
+                connections.to_string(),
+            ),
+            (
+                "process_uptime_seconds",
+                "gauge",
+                "Seconds since the server was built.",
+                self.started.elapsed().as_secs_f64().to_string(),
+            ),
+            (
+                "threadpool_workers",
+                "gauge",
+                "Worker threads alive in the pool.",
+                pool.workers.current_workers.to_string(),
+            ),
+            (
+                "threadpool_busy_workers",
+                "gauge",
+                "Worker threads currently running a job.",
+                busy.to_string(),
+            ),
+            (
+                "threadpool_utilization",
+                "gauge",
+                "Fraction of worker threads currently running a job.",
+                utilisation.to_string(),
+            ),
+            (
+                "threadpool_queue_depth",
+                "gauge",
+                "Jobs submitted but not yet started.",
+                pool.workers.queued_jobs.to_string(),
+            ),
+            (
+                "threadpool_jobs_completed_total",
+                "counter",
+                "Jobs the pool has finished, including ones that panicked.",
+                (pool.jobs_completed + pool.jobs_panicked).to_string(),
+            ),
+        ];
+        for (name, kind, help, value) in samples {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        header(
+            &mut out,
+            "threadpool_queue_wait_seconds",
+            "histogram",
+            "Time jobs spent queued before a worker started them.",
+        );
+        histogram_lines(
+            &mut out,
+            "threadpool_queue_wait_seconds",
+            "",
+            &pool.queue_wait,
+        );
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+//直方图的桶在 Prometheus 里是累计的：le="x" 的值是所有不超过 x 的样本数
+fn histogram_lines(out: &mut String, name: &str, labels: &str, snapshot: &HistogramSnapshot) {
+    let separator = if labels.is_empty() { "" } else { "," };
+    let mut cumulative = 0;
+    for (bound, count) in snapshot.buckets() {
+        cumulative += count;
+        let le = match bound {
+            Some(bound) => bound.as_secs_f64().to_string(),
+            None => "+Inf".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"{}\"}} {}",
+            name, labels, separator, le, cumulative
+        );
+    }
+    let braces = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    };
+    let _ = writeln!(out, "{}_sum{} {}", name, braces, snapshot.sum.as_secs_f64());
+    let _ = writeln!(out, "{}_count{} {}", name, braces, snapshot.count);
+}
+
+/// 内置的路径由 server 直接回复；不是这些路径，或者路由表里有同一路径的路由时返回 None
+pub(super) fn builtin(shared: &ServerShared, request: &Request) -> Option<Response> {
+    if !matches!(request.method, Method::Get | Method::Head) || shared.router.has_route(request) {
+        return None;
+    }
+    let response = match request.path() {
+        "/healthz" => Response::text(StatusCode::OK, "ok\n"),
+        "/readyz" => {
+            if shared.stop.stopped.load(Ordering::SeqCst) {
+                Response::text(StatusCode::SERVICE_UNAVAILABLE, "shutting down\n")
+            } else if shared.connections.load(Ordering::SeqCst) >= shared.config.max_connections {
+                Response::text(StatusCode::SERVICE_UNAVAILABLE, "at max_connections\n")
+            } else {
+                Response::text(StatusCode::OK, "ready\n")
+            }
+        }
+        "/metrics" => {
+            let connections = shared.connections.load(Ordering::SeqCst);
+            let body = shared.metrics.render(connections, &shared.pool.stats());
+            Response::new(StatusCode::OK)
+                .with_header("Content-Type", CONTENT_TYPE)
+                .with_body(body)
+        }
+        _ => return None,
+    };
+    //探测和抓取都是定时的，不应该被缓存
+    Some(response.with_header("Cache-Control", "no-store"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ThreadPool;
+
+    #[test]
+    fn counts_by_status_and_renders_cumulative_buckets() {
+        let metrics = Metrics::default();
+        metrics.record(StatusCode::OK, 10, Duration::from_micros(3));
+        metrics.record(StatusCode::OK, 5, Duration::from_micros(100));
+        metrics.record(StatusCode::NOT_FOUND, 0, Duration::from_millis(2));
+        let pool = ThreadPool::new(2);
+        let text = metrics.render(7, &pool.stats());
+
+        assert!(text.contains("# TYPE http_requests_total counter\n"));
+        assert!(text.contains("http_requests_total{status=\"200\"} 2\n"));
+        assert!(text.contains("http_requests_total{status=\"404\"} 1\n"));
+        assert!(!text.contains("status=\"500\""));
+        //3 微秒落在 4 微秒的桶里，之后的桶都是累计值
+        assert!(text
+            .contains("http_request_duration_seconds_bucket{class=\"2xx\",le=\"0.000002\"} 0\n"));
+        assert!(text
+            .contains("http_request_duration_seconds_bucket{class=\"2xx\",le=\"0.000004\"} 1\n"));
+        assert!(
+            text.contains("http_request_duration_seconds_bucket{class=\"2xx\",le=\"+Inf\"} 2\n")
+        );
+        assert!(text.contains("http_request_duration_seconds_count{class=\"2xx\"} 2\n"));
+        assert!(text.contains("http_request_duration_seconds_count{class=\"4xx\"} 1\n"));
+        assert!(text.contains("http_response_bytes_total 15\n"));
+        assert!(text.contains("http_connections_open 7\n"));
+        assert!(text.contains("threadpool_workers 2\n"));
+        assert!(text.contains("threadpool_queue_depth 0\n"));
+        assert!(text.contains("threadpool_queue_wait_seconds_count 0\n"));
+        //每一行要么是注释，要么是“名字 值”
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let (_, value) = line.rsplit_once(' ').unwrap();
+            assert!(value == "+Inf" || value.parse::<f64>().is_ok(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,750 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 23:08:45
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:44:18
+ * @Description: 中间件：在处理函数前后运行的横切逻辑，以及内置的 Basic 认证、CORS、请求 ID 和按 IP 限流
+ */
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+
+use super::router::segments;
+use super::{Method, Request, Response, StatusCode};
+
+//认证、跨域、限流这些逻辑与具体的路由无关，以前只能写进每个处理函数，或者改 server 里处理连接的代码。
+//中间件用 ServerBuilder::middleware 按顺序注册，组成一条链：
+//
+//    请求 ─> A.before ─> B.before ─> 处理函数 ─> B.after ─> A.after ─> 响应
+//
+//before 返回 Some(response) 时链条在这里折返：后面的中间件和处理函数都不再调用，
+//但是已经调用过 before 的中间件（包括返回响应的这一个）仍然会按相反的顺序调用 after。
+//中间件在 worker 上运行（事件循环模式下可能就在事件循环线程上），应当尽快返回。
+
+/// 在处理函数前后运行的中间件
+pub trait Middleware: Send + Sync + 'static {
+    /// 处理函数之前调用，可以修改请求。返回 `Some` 时直接用这个响应回复，不再调用后面的中间件和处理函数。
+    /// `peer` 是客户端的地址。
+    fn before(&self, request: &mut Request, peer: Option<SocketAddr>) -> Option<Response> {
+        let _ = (request, peer);
+        None
+    }
+
+    /// 得到响应之后调用，可以修改响应
+    fn after(&self, request: &Request, response: &mut Response) {
+        let _ = (request, response);
+    }
+
+    /// 处理 `request` 时 before 会不会阻塞（例如转发给其他服务）。
+    /// [`Mode::EventLoop`](super::Mode::EventLoop) 模式下这样的请求整个交给线程池处理，不占用事件循环线程。
+    fn blocking(&self, request: &Request) -> bool {
+        let _ = request;
+        false
+    }
+}
+
+/// 按注册顺序组成的中间件链
+#[derive(Default)]
+pub(crate) struct Chain(Vec<Box<dyn Middleware>>);
+
+impl Chain {
+    pub(crate) fn push(&mut self, middleware: impl Middleware) {
+        self.0.push(Box::new(middleware));
+    }
+
+    /// 依次调用 before，再调用 `handler`，最后按相反的顺序调用 after
+    pub(crate) fn run(
+        &self,
+        request: &mut Request,
+        peer: Option<SocketAddr>,
+        handler: impl FnOnce(&mut Request) -> Response,
+    ) -> Response {
+        let mut called = 0;
+        let mut early = None;
+        for middleware in &self.0 {
+            called += 1;
+            early = middleware.before(request, peer);
+            if early.is_some() {
+                break;
+            }
+        }
+        let mut response = early.unwrap_or_else(|| handler(request));
+        for middleware in self.0[..called].iter().rev() {
+            middleware.after(request, &mut response);
+        }
+        response
+    }
+
+    /// 有没有中间件处理 `request` 时会阻塞
+    pub(crate) fn is_blocking(&self, request: &Request) -> bool {
+        self.0.iter().any(|middleware| middleware.blocking(request))
+    }
+}
+
+/// 只对路径以 `prefix` 开头的请求生效的中间件，按整段匹配：`/admin` 匹配 `/admin` 和 `/admin/users`，不匹配 `/administrator`
+///
+/// ```
+/// use multithreaded::http::{BasicAuth, Scoped};
+///
+/// let admin = Scoped::new("/admin", BasicAuth::new("admin").user("root", "hunter2"));
+/// # let _ = admin;
+/// ```
+pub struct Scoped<M> {
+    //前缀的各段
+    prefix: Vec<String>,
+    inner: M,
+}
+
+impl<M: Middleware> Scoped<M> {
+    pub fn new(prefix: impl Into<String>, inner: M) -> Scoped<M> {
+        let prefix = prefix
+            .into()
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect();
+        Scoped { prefix, inner }
+    }
+
+    //和 Router 一样先分段、去掉空段、解码，否则 `//admin/users`、`/%61dmin/users` 会绕过中间件，却照样匹配到 /admin 下的路由。
+    //路径无法解码时也算生效：宁可多运行一次中间件，router 随后会回复 400
+    fn applies(&self, request: &Request) -> bool {
+        segments(request.path()).is_none_or(|path| path.starts_with(&self.prefix))
+    }
+}
+
+impl<M: Middleware> Middleware for Scoped<M> {
+    fn before(&self, request: &mut Request, peer: Option<SocketAddr>) -> Option<Response> {
+        if self.applies(request) {
+            self.inner.before(request, peer)
+        } else {
+            None
+        }
+    }
+
+    fn after(&self, request: &Request, response: &mut Response) {
+        if self.applies(request) {
+            self.inner.after(request, response);
+        }
+    }
+
+    fn blocking(&self, request: &Request) -> bool {
+        self.applies(request) && self.inner.blocking(request)
+    }
+}
+
+/// HTTP Basic 认证（RFC 7617）。没有带上正确的用户名和密码时回复 401，浏览器会弹出登录框。
+///
+/// 密码以明文在网络上传输，只应在 HTTPS 下使用。
+pub struct BasicAuth {
+    realm: String,
+    users: HashMap<String, String>,
+}
+
+impl BasicAuth {
+    /// `realm` 显示在浏览器的登录框里
+    pub fn new(realm: impl Into<String>) -> BasicAuth {
+        BasicAuth {
+            realm: realm.into(),
+            users: HashMap::new(),
+        }
+    }
+
+    /// 添加一个用户
+    pub fn user(mut self, name: impl Into<String>, password: impl Into<String>) -> BasicAuth {
+        self.users.insert(name.into(), password.into());
+        self
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        let Some((user, password)) = basic_credentials(request) else {
+            return false;
+        };
+        self.users
+            .get(&user)
+            .is_some_and(|expected| constant_time_eq(expected.as_bytes(), password.as_bytes()))
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, request: &mut Request, _: Option<SocketAddr>) -> Option<Response> {
+        if self.authorized(request) {
+            return None;
+        }
+        let challenge = format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            self.realm.replace(['"', '\\'], "")
+        );
+        Some(
+            Response::text(StatusCode::UNAUTHORIZED, "401 Unauthorized\n")
+                .with_header("WWW-Authenticate", challenge),
+        )
+    }
+}
+
+/// 请求中 `Authorization: Basic` 头部给出的用户名和密码
+pub fn basic_credentials(request: &Request) -> Option<(String, String)> {
+    let value = request.headers.get("authorization")?.trim();
+    let (scheme, token) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(token.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+//比较所用的时间只与长度有关，不会因为前几个字节对了就比较得更久
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// 跨域资源共享（CORS）。回复浏览器的预检请求，并给跨域请求的响应加上 `Access-Control-Allow-Origin`。
+///
+/// ```
+/// use std::time::Duration;
+/// use multithreaded::http::Cors;
+///
+/// let cors = Cors::new()
+///     .allow_origin("https://example.com")
+///     .allow_headers(&["Content-Type", "Authorization"])
+///     .max_age(Duration::from_secs(3600));
+/// # let _ = cors;
+/// ```
+pub struct Cors {
+    //None 表示允许任何来源
+    origins: Option<Vec<String>>,
+    methods: String,
+    //None 表示允许预检请求中列出的任何头部
+    headers: Option<String>,
+    expose: Option<String>,
+    max_age: Option<Duration>,
+    credentials: bool,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
+
+impl Cors {
+    /// 允许任何来源使用常见的方法和任何请求头部，不允许携带凭据
+    pub fn new() -> Cors {
+        Cors {
+            origins: None,
+            methods: "GET, HEAD, POST, PUT, PATCH, DELETE".to_string(),
+            headers: None,
+            expose: None,
+            max_age: Some(Duration::from_secs(600)),
+            credentials: false,
+        }
+    }
+
+    /// 只允许列出的来源，例如 `https://example.com`。可以调用多次。
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Cors {
+        self.origins
+            .get_or_insert_with(Vec::new)
+            .push(origin.into());
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: &[&str]) -> Cors {
+        self.methods = methods.join(", ");
+        self
+    }
+
+    /// 只允许列出的请求头部
+    pub fn allow_headers(mut self, headers: &[&str]) -> Cors {
+        self.headers = Some(headers.join(", "));
+        self
+    }
+
+    /// 允许页面脚本读取的响应头部
+    pub fn expose_headers(mut self, headers: &[&str]) -> Cors {
+        self.expose = Some(headers.join(", "));
+        self
+    }
+
+    /// 浏览器可以缓存预检结果多久，`None` 表示不告诉浏览器
+    pub fn max_age(mut self, max_age: impl Into<Option<Duration>>) -> Cors {
+        self.max_age = max_age.into();
+        self
+    }
+
+    /// 是否允许携带 Cookie 等凭据。允许时不能回复 `*`，总是回复请求的来源。
+    pub fn allow_credentials(mut self, allow: bool) -> Cors {
+        self.credentials = allow;
+        self
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.origins
+            .as_ref()
+            .is_none_or(|origins| origins.iter().any(|allowed| allowed == origin))
+    }
+
+    //给响应加上允许的来源；回复的是请求的来源时，响应随 Origin 变化
+    fn allow(&self, origin: &str, response: &mut Response) {
+        if self.origins.is_none() && !self.credentials {
+            response.headers.insert("Access-Control-Allow-Origin", "*");
+        } else {
+            response
+                .headers
+                .insert("Access-Control-Allow-Origin", origin);
+            response.headers.append_token("Vary", "Origin");
+        }
+        if self.credentials {
+            response
+                .headers
+                .insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&self, request: &mut Request, _: Option<SocketAddr>) -> Option<Response> {
+        //预检请求：带着 Origin 和 Access-Control-Request-Method 的 OPTIONS 请求
+        if request.method != Method::Options
+            || !request.headers.contains("access-control-request-method")
+        {
+            return None;
+        }
+        let origin = request.headers.get("origin")?;
+        if !self.allows(origin) {
+            return Some(Response::text(StatusCode::FORBIDDEN, "403 Forbidden\n"));
+        }
+        let mut response = Response::new(StatusCode::NO_CONTENT);
+        self.allow(origin, &mut response);
+        response
+            .headers
+            .insert("Access-Control-Allow-Methods", self.methods.as_str());
+        let headers = match &self.headers {
+            Some(headers) => Some(headers.as_str()),
+            None => request.headers.get("access-control-request-headers"),
+        };
+        if let Some(headers) = headers {
+            response
+                .headers
+                .insert("Access-Control-Allow-Headers", headers);
+        }
+        if let Some(max_age) = self.max_age {
+            response
+                .headers
+                .insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        response
+            .headers
+            .append_token("Vary", "Access-Control-Request-Method");
+        response
+            .headers
+            .append_token("Vary", "Access-Control-Request-Headers");
+        Some(response)
+    }
+
+    fn after(&self, request: &Request, response: &mut Response) {
+        //预检的响应在 before 里已经处理过了
+        if response.headers.contains("access-control-allow-origin") {
+            return;
+        }
+        let Some(origin) = request.headers.get("origin") else {
+            return;
+        };
+        if self.allows(origin) {
+            self.allow(origin, response);
+            if let Some(expose) = &self.expose {
+                response
+                    .headers
+                    .insert("Access-Control-Expose-Headers", expose.as_str());
+            }
+        }
+    }
+}
+
+/// 给每个请求一个 ID，放在请求和响应的 `X-Request-Id` 头部里，方便把日志和具体的请求对应起来。
+/// 请求已经带着合法的 `X-Request-Id`（例如由前面的代理生成）时沿用它。
+pub struct RequestId {
+    //每个进程随机的前缀，重启之后的 ID 也不会与之前的重复
+    prefix: u64,
+    next: AtomicU64,
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId::new()
+    }
+}
+
+impl RequestId {
+    pub const HEADER: &'static str = "X-Request-Id";
+
+    pub fn new() -> RequestId {
+        RequestId {
+            prefix: RandomState::new().build_hasher().finish(),
+            next: AtomicU64::new(1),
+        }
+    }
+
+    fn generate(&self) -> String {
+        format!(
+            "{:016x}-{:x}",
+            self.prefix,
+            self.next.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+}
+
+//沿用客户端给出的 ID 之前检查一下，不让它把任意内容写进日志
+fn valid_request_id(id: &str) -> bool {
+    (1..=128).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+impl Middleware for RequestId {
+    fn before(&self, request: &mut Request, _: Option<SocketAddr>) -> Option<Response> {
+        let id = match request.headers.get(Self::HEADER) {
+            Some(id) if valid_request_id(id) => id.to_string(),
+            _ => self.generate(),
+        };
+        request.headers.insert(Self::HEADER, id);
+        None
+    }
+
+    fn after(&self, request: &Request, response: &mut Response) {
+        if let Some(id) = request.headers.get(Self::HEADER) {
+            response.headers.insert(Self::HEADER, id);
+        }
+    }
+}
+
+/// 按客户端 IP 限流的令牌桶：每个 IP 的桶最多装 `burst` 个令牌，每秒补充 `per_second` 个，
+/// 每个请求消耗一个。桶空了就回复 429，`Retry-After` 告诉客户端多久之后再试。
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    //按最近一次使用的先后排列，键是递增的序号。桶满了的时候淘汰最久没用的那个
+    order: BTreeMap<u64, IpAddr>,
+    next: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    //在 order 中的序号
+    seq: u64,
+}
+
+//最多记录多少个 IP 的桶。不断换源地址（IPv6 很容易做到）的客户端只会把最久没用的桶挤出去，
+//内存有上限，每个请求也只是 O(log n)。被挤出去的 IP 下次得到一个装满的桶
+const MAX_BUCKETS: usize = 4096;
+
+impl RateLimit {
+    /// # Panics
+    ///
+    /// `per_second` 不是正数或者 `burst` 为 0 时 panic
+    pub fn new(per_second: f64, burst: u32) -> RateLimit {
+        assert!(per_second > 0.0, "per_second must be positive");
+        assert!(burst > 0, "burst must be positive");
+        RateLimit {
+            per_second,
+            burst: burst as f64,
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+
+    /// 从 `ip` 来的请求现在能否通过；不能时返回需要等待的时间
+    fn take(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { by_ip, order, next } = &mut *buckets;
+        let seq = *next;
+        *next += 1;
+        if !by_ip.contains_key(&ip) && by_ip.len() >= MAX_BUCKETS {
+            if let Some((_, oldest)) = order.pop_first() {
+                by_ip.remove(&oldest);
+            }
+        }
+        let bucket = by_ip.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+            seq,
+        });
+        order.remove(&bucket.seq);
+        order.insert(seq, ip);
+        bucket.seq = seq;
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+
+    //补充上次更新以来的令牌之后桶里有多少
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, _: &mut Request, peer: Option<SocketAddr>) -> Option<Response> {
+        let peer = peer?;
+        let wait = self.take(peer.ip(), Instant::now()).err()?;
+        //Retry-After 只能是整秒，向上取整
+        let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        Some(
+            Response::text(StatusCode::TOO_MANY_REQUESTS, "429 Too Many Requests\n")
+                .with_header("Retry-After", retry_after.to_string()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Version};
+
+    fn request(method: Method, target: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request {
+            method,
+            target: target.to_string(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: Vec::new(),
+        };
+        for (name, value) in headers {
+            request.headers.append(name, *value);
+        }
+        request
+    }
+
+    fn run(chain: &Chain, request: &mut Request) -> Response {
+        chain.run(request, Some(([127, 0, 0, 1], 4000).into()), |_| {
+            Response::text(StatusCode::OK, "handled")
+        })
+    }
+
+    //记录调用顺序的中间件
+    struct Trace(&'static str, bool, std::sync::Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Trace {
+        fn before(&self, _: &mut Request, _: Option<SocketAddr>) -> Option<Response> {
+            self.2.lock().unwrap().push(format!("{} before", self.0));
+            self.1
+                .then(|| Response::text(StatusCode::FORBIDDEN, self.0))
+        }
+
+        fn after(&self, _: &Request, response: &mut Response) {
+            self.2.lock().unwrap().push(format!("{} after", self.0));
+            response.headers.append("X-Trace", self.0);
+        }
+    }
+
+    #[test]
+    fn chain_unwinds_in_reverse_order() {
+        let calls = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let mut chain = Chain::default();
+        chain.push(Trace("a", false, calls.clone()));
+        chain.push(Trace("b", true, calls.clone()));
+        chain.push(Trace("c", false, calls.clone()));
+        let response = run(&chain, &mut request(Method::Get, "/", &[]));
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.body, b"b");
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["a before", "b before", "b after", "a after"]
+        );
+        assert_eq!(
+            response.headers.get_all("x-trace").collect::<Vec<_>>(),
+            ["b", "a"]
+        );
+    }
+
+    #[test]
+    fn basic_auth_checks_credentials() {
+        let mut chain = Chain::default();
+        chain.push(Scoped::new(
+            "/admin/",
+            BasicAuth::new("admin").user("root", "hunter2"),
+        ));
+        //root:hunter2 和 root:wrong
+        let good = [("Authorization", "basic cm9vdDpodW50ZXIy")];
+        let bad = [("Authorization", "Basic cm9vdDp3cm9uZw==")];
+
+        let response = run(&chain, &mut request(Method::Get, "/admin/users", &[]));
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers.get("www-authenticate"),
+            Some("Basic realm=\"admin\", charset=\"UTF-8\"")
+        );
+        let response = run(&chain, &mut request(Method::Get, "/admin", &bad));
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        let response = run(&chain, &mut request(Method::Get, "/admin?x=1", &good));
+        assert_eq!(response.status, StatusCode::OK);
+        let response = run(&chain, &mut request(Method::Get, "/administrator", &[]));
+        assert_eq!(response.status, StatusCode::OK);
+        //多余的斜杠和百分号编码都和 router 一样先规范化，不能绕过认证
+        for path in ["//admin/users", "/%61dmin/users", "/admin//users/"] {
+            let response = run(&chain, &mut request(Method::Get, path, &[]));
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{}", path);
+        }
+        assert_eq!(
+            basic_credentials(&request(Method::Get, "/", &good)),
+            Some(("root".to_string(), "hunter2".to_string()))
+        );
+        assert_eq!(
+            basic_credentials(&request(Method::Get, "/", &[("Authorization", "Basic !!")])),
+            None
+        );
+    }
+
+    #[test]
+    fn cors_answers_preflight_and_tags_responses() {
+        let mut chain = Chain::default();
+        chain.push(
+            Cors::new()
+                .allow_origin("https://app.example")
+                .expose_headers(&["X-Request-Id"]),
+        );
+        let preflight = [
+            ("Origin", "https://app.example"),
+            ("Access-Control-Request-Method", "PUT"),
+            ("Access-Control-Request-Headers", "content-type"),
+        ];
+        let response = run(&chain, &mut request(Method::Options, "/items", &preflight));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let header = |name| response.headers.get(name);
+        assert_eq!(
+            header("access-control-allow-origin"),
+            Some("https://app.example")
+        );
+        assert_eq!(header("access-control-allow-headers"), Some("content-type"));
+        assert_eq!(header("access-control-max-age"), Some("600"));
+        assert!(response.headers.has_token("vary", "origin"));
+        assert!(!response.headers.contains("access-control-expose-headers"));
+
+        let response = run(
+            &chain,
+            &mut request(Method::Get, "/items", &[("Origin", "https://app.example")]),
+        );
+        assert_eq!(response.body, b"handled");
+        assert_eq!(
+            response.headers.get("access-control-expose-headers"),
+            Some("X-Request-Id")
+        );
+
+        let evil = [
+            ("Origin", "https://evil.example"),
+            ("Access-Control-Request-Method", "PUT"),
+        ];
+        let response = run(&chain, &mut request(Method::Options, "/items", &evil));
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = run(&chain, &mut request(Method::Get, "/items", &evil[..1]));
+        assert!(!response.headers.contains("access-control-allow-origin"));
+
+        //允许任何来源又不带凭据时回复 *
+        let mut chain = Chain::default();
+        chain.push(Cors::new());
+        let response = run(&chain, &mut request(Method::Get, "/", &evil[..1]));
+        assert_eq!(
+            response.headers.get("access-control-allow-origin"),
+            Some("*")
+        );
+        assert!(!response.headers.contains("vary"));
+    }
+
+    #[test]
+    fn request_ids_are_generated_or_kept() {
+        let mut chain = Chain::default();
+        chain.push(RequestId::new());
+        let mut first = request(Method::Get, "/", &[]);
+        let response = run(&chain, &mut first);
+        let id = response.headers.get("x-request-id").unwrap().to_string();
+        assert_eq!(first.headers.get("x-request-id"), Some(id.as_str()));
+        let second = run(&chain, &mut request(Method::Get, "/", &[]));
+        assert_ne!(second.headers.get("x-request-id"), Some(id.as_str()));
+
+        let response = run(
+            &chain,
+            &mut request(Method::Get, "/", &[("X-Request-Id", "from-proxy")]),
+        );
+        assert_eq!(response.headers.get("x-request-id"), Some("from-proxy"));
+        let response = run(
+            &chain,
+            &mut request(Method::Get, "/", &[("X-Request-Id", "has spaces")]),
+        );
+        assert_ne!(response.headers.get("x-request-id"), Some("has spaces"));
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let limit = RateLimit::new(2.0, 3);
+        let a: IpAddr = [10, 0, 0, 1].into();
+        let b: IpAddr = [10, 0, 0, 2].into();
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limit.take(a, start).is_ok());
+        }
+        assert_eq!(limit.take(a, start), Err(Duration::from_millis(500)));
+        //别的 IP 有自己的桶
+        assert!(limit.take(b, start).is_ok());
+        //半秒补充一个令牌
+        assert!(limit.take(a, start + Duration::from_millis(500)).is_ok());
+        assert!(limit.take(a, start + Duration::from_millis(500)).is_err());
+        //桶最多装 burst 个令牌
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limit.take(a, later).is_ok());
+        }
+        assert!(limit.take(a, later).is_err());
+
+        let mut chain = Chain::default();
+        chain.push(RateLimit::new(0.5, 1));
+        assert_eq!(
+            run(&chain, &mut request(Method::Get, "/", &[])).status,
+            StatusCode::OK
+        );
+        let response = run(&chain, &mut request(Method::Get, "/", &[]));
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers.get("retry-after"), Some("2"));
+    }
+
+    #[test]
+    fn token_buckets_are_capped_and_evict_the_least_recently_used() {
+        let limit = RateLimit::new(1.0, 1);
+        let now = Instant::now();
+        let busy: IpAddr = [10, 0, 0, 1].into();
+        assert!(limit.take(busy, now).is_ok());
+        for i in 0..2 * MAX_BUCKETS as u32 {
+            let ip: IpAddr = std::net::Ipv6Addr::from(u128::from(i) + 1).into();
+            assert!(limit.take(ip, now).is_ok());
+            //一直在用的 IP 不会被挤出去，仍然受限
+            if i % 1000 == 0 {
+                assert!(limit.take(busy, now).is_err());
+            }
+        }
+        let buckets = limit.buckets.lock().unwrap();
+        assert_eq!(buckets.by_ip.len(), MAX_BUCKETS);
+        assert_eq!(buckets.order.len(), MAX_BUCKETS);
+        assert!(buckets.by_ip.contains_key(&busy));
+    }
+}
@@ -0,0 +1,58 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 17:20:12
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 06:24:10
+ * @Description: 第二十章 web server 用到的 HTTP/1.1 组件
+ */
+//最初 server 只是读一个 1024 字节的缓冲区，再用 buffer.starts_with(b"GET / HTTP/1.1\r\n") 判断请求的是什么。
+//这里把解析 HTTP 请求需要的东西放进库里，bin/main.rs 里的 server 和以后的测试都可以使用。
+
+mod client;
+mod compress;
+mod config;
+mod date;
+mod error;
+mod event_loop;
+mod form;
+mod headers;
+mod log;
+mod metrics;
+mod middleware;
+mod parser;
+mod proxy;
+mod request;
+mod response;
+mod router;
+mod server;
+mod static_files;
+mod template;
+mod tls;
+mod url;
+mod websocket;
+
+pub use client::{Client, ClientError, ClientRequest, ClientResponse};
+pub use compress::{is_compressible, negotiate_encoding, Encoding};
+pub use config::{Config, ConfigError, USAGE};
+pub use date::{format_http_date, parse_http_date};
+pub use error::ConnectionError;
+pub use form::{Form, FormError, FormParser, UploadedFile};
+pub use headers::Headers;
+pub use log::{AccessLog, LogEntry, LogFormat, Rotation};
+pub use middleware::{
+    basic_credentials, BasicAuth, Cors, Middleware, RateLimit, RequestId, Scoped,
+};
+pub use parser::{read_request, Limits, ParseError, Parser, ReadError};
+pub use proxy::{Proxy, DEFAULT_CONNECT_TIMEOUT, DEFAULT_UPSTREAM_TIMEOUT};
+pub use request::{Method, Request, Version};
+pub use response::{Response, StatusCode};
+pub use router::{BodyHandler, Handler, Router};
+pub use server::{Mode, Server, ServerBuilder, ServerConfig, ShutdownHandle};
+pub use static_files::{mime_type, StaticFiles};
+pub use template::{Context, TemplateError, Templates, Value};
+pub use tls::{TlsConfig, TlsError};
+pub use url::percent_decode;
+pub use websocket::{
+    close_code, websocket_accept_key, Message, WebSocket, WebSocketError, WebSocketHandler,
+    WebSocketSender, DEFAULT_MAX_MESSAGE,
+};
@@ -0,0 +1,1064 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 17:20:12
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 06:24:10
+ * @Description: 增量式的 HTTP/1.1 请求解析器：请求行、头部、Content-Length 和分块传输的请求体
+ */
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::{Headers, Method, Request, Response, StatusCode, Version};
+
+//最初的 handle_connection 只读一次、最多 1024 字节，再看缓冲区是不是以 "GET / HTTP/1.1\r\n" 开头。
+//这样一来，超过 1KB 的请求会被截断，分成好几次 TCP 读取才到达的请求只能看到第一段，其他方法的请求一律被当成 404。
+//TCP 是字节流，一次 read 读到的可能是半个请求，也可能是一个半请求（流水线），所以解析器需要是增量式的：
+//每读到一段数据就 feed 进去，再调用 next_request 看看是不是已经凑够了一个完整的请求。
+//没有凑够时返回 Ok(None)，已经解析过的进度保存在解析器里，下次接着来；凑够了就返回请求，多出来的字节留给下一个请求。
+//
+//为了不被恶意的客户端拖垮，每一部分都有上限（见 Limits），超过上限时返回对应的错误，由 server 回复 413、414 或 431。
+
+//分块传输中块大小这一行（包括扩展）的最大长度
+const MAX_CHUNK_LINE: usize = 1024;
+
+/// 解析请求时的各项上限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// 请求行的最大字节数，超过时返回 [`ParseError::UriTooLong`]
+    pub max_request_line: usize,
+    /// 请求行加上所有头部的最大字节数，超过时返回 [`ParseError::HeadersTooLarge`]
+    pub max_header_bytes: usize,
+    /// 头部的最大个数，超过时返回 [`ParseError::HeadersTooLarge`]
+    pub max_headers: usize,
+    /// 请求体（解码之后）的最大字节数，超过时返回 [`ParseError::PayloadTooLarge`]。
+    /// 请求体整个放在内存里；[`Router::streaming`](super::Router::streaming) 注册的路由自己读取请求体，不受它限制
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_request_line: 8 * 1024,
+            max_header_bytes: 32 * 1024,
+            max_headers: 100,
+            max_body: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// 请求不合法或者超过了上限。每种错误对应一个应当回复给客户端的状态码。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// 400：请求的格式错误，附带具体的原因
+    BadRequest(&'static str),
+    /// 413：请求体太大
+    PayloadTooLarge,
+    /// 414：请求行太长
+    UriTooLong,
+    /// 431：头部太大或者太多
+    HeadersTooLarge,
+    /// 501：不支持的传输编码
+    NotImplemented(&'static str),
+    /// 505：不支持的 HTTP 版本
+    VersionNotSupported,
+}
+
+impl ParseError {
+    /// 应当回复的状态码
+    pub fn status(&self) -> u16 {
+        match self {
+            ParseError::BadRequest(_) => 400,
+            ParseError::PayloadTooLarge => 413,
+            ParseError::UriTooLong => 414,
+            ParseError::HeadersTooLarge => 431,
+            ParseError::NotImplemented(_) => 501,
+            ParseError::VersionNotSupported => 505,
+        }
+    }
+
+    /// 状态码对应的原因短语
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ParseError::BadRequest(_) => "Bad Request",
+            ParseError::PayloadTooLarge => "Payload Too Large",
+            ParseError::UriTooLong => "URI Too Long",
+            ParseError::HeadersTooLarge => "Request Header Fields Too Large",
+            ParseError::NotImplemented(_) => "Not Implemented",
+            ParseError::VersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+
+    /// 回复给客户端的错误响应。解析出错之后连接上剩下的字节已经无法理解，所以总是带上 `Connection: close`。
+    pub fn to_response(&self) -> Response {
+        Response::text(StatusCode::new(self.status()), format!("{}\n", self))
+            .with_header("Connection", "close")
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::BadRequest(detail) | ParseError::NotImplemented(detail) => {
+                write!(f, "{} {}: {}", self.status(), self.reason(), detail)
+            }
+            _ => write!(f, "{} {}", self.status(), self.reason()),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// [`read_request`] 的错误：读取失败，或者读到的请求不合法
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "failed to read request: {}", err),
+            ReadError::Parse(err) => write!(f, "invalid request: {}", err),
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReadError::Io(err) => Some(err),
+            ReadError::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> ReadError {
+        ReadError::Io(err)
+    }
+}
+
+impl From<ParseError> for ReadError {
+    fn from(err: ParseError) -> ReadError {
+        ReadError::Parse(err)
+    }
+}
+
+enum State {
+    //正在等待请求行和头部。scanned 是已经找过空行的位置（相对于 pos），下次从这里接着找
+    Head { scanned: usize },
+    //头部已经解析完了，正在读请求体
+    Body { request: Request, body: Body },
+    //头部已经交给了自己读取请求体的处理函数，请求体由 read_body 一段一段地取走
+    Streaming(Body),
+}
+
+/// [`Parser::next`] 解析出的下一个请求
+pub(crate) enum Next {
+    /// 完整的请求
+    Request(Request),
+    /// 只有头部，请求体留在连接上，用 [`Parser::read_body`] 读取
+    Head(Request),
+}
+
+enum Body {
+    //还剩多少字节
+    Length(usize),
+    Chunked(Chunk),
+}
+
+enum Chunk {
+    //等待块大小那一行
+    Size,
+    //当前块还剩多少字节
+    Data(usize),
+    //块数据后面的 CRLF
+    DataEnd,
+    //最后一个块（大小为 0）之后的 trailer 头部，bytes 是已经读过的字节数
+    Trailers { bytes: usize },
+}
+
+/// 增量式的请求解析器。
+///
+/// ```
+/// use multithreaded::http::{Method, Parser};
+///
+/// let mut parser = Parser::default();
+/// parser.feed(b"GET /index.html HTTP/1.1\r\nHo");
+/// assert_eq!(parser.next_request(), Ok(None));
+/// parser.feed(b"st: example.com\r\n\r\n");
+/// let request = parser.next_request().unwrap().unwrap();
+/// assert_eq!(request.method, Method::Get);
+/// assert_eq!(request.path(), "/index.html");
+/// ```
+pub struct Parser {
+    limits: Limits,
+    buf: Vec<u8>,
+    //buf 中 pos 之前的字节已经解析过了
+    pos: usize,
+    state: State,
+    //请求带有 Expect: 100-continue，需要先回复 100 再等客户端发送请求体
+    expect_continue: bool,
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new(Limits::default())
+    }
+}
+
+impl Parser {
+    pub fn new(limits: Limits) -> Parser {
+        Parser {
+            limits,
+            buf: Vec::new(),
+            pos: 0,
+            state: State::Head { scanned: 0 },
+            expect_continue: false,
+        }
+    }
+
+    /// 追加从连接中读到的数据
+    pub fn feed(&mut self, data: &[u8]) {
+        //已经解析过的数据超过一半时再整理缓冲区，避免每次都搬动剩下的数据
+        if self.pos > 0 && self.pos * 2 >= self.buf.len() {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// 取出还没有解析的数据，连接升级为其他协议（WebSocket）时交给新的协议
+    pub(crate) fn into_buffered(mut self) -> Vec<u8> {
+        self.buf.drain(..self.pos);
+        self.buf
+    }
+
+    /// 是否有读了一半的请求：缓冲区里还有没解析完的数据，或者正在读请求体
+    pub fn has_partial(&self) -> bool {
+        self.pos < self.buf.len() || !matches!(self.state, State::Head { .. })
+    }
+
+    /// [`Parser::next`] 返回的请求体还没有读完
+    pub(crate) fn is_streaming(&self) -> bool {
+        matches!(self.state, State::Streaming(_))
+    }
+
+    /// 刚刚解析完的请求头部中带有 `Expect: 100-continue`，调用者应当先回复 `100 Continue`。
+    /// 每个请求只会返回一次 `true`。
+    pub fn take_continue(&mut self) -> bool {
+        std::mem::take(&mut self.expect_continue)
+    }
+
+    /// 尝试从已经读到的数据中解析出下一个完整的请求。
+    ///
+    /// 数据还不够时返回 `Ok(None)`，需要继续 [`feed`](Parser::feed)。返回错误之后解析器的状态不再有意义，应当关闭连接。
+    pub fn next_request(&mut self) -> Result<Option<Request>, ParseError> {
+        match self.next(|_| false)? {
+            Some(Next::Request(request) | Next::Head(request)) => Ok(Some(request)),
+            None => Ok(None),
+        }
+    }
+
+    /// 和 [`Parser::next_request`] 一样，但头部解析完之后先问 `streaming`：返回 true 时不读请求体，
+    /// 直接返回 [`Next::Head`]，请求体不受 `max_body` 限制，之后用 [`Parser::read_body`] 取出
+    pub(crate) fn next(
+        &mut self,
+        streaming: impl Fn(&Request) -> bool,
+    ) -> Result<Option<Next>, ParseError> {
+        loop {
+            match std::mem::replace(&mut self.state, State::Head { scanned: 0 }) {
+                State::Head { mut scanned } => match self.parse_head(&mut scanned)? {
+                    None => {
+                        self.state = State::Head { scanned };
+                        return Ok(None);
+                    }
+                    Some((request, body)) if streaming(&request) => {
+                        match body {
+                            //没有请求体，也就不必回复 100 Continue
+                            Body::Length(0) => self.expect_continue = false,
+                            body => self.state = State::Streaming(body),
+                        }
+                        return Ok(Some(Next::Head(request)));
+                    }
+                    Some((request, Body::Length(0))) => return Ok(Some(Next::Request(request))),
+                    Some((_, Body::Length(length))) if length > self.limits.max_body => {
+                        self.expect_continue = false;
+                        return Err(ParseError::PayloadTooLarge);
+                    }
+                    Some((request, body)) => self.state = State::Body { request, body },
+                },
+                State::Body {
+                    mut request,
+                    mut body,
+                } => {
+                    let max = self.limits.max_body;
+                    if self.parse_body(&mut request.body, &mut body, max)? {
+                        return Ok(Some(Next::Request(request)));
+                    }
+                    self.state = State::Body { request, body };
+                    return Ok(None);
+                }
+                //调用者应当先用 read_body 读完请求体
+                State::Streaming(body) => {
+                    self.state = State::Streaming(body);
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// 把缓冲区里属于 [`Next::Head`] 的请求体的数据（已经去掉分块编码）追加到 `out`。
+    /// 请求体读完时返回 true，之后接着解析下一个请求；返回 false 时需要先 [`feed`](Parser::feed) 更多数据
+    pub(crate) fn read_body(&mut self, out: &mut Vec<u8>) -> Result<bool, ParseError> {
+        let State::Streaming(mut body) =
+            std::mem::replace(&mut self.state, State::Head { scanned: 0 })
+        else {
+            return Ok(true);
+        };
+        let result = self.parse_body(out, &mut body, usize::MAX);
+        //出错之后也留在这个状态，连接不能再用
+        if !matches!(result, Ok(true)) {
+            self.state = State::Streaming(body);
+        }
+        result
+    }
+
+    fn parse_head(&mut self, scanned: &mut usize) -> Result<Option<(Request, Body)>, ParseError> {
+        //请求行之前的空行可以忽略（例如有的客户端会在上一个请求体后面多发一个 CRLF）
+        loop {
+            let rest = &self.buf[self.pos..];
+            if rest.starts_with(b"\r\n") {
+                self.pos += 2;
+            } else if rest.starts_with(b"\n") {
+                self.pos += 1;
+            } else {
+                break;
+            }
+            *scanned = 0;
+        }
+        let data = &self.buf[self.pos..];
+        let line_limit = self.limits.max_request_line;
+        let line_len = data.iter().position(|&b| b == b'\n');
+        if line_len.unwrap_or(data.len()) > line_limit {
+            return Err(ParseError::UriTooLong);
+        }
+        let end = match find_head_end(data, *scanned) {
+            Some(end) => end,
+            None => {
+                if data.len() > self.limits.max_header_bytes {
+                    return Err(ParseError::HeadersTooLarge);
+                }
+                *scanned = data.len().saturating_sub(3);
+                return Ok(None);
+            }
+        };
+        if end > self.limits.max_header_bytes {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        let head = std::str::from_utf8(&data[..end])
+            .map_err(|_| ParseError::BadRequest("request head is not valid UTF-8"))?;
+        let (request, body, expect_continue) = self.parse_head_text(head)?;
+        self.expect_continue = expect_continue;
+        self.pos += end;
+        Ok(Some((request, body)))
+    }
+
+    //解析请求行和头部，第三个返回值表示是否需要先回复 100 Continue
+    fn parse_head_text(&self, head: &str) -> Result<(Request, Body, bool), ParseError> {
+        let mut lines = head
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line));
+        let request_line = lines.next().unwrap_or_default();
+        let parts: Vec<&str> = request_line.split(' ').collect();
+        let [method, target, version] = parts[..] else {
+            return Err(ParseError::BadRequest("malformed request line"));
+        };
+        if method.is_empty() || !method.bytes().all(is_token) {
+            return Err(ParseError::BadRequest("invalid method"));
+        }
+        if target.is_empty() || target.bytes().any(|b| b.is_ascii_control()) {
+            return Err(ParseError::BadRequest("invalid request target"));
+        }
+        let version = parse_version(version)?;
+
+        let mut headers = Headers::new();
+        for line in lines {
+            if line.is_empty() {
+                break;
+            }
+            //以空白开头的行是已经废弃的折行写法，RFC 9112 要求直接拒绝
+            if line.starts_with([' ', '\t']) {
+                return Err(ParseError::BadRequest("obsolete header line folding"));
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(ParseError::BadRequest("header line without a colon"))?;
+            if name.is_empty() || !name.bytes().all(is_token) {
+                return Err(ParseError::BadRequest("invalid header name"));
+            }
+            let value = value.trim_matches([' ', '\t']);
+            if value.bytes().any(|b| b.is_ascii_control() && b != b'\t') {
+                return Err(ParseError::BadRequest("invalid header value"));
+            }
+            if headers.len() == self.limits.max_headers {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            headers.append(name, value);
+        }
+
+        if version == Version::Http11 && headers.get_all("host").count() != 1 {
+            return Err(ParseError::BadRequest("missing or duplicate Host header"));
+        }
+        let body = self.body_kind(&headers, version)?;
+        let has_body = !matches!(body, Body::Length(0));
+        let expect_continue =
+            has_body && version == Version::Http11 && headers.has_token("expect", "100-continue");
+        let request = Request {
+            method: Method::parse(method),
+            target: target.to_string(),
+            version,
+            headers,
+            body: Vec::new(),
+            params: Vec::new(),
+        };
+        Ok((request, body, expect_continue))
+    }
+
+    //根据 Transfer-Encoding 和 Content-Length 决定如何读取请求体
+    fn body_kind(&self, headers: &Headers, version: Version) -> Result<Body, ParseError> {
+        let codings: Vec<&str> = headers
+            .get_all("transfer-encoding")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty())
+            .collect();
+        if let Some(last) = codings.last() {
+            //两者同时出现时，前后两个服务器可能对请求体的长度有不同的理解（请求走私），直接拒绝
+            if headers.contains("content-length") {
+                return Err(ParseError::BadRequest(
+                    "both Transfer-Encoding and Content-Length",
+                ));
+            }
+            if version == Version::Http10 {
+                return Err(ParseError::BadRequest("Transfer-Encoding in HTTP/1.0"));
+            }
+            if !last.eq_ignore_ascii_case("chunked") {
+                return Err(ParseError::BadRequest("chunked is not the final coding"));
+            }
+            if codings.len() > 1 {
+                return Err(ParseError::NotImplemented("unsupported transfer coding"));
+            }
+            return Ok(Body::Chunked(Chunk::Size));
+        }
+
+        let mut length = None;
+        for value in headers
+            .get_all("content-length")
+            .flat_map(|value| value.split(','))
+        {
+            let value = value.trim();
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::BadRequest("invalid Content-Length"));
+            }
+            //全是数字却解析失败只可能是溢出了
+            let value: usize = value.parse().map_err(|_| ParseError::PayloadTooLarge)?;
+            if length.is_some_and(|length| length != value) {
+                return Err(ParseError::BadRequest("conflicting Content-Length"));
+            }
+            length = Some(value);
+        }
+        Ok(Body::Length(length.unwrap_or(0)))
+    }
+
+    //把请求体追加到 out，读完时返回 true。out 超过 max 字节时返回 PayloadTooLarge
+    fn parse_body(
+        &mut self,
+        out: &mut Vec<u8>,
+        body: &mut Body,
+        max: usize,
+    ) -> Result<bool, ParseError> {
+        match body {
+            Body::Length(remaining) => {
+                let take = (*remaining).min(self.buf.len() - self.pos);
+                out.extend_from_slice(&self.buf[self.pos..self.pos + take]);
+                self.pos += take;
+                *remaining -= take;
+                Ok(*remaining == 0)
+            }
+            Body::Chunked(chunk) => self.parse_chunks(out, chunk, max),
+        }
+    }
+
+    fn parse_chunks(
+        &mut self,
+        out: &mut Vec<u8>,
+        chunk: &mut Chunk,
+        max: usize,
+    ) -> Result<bool, ParseError> {
+        loop {
+            let data = &self.buf[self.pos..];
+            match chunk {
+                Chunk::Size => {
+                    let Some((line, used)) = take_line(data) else {
+                        if data.len() > MAX_CHUNK_LINE {
+                            return Err(ParseError::BadRequest("chunk size line too long"));
+                        }
+                        return Ok(false);
+                    };
+                    if used > MAX_CHUNK_LINE {
+                        return Err(ParseError::BadRequest("chunk size line too long"));
+                    }
+                    //分号后面是块扩展，忽略
+                    let size = line.split(|&b| b == b';').next().unwrap_or_default();
+                    let size = parse_chunk_size(trim_whitespace(size))?;
+                    if size > max - out.len() {
+                        return Err(ParseError::PayloadTooLarge);
+                    }
+                    self.pos += used;
+                    *chunk = if size == 0 {
+                        Chunk::Trailers { bytes: 0 }
+                    } else {
+                        Chunk::Data(size)
+                    };
+                }
+                Chunk::Data(remaining) => {
+                    let take = (*remaining).min(data.len());
+                    out.extend_from_slice(&data[..take]);
+                    self.pos += take;
+                    *remaining -= take;
+                    if *remaining > 0 {
+                        return Ok(false);
+                    }
+                    *chunk = Chunk::DataEnd;
+                }
+                Chunk::DataEnd => {
+                    if data.starts_with(b"\r\n") {
+                        self.pos += 2;
+                    } else if data.starts_with(b"\n") {
+                        self.pos += 1;
+                    } else if data.is_empty() || data == b"\r" {
+                        return Ok(false);
+                    } else {
+                        return Err(ParseError::BadRequest("missing CRLF after chunk data"));
+                    }
+                    *chunk = Chunk::Size;
+                }
+                Chunk::Trailers { bytes } => {
+                    let Some((line, used)) = take_line(data) else {
+                        if *bytes + data.len() > self.limits.max_header_bytes {
+                            return Err(ParseError::HeadersTooLarge);
+                        }
+                        return Ok(false);
+                    };
+                    self.pos += used;
+                    //trailer 里的头部不合并到请求里，只检查大小
+                    if line.is_empty() {
+                        return Ok(true);
+                    }
+                    *bytes += used;
+                    if *bytes > self.limits.max_header_bytes {
+                        return Err(ParseError::HeadersTooLarge);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 从 `stream` 中读取下一个完整的请求。
+///
+/// 连接在两个请求之间被对方正常关闭时返回 `Ok(None)`；请求读到一半时连接被关闭返回 [`io::ErrorKind::UnexpectedEof`]。
+/// 请求带有 `Expect: 100-continue` 时会先向 `stream` 写一个 `100 Continue`。
+pub fn read_request<S: Read + Write>(
+    stream: &mut S,
+    parser: &mut Parser,
+) -> Result<Option<Request>, ReadError> {
+    match read_next(stream, parser, |_| false)? {
+        Some(Next::Request(request) | Next::Head(request)) => Ok(Some(request)),
+        None => Ok(None),
+    }
+}
+
+//和 read_request 一样，`streaming` 的含义见 Parser::next
+pub(crate) fn read_next<S: Read + Write>(
+    stream: &mut S,
+    parser: &mut Parser,
+    streaming: impl Fn(&Request) -> bool,
+) -> Result<Option<Next>, ReadError> {
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(next) = parser.next(&streaming)? {
+            return Ok(Some(next));
+        }
+        if parser.take_continue() {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            stream.flush()?;
+        }
+        let n = match stream.read(&mut chunk) {
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if n == 0 {
+            if parser.has_partial() {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            return Ok(None);
+        }
+        parser.feed(&chunk[..n]);
+    }
+}
+
+/// [`Router::streaming`](super::Router::streaming) 的处理函数拿到的请求体：先取解析器里已经读到的数据，不够时再从连接中读。
+/// 分块编码已经去掉了；请求体的格式错误变成 [`io::ErrorKind::InvalidData`]，读完之后返回 0
+pub(crate) struct BodyReader<'a, S> {
+    stream: &'a mut S,
+    parser: &'a mut Parser,
+    //已经解码、还没有交给调用者的数据
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+    //出过错，连接上的数据已经不可信
+    failed: bool,
+}
+
+impl<'a, S: Read + Write> BodyReader<'a, S> {
+    /// `parser` 刚刚返回了 [`Next::Head`]
+    pub(crate) fn new(stream: &'a mut S, parser: &'a mut Parser) -> BodyReader<'a, S> {
+        let done = !parser.is_streaming();
+        BodyReader {
+            stream,
+            parser,
+            buf: Vec::new(),
+            pos: 0,
+            done,
+            failed: false,
+        }
+    }
+
+    /// 处理函数返回之后调用，读出并丢掉最多 `max` 字节剩下的请求体。
+    /// 返回 true 表示请求体已经读完，连接上接下来是下一个请求
+    pub(crate) fn finish(&mut self, max: u64) -> bool {
+        //客户端还在等 100 Continue，请求体根本没有发送，不必为了丢掉它再去要
+        if self.failed || self.parser.take_continue() {
+            return false;
+        }
+        let drained = io::copy(&mut Read::take(&mut *self, max), &mut io::sink()).is_ok();
+        drained && self.done
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        let mut chunk = [0; 4096];
+        loop {
+            self.done = self
+                .parser
+                .read_body(&mut self.buf)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if self.done || !self.buf.is_empty() {
+                return Ok(());
+            }
+            //处理函数开始读取时才让客户端发送请求体
+            if self.parser.take_continue() {
+                self.stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+                self.stream.flush()?;
+            }
+            let n = match self.stream.read(&mut chunk) {
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.parser.feed(&chunk[..n]);
+        }
+    }
+}
+
+impl<S: Read + Write> Read for BodyReader<'_, S> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() && !self.done {
+            if let Err(err) = self.fill() {
+                self.failed = true;
+                return Err(err);
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+//头部结束的位置（空行之后），从 from 开始找。允许只用 LF 换行。
+fn find_head_end(data: &[u8], from: usize) -> Option<usize> {
+    let mut i = from;
+    while let Some(offset) = data[i..].iter().position(|&b| b == b'\n') {
+        let newline = i + offset;
+        let rest = &data[newline + 1..];
+        if rest.starts_with(b"\n") {
+            return Some(newline + 2);
+        }
+        if rest.starts_with(b"\r\n") {
+            return Some(newline + 3);
+        }
+        i = newline + 1;
+    }
+    None
+}
+
+//取出一行（不包括行尾的 CRLF 或 LF），返回这一行和连同换行符在内一共用掉的字节数
+fn take_line(data: &[u8]) -> Option<(&[u8], usize)> {
+    let newline = data.iter().position(|&b| b == b'\n')?;
+    let line = &data[..newline];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    Some((line, newline + 1))
+}
+
+fn trim_whitespace(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|&b| b != b' ' && b != b'\t')
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|&b| b != b' ' && b != b'\t')
+        .map_or(start, |end| end + 1);
+    &bytes[start..end]
+}
+
+fn parse_chunk_size(digits: &[u8]) -> Result<usize, ParseError> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return Err(ParseError::BadRequest("invalid chunk size"));
+    }
+    //前面的 0 不影响大小，去掉之后超过 16 位十六进制数就一定溢出了
+    let digits = match digits.iter().position(|&b| b != b'0') {
+        Some(start) => &digits[start..],
+        None => return Ok(0),
+    };
+    if digits.len() > 16 {
+        return Err(ParseError::PayloadTooLarge);
+    }
+    let digits = std::str::from_utf8(digits).unwrap();
+    let size = u64::from_str_radix(digits, 16).unwrap();
+    usize::try_from(size).map_err(|_| ParseError::PayloadTooLarge)
+}
+
+fn parse_version(version: &str) -> Result<Version, ParseError> {
+    match version {
+        "HTTP/1.1" => Ok(Version::Http11),
+        "HTTP/1.0" => Ok(Version::Http10),
+        _ => {
+            let bytes = version.as_bytes();
+            let well_formed = bytes.len() == 8
+                && version.starts_with("HTTP/")
+                && bytes[5].is_ascii_digit()
+                && bytes[6] == b'.'
+                && bytes[7].is_ascii_digit();
+            if well_formed {
+                Err(ParseError::VersionNotSupported)
+            } else {
+                Err(ParseError::BadRequest("invalid HTTP version"))
+            }
+        }
+    }
+}
+
+//RFC 9110 中 token 允许的字符
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(data: &[u8]) -> Result<Vec<Request>, ParseError> {
+        let mut parser = Parser::default();
+        parser.feed(data);
+        let mut requests = Vec::new();
+        while let Some(request) = parser.next_request()? {
+            requests.push(request);
+        }
+        Ok(requests)
+    }
+
+    fn parse_one(data: &[u8]) -> Result<Request, ParseError> {
+        let mut requests = parse_all(data)?;
+        assert_eq!(requests.len(), 1, "expected exactly one request");
+        Ok(requests.remove(0))
+    }
+
+    fn parse_err(limits: Limits, data: &[u8]) -> ParseError {
+        let mut parser = Parser::new(limits);
+        parser.feed(data);
+        parser.next_request().unwrap_err()
+    }
+
+    #[test]
+    fn parses_a_simple_get() {
+        let request =
+            parse_one(b"GET /sleep?x=1 HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\n\r\n")
+                .unwrap();
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path(), "/sleep");
+        assert_eq!(request.query(), Some("x=1"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.headers.get("user-agent"), Some("test"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn handles_requests_split_at_every_byte() {
+        let raw = b"POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\nhello world";
+        let mut parser = Parser::default();
+        for (i, byte) in raw.iter().enumerate() {
+            parser.feed(std::slice::from_ref(byte));
+            let result = parser.next_request().unwrap();
+            if i + 1 < raw.len() {
+                assert!(result.is_none(), "request completed early at byte {}", i);
+            } else {
+                assert_eq!(result.unwrap().body, b"hello world");
+            }
+        }
+        assert!(!parser.has_partial());
+    }
+
+    #[test]
+    fn requests_larger_than_one_kilobyte_are_not_truncated() {
+        let cookie = "c".repeat(4000);
+        let raw = format!("GET / HTTP/1.1\r\nHost: a\r\nCookie: {}\r\n\r\n", cookie);
+        let request = parse_one(raw.as_bytes()).unwrap();
+        assert_eq!(request.headers.get("cookie").unwrap().len(), 4000);
+    }
+
+    #[test]
+    fn decodes_chunked_bodies_with_extensions_and_trailers() {
+        let raw = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5;name=value\r\nhello\r\n6\r\n world\r\n0\r\nExpires: never\r\n\r\n";
+        let request = parse_one(raw).unwrap();
+        assert_eq!(request.body, b"hello world");
+    }
+
+    #[test]
+    fn pipelined_requests_are_returned_in_order() {
+        let raw = b"GET /a HTTP/1.1\r\nHost: a\r\n\r\n\
+                    POST /b HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc\
+                    GET /c HTTP/1.1\r\nHost: a\r\n\r\n";
+        let paths: Vec<String> = parse_all(raw)
+            .unwrap()
+            .into_iter()
+            .map(|request| request.target)
+            .collect();
+        assert_eq!(paths, ["/a", "/b", "/c"]);
+    }
+
+    #[test]
+    fn accepts_bare_lf_and_leading_empty_lines() {
+        let request = parse_one(b"\r\n\nDELETE /x HTTP/1.0\nAccept: */*\n\n").unwrap();
+        assert_eq!(request.method, Method::Delete);
+        assert_eq!(request.version, Version::Http10);
+    }
+
+    #[test]
+    fn rejects_malformed_requests_with_400() {
+        let cases: [&[u8]; 8] = [
+            b"GET /\r\n\r\n",
+            b"GET  / HTTP/1.1\r\nHost: a\r\n\r\n",
+            b"G(T / HTTP/1.1\r\nHost: a\r\n\r\n",
+            b"GET / HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\nBad Name: x\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\nX: 1\r\n  folded\r\n\r\n",
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1, 2\r\n\r\n",
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n",
+        ];
+        for raw in cases {
+            let err = parse_all(raw).unwrap_err();
+            assert_eq!(
+                err.status(),
+                400,
+                "{:?} -> {}",
+                String::from_utf8_lossy(raw),
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_chunk_framing() {
+        let err =
+            parse_all(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n")
+                .unwrap_err();
+        assert_eq!(err, ParseError::BadRequest("invalid chunk size"));
+        let err =
+            parse_all(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nabX")
+                .unwrap_err();
+        assert_eq!(err, ParseError::BadRequest("missing CRLF after chunk data"));
+    }
+
+    #[test]
+    fn unknown_versions_and_codings_get_their_own_status() {
+        assert_eq!(
+            parse_all(b"GET / HTTP/2.0\r\n\r\n").unwrap_err().status(),
+            505
+        );
+        let raw = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        assert_eq!(parse_all(raw).unwrap_err().status(), 501);
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = Limits {
+            max_request_line: 32,
+            max_header_bytes: 128,
+            max_headers: 3,
+            max_body: 10,
+        };
+        let long_target = format!("GET /{} HTTP/1.1\r\n", "a".repeat(40));
+        assert_eq!(
+            parse_err(limits, long_target.as_bytes()),
+            ParseError::UriTooLong
+        );
+
+        //头部还没结束就已经超过上限了，不必等到读完
+        let big_header = format!("GET / HTTP/1.1\r\nHost: a\r\nX: {}", "b".repeat(200));
+        assert_eq!(
+            parse_err(limits, big_header.as_bytes()),
+            ParseError::HeadersTooLarge
+        );
+
+        let many = b"GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert_eq!(parse_err(limits, many), ParseError::HeadersTooLarge);
+
+        let declared = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\n";
+        assert_eq!(parse_err(limits, declared), ParseError::PayloadTooLarge);
+
+        let chunked = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n8\r\n12345678\r\n8\r\n";
+        assert_eq!(parse_err(limits, chunked), ParseError::PayloadTooLarge);
+    }
+
+    #[test]
+    fn asks_for_continue_only_when_a_body_is_expected() {
+        let mut parser = Parser::default();
+        parser.feed(
+            b"PUT /f HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\n",
+        );
+        assert_eq!(parser.next_request(), Ok(None));
+        assert!(parser.take_continue());
+        assert!(!parser.take_continue());
+        parser.feed(b"ok");
+        assert_eq!(parser.next_request().unwrap().unwrap().body, b"ok");
+    }
+
+    //按照脚本分几次返回数据，并记录写入的内容
+    struct MockStream {
+        reads: Vec<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.reads.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.reads.remove(0);
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_request_collects_several_reads() {
+        let mut stream = MockStream {
+            reads: vec![
+                b"POST /upload HTTP/1.1\r\nHost: a\r\n".to_vec(),
+                b"Expect: 100-continue\r\nContent-Length: 5\r\n\r\n".to_vec(),
+                b"hello".to_vec(),
+            ],
+            written: Vec::new(),
+        };
+        let mut parser = Parser::default();
+        let request = read_request(&mut stream, &mut parser).unwrap().unwrap();
+        assert_eq!(request.body, b"hello");
+        assert_eq!(stream.written, b"HTTP/1.1 100 Continue\r\n\r\n");
+        assert!(read_request(&mut stream, &mut parser).unwrap().is_none());
+    }
+
+    #[test]
+    fn streaming_bodies_are_read_from_the_connection() {
+        let post = |request: &Request| request.method == Method::Post;
+        let mut stream = MockStream {
+            reads: vec![
+                b"POST /upload HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\n".to_vec(),
+                b"Transfer-Encoding: chunked\r\n\r\n".to_vec(),
+                b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n".to_vec(),
+                b"GET / HTTP/1.1\r\nHost: a\r\n\r\n".to_vec(),
+            ],
+            written: Vec::new(),
+        };
+        //很小的 max_body 也不影响流式读取的请求体
+        let mut parser = Parser::new(Limits {
+            max_body: 4,
+            ..Limits::default()
+        });
+        let Some(Next::Head(request)) = read_next(&mut stream, &mut parser, post).unwrap() else {
+            panic!("expected the request head");
+        };
+        assert!(request.body.is_empty());
+        //开始读请求体时才回复 100 Continue
+        assert!(stream.written.is_empty());
+        let mut body = BodyReader::new(&mut stream, &mut parser);
+        let mut data = String::new();
+        body.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "hello world");
+        assert!(body.finish(0));
+        assert_eq!(stream.written, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        //请求体读完之后接着解析下一个请求
+        let Some(Next::Request(request)) = read_next(&mut stream, &mut parser, post).unwrap()
+        else {
+            panic!("expected the next request");
+        };
+        assert_eq!(request.path(), "/");
+
+        //请求体格式错误
+        let mut stream = MockStream {
+            reads: vec![
+                b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n".to_vec(),
+            ],
+            written: Vec::new(),
+        };
+        let mut parser = Parser::default();
+        read_next(&mut stream, &mut parser, post).unwrap().unwrap();
+        let mut body = BodyReader::new(&mut stream, &mut parser);
+        let err = body.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!body.finish(1024));
+    }
+
+    #[test]
+    fn read_request_reports_truncated_requests() {
+        let mut stream = MockStream {
+            reads: vec![b"GET / HTTP/1.1\r\nHo".to_vec()],
+            written: Vec::new(),
+        };
+        let err = read_request(&mut stream, &mut Parser::default()).unwrap_err();
+        assert!(matches!(err, ReadError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof));
+    }
+}
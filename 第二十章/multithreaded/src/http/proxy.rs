@@ -0,0 +1,448 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 00:06:52
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 00:06:52
+ * @Description: 反向代理：把请求转发给上游的 HTTP 服务，复用到上游的连接
+ */
+use std::fmt;
+use std::io::{self, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::client::{self, is_open, is_timeout, ClientError};
+use super::{Headers, Method, Middleware, Request, Response, StatusCode};
+
+//后端的 API 服务往往是另一个进程（甚至另一台机器），浏览器却只认识这个 server 的地址。
+//Proxy 作为中间件把请求原样转发给上游，再把上游的响应交回给客户端：
+// 1. 方法、请求目标、头部和请求体都照搬，只去掉逐跳（hop-by-hop）的头部，例如 Connection、Transfer-Encoding，
+//    Host 换成上游的地址，原来的 Host 放进 X-Forwarded-Host，客户端的 IP 追加到 X-Forwarded-For；
+// 2. 上游的响应按 Content-Length、分块编码或者读到连接关闭为止读完，同样去掉逐跳的头部；
+// 3. 到上游的连接用完之后放回连接池，下一个请求直接复用，省掉 TCP 握手。池里的连接可能已经被上游关闭，
+//    复用的连接还没收到任何响应就断开时，幂等的请求换一个新连接重试一次；
+// 4. 连接上游失败或者响应不合法时回复 502，连接、读写超时时回复 504。
+//
+//读上游响应的部分和 Client 共用，见 http/client.rs。
+//
+//通常和 Scoped 一起使用，只代理某个前缀下的请求。转发会阻塞，事件循环模式下这些请求交给线程池处理。
+
+/// 默认的连接超时
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 默认的读写超时：上游在这么长时间内没有任何进展时回复 504
+pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+//连接池里最多保留多少个空闲连接
+const DEFAULT_MAX_IDLE: usize = 16;
+
+//空闲连接保留多久。比大多数 server 的 keep-alive（包括这个 server 默认的 5 秒）短，免得拿到上游正要关闭的连接
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(4);
+
+//上游响应体的默认大小上限
+const DEFAULT_MAX_BODY: usize = 64 * 1024 * 1024;
+
+//逐跳的头部，只对一个连接有意义，不能转发
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// 把请求转发给上游 HTTP 服务的中间件
+///
+/// ```
+/// use std::time::Duration;
+/// use multithreaded::http::{Proxy, Scoped};
+///
+/// //把 /api/ 下的请求转发给本机 9000 端口上的服务，路径不变
+/// let api = Scoped::new("/api", Proxy::new("127.0.0.1:9000").timeout(Duration::from_secs(10)));
+/// # let _ = api;
+/// ```
+pub struct Proxy {
+    //host:port，也用作转发请求的 Host
+    upstream: String,
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_idle: usize,
+    idle_timeout: Duration,
+    max_body: usize,
+    idle: Mutex<Vec<Idle>>,
+}
+
+//连接池里的一个空闲连接
+struct Idle {
+    conn: BufReader<TcpStream>,
+    since: Instant,
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("upstream", &self.upstream)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("timeout", &self.timeout)
+            .field("idle", &self.idle.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Proxy {
+    /// 转发给 `upstream`，形如 `host:port` 或者 `http://host:port`
+    ///
+    /// # Panics
+    ///
+    /// `upstream` 是 `https://` 地址时 panic，目前只支持明文的上游
+    pub fn new(upstream: impl Into<String>) -> Proxy {
+        let upstream = upstream.into();
+        assert!(
+            !upstream.starts_with("https://"),
+            "HTTPS upstream {:?} is not supported",
+            upstream
+        );
+        let upstream = upstream
+            .strip_prefix("http://")
+            .unwrap_or(&upstream)
+            .trim_end_matches('/')
+            .to_string();
+        Proxy {
+            upstream,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_UPSTREAM_TIMEOUT,
+            max_idle: DEFAULT_MAX_IDLE,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_body: DEFAULT_MAX_BODY,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 连接上游的超时
+    pub fn connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 每次读写上游的超时
+    pub fn timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 连接池里最多保留多少个空闲连接，0 表示不复用连接
+    pub fn max_idle(mut self, max: usize) -> Proxy {
+        self.max_idle = max;
+        self
+    }
+
+    /// 空闲连接保留多久
+    pub fn idle_timeout(mut self, timeout: Duration) -> Proxy {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// 上游响应体的大小上限，超过时回复 502
+    pub fn max_body(mut self, max: usize) -> Proxy {
+        self.max_body = max;
+        self
+    }
+
+    /// 把 `request` 转发给上游，返回要交给客户端的响应。`peer` 是客户端的地址，追加到 `X-Forwarded-For`。
+    pub fn forward(&self, request: &Request, peer: Option<SocketAddr>) -> Response {
+        let head = self.request_head(request, peer);
+        let idempotent = matches!(
+            request.method,
+            Method::Get
+                | Method::Head
+                | Method::Put
+                | Method::Delete
+                | Method::Options
+                | Method::Trace
+        );
+        loop {
+            let (mut conn, reused) = match self.checkout() {
+                Some(conn) => (conn, true),
+                None => match self.connect() {
+                    Ok(conn) => (conn, false),
+                    Err(err) => return err.to_response(),
+                },
+            };
+            match self.exchange(&mut conn, &head, request) {
+                Ok((response, reusable)) => {
+                    if reusable {
+                        self.checkin(conn);
+                    }
+                    return response;
+                }
+                //池里的连接已经被上游关闭了，换一个新连接
+                Err(UpstreamError::Closed) if reused && idempotent => continue,
+                Err(err) => return err.to_response(),
+            }
+        }
+    }
+
+    //转发请求的请求行和头部
+    fn request_head(&self, request: &Request, peer: Option<SocketAddr>) -> Vec<u8> {
+        let mut headers = Headers::new();
+        //Connection 里列出的头部也是逐跳的
+        let listed: Vec<String> = request
+            .headers
+            .get_all("connection")
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect();
+        for (name, value) in request.headers.iter() {
+            let lower = name.to_ascii_lowercase();
+            let skip = HOP_BY_HOP.contains(&lower.as_str())
+                || listed.contains(&lower)
+                || matches!(
+                    lower.as_str(),
+                    "host" | "content-length" | "expect" | "x-forwarded-for"
+                );
+            if !skip {
+                headers.append(name, value);
+            }
+        }
+        headers.insert("Host", self.upstream.as_str());
+        if let Some(host) = request.headers.get("host") {
+            headers.insert("X-Forwarded-Host", host);
+        }
+        let mut forwarded: Vec<&str> = request.headers.get_all("x-forwarded-for").collect();
+        let ip = peer.map(|peer| peer.ip().to_string());
+        forwarded.extend(ip.as_deref());
+        if !forwarded.is_empty() {
+            headers.insert("X-Forwarded-For", forwarded.join(", "));
+        }
+        let has_body = !request.body.is_empty()
+            || matches!(request.method, Method::Post | Method::Put | Method::Patch);
+        if has_body {
+            headers.insert("Content-Length", request.body.len().to_string());
+        }
+
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method, request.target);
+        for (name, value) in headers.iter() {
+            head.push_str(name);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+
+    fn connect(&self) -> Result<BufReader<TcpStream>, UpstreamError> {
+        let addrs = self
+            .upstream
+            .to_socket_addrs()
+            .map_err(UpstreamError::Connect)?;
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "upstream has no addresses");
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true).map_err(UpstreamError::Connect)?;
+                    return Ok(BufReader::new(stream));
+                }
+                Err(err) if is_timeout(&err) => return Err(UpstreamError::Timeout),
+                Err(err) => last = err,
+            }
+        }
+        Err(UpstreamError::Connect(last))
+    }
+
+    //从连接池里取一个还能用的空闲连接
+    fn checkout(&self) -> Option<BufReader<TcpStream>> {
+        let mut idle = self.idle.lock().unwrap();
+        while let Some(Idle { conn, since }) = idle.pop() {
+            if since.elapsed() < self.idle_timeout && is_open(conn.get_ref()) {
+                return Some(conn);
+            }
+        }
+        None
+    }
+
+    fn checkin(&self, conn: BufReader<TcpStream>) {
+        let mut idle = self.idle.lock().unwrap();
+        //刚放回的连接在最后，最先被取出
+        idle.retain(|idle| idle.since.elapsed() < self.idle_timeout);
+        if idle.len() < self.max_idle {
+            idle.push(Idle {
+                conn,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    //在一个连接上发送请求、读回响应。返回的 bool 表示连接能不能放回连接池
+    fn exchange(
+        &self,
+        conn: &mut BufReader<TcpStream>,
+        head: &[u8],
+        request: &Request,
+    ) -> Result<(Response, bool), UpstreamError> {
+        let stream = conn.get_mut();
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|()| stream.set_write_timeout(Some(self.timeout)))
+            .map_err(UpstreamError::Io)?;
+        stream
+            .write_all(head)
+            .and_then(|()| stream.write_all(&request.body))
+            .and_then(|()| stream.flush())
+            .map_err(client::write_error)?;
+
+        let (upstream, reusable) =
+            client::read_response(conn, request.method == Method::Head, self.max_body)?;
+        let headers = upstream.headers;
+        let listed: Vec<String> = headers
+            .get_all("connection")
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect();
+        let mut response = Response::new(upstream.status).with_body(upstream.body);
+        for (name, value) in headers.iter() {
+            let lower = name.to_ascii_lowercase();
+            //HEAD 的响应保留上游给出的长度，其余的由 write_to 按实际的响应体计算
+            let skip = HOP_BY_HOP.contains(&lower.as_str())
+                || listed.contains(&lower)
+                || (lower == "content-length" && request.method != Method::Head);
+            if !skip {
+                response.headers.append(name, value);
+            }
+        }
+        Ok((response, reusable))
+    }
+}
+
+impl Middleware for Proxy {
+    fn before(&self, request: &mut Request, peer: Option<SocketAddr>) -> Option<Response> {
+        Some(self.forward(request, peer))
+    }
+
+    fn blocking(&self, _: &Request) -> bool {
+        true
+    }
+}
+
+//转发失败的原因
+#[derive(Debug)]
+enum UpstreamError {
+    Connect(io::Error),
+    //连接在收到任何响应之前就断开了
+    Closed,
+    Timeout,
+    Io(io::Error),
+    Invalid(&'static str),
+    TooLarge,
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::Connect(err) => write!(f, "cannot connect to upstream: {}", err),
+            UpstreamError::Closed => f.write_str("upstream closed the connection"),
+            UpstreamError::Timeout => f.write_str("upstream timed out"),
+            UpstreamError::Io(err) => write!(f, "upstream I/O error: {}", err),
+            UpstreamError::Invalid(message) => write!(f, "invalid upstream response: {}", message),
+            UpstreamError::TooLarge => f.write_str("upstream response too large"),
+        }
+    }
+}
+
+//读上游响应的部分和 Client 共用，错误一一对应
+impl From<ClientError> for UpstreamError {
+    fn from(err: ClientError) -> UpstreamError {
+        match err {
+            ClientError::Connect(err) => UpstreamError::Connect(err),
+            ClientError::Closed => UpstreamError::Closed,
+            ClientError::Timeout => UpstreamError::Timeout,
+            ClientError::Io(err) => UpstreamError::Io(err),
+            ClientError::InvalidResponse(message) => UpstreamError::Invalid(message),
+            ClientError::TooLarge => UpstreamError::TooLarge,
+            ClientError::InvalidUrl(_) => UpstreamError::Invalid("bad upstream address"),
+        }
+    }
+}
+
+impl UpstreamError {
+    //和解析器的错误一样，在响应体里说明原因
+    fn to_response(&self) -> Response {
+        let status = match self {
+            UpstreamError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        Response::text(
+            status,
+            format!("{} {}: {}\n", status.as_u16(), status.reason(), self),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Version;
+
+    fn request(method: Method, headers: &[(&str, &str)], body: &[u8]) -> Request {
+        let mut request = Request {
+            method,
+            target: "/api/items?page=2".to_string(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: body.to_vec(),
+            params: Vec::new(),
+        };
+        for (name, value) in headers {
+            request.headers.append(name, *value);
+        }
+        request
+    }
+
+    #[test]
+    fn request_head_drops_hop_by_hop_headers_and_adds_forwarding_headers() {
+        let proxy = Proxy::new("http://127.0.0.1:9000/");
+        let request = request(
+            Method::Post,
+            &[
+                ("Host", "example.com"),
+                ("Connection", "keep-alive, X-Secret"),
+                ("X-Secret", "hop"),
+                ("Transfer-Encoding", "chunked"),
+                ("X-Forwarded-For", "203.0.113.7"),
+                ("Accept", "application/json"),
+            ],
+            b"{}",
+        );
+        let peer: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let head = String::from_utf8(proxy.request_head(&request, Some(peer))).unwrap();
+        assert!(
+            head.starts_with("POST /api/items?page=2 HTTP/1.1\r\n"),
+            "{}",
+            head
+        );
+        assert!(head.contains("Host: 127.0.0.1:9000\r\n"));
+        assert!(head.contains("X-Forwarded-Host: example.com\r\n"));
+        assert!(head.contains("X-Forwarded-For: 203.0.113.7, 192.0.2.1\r\n"));
+        assert!(head.contains("Accept: application/json\r\n"));
+        assert!(head.contains("Content-Length: 2\r\n"));
+        for dropped in ["Connection", "X-Secret", "Transfer-Encoding"] {
+            assert!(!head.contains(dropped), "{}", head);
+        }
+    }
+
+    #[test]
+    fn unreachable_upstreams_get_502() {
+        //先占一个端口再关掉，连接会被拒绝
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let proxy = Proxy::new(addr.to_string());
+        let response = proxy.forward(&request(Method::Get, &[], b""), None);
+        assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    }
+}
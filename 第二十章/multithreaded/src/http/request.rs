@@ -0,0 +1,142 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 17:20:12
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 17:20:12
+ * @Description: 解析好的 HTTP 请求
+ */
+use std::fmt;
+
+use super::Headers;
+
+/// 请求方法
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Trace,
+    Connect,
+    /// 其他扩展方法，保存原样的方法名
+    Other(String),
+}
+
+impl Method {
+    pub(crate) fn parse(method: &str) -> Method {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            "PATCH" => Method::Patch,
+            "TRACE" => Method::Trace,
+            "CONNECT" => Method::Connect,
+            other => Method::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+            Method::Other(method) => method,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 协议版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        })
+    }
+}
+
+/// 一个完整的 HTTP 请求，由 [`Parser`](super::Parser) 解析得到
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    /// 请求行中原样的请求目标，例如 `/search?q=rust`
+    pub target: String,
+    pub version: Version,
+    pub headers: Headers,
+    /// 请求体。分块传输的请求体已经解码
+    pub body: Vec<u8>,
+    /// 路由匹配时从路径中取出的参数，例如 `/users/:id` 中的 `id`，见 [`Router`](super::Router)
+    pub params: Vec<(String, String)>,
+}
+
+impl Request {
+    /// 请求目标中 `?` 之前的部分
+    pub fn path(&self) -> &str {
+        match self.target.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.target,
+        }
+    }
+
+    /// 请求目标中 `?` 之后的部分
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    /// 名为 `name` 的路径参数，已经做过百分号解码
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_path_and_query() {
+        let request = Request {
+            method: Method::Get,
+            target: "/search?q=rust".to_string(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: Vec::new(),
+        };
+        assert_eq!(request.path(), "/search");
+        assert_eq!(request.query(), Some("q=rust"));
+    }
+
+    #[test]
+    fn unknown_methods_round_trip() {
+        assert_eq!(Method::parse("GET"), Method::Get);
+        assert_eq!(Method::parse("PURGE").as_str(), "PURGE");
+    }
+}
@@ -0,0 +1,243 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 18:05:37
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 18:05:37
+ * @Description: HTTP 响应和状态码
+ */
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::Headers;
+
+/// HTTP 状态码
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    pub const CONTINUE: StatusCode = StatusCode(100);
+    pub const SWITCHING_PROTOCOLS: StatusCode = StatusCode(101);
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const FOUND: StatusCode = StatusCode(302);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const UNAUTHORIZED: StatusCode = StatusCode(401);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    pub const UNSUPPORTED_MEDIA_TYPE: StatusCode = StatusCode(415);
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    pub const UPGRADE_REQUIRED: StatusCode = StatusCode(426);
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+
+    /// 任意的三位数状态码
+    ///
+    /// # Panics
+    ///
+    /// `code` 不在 100 到 999 之间时 panic
+    pub fn new(code: u16) -> StatusCode {
+        assert!((100..1000).contains(&code), "invalid status code {}", code);
+        StatusCode(code)
+    }
+
+    pub fn as_u16(self) -> u16 {
+        self.0
+    }
+
+    /// 标准的原因短语，未知的状态码返回空字符串
+    pub fn reason(self) -> &'static str {
+        match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            206 => "Partial Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            413 => "Payload Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            426 => "Upgrade Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => "",
+        }
+    }
+
+    /// 1xx、204 和 304 响应不能带有响应体
+    pub fn allows_body(self) -> bool {
+        !(self.0 < 200 || self.0 == 204 || self.0 == 304)
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason())
+    }
+}
+
+/// HTTP 响应。处理函数返回它，再由 server 写回连接。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// 没有响应体的响应
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// `text/plain` 响应
+    pub fn text(status: StatusCode, text: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(text.into())
+    }
+
+    /// `text/html` 响应
+    pub fn html(status: StatusCode, html: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(html.into())
+    }
+
+    /// 读取 HTML 文件作为响应体
+    ///
+    /// # Errors
+    ///
+    /// 文件不存在或者无法读取时返回读取的错误，调用者通常改为回复 [`Response::error_page`]
+    pub fn html_file(status: StatusCode, path: impl AsRef<Path>) -> io::Result<Response> {
+        Ok(Response::html(status, fs::read_to_string(path)?))
+    }
+
+    /// 内置的错误页面，不依赖任何文件。自定义的错误页面读取失败、处理函数 panic 时使用。
+    pub fn error_page(status: StatusCode) -> Response {
+        Response::html(
+            status,
+            format!(
+                "<!DOCTYPE html>\n<html lang=\"en\">\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+                 <body><h1>{0}</h1></body>\n</html>\n",
+                status
+            ),
+        )
+    }
+
+    /// 设置头部，替换同名的旧值
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// 把响应写到 `writer`。`include_body` 为 false 时（回复 HEAD 请求）只写状态行和头部，`Content-Length` 仍然是响应体的长度。
+    pub fn write_to<W: Write>(&self, writer: &mut W, include_body: bool) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in self.headers.iter() {
+            head.push_str(name);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        if self.status.allows_body() && !self.headers.contains("content-length") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        writer.write_all(head.as_bytes())?;
+        if include_body && self.status.allows_body() {
+            writer.write_all(&self.body)?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_status_line_headers_and_body() {
+        let response = Response::text(StatusCode::OK, "hi");
+        let mut out = Vec::new();
+        response.write_to(&mut out, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 2\r\n\r\nhi"
+        );
+    }
+
+    #[test]
+    fn head_responses_keep_the_length_but_drop_the_body() {
+        let response = Response::html(StatusCode::OK, "<p>hello</p>");
+        let mut out = Vec::new();
+        response.write_to(&mut out, false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Content-Length: 12\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn not_modified_has_no_body_or_length() {
+        let mut out = Vec::new();
+        Response::new(StatusCode::NOT_MODIFIED)
+            .write_to(&mut out, true)
+            .unwrap();
+        assert_eq!(out, b"HTTP/1.1 304 Not Modified\r\n\r\n");
+    }
+
+    #[test]
+    fn missing_files_fall_back_to_the_built_in_page() {
+        let err = Response::html_file(StatusCode::NOT_FOUND, "no-such-page.html").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let response = Response::error_page(StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            response.headers.get("content-type"),
+            Some("text/html; charset=utf-8")
+        );
+        let body = String::from_utf8(response.body).unwrap();
+        assert!(body.contains("<h1>500 Internal Server Error</h1>"));
+    }
+}
@@ -0,0 +1,572 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 18:05:37
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 06:24:10
+ * @Description: 按方法和路径把请求分发给处理函数，支持路径参数和通配符
+ */
+use std::io::Read;
+use std::sync::Arc;
+
+use super::url::percent_decode;
+use super::websocket::{self, WebSocket, WebSocketHandler};
+use super::{Method, Request, Response, StatusCode};
+
+//最初的 handle_connection 用 if/else 比较缓冲区的前缀，决定返回 hello.html 还是 404.html，
+//每加一个页面都要再改一遍这个判断，处理函数还要自己往 TcpStream 里写响应。
+//Router 把“哪个路径由谁处理”和“怎么处理”分开：
+// 1. 路由模式按 `/` 分成若干段，普通的段要求完全相同，`:name` 匹配任意一段并作为参数 name，
+//    `*name` 只能是最后一段，匹配剩下的零段或多段；
+// 2. 按注册的顺序逐个尝试，第一个方法和路径都匹配的路由胜出；
+// 3. 路径匹配但方法不匹配时回复 405，并在 Allow 头部中列出这个路径支持的方法；OPTIONS 请求则回复 204 和 Allow；
+// 4. 处理函数拿到解析好的 Request，返回 Response，由 server 负责写回连接。
+//
+//事件循环模式下处理函数直接在事件循环线程上运行，会阻塞或者很耗 CPU 的处理函数需要用 blocking 标记，交给线程池运行。
+//WebSocket 路由的处理函数由 server 先完成握手，再拿着升级后的连接调用。
+//streaming 注册的处理函数在头部读完时就被调用，请求体由它自己从连接中一段一段地读。
+
+/// 路由的处理函数
+pub type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// 自己读取请求体的处理函数，见 [`Router::streaming`]
+pub type BodyHandler = dyn Fn(&Request, &mut dyn Read) -> Response + Send + Sync;
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Box<Handler>,
+    blocking: bool,
+    //WebSocket 路由在握手成功之后调用的处理函数
+    upgrade: Option<Box<WebSocketHandler>>,
+    //streaming 注册的路由用它代替 handler
+    body_handler: Option<Box<BodyHandler>>,
+}
+
+impl Route {
+    //路径匹配时返回取出的参数
+    fn matches(&self, path: &[String]) -> Option<Vec<(String, String)>> {
+        let mut params = Vec::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Wildcard(name) => {
+                    params.push((name.clone(), path[i.min(path.len())..].join("/")));
+                    return Some(params);
+                }
+                Segment::Literal(literal) => {
+                    if path.get(i) != Some(literal) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => params.push((name.clone(), path.get(i)?.clone())),
+            }
+        }
+        (self.segments.len() == path.len()).then_some(params)
+    }
+}
+
+/// 路由表
+///
+/// ```
+/// use multithreaded::http::{Response, Router, StatusCode};
+///
+/// let router = Router::new()
+///     .get("/users/:id", |request| {
+///         Response::text(StatusCode::OK, format!("user {}", request.param("id").unwrap()))
+///     })
+///     .get("/static/*path", |request| {
+///         Response::text(StatusCode::OK, request.param("path").unwrap())
+///     });
+/// # let _ = router;
+/// ```
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Box<Handler>,
+    not_found_blocking: bool,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Router {
+    /// 空的路由表，没有匹配的路由时回复纯文本的 404
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_| Response::text(StatusCode::NOT_FOUND, "404 Not Found\n")),
+            not_found_blocking: false,
+        }
+    }
+
+    /// 注册一个路由
+    ///
+    /// # Panics
+    ///
+    /// `pattern` 不以 `/` 开头，或者 `*` 段不是最后一段时 panic
+    pub fn route<F>(mut self, method: Method, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        assert!(
+            pattern.starts_with('/'),
+            "route pattern {:?} must start with '/'",
+            pattern
+        );
+        let parts: Vec<&str> = split(pattern).collect();
+        let segments = parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                if let Some(name) = part.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = part.strip_prefix('*') {
+                    assert!(
+                        i + 1 == parts.len(),
+                        "wildcard in route pattern {:?} must be the last segment",
+                        pattern
+                    );
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Literal(part.to_string())
+                }
+            })
+            .collect();
+        self.routes.push(Route {
+            method,
+            segments,
+            handler: Box::new(handler),
+            blocking: false,
+            upgrade: None,
+            body_handler: None,
+        });
+        self
+    }
+
+    /// 把上一个注册的路由标记为会阻塞（读文件、sleep、大量计算等）。
+    /// [`Mode::EventLoop`](super::Mode::EventLoop) 模式下这些处理函数交给线程池运行，不占用事件循环线程；
+    /// 线程模式下没有区别。
+    ///
+    /// # Panics
+    ///
+    /// 还没有注册任何路由时 panic
+    pub fn blocking(mut self) -> Router {
+        self.routes
+            .last_mut()
+            .expect("blocking() must follow a route")
+            .blocking = true;
+        self
+    }
+
+    pub fn get<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    pub fn patch<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Patch, pattern, handler)
+    }
+
+    /// 注册一个 WebSocket 路由。GET 请求先按 RFC 6455 握手，成功（101）之后 server 在线程池上用升级后的连接调用
+    /// `handler`，直到它返回时关闭连接；握手不合法时回复 400 或者 426。
+    ///
+    /// ```
+    /// use multithreaded::http::{Message, Router};
+    ///
+    /// let router = Router::new().websocket("/ws/echo", |_request, mut ws| {
+    ///     while let Ok(Some(message)) = ws.recv() {
+    ///         if ws.send(&message).is_err() {
+    ///             break;
+    ///         }
+    ///     }
+    /// });
+    /// # let _ = router;
+    /// ```
+    pub fn websocket<F>(mut self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, WebSocket) + Send + Sync + 'static,
+    {
+        self = self.route(Method::Get, pattern, websocket::handshake);
+        self.routes.last_mut().unwrap().upgrade = Some(Box::new(handler));
+        self
+    }
+
+    /// 注册一个自己读取请求体的路由。server 读完请求的头部就调用 `handler`，第二个参数从连接中读出请求体
+    /// （已经去掉了分块编码），请求体不会先整个放进内存，也不受 [`Limits::max_body`](super::Limits::max_body) 限制，
+    /// 读多少由 `handler` 自己决定，比如用 [`FormParser::parse_stream`](super::FormParser::parse_stream) 接收上传的大文件。
+    ///
+    /// `handler` 总是在线程池上运行。它没有读完请求体就返回时，server 写完响应后关闭连接；
+    /// [`Mode::EventLoop`](super::Mode::EventLoop) 模式下这个连接处理完这个请求之后总是关闭。
+    ///
+    /// ```no_run
+    /// use multithreaded::http::{FormParser, Method, Response, Router, StatusCode};
+    ///
+    /// let forms = FormParser::new().max_file_size(1 << 30).max_total_size(1 << 30);
+    /// let router = Router::new().streaming(Method::Post, "/upload", move |request, body| {
+    ///     match forms.parse_stream(request, body) {
+    ///         Ok(form) => Response::text(StatusCode::OK, format!("{} files\n", form.files().len())),
+    ///         Err(err) => err.to_response(),
+    ///     }
+    /// });
+    /// # let _ = router;
+    /// ```
+    pub fn streaming<F>(mut self, method: Method, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &mut dyn Read) -> Response + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let buffered = Arc::clone(&handler);
+        //请求体已经在内存里时（例如中间件把别的请求改写到了这个路由）就从内存里读
+        self = self.route(method, pattern, move |request| {
+            buffered(request, &mut request.body.as_slice())
+        });
+        let route = self.routes.last_mut().unwrap();
+        route.blocking = true;
+        route.body_handler = Some(Box::new(move |request, body| handler(request, body)));
+        self
+    }
+
+    /// 替换没有任何路由匹配路径时使用的处理函数
+    pub fn not_found<F>(mut self, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// 和 [`Router::blocking`] 一样，但标记的是 [`Router::not_found`] 设置的处理函数，比如渲染模板的 404 页面
+    pub fn blocking_not_found(mut self) -> Router {
+        self.not_found_blocking = true;
+        self
+    }
+
+    /// 找到匹配的路由，把路径参数放进 `request.params`，再调用它的处理函数
+    ///
+    /// 没有单独注册 HEAD 的路径由 GET 的处理函数处理，写回响应时由 server 去掉响应体。
+    pub fn handle(&self, request: &mut Request) -> Response {
+        let path = segments(request.path());
+        let Some(path) = path else {
+            return Response::text(StatusCode::BAD_REQUEST, "400 Bad Request: malformed path\n");
+        };
+
+        let found = self.lookup(&request.method, &path).or_else(|| {
+            (request.method == Method::Head)
+                .then(|| self.lookup(&Method::Get, &path))
+                .flatten()
+        });
+        if let Some((route, params)) = found {
+            request.params = params;
+            return (route.handler)(request);
+        }
+
+        let allowed = self.allowed(&path);
+        if allowed.is_empty() {
+            return (self.not_found)(request);
+        }
+        let allow = allowed.join(", ");
+        if request.method == Method::Options {
+            Response::new(StatusCode::NO_CONTENT).with_header("Allow", allow)
+        } else {
+            Response::text(StatusCode::METHOD_NOT_ALLOWED, "405 Method Not Allowed\n")
+                .with_header("Allow", allow)
+        }
+    }
+
+    /// 找到 `request` 对应的 [`Router::streaming`] 路由，用 `body` 调用它的处理函数。
+    /// 请求已经不再匹配这样的路由（被中间件改写了）时和 [`Router::handle`] 一样处理
+    pub(crate) fn handle_streaming(&self, request: &mut Request, body: &mut dyn Read) -> Response {
+        let Some((route, params)) = self.find(request) else {
+            return self.handle(request);
+        };
+        let Some(handler) = &route.body_handler else {
+            return self.handle(request);
+        };
+        request.params = params;
+        handler(request, body)
+    }
+
+    /// 处理 `request` 的路由是不是 [`Router::streaming`] 注册的，是的话 server 不先读请求体
+    pub(crate) fn streams_body(&self, request: &Request) -> bool {
+        self.find(request)
+            .is_some_and(|(route, _)| route.body_handler.is_some())
+    }
+
+    /// 处理 `request` 的路由是否用 [`Router::blocking`] 标记过
+    pub(crate) fn is_blocking(&self, request: &Request) -> bool {
+        match self.find(request) {
+            Some((route, _)) => route.blocking,
+            //没有匹配的路由时大多由 not_found 处理；回复 400、405 的那些也交给线程池，只是多了一次转手
+            None => self.not_found_blocking,
+        }
+    }
+
+    /// 是否有路由处理 `request`。server 内置的 /healthz 等路径只在没有路由时才由 server 回复
+    pub(crate) fn has_route(&self, request: &Request) -> bool {
+        self.find(request).is_some()
+    }
+
+    /// 处理 `request` 的路由是 WebSocket 路由时，返回它升级之后的处理函数
+    pub(crate) fn websocket_handler(&self, request: &Request) -> Option<&WebSocketHandler> {
+        self.find(request)?.0.upgrade.as_deref()
+    }
+
+    //和 handle 一样找到处理请求的路由和路径参数，但不调用它
+    fn find(&self, request: &Request) -> Option<(&Route, Vec<(String, String)>)> {
+        let path = segments(request.path());
+        let path = path?;
+        self.lookup(&request.method, &path).or_else(|| {
+            (request.method == Method::Head)
+                .then(|| self.lookup(&Method::Get, &path))
+                .flatten()
+        })
+    }
+
+    fn lookup(&self, method: &Method, path: &[String]) -> Option<(&Route, Vec<(String, String)>)> {
+        self.routes
+            .iter()
+            .filter(|route| route.method == *method)
+            .find_map(|route| route.matches(path).map(|params| (route, params)))
+    }
+
+    //路径匹配的路由支持的方法，按注册顺序去重，支持 GET 时也支持 HEAD，OPTIONS 总是支持
+    fn allowed(&self, path: &[String]) -> Vec<&str> {
+        let mut allowed: Vec<&str> = Vec::new();
+        for route in &self.routes {
+            if route.matches(path).is_some() && !allowed.contains(&route.method.as_str()) {
+                allowed.push(route.method.as_str());
+            }
+        }
+        if allowed.is_empty() {
+            return allowed;
+        }
+        for implied in ["HEAD", "OPTIONS"] {
+            if (implied == "OPTIONS" || allowed.contains(&"GET")) && !allowed.contains(&implied) {
+                allowed.push(implied);
+            }
+        }
+        allowed
+    }
+}
+
+//按 `/` 切分路径，忽略空段，所以 `/a//b/` 和 `/a/b` 是同一个路径
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+//路由匹配时看到的路径：按 '/' 分段，去掉空段，再逐段百分号解码。有不合法的百分号编码时返回 None。
+//Scoped 也按它匹配前缀，两边对同一个路径的理解才一致
+pub(crate) fn segments(path: &str) -> Option<Vec<String>> {
+    split(path).map(percent_decode).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Version};
+
+    fn request(method: Method, target: &str) -> Request {
+        Request {
+            method,
+            target: target.to_string(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(&response.body).unwrap()
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_| Response::text(StatusCode::OK, "index"))
+            .get("/users/:id", |request| {
+                Response::text(
+                    StatusCode::OK,
+                    format!("get {}", request.param("id").unwrap()),
+                )
+            })
+            .post("/users/:id", |request| {
+                Response::new(StatusCode::CREATED).with_body(request.body.clone())
+            })
+            .get("/files/*path", |request| {
+                Response::text(StatusCode::OK, request.param("path").unwrap().to_string())
+            })
+    }
+
+    #[test]
+    fn dispatches_by_method_and_extracts_params() {
+        let router = router();
+        let response = router.handle(&mut request(Method::Get, "/users/42?verbose=1"));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(body(&response), "get 42");
+
+        let mut post = request(Method::Post, "/users/42");
+        post.body = b"created".to_vec();
+        let response = router.handle(&mut post);
+        assert_eq!(response.status, StatusCode::CREATED);
+        assert_eq!(post.param("id"), Some("42"));
+
+        assert_eq!(
+            body(&router.handle(&mut request(Method::Get, "/"))),
+            "index"
+        );
+    }
+
+    #[test]
+    fn params_are_percent_decoded() {
+        let response = router().handle(&mut request(Method::Get, "/users/j%C3%B6rg"));
+        assert_eq!(body(&response), "get jörg");
+        let response = router().handle(&mut request(Method::Get, "/users/%zz"));
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn wildcards_capture_the_rest_of_the_path() {
+        let router = router();
+        let response = router.handle(&mut request(Method::Get, "/files/css/site.css"));
+        assert_eq!(body(&response), "css/site.css");
+        let response = router.handle(&mut request(Method::Get, "/files"));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(body(&response), "");
+    }
+
+    #[test]
+    fn wrong_method_gets_405_with_allow() {
+        let response = router().handle(&mut request(Method::Delete, "/users/1"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers.get("allow"),
+            Some("GET, POST, HEAD, OPTIONS")
+        );
+
+        let response = router().handle(&mut request(Method::Options, "/users/1"));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers.get("allow"),
+            Some("GET, POST, HEAD, OPTIONS")
+        );
+    }
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let response = router().handle(&mut request(Method::Head, "/users/7"));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(body(&response), "get 7");
+    }
+
+    #[test]
+    fn unmatched_paths_use_the_not_found_handler() {
+        let router = router().not_found(|request| {
+            Response::text(StatusCode::NOT_FOUND, format!("no {}", request.path()))
+        });
+        let response = router.handle(&mut request(Method::Get, "/users/1/posts"));
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(body(&response), "no /users/1/posts");
+    }
+
+    #[test]
+    fn earlier_routes_win() {
+        let router = Router::new()
+            .get("/users/me", |_| Response::text(StatusCode::OK, "me"))
+            .get("/users/:id", |_| Response::text(StatusCode::OK, "someone"));
+        assert_eq!(
+            body(&router.handle(&mut request(Method::Get, "/users/me"))),
+            "me"
+        );
+        assert_eq!(
+            body(&router.handle(&mut request(Method::Get, "/users/you"))),
+            "someone"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "must be the last segment")]
+    fn wildcard_must_be_last() {
+        let _ = Router::new().get("/files/*path/edit", |_| Response::new(StatusCode::OK));
+    }
+
+    #[test]
+    fn blocking_marks_the_previous_route() {
+        let router = router()
+            .blocking()
+            .get("/fast", |_| Response::new(StatusCode::OK));
+        assert!(router.is_blocking(&request(Method::Get, "/files/a/b")));
+        assert!(router.is_blocking(&request(Method::Head, "/files/a")));
+        assert!(!router.is_blocking(&request(Method::Get, "/fast")));
+        assert!(!router.is_blocking(&request(Method::Get, "/missing")));
+
+        let router = router.blocking_not_found();
+        assert!(router.is_blocking(&request(Method::Get, "/missing")));
+        assert!(!router.is_blocking(&request(Method::Get, "/fast")));
+    }
+
+    #[test]
+    fn streaming_routes_read_the_body_themselves() {
+        let router = router().streaming(Method::Put, "/uploads/:name", |request, body| {
+            let mut data = String::new();
+            body.read_to_string(&mut data).unwrap();
+            let name = request.param("name").unwrap();
+            Response::text(StatusCode::OK, format!("{}: {}", name, data))
+        });
+        let mut put = request(Method::Put, "/uploads/a.txt");
+        assert!(router.streams_body(&put));
+        assert!(router.is_blocking(&put));
+        assert!(!router.streams_body(&request(Method::Get, "/users/1")));
+
+        let response = router.handle_streaming(&mut put, &mut &b"from the connection"[..]);
+        assert_eq!(response.body, b"a.txt: from the connection");
+        //请求体已经在内存里时也能处理
+        put.body = b"in memory".to_vec();
+        assert_eq!(router.handle(&mut put).body, b"a.txt: in memory");
+    }
+
+    #[test]
+    fn websocket_routes_handshake_before_upgrading() {
+        let router = router().websocket("/ws/:room", |_, _| {});
+        let mut plain = request(Method::Get, "/ws/lobby");
+        let response = router.handle(&mut plain);
+        assert_eq!(response.status, StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(plain.param("room"), Some("lobby"));
+        assert!(router.websocket_handler(&plain).is_some());
+        assert!(router
+            .websocket_handler(&request(Method::Get, "/users/1"))
+            .is_none());
+    }
+}
@@ -0,0 +1,1172 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 19:12:08
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 06:24:10
+ * @Description: 在线程池上处理连接：持久连接、流水线请求，以及不占用 worker 的空闲连接
+ */
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use mio::net::TcpStream as MioStream;
+use mio::{Events, Interest, Poll, Token, Waker};
+
+use super::compress;
+use super::event_loop;
+use super::metrics::{self, Metrics};
+use super::middleware::{Chain, Middleware};
+use super::parser::{read_next, BodyReader, Next};
+use super::tls::{Stream, TlsConfig};
+use super::websocket::{self, Socket};
+use super::{AccessLog, ConnectionError, LogEntry, StatusCode, Version};
+use super::{Limits, Method, Parser, ReadError, Request, Response, Router};
+use crate::{ShutdownTimeout, Spawner, ThreadPool};
+
+//最初每个连接只处理一个请求：读一个请求、写一个响应，然后关闭连接，浏览器每请求一个资源都要重新建立 TCP 连接。
+//HTTP/1.1 默认使用持久连接，一个连接上可以先后发送多个请求，甚至不等响应就连续发送（流水线）。
+//如果让 worker 阻塞在空闲连接的 read 上等下一个请求，几个什么都不发送的客户端就能占满整个线程池。所以：
+// 1. 一个连接在同一时刻只由一个 worker 处理，请求按顺序逐个处理、逐个写回响应，流水线请求的响应顺序自然与请求的顺序一致；
+// 2. 写完响应后，如果解析器里还有流水线请求的数据，或者套接字上已经有数据可读，就接着处理；
+// 3. 否则把连接交给空闲连接线程，worker 去处理别的任务。空闲连接线程把这些连接注册到 epoll（通过 mio）上，
+//    阻塞到有连接可读或者最早的 keep_alive 到期为止：有数据可读时把连接作为新任务交还给线程池，
+//    超过 keep_alive 还没有数据就关闭连接。没有事件时这个线程不做任何系统调用；
+// 4. 一个连接处理的请求数达到 max_requests 之后，在最后一个响应上带上 Connection: close 并关闭连接。
+//
+//同时打开的连接数（包括空闲的持久连接）超过 max_connections 时，新连接直接收到 503 并被关闭。
+//关闭 server 时先停止接受新连接，正在处理的连接写完当前的响应后关闭，空闲的连接直接关闭，最后优雅地关闭线程池。
+//配置了访问日志时，每个写出的响应（包括 400、408、503 这些错误响应）都记录一条，见 http/log.rs。
+//同样的地方也统计每个响应的状态码和延迟，由内置的 /metrics 报告；/healthz 和 /readyz 给负载均衡检查，见 http/metrics.rs。
+//读写失败、请求不合法、处理函数 panic 都不会让 worker panic，而是交给错误回调并关闭连接，见 http/error.rs。
+//
+//配置了 TLS 时，接受的连接先包装成 rustls 会话，两种模式都一样，见 http/tls.rs。
+//WebSocket 路由握手成功（101）之后，连接不再回到空闲连接线程，而是在 worker 上运行处理函数直到会话结束，见 http/websocket.rs。
+//ServerConfig::mode 为 Mode::EventLoop 时改为由一个线程用 epoll 同时照看所有连接，见 http/event_loop.rs。
+
+//空闲连接线程的 poll 上的 Waker，有新的空闲连接、server 停止或者被 drop 时用它唤醒线程
+const IDLE_WAKER: Token = Token(0);
+
+//流式请求体的处理函数没有读完时，最多替它读掉这么多字节来保住持久连接，剩下更多就关闭连接
+pub(super) const MAX_DISCARD: u64 = 64 * 1024;
+
+type ErrorCallback = dyn Fn(Option<SocketAddr>, &ConnectionError) + Send + Sync;
+
+/// [`Server`] 的配置
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// 持久连接空闲多久之后关闭
+    pub keep_alive: Duration,
+    /// 一个连接最多处理多少个请求，之后关闭连接
+    pub max_requests: usize,
+    /// 请求开始到达之后，每次读取最多等待多久；超时时回复 408 并关闭连接。
+    /// 写响应时每次写入也最多等待这么久，客户端不读取响应时关闭连接
+    pub request_timeout: Duration,
+    /// 请求各部分的大小上限
+    pub limits: Limits,
+    /// 最多同时打开多少个连接
+    pub max_connections: usize,
+    /// 处理连接的方式
+    pub mode: Mode,
+    /// 不小于这个大小的文本响应按 `Accept-Encoding` 压缩，`None` 表示不压缩
+    pub compress_min_size: Option<usize>,
+}
+
+/// [`Server`] 处理连接的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// 每个正在处理的连接占用线程池中的一个线程，读写都是阻塞的
+    #[default]
+    Threads,
+    /// 一个事件循环线程用 epoll 读写所有连接，处理函数直接在这个线程上运行；
+    /// 用 [`Router::blocking`] 标记的处理函数交给线程池运行
+    EventLoop,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "threads" => Ok(Mode::Threads),
+            "event-loop" => Ok(Mode::EventLoop),
+            _ => Err(format!("expected threads or event-loop, got {:?}", s)),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            keep_alive: Duration::from_secs(5),
+            max_requests: 100,
+            request_timeout: Duration::from_secs(10),
+            limits: Limits::default(),
+            max_connections: 1024,
+            mode: Mode::default(),
+            compress_min_size: Some(compress::DEFAULT_MIN_SIZE),
+        }
+    }
+}
+
+/// 在线程池上处理连接的 HTTP 服务器
+///
+/// ```no_run
+/// use std::net::TcpListener;
+/// use multithreaded::http::{Response, Router, Server, ServerConfig, StatusCode};
+/// use multithreaded::ThreadPool;
+///
+/// let router = Router::new().get("/", |_| Response::text(StatusCode::OK, "hello"));
+/// let server = Server::new(router, ServerConfig::default(), ThreadPool::new(4));
+/// let stop = server.shutdown_handle();
+/// //在另一个线程里（例如收到 SIGTERM 时）调用 stop.shutdown()，serve 就会返回
+/// server.serve(&TcpListener::bind("127.0.0.1:7878").unwrap());
+/// server.shutdown(Duration::from_secs(30)).unwrap();
+/// # use std::time::Duration;
+/// ```
+///
+/// 需要访问日志等可选功能时使用 [`Server::builder`]。
+pub struct Server {
+    //先 drop 线程池，等正在处理的请求结束，再 drop 共享状态，空闲连接线程随之退出
+    pool: ThreadPool,
+    shared: Arc<ServerShared>,
+}
+
+pub(super) struct ServerShared {
+    pub(super) router: Router,
+    pub(super) config: ServerConfig,
+    //交给空闲连接线程的连接。ServerShared 被 drop 时空闲连接线程关闭所有空闲连接并退出
+    idle: IdleSender,
+    //当前打开的连接数。Connection 里也持有一份，drop 时减一；
+    //Connection 不能直接持有 Arc<ServerShared>，否则信道里的空闲连接会让 ServerShared 永远不被 drop
+    pub(super) connections: Arc<AtomicUsize>,
+    pub(super) stop: Arc<Stop>,
+    access_log: Option<AccessLog>,
+    pub(super) on_error: Box<ErrorCallback>,
+    pub(super) tls: Option<TlsConfig>,
+    pub(super) middleware: Chain,
+    pub(super) metrics: Metrics,
+    //报告线程池的状态用
+    pub(super) pool: Spawner,
+}
+
+struct Connection {
+    stream: Stream<TcpStream>,
+    //写访问日志用，accept 时记下来
+    peer: Option<SocketAddr>,
+    //跨请求保留，流水线请求中多读到的数据留在里面
+    parser: Parser,
+    served: usize,
+    _counted: Counted,
+}
+
+//发送之后唤醒阻塞在 poll 上的空闲连接线程。被 drop 时（也就是 ServerShared 被 drop 时）同样唤醒它，
+//它发现 ServerShared 已经不在了就会退出
+struct IdleSender {
+    tx: Sender<Connection>,
+    waker: Arc<Waker>,
+}
+
+impl IdleSender {
+    //空闲连接线程已经退出时发送失败，连接随之关闭
+    fn send(&self, conn: Connection) {
+        if self.tx.send(conn).is_ok() {
+            let _ = self.waker.wake();
+        }
+    }
+}
+
+impl Drop for IdleSender {
+    fn drop(&mut self) {
+        let _ = self.waker.wake();
+    }
+}
+
+pub(super) struct Counted(pub(super) Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//停止 server 的标志，以及 serve 正在监听的地址
+#[derive(Default)]
+pub(super) struct Stop {
+    pub(super) stopped: AtomicBool,
+    addr: Mutex<Option<SocketAddr>>,
+    //停止时唤醒空闲连接线程，让它马上关闭空闲的连接
+    idle_waker: Mutex<Option<Arc<Waker>>>,
+}
+
+impl Stop {
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(waker) = &*self.idle_waker.lock().unwrap() {
+            let _ = waker.wake();
+        }
+    }
+}
+
+/// 从其他线程停止 [`Server::serve`] 的句柄，由 [`Server::shutdown_handle`] 得到
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<Stop>);
+
+impl ShutdownHandle {
+    /// 让 `serve` 停止接受新连接并返回。之后应当调用 [`Server::shutdown`] 处理完已有的连接。
+    pub fn shutdown(&self) {
+        self.0.stop();
+        //serve 阻塞在 accept 上，连接一下监听的地址把它唤醒
+        if let Some(mut addr) = *self.0.addr.lock().unwrap() {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr {
+                    SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                    SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.0.stopped.load(Ordering::SeqCst)
+    }
+}
+
+/// [`Server`] 的构建器，由 [`Server::builder`] 得到
+pub struct ServerBuilder {
+    router: Router,
+    config: ServerConfig,
+    pool: Option<ThreadPool>,
+    access_log: Option<AccessLog>,
+    on_error: Box<ErrorCallback>,
+    tls: Option<TlsConfig>,
+    middleware: Chain,
+}
+
+impl ServerBuilder {
+    pub fn config(mut self, config: ServerConfig) -> ServerBuilder {
+        self.config = config;
+        self
+    }
+
+    /// 处理连接的线程池，默认是 4 个线程的 [`ThreadPool::new`]
+    pub fn pool(mut self, pool: ThreadPool) -> ServerBuilder {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// 记录访问日志。日志在 server 被 drop 时写完并关闭。
+    pub fn access_log(mut self, log: AccessLog) -> ServerBuilder {
+        self.access_log = Some(log);
+        self
+    }
+
+    /// 处理连接出错时调用 `f`，参数是对方的地址和错误。默认打印到标准错误，
+    /// 但不打印客户端断开连接（见 [`ConnectionError::is_disconnect`]）。
+    ///
+    /// 在 worker 上调用，应当尽快返回。
+    pub fn on_error<F>(mut self, f: F) -> ServerBuilder
+    where
+        F: Fn(Option<SocketAddr>, &ConnectionError) + Send + Sync + 'static,
+    {
+        self.on_error = Box::new(f);
+        self
+    }
+
+    /// 提供 HTTPS：每个连接先完成 TLS 握手，ALPN 协商为 http/1.1
+    pub fn tls(mut self, config: TlsConfig) -> ServerBuilder {
+        self.tls = Some(config);
+        self
+    }
+
+    /// 在处理函数前后运行的中间件。先添加的 before 先调用、after 后调用。
+    ///
+    /// ```no_run
+    /// use multithreaded::http::{Cors, RateLimit, RequestId, Router, Server};
+    ///
+    /// let server = Server::builder(Router::new())
+    ///     .middleware(RequestId::new())
+    ///     .middleware(Cors::new())
+    ///     .middleware(RateLimit::new(10.0, 20))
+    ///     .build();
+    /// # let _ = server;
+    /// ```
+    pub fn middleware(mut self, middleware: impl Middleware) -> ServerBuilder {
+        self.middleware.push(middleware);
+        self
+    }
+
+    pub fn build(self) -> Server {
+        let pool = self.pool.unwrap_or_else(|| ThreadPool::new(4));
+        let poll = Poll::new().expect("failed to create the idle connection poll");
+        let waker = Waker::new(poll.registry(), IDLE_WAKER)
+            .map(Arc::new)
+            .expect("failed to create the idle connection waker");
+        let (tx, rx) = mpsc::channel();
+        let stop = Stop {
+            idle_waker: Mutex::new(Some(Arc::clone(&waker))),
+            ..Stop::default()
+        };
+        let shared = Arc::new(ServerShared {
+            router: self.router,
+            config: self.config,
+            idle: IdleSender { tx, waker },
+            connections: Arc::default(),
+            stop: Arc::new(stop),
+            access_log: self.access_log,
+            on_error: self.on_error,
+            tls: self.tls,
+            middleware: self.middleware,
+            metrics: Metrics::default(),
+            pool: pool.spawner(),
+        });
+        let weak = Arc::downgrade(&shared);
+        let spawner = pool.spawner();
+        thread::Builder::new()
+            .name("idle-connections".to_string())
+            .spawn(move || watch_idle(poll, rx, weak, spawner))
+            .expect("failed to spawn the idle connection thread");
+        Server { pool, shared }
+    }
+}
+
+//默认的错误回调。客户端在响应写完之前关闭连接（浏览器取消请求、关掉页面）很常见，不是 server 的问题，
+//每次都打印一行只会淹没真正的错误，所以跳过
+fn log_error(out: &mut impl Write, peer: Option<SocketAddr>, err: &ConnectionError) {
+    if err.is_disconnect() {
+        return;
+    }
+    let _ = match peer {
+        Some(peer) => writeln!(out, "{}: {}", peer, err),
+        None => writeln!(out, "{}", err),
+    };
+}
+
+impl Server {
+    pub fn new(router: Router, config: ServerConfig, pool: ThreadPool) -> Server {
+        Server::builder(router).config(config).pool(pool).build()
+    }
+
+    /// 创建一个 [`ServerBuilder`]，用来配置访问日志等可选功能
+    pub fn builder(router: Router) -> ServerBuilder {
+        ServerBuilder {
+            router,
+            config: ServerConfig::default(),
+            pool: None,
+            access_log: None,
+            on_error: Box::new(|peer, err| log_error(&mut io::stderr(), peer, err)),
+            tls: None,
+            middleware: Chain::default(),
+        }
+    }
+
+    /// 把新接受的连接交给线程池处理。打开的连接已经达到上限时回复 503 并关闭连接。
+    pub fn handle(&self, mut stream: TcpStream) {
+        let peer = stream.peer_addr().ok();
+        let connections = &self.shared.connections;
+        if connections.fetch_add(1, Ordering::SeqCst) >= self.shared.config.max_connections {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            //TLS 连接要先握手才能回复，不值得为此占用 accept 所在的线程，直接关闭
+            if self.shared.tls.is_some() {
+                return;
+            }
+            let response = service_unavailable();
+            let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+            if let Err(err) = respond(&self.shared, &mut stream, peer, &response) {
+                (self.shared.on_error)(peer, &err);
+            }
+            return;
+        }
+        let stream = match Stream::new(stream, self.shared.tls.as_ref()) {
+            Ok(stream) => stream,
+            Err(err) => {
+                (self.shared.on_error)(peer, &ConnectionError::Read(err));
+                return;
+            }
+        };
+        let counted = Counted(Arc::clone(connections));
+        let conn = Connection {
+            stream,
+            peer,
+            parser: Parser::new(self.shared.config.limits),
+            served: 0,
+            _counted: counted,
+        };
+        let shared = Arc::clone(&self.shared);
+        self.pool.execute(move || serve_connection(&shared, conn));
+    }
+
+    /// 不断接受 `listener` 上的连接并处理，直到通过 [`ShutdownHandle::shutdown`] 停止。接受连接失败时跳过这个连接。
+    ///
+    /// [`Mode::EventLoop`] 模式下在当前线程上运行事件循环，停止之后还要等所有连接处理完才返回。
+    pub fn serve(&self, listener: &TcpListener) {
+        let stop = &self.shared.stop;
+        *stop.addr.lock().unwrap() = listener.local_addr().ok();
+        match self.shared.config.mode {
+            Mode::Threads => {
+                for stream in listener.incoming() {
+                    if stop.stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        self.handle(stream);
+                    }
+                }
+            }
+            Mode::EventLoop => {
+                if let Err(err) = event_loop::run(&self.shared, &self.pool, listener) {
+                    eprintln!("event loop failed: {}", err);
+                }
+            }
+        }
+        *stop.addr.lock().unwrap() = None;
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(Arc::clone(&self.shared.stop))
+    }
+
+    /// 当前打开的连接数，包括空闲的持久连接
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::SeqCst)
+    }
+
+    /// 优雅地关闭 server：正在处理的连接写完当前的响应后关闭，空闲的连接直接关闭，
+    /// 然后在 `timeout` 之内关闭线程池，见 [`ThreadPool::shutdown_timeout`]。
+    ///
+    /// 还在 `serve` 的话应当先通过 [`ShutdownHandle::shutdown`] 让它返回。
+    ///
+    /// # Errors
+    ///
+    /// 到期时还有请求没有处理完，返回 [`ShutdownTimeout`]
+    pub fn shutdown(self, timeout: Duration) -> Result<(), ShutdownTimeout> {
+        self.shared.stop.stop();
+        let result = self.pool.shutdown_timeout(timeout);
+        //最后一个 ServerShared 被 drop 后空闲连接线程退出
+        drop(self.shared);
+        result
+    }
+
+    pub fn pool(&self) -> &ThreadPool {
+        &self.pool
+    }
+}
+
+fn serve_connection(shared: &Arc<ServerShared>, mut conn: Connection) {
+    let peer = conn.peer;
+    match serve_requests(
+        shared,
+        &mut conn.stream,
+        &mut conn.parser,
+        &mut conn.served,
+        peer,
+    ) {
+        Ok(After::Idle) => shared.idle.send(conn),
+        Ok(After::Close) => {}
+        Ok(After::Upgrade(request)) => {
+            let control = match conn.stream.get_ref().try_clone() {
+                Ok(control) => control,
+                Err(err) => return (shared.on_error)(peer, &ConnectionError::Read(err)),
+            };
+            let Connection {
+                stream,
+                parser,
+                _counted,
+                ..
+            } = conn;
+            run_websocket(
+                shared,
+                peer,
+                &request,
+                Box::new(stream),
+                control,
+                parser.into_buffered(),
+            );
+        }
+        Err(err) => (shared.on_error)(peer, &err),
+    }
+}
+
+//在当前线程上运行升级后的 WebSocket 会话，直到处理函数返回。两种模式共用；
+//`control` 与 `socket` 是同一个阻塞的套接字，`buffered` 是解析器里多读到的数据
+pub(super) fn run_websocket(
+    shared: &ServerShared,
+    peer: Option<SocketAddr>,
+    request: &Request,
+    socket: Box<dyn Socket>,
+    control: TcpStream,
+    buffered: Vec<u8>,
+) {
+    let Some(handler) = shared.router.websocket_handler(request) else {
+        return;
+    };
+    let stop = Arc::clone(&shared.stop);
+    if let Err(err) = websocket::run_session(handler, request, socket, control, buffered, stop) {
+        (shared.on_error)(peer, &err);
+    }
+}
+
+//处理完一批请求之后连接该怎么办
+#[derive(Debug, PartialEq, Eq)]
+enum After {
+    //交给空闲连接线程等下一个请求
+    Idle,
+    Close,
+    //握手成功，连接交给这个请求对应的 WebSocket 处理函数
+    Upgrade(Box<Request>),
+}
+
+//处理连接需要的操作。除了 TcpStream，测试里用模拟的连接代替
+trait Transport: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn readiness(&mut self) -> Readiness;
+}
+
+impl Transport for Stream<TcpStream> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_write_timeout(timeout)
+    }
+
+    fn readiness(&mut self) -> Readiness {
+        if self.has_buffered() {
+            return Readiness::Ready;
+        }
+        readiness(self.get_ref())
+    }
+}
+
+//一个接一个地处理连接上的请求，直到连接空闲、需要关闭或者出错
+fn serve_requests<S: Transport>(
+    shared: &ServerShared,
+    stream: &mut S,
+    parser: &mut Parser,
+    served: &mut usize,
+    peer: Option<SocketAddr>,
+) -> Result<After, ConnectionError> {
+    let config = &shared.config;
+    stream
+        .set_read_timeout(Some(config.request_timeout))
+        .map_err(ConnectionError::Read)?;
+    //客户端发完请求就不再读取时，套接字的发送缓冲区写满之后 write 会一直阻塞，worker 也就一直被占着
+    stream
+        .set_write_timeout(Some(config.request_timeout))
+        .map_err(ConnectionError::Write)?;
+    loop {
+        let streaming = |request: &Request| shared.router.streams_body(request);
+        let (mut request, streamed) = match read_next(stream, parser, streaming) {
+            Ok(Some(Next::Request(request))) => (request, false),
+            //Router::streaming 的路由：请求体还在连接上，由处理函数自己读
+            Ok(Some(Next::Head(request))) => (request, true),
+            //客户端在两个请求之间关闭了连接
+            Ok(None) => return Ok(After::Close),
+            Err(ReadError::Io(err)) if is_timeout(&err) => {
+                //还没有收到下一个请求的任何数据，客户端只是不再发送了
+                if !parser.has_partial() {
+                    return Ok(After::Close);
+                }
+                let response = Response::text(StatusCode::REQUEST_TIMEOUT, "408 Request Timeout\n")
+                    .with_header("Connection", "close");
+                respond(shared, stream, peer, &response)?;
+                return Err(ConnectionError::Timeout);
+            }
+            Err(ReadError::Io(err)) => return Err(ConnectionError::Read(err)),
+            //请求不合法或者超过了解析器的上限时，回复对应的状态码（400、413、414、431 等）并关闭连接
+            Err(ReadError::Parse(err)) => {
+                respond(shared, stream, peer, &err.to_response())?;
+                return Err(ConnectionError::Parse(err));
+            }
+        };
+        *served += 1;
+        let started = Instant::now();
+
+        let (response, keep_alive) = if streamed {
+            let mut body = BodyReader::new(stream, parser);
+            let (mut response, keep_alive) =
+                handle_request(shared, peer, &mut request, *served, Some(&mut body));
+            //处理函数没有读完请求体，又剩下太多，就不知道下一个请求从哪里开始了，只能关闭连接
+            if keep_alive && !body.finish(MAX_DISCARD) {
+                response.headers.insert("Connection", "close");
+                (response, false)
+            } else {
+                (response, keep_alive)
+            }
+        } else {
+            handle_request(shared, peer, &mut request, *served, None)
+        };
+        let include_body = request.method != Method::Head;
+        response
+            .write_to(stream, include_body)
+            .map_err(ConnectionError::Write)?;
+        log_response(
+            shared,
+            peer,
+            Some(&request),
+            &response,
+            include_body,
+            started.elapsed(),
+        );
+        if response.status == StatusCode::SWITCHING_PROTOCOLS {
+            return Ok(After::Upgrade(Box::new(request)));
+        }
+        if !keep_alive {
+            return Ok(After::Close);
+        }
+
+        //流水线请求的数据已经在解析器里了
+        if parser.has_partial() {
+            continue;
+        }
+        match stream.readiness() {
+            Readiness::Ready => continue,
+            Readiness::Closed => return Ok(After::Close),
+            Readiness::Idle => return Ok(After::Idle),
+        }
+    }
+}
+
+//调用处理函数，并决定写完这个响应之后是否保持连接。两种模式共用。
+//`body` 是 Router::streaming 的路由还留在连接上的请求体
+pub(super) fn handle_request(
+    shared: &ServerShared,
+    peer: Option<SocketAddr>,
+    request: &mut Request,
+    served: usize,
+    body: Option<&mut dyn Read>,
+) -> (Response, bool) {
+    //处理函数 panic 时回复内置的 500 页面，中间件的 after 照常调用，连接照常使用；中间件本身 panic 时也一样回复 500
+    let mut response = guarded(shared, peer, || {
+        shared.middleware.run(request, peer, |request| {
+            guarded(shared, peer, || match body {
+                Some(body) => shared.router.handle_streaming(request, body),
+                None => metrics::builtin(shared, request)
+                    .unwrap_or_else(|| shared.router.handle(request)),
+            })
+        })
+    });
+    //握手成功，这个连接上不会再有 HTTP 请求，也不需要 Connection 头部
+    if response.status == StatusCode::SWITCHING_PROTOCOLS {
+        return (response, false);
+    }
+    if let Some(min_size) = shared.config.compress_min_size {
+        compress::compress_response(request, &mut response, min_size);
+    }
+    let keep_alive = wants_keep_alive(request)
+        && served < shared.config.max_requests
+        && !shared.stop.stopped.load(Ordering::SeqCst)
+        && !response.headers.has_token("connection", "close");
+    if !keep_alive {
+        response.headers.insert("Connection", "close");
+    } else if request.version == Version::Http10 {
+        response.headers.insert("Connection", "keep-alive");
+    }
+    (response, keep_alive)
+}
+
+fn guarded(
+    shared: &ServerShared,
+    peer: Option<SocketAddr>,
+    f: impl FnOnce() -> Response,
+) -> Response {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        (shared.on_error)(peer, &ConnectionError::Handler(message));
+        Response::error_page(StatusCode::INTERNAL_SERVER_ERROR)
+    })
+}
+
+//打开的连接已经达到上限
+pub(super) fn service_unavailable() -> Response {
+    Response::text(StatusCode::SERVICE_UNAVAILABLE, "503 Service Unavailable\n")
+        .with_header("Connection", "close")
+        .with_header("Retry-After", "1")
+}
+
+pub(super) fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+//写出没有对应请求的错误响应（400、408、503），并记入访问日志
+pub(super) fn respond<W: Write>(
+    shared: &ServerShared,
+    stream: &mut W,
+    peer: Option<SocketAddr>,
+    response: &Response,
+) -> Result<(), ConnectionError> {
+    response
+        .write_to(stream, true)
+        .map_err(ConnectionError::Write)?;
+    log_response(shared, peer, None, response, true, Duration::ZERO);
+    Ok(())
+}
+
+//把写出的响应计入统计并记入访问日志。请求没能解析出来时 request 为 None
+pub(super) fn log_response(
+    shared: &ServerShared,
+    peer: Option<SocketAddr>,
+    request: Option<&Request>,
+    response: &Response,
+    include_body: bool,
+    latency: Duration,
+) {
+    let bytes = if include_body && response.status.allows_body() {
+        response.body.len() as u64
+    } else {
+        0
+    };
+    shared.metrics.record(response.status, bytes, latency);
+    let Some(log) = &shared.access_log else {
+        return;
+    };
+    let header = |name| request.and_then(|request| request.headers.get(name).map(String::from));
+    log.log(LogEntry {
+        time: SystemTime::now() - latency,
+        client: peer,
+        method: request.map_or_else(String::new, |request| request.method.to_string()),
+        target: request.map_or_else(String::new, |request| request.target.clone()),
+        version: request.map_or_else(String::new, |request| request.version.to_string()),
+        status: response.status.as_u16(),
+        bytes,
+        referer: header("referer"),
+        user_agent: header("user-agent"),
+        latency,
+    });
+}
+
+//HTTP/1.1 默认保持连接，除非请求带有 Connection: close；HTTP/1.0 默认关闭，除非请求带有 Connection: keep-alive
+fn wants_keep_alive(request: &Request) -> bool {
+    match request.version {
+        Version::Http11 => !request.headers.has_token("connection", "close"),
+        Version::Http10 => request.headers.has_token("connection", "keep-alive"),
+    }
+}
+
+enum Readiness {
+    Ready,
+    Idle,
+    Closed,
+}
+
+//不阻塞地看一眼套接字：有数据可读、暂时没有数据，还是对方已经关闭（或者出错）
+fn readiness(stream: &TcpStream) -> Readiness {
+    if stream.set_nonblocking(true).is_err() {
+        return Readiness::Closed;
+    }
+    let result = stream.peek(&mut [0; 1]);
+    if stream.set_nonblocking(false).is_err() {
+        return Readiness::Closed;
+    }
+    match result {
+        Ok(0) => Readiness::Closed,
+        Ok(_) => Readiness::Ready,
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Readiness::Idle,
+        Err(_) => Readiness::Closed,
+    }
+}
+
+//空闲连接线程照看的连接。register 的是套接字的一个副本（同一个打开的文件），
+//移出之前一定要先 deregister：只关闭副本的话，epoll 里的登记并不会随之删除
+struct IdleConn {
+    conn: Connection,
+    source: MioStream,
+    //keep_alive 大到超出 Instant 的范围时是 None，永不超时
+    deadline: Option<Instant>,
+}
+
+struct IdleSet {
+    poll: Poll,
+    conns: HashMap<Token, IdleConn>,
+    next_token: usize,
+}
+
+impl IdleSet {
+    //注册失败时 drop 连接，也就是关闭它
+    fn insert(&mut self, conn: Connection, deadline: Option<Instant>) {
+        let Ok(source) = conn.stream.get_ref().try_clone() else {
+            return;
+        };
+        let mut source = MioStream::from_std(source);
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        if self
+            .poll
+            .registry()
+            .register(&mut source, token, Interest::READABLE)
+            .is_ok()
+        {
+            let idle = IdleConn {
+                conn,
+                source,
+                deadline,
+            };
+            self.conns.insert(token, idle);
+        }
+    }
+
+    fn remove(&mut self, token: Token) -> Option<Connection> {
+        let mut idle = self.conns.remove(&token)?;
+        let _ = self.poll.registry().deregister(&mut idle.source);
+        Some(idle.conn)
+    }
+
+    fn clear(&mut self) {
+        let tokens: Vec<Token> = self.conns.keys().copied().collect();
+        for token in tokens {
+            self.remove(token);
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.conns.values().filter_map(|idle| idle.deadline).min()
+    }
+}
+
+//空闲连接线程。阻塞在 poll 上，直到某个空闲连接可读、最早的 keep_alive 到期，或者被 IdleSender、Stop 唤醒。
+fn watch_idle(poll: Poll, rx: Receiver<Connection>, shared: Weak<ServerShared>, spawner: Spawner) {
+    let mut idle = IdleSet {
+        poll,
+        conns: HashMap::new(),
+        next_token: IDLE_WAKER.0 + 1,
+    };
+    let mut events = Events::with_capacity(1024);
+    loop {
+        let timeout = idle
+            .next_deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if let Err(err) = idle.poll.poll(&mut events, timeout) {
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            //poll 本身出错时没法再照看任何连接，关闭它们；之后交过来的连接因为发送失败而关闭
+            return;
+        }
+        let Some(server) = shared.upgrade() else {
+            return;
+        };
+        let keep_alive = server.config.keep_alive;
+        loop {
+            match rx.try_recv() {
+                Ok(conn) => idle.insert(conn, Instant::now().checked_add(keep_alive)),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        //正在关闭时不再等空闲连接的下一个请求
+        if server.stop.stopped.load(Ordering::SeqCst) {
+            idle.clear();
+            continue;
+        }
+        for event in events.iter() {
+            let token = event.token();
+            let Some(entry) = idle.conns.get_mut(&token) else {
+                continue;
+            };
+            //确认一下：可能是对方关闭了连接，也可能是虚假的通知
+            match entry.conn.stream.readiness() {
+                Readiness::Ready => {
+                    let conn = idle.remove(token).unwrap();
+                    //上面检查之后可能已经开始关闭了，这时直接关闭连接
+                    if server.stop.stopped.load(Ordering::SeqCst) {
+                        continue;
+                    }
+                    let server = Arc::clone(&server);
+                    //线程池已经关闭时任务被交还回来，drop 掉它即关闭连接，并释放它持有的 ServerShared
+                    let _ = spawner.execute(move || serve_connection(&server, conn));
+                }
+                Readiness::Idle => {}
+                Readiness::Closed => {
+                    idle.remove(token);
+                }
+            }
+        }
+        //超过 keep_alive 没有新请求，drop 连接即关闭
+        let now = Instant::now();
+        let expired: Vec<Token> = idle
+            .conns
+            .iter()
+            .filter(|(_, entry)| entry.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(token, _)| *token)
+            .collect();
+        for token in expired {
+            idle.remove(token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //模拟的连接：先读出 input，之后返回 read_error（没有就是 EOF）；写出超过 write_limit 个字节时返回 broken pipe
+    struct MockStream {
+        input: io::Cursor<Vec<u8>>,
+        read_error: Option<io::ErrorKind>,
+        output: Vec<u8>,
+        write_limit: usize,
+    }
+
+    impl MockStream {
+        fn new(input: &str) -> MockStream {
+            MockStream {
+                input: io::Cursor::new(input.as_bytes().to_vec()),
+                read_error: None,
+                output: Vec::new(),
+                write_limit: usize::MAX,
+            }
+        }
+
+        fn output(&self) -> String {
+            String::from_utf8_lossy(&self.output).into_owned()
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.input.read(buf)? {
+                0 => match self.read_error {
+                    Some(kind) => Err(kind.into()),
+                    None => Ok(0),
+                },
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let room = self.write_limit - self.output.len();
+            if room == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let n = buf.len().min(room);
+            self.output.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for MockStream {
+        fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn readiness(&mut self) -> Readiness {
+            if (self.input.position() as usize) < self.input.get_ref().len() {
+                Readiness::Ready
+            } else {
+                Readiness::Idle
+            }
+        }
+    }
+
+    fn server(errors: Arc<Mutex<Vec<String>>>) -> Server {
+        let router = Router::new()
+            .get("/", |_| Response::text(StatusCode::OK, "x".repeat(1000)))
+            .get("/panic", |_| panic!("handler bug"));
+        Server::builder(router)
+            .pool(ThreadPool::new(1))
+            .on_error(move |_, err| errors.lock().unwrap().push(err.to_string()))
+            .build()
+    }
+
+    fn serve(server: &Server, stream: &mut MockStream) -> Result<After, ConnectionError> {
+        let mut parser = Parser::new(server.shared.config.limits);
+        serve_requests(&server.shared, stream, &mut parser, &mut 0, None)
+    }
+
+    #[test]
+    fn serves_requests_until_the_connection_is_idle() {
+        let server = server(Arc::default());
+        let mut stream =
+            MockStream::new("GET / HTTP/1.1\r\nHost: a\r\n\r\nHEAD / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(serve(&server, &mut stream).unwrap(), After::Idle);
+        assert_eq!(stream.output().matches("HTTP/1.1 200 OK").count(), 2);
+    }
+
+    #[test]
+    fn unread_streaming_bodies_are_drained_or_close_the_connection() {
+        //只看了头部就回复的处理函数
+        let router = Router::new()
+            .streaming(Method::Post, "/ignore", |_, _| {
+                Response::text(StatusCode::FORBIDDEN, "no\n")
+            })
+            .get("/", |_| Response::text(StatusCode::OK, "hello"));
+        let server = Server::builder(router).pool(ThreadPool::new(1)).build();
+        let post = |length: usize, extra: &str| {
+            format!(
+                "POST /ignore HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n{}\r\n",
+                length, extra
+            )
+        };
+
+        //剩下的不多，server 读掉之后接着处理流水线上的下一个请求
+        let input = post(1000, "") + &"x".repeat(1000) + "GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let mut stream = MockStream::new(&input);
+        assert_eq!(serve(&server, &mut stream).unwrap(), After::Idle);
+        let output = stream.output();
+        assert!(
+            output.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("HTTP/1.1 200 OK\r\n"), "{}", output);
+        assert!(!output.contains("Connection: close"), "{}", output);
+
+        //剩下太多就不读了，关闭连接
+        let length = MAX_DISCARD as usize * 2;
+        let mut stream = MockStream::new(&(post(length, "") + &"x".repeat(length)));
+        assert_eq!(serve(&server, &mut stream).unwrap(), After::Close);
+        assert!(stream.output().contains("Connection: close\r\n"));
+
+        //客户端还在等 100 Continue，请求体没有发送，也不必让它发送
+        let mut stream = MockStream::new(&post(10, "Expect: 100-continue\r\n"));
+        assert_eq!(serve(&server, &mut stream).unwrap(), After::Close);
+        let output = stream.output();
+        assert!(
+            output.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("Connection: close\r\n"), "{}", output);
+    }
+
+    #[test]
+    fn broken_pipe_is_a_disconnect() {
+        let server = server(Arc::default());
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        stream.write_limit = 100;
+        let err = serve(&server, &mut stream).unwrap_err();
+        assert!(matches!(err, ConnectionError::Write(_)), "{:?}", err);
+        assert!(err.is_disconnect());
+    }
+
+    #[test]
+    fn default_callback_skips_disconnects() {
+        let server = server(Arc::default());
+        let peer = Some(SocketAddr::from((Ipv4Addr::LOCALHOST, 4000)));
+        let mut out = Vec::new();
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        stream.write_limit = 100;
+        let err = serve(&server, &mut stream).unwrap_err();
+        log_error(&mut out, peer, &err);
+        assert!(out.is_empty(), "{}", String::from_utf8_lossy(&out));
+        //其余的错误照常打印
+        let err = serve(&server, &mut MockStream::new("BROKEN\r\n\r\n")).unwrap_err();
+        log_error(&mut out, peer, &err);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("127.0.0.1:4000: "), "{}", out);
+    }
+
+    #[test]
+    fn reset_while_reading_is_a_disconnect() {
+        let server = server(Arc::default());
+        let mut stream = MockStream::new("GET / HT");
+        stream.read_error = Some(io::ErrorKind::ConnectionReset);
+        let err = serve(&server, &mut stream).unwrap_err();
+        assert!(matches!(err, ConnectionError::Read(_)), "{:?}", err);
+        assert!(err.is_disconnect());
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn stalled_requests_get_408() {
+        let server = server(Arc::default());
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: te");
+        stream.read_error = Some(io::ErrorKind::WouldBlock);
+        assert!(matches!(
+            serve(&server, &mut stream),
+            Err(ConnectionError::Timeout)
+        ));
+        assert!(stream
+            .output()
+            .starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+        //还没有收到下一个请求的任何数据时超时不是错误，直接关闭连接
+        let mut stream = MockStream::new("");
+        stream.read_error = Some(io::ErrorKind::WouldBlock);
+        assert_eq!(serve(&server, &mut stream).unwrap(), After::Close);
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn malformed_requests_get_400() {
+        let server = server(Arc::default());
+        let mut stream = MockStream::new("NOT HTTP\r\n\r\n");
+        let err = serve(&server, &mut stream).unwrap_err();
+        assert!(matches!(err, ConnectionError::Parse(_)), "{:?}", err);
+        assert!(!err.is_disconnect());
+        assert!(stream.output().starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn panicking_handlers_get_the_built_in_500_page() {
+        let errors = Arc::default();
+        let server = server(Arc::clone(&errors));
+        let mut stream = MockStream::new(
+            "GET /panic HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n",
+        );
+        assert_eq!(serve(&server, &mut stream).unwrap(), After::Idle);
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(output.contains("<h1>500 Internal Server Error</h1>"));
+        //panic 之后连接上的下一个请求照常处理
+        assert!(output.contains("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            *errors.lock().unwrap(),
+            ["handler panicked: handler bug".to_string()]
+        );
+    }
+
+    #[test]
+    fn built_in_endpoints_report_health_and_metrics() {
+        let server = server(Arc::default());
+        let mut stream = MockStream::new(
+            "GET /healthz HTTP/1.1\r\nHost: a\r\n\r\nGET /readyz HTTP/1.1\r\nHost: a\r\n\r\n\
+             GET /missing HTTP/1.1\r\nHost: a\r\n\r\nGET /metrics HTTP/1.1\r\nHost: a\r\n\r\n",
+        );
+        assert_eq!(serve(&server, &mut stream).unwrap(), After::Idle);
+        let output = stream.output();
+        assert_eq!(output.matches("HTTP/1.1 200 OK\r\n").count(), 3);
+        assert!(output.contains("Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n"));
+        //在 /metrics 之前写出的响应都已经计入
+        assert!(output.contains("http_requests_total{status=\"200\"} 2\n"));
+        assert!(output.contains("http_requests_total{status=\"404\"} 1\n"));
+
+        //关闭 server 的过程中 /readyz 回复 503，/healthz 仍然是 200
+        server.shared.stop.stopped.store(true, Ordering::SeqCst);
+        let mut stream = MockStream::new("GET /readyz HTTP/1.1\r\nHost: a\r\n\r\n");
+        serve(&server, &mut stream).unwrap();
+        assert!(stream
+            .output()
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        let mut stream = MockStream::new("GET /healthz HTTP/1.1\r\nHost: a\r\n\r\n");
+        serve(&server, &mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
@@ -0,0 +1,634 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 18:40:52
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 05:26:09
+ * @Description: 从文档根目录提供静态文件：防止目录穿越、按扩展名设置 MIME、条件请求、Range 请求和目录索引
+ */
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use super::compress::{self, Cache};
+use super::date::{format_http_date, parse_http_date};
+use super::{Request, Response, StatusCode};
+
+//最初 server 只会用 fs::read_to_string 读 hello.html 和 404.html 两个文件名写死的页面，
+//读成 String 意味着图片之类的二进制文件根本没法提供。StaticFiles 把请求路径映射到文档根目录下的文件：
+// 1. 路径中出现 `..` 等可以跳出文档根目录的段时直接回复 403；为了防止通过符号链接跳出去，还会比较规范化之后的路径；
+// 2. 文件按字节读取，Content-Type 由扩展名决定；
+// 3. 回复中带有 ETag 和 Last-Modified，客户端带着 If-None-Match 或 If-Modified-Since 再次请求时，文件没有变化就回复 304；
+// 4. 支持单个区间的 Range 请求，回复 206，区间超出文件时回复 416；多个区间的请求按规范可以忽略，直接回复整个文件；
+// 5. 请求的是目录时，先重定向到以 `/` 结尾的地址（这样页面里的相对链接才正确），再提供目录下的索引文件，可选地生成文件列表；
+// 6. 文本文件按 Accept-Encoding 压缩，压缩后的副本缓存起来，文件不变就不用再压缩（见 http/compress.rs）。
+
+/// 静态文件的处理器，通常挂在路由的通配符上
+///
+/// ```no_run
+/// use multithreaded::http::{Router, StaticFiles};
+///
+/// let files = StaticFiles::new("public");
+/// let router = Router::new().get("/static/*path", move |request| {
+///     files.serve(request, request.param("path").unwrap_or(""))
+/// });
+/// # let _ = router;
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: Option<String>,
+    listing: bool,
+    //克隆出来的 StaticFiles 共用同一个缓存
+    compressed: Option<Arc<Cache>>,
+    compress_min_size: usize,
+}
+
+impl StaticFiles {
+    /// 以 `root` 为文档根目录，目录的索引文件是 `index.html`，不生成文件列表
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: Some("index.html".to_string()),
+            listing: false,
+            compressed: Some(Arc::new(Cache::default())),
+            compress_min_size: compress::DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// 设置目录的索引文件，`None` 表示不使用索引文件
+    pub fn index_file(mut self, name: Option<&str>) -> StaticFiles {
+        self.index = name.map(str::to_string);
+        self
+    }
+
+    /// 目录下没有索引文件时是否生成文件列表
+    pub fn listing(mut self, enabled: bool) -> StaticFiles {
+        self.listing = enabled;
+        self
+    }
+
+    /// 是否按 `Accept-Encoding` 用 gzip 或 brotli 压缩文本文件，默认压缩
+    pub fn compression(mut self, enabled: bool) -> StaticFiles {
+        self.compressed = enabled.then(|| Arc::new(Cache::default()));
+        self
+    }
+
+    /// 小于 `min_size` 字节的文件不压缩，`None` 表示不压缩，与
+    /// [`ServerConfig::compress_min_size`](super::ServerConfig::compress_min_size) 的含义相同。默认是 1024
+    pub fn compress_min_size(mut self, min_size: Option<usize>) -> StaticFiles {
+        match min_size {
+            Some(min_size) => {
+                self.compress_min_size = min_size;
+                if self.compressed.is_none() {
+                    self = self.compression(true);
+                }
+            }
+            None => self = self.compression(false),
+        }
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// 回复文档根目录下相对路径为 `path` 的文件。`path` 应该已经做过百分号解码，路由通配符取出的参数就是这样的。
+    pub fn serve(&self, request: &Request, path: &str) -> Response {
+        let mut full = self.root.clone();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return forbidden(),
+                _ if segment.contains(['\\', '\0']) => return forbidden(),
+                _ => full.push(segment),
+            }
+        }
+        let metadata = match fs::metadata(&full) {
+            Ok(metadata) => metadata,
+            Err(err) => return io_error(&err),
+        };
+        //文件本身或者路径上的某个目录可能是指向文档根目录之外的符号链接
+        match (self.root.canonicalize(), full.canonicalize()) {
+            (Ok(root), Ok(real)) if real.starts_with(&root) => {}
+            (Err(err), _) | (_, Err(err)) => return io_error(&err),
+            _ => return forbidden(),
+        }
+
+        if !metadata.is_dir() {
+            return self.serve_file(request, &full, &metadata);
+        }
+        if !request.path().ends_with('/') {
+            let mut location = format!("{}/", request.path());
+            if let Some(query) = request.query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            return Response::new(StatusCode::MOVED_PERMANENTLY).with_header("Location", location);
+        }
+        if let Some(index) = &self.index {
+            let index = full.join(index);
+            if let Ok(metadata) = fs::metadata(&index) {
+                if metadata.is_file() {
+                    return self.serve_file(request, &index, &metadata);
+                }
+            }
+        }
+        if self.listing {
+            return match list_directory(request.path(), &full) {
+                Ok(html) => Response::html(StatusCode::OK, html),
+                Err(err) => io_error(&err),
+            };
+        }
+        Response::text(StatusCode::NOT_FOUND, "404 Not Found\n")
+    }
+
+    fn serve_file(&self, request: &Request, path: &Path, metadata: &Metadata) -> Response {
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+        let mtime = modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        //大小和修改时间都不变时认为内容没有变化
+        let etag = format!("\"{:x}-{:x}\"", len, mtime.as_nanos());
+        let last_modified = modified.map(format_http_date);
+
+        let mut response = Response::new(StatusCode::OK)
+            .with_header("ETag", etag.as_str())
+            .with_header("Accept-Ranges", "bytes");
+        if let Some(last_modified) = &last_modified {
+            response = response.with_header("Last-Modified", last_modified.as_str());
+        }
+        //304 也要带上与完整响应相同的 Vary
+        let mime = mime_type(path);
+        if self.compressed.is_some() && compress::is_compressible(mime) {
+            response = response.with_header("Vary", "Accept-Encoding");
+        }
+
+        //If-None-Match 优先于 If-Modified-Since；HTTP 日期只精确到秒
+        let not_modified = match request.headers.get("if-none-match") {
+            Some(tags) => etag_matches(tags, &etag),
+            None => request
+                .headers
+                .get("if-modified-since")
+                .and_then(parse_http_date)
+                .and_then(|since| since.duration_since(UNIX_EPOCH).ok())
+                .is_some_and(|since| mtime.as_secs() <= since.as_secs()),
+        };
+        if not_modified {
+            response.status = StatusCode::NOT_MODIFIED;
+            return response;
+        }
+
+        response = response.with_header("Content-Type", mime);
+        //If-Range 与当前版本不一致时说明客户端手里的那部分已经过时，要回复整个文件
+        let range = request.headers.get("range").filter(|_| {
+            request.headers.get("if-range").is_none_or(|if_range| {
+                if_range == etag || Some(if_range) == last_modified.as_deref()
+            })
+        });
+        let result = match range.map(|range| parse_range(range, len)) {
+            Some(RangeSpec::Partial(start, end)) => read_range(path, start, end).map(|body| {
+                response.status = StatusCode::PARTIAL_CONTENT;
+                response
+                    .with_header("Content-Range", format!("bytes {}-{}/{}", start, end, len))
+                    .with_body(body)
+            }),
+            Some(RangeSpec::Unsatisfiable) => Ok(Response::text(
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "416 Range Not Satisfiable\n",
+            )
+            .with_header("Content-Range", format!("bytes */{}", len))),
+            Some(RangeSpec::Full) | None => match &self.compressed {
+                Some(cache) => cache.serve(request, response, path, len, self.compress_min_size),
+                None => fs::read(path).map(|body| response.with_body(body)),
+            },
+        };
+        result.unwrap_or_else(|err| io_error(&err))
+    }
+}
+
+//If-None-Match 的值是逗号分隔的实体标签列表或者 `*`，按弱比较的规则忽略 `W/` 前缀
+fn etag_matches(tags: &str, etag: &str) -> bool {
+    tags.split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeSpec {
+    /// 不认识或者不支持的 Range，回复整个文件
+    Full,
+    /// 闭区间 [start, end]
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+fn parse_range(value: &str, len: u64) -> RangeSpec {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeSpec::Full;
+    };
+    if spec.contains(',') {
+        return RangeSpec::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return RangeSpec::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        //bytes=-N 表示最后 N 个字节
+        return match end.parse::<u64>() {
+            Ok(0) => RangeSpec::Unsatisfiable,
+            Ok(_) if len == 0 => RangeSpec::Unsatisfiable,
+            Ok(suffix) => RangeSpec::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => RangeSpec::Full,
+        };
+    }
+    let Ok(start) = start.parse::<u64>() else {
+        return RangeSpec::Full;
+    };
+    let end = if end.is_empty() {
+        u64::MAX
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return RangeSpec::Full,
+        }
+    };
+    if start >= len {
+        return RangeSpec::Unsatisfiable;
+    }
+    RangeSpec::Partial(start, end.min(len - 1))
+}
+
+fn read_range(path: &Path, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut body = Vec::with_capacity((end - start + 1) as usize);
+    file.take(end - start + 1).read_to_end(&mut body)?;
+    Ok(body)
+}
+
+fn list_directory(url_path: &str, dir: &Path) -> io::Result<String> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    names.sort();
+    let title = escape_html(url_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    for name in names {
+        let name = escape_html(&name);
+        html.push_str(&format!("<li><a href=\"{0}\">{0}</a></li>\n", name));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+//把 HTML 中有特殊含义的字符转义，属性值里也可以安全使用
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn forbidden() -> Response {
+    Response::text(StatusCode::FORBIDDEN, "403 Forbidden\n")
+}
+
+fn io_error(err: &io::Error) -> Response {
+    match err.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => {
+            Response::text(StatusCode::NOT_FOUND, "404 Not Found\n")
+        }
+        io::ErrorKind::PermissionDenied => forbidden(),
+        _ => Response::text(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "500 Internal Server Error\n",
+        ),
+    }
+}
+
+/// 根据扩展名（不区分大小写）猜测文件的 MIME 类型，不认识的扩展名返回 `application/octet-stream`
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "br" => "application/x-brotli",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Method, Version};
+    use tempfile::TempDir;
+
+    //每个测试一个独立的临时文档根目录，TempDir 在 drop 时（包括测试 panic 时）删除它
+    fn root() -> TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path();
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(
+            dir.join("logo.png"),
+            [0x89, b'P', b'N', b'G', 0, 0xff, 0x00, 0x0a],
+        )
+        .unwrap();
+        fs::write(dir.join("hello.txt"), "hello, world").unwrap();
+        fs::write(dir.join("docs").join("index.html"), "<h1>docs</h1>").unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        root
+    }
+
+    fn get(target: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request {
+            method: Method::Get,
+            target: target.to_string(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: Vec::new(),
+        };
+        for (name, value) in headers {
+            request.headers.append(name, *value);
+        }
+        request
+    }
+
+    fn serve(files: &StaticFiles, path: &str, headers: &[(&str, &str)]) -> Response {
+        files.serve(&get(&format!("/{}", path), headers), path)
+    }
+
+    #[test]
+    fn serves_binary_files_with_their_mime_type() {
+        let root = root();
+        let files = StaticFiles::new(root.path());
+        let response = serve(&files, "logo.png", &[]);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers.get("content-type"), Some("image/png"));
+        assert_eq!(response.body, [0x89, b'P', b'N', b'G', 0, 0xff, 0x00, 0x0a]);
+        assert_eq!(
+            serve(&files, "missing.css", &[]).status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn refuses_to_leave_the_root() {
+        let root = root();
+        let files = StaticFiles::new(root.path().join("docs"));
+        assert_eq!(
+            serve(&files, "../hello.txt", &[]).status,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            serve(&files, "a/../../hello.txt", &[]).status,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            serve(&files, "..\\hello.txt", &[]).status,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_out_of_the_root() {
+        let root = root();
+        std::os::unix::fs::symlink(
+            root.path().join("hello.txt"),
+            root.path().join("docs").join("escape"),
+        )
+        .unwrap();
+        let files = StaticFiles::new(root.path().join("docs"));
+        assert_eq!(serve(&files, "escape", &[]).status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn conditional_requests_get_304() {
+        let root = root();
+        let files = StaticFiles::new(root.path());
+        let first = serve(&files, "hello.txt", &[]);
+        let etag = first.headers.get("etag").unwrap().to_string();
+        let last_modified = first.headers.get("last-modified").unwrap().to_string();
+
+        let response = serve(
+            &files,
+            "hello.txt",
+            &[("If-None-Match", &format!("\"x\", W/{}", etag))],
+        );
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert!(response.body.is_empty());
+        let response = serve(
+            &files,
+            "hello.txt",
+            &[("If-Modified-Since", &last_modified)],
+        );
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        //If-None-Match 不匹配时不再看 If-Modified-Since
+        let response = serve(
+            &files,
+            "hello.txt",
+            &[
+                ("If-None-Match", "\"stale\""),
+                ("If-Modified-Since", &last_modified),
+            ],
+        );
+        assert_eq!(response.status, StatusCode::OK);
+        let response = serve(
+            &files,
+            "hello.txt",
+            &[("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")],
+        );
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn range_requests() {
+        let root = root();
+        let files = StaticFiles::new(root.path());
+        let response = serve(&files, "hello.txt", &[("Range", "bytes=0-4")]);
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.body, b"hello");
+        assert_eq!(response.headers.get("content-range"), Some("bytes 0-4/12"));
+
+        let response = serve(&files, "hello.txt", &[("Range", "bytes=-5")]);
+        assert_eq!(response.body, b"world");
+        let response = serve(&files, "hello.txt", &[("Range", "bytes=7-100")]);
+        assert_eq!(response.body, b"world");
+
+        let response = serve(&files, "hello.txt", &[("Range", "bytes=12-")]);
+        assert_eq!(response.status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers.get("content-range"), Some("bytes */12"));
+
+        //多个区间和过时的 If-Range 都回复整个文件
+        let response = serve(&files, "hello.txt", &[("Range", "bytes=0-1,3-4")]);
+        assert_eq!(response.status, StatusCode::OK);
+        let response = serve(
+            &files,
+            "hello.txt",
+            &[("Range", "bytes=0-4"), ("If-Range", "\"old\"")],
+        );
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, b"hello, world");
+    }
+
+    #[test]
+    fn directories_redirect_then_serve_their_index() {
+        let root = root();
+        let files = StaticFiles::new(root.path());
+        let response = files.serve(&get("/docs?lang=zh", &[]), "docs");
+        assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers.get("location"), Some("/docs/?lang=zh"));
+
+        let response = files.serve(&get("/docs/", &[]), "docs/");
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, b"<h1>docs</h1>");
+        assert_eq!(
+            files.serve(&get("/empty/", &[]), "empty/").status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn listings_escape_file_names() {
+        let root = root();
+        fs::write(root.path().join("empty").join("<b>.txt"), "").unwrap();
+        let files = StaticFiles::new(root.path()).listing(true);
+        let response = files.serve(&get("/empty/", &[]), "empty/");
+        let html = String::from_utf8(response.body).unwrap();
+        assert!(html.contains("&lt;b&gt;.txt"));
+        assert!(!html.contains("<b>"));
+    }
+
+    fn gunzip(body: &[u8]) -> String {
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(body)
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn compresses_text_files() {
+        let root = root();
+        let text = "body { color: red; }\n".repeat(100);
+        fs::write(root.path().join("site.css"), &text).unwrap();
+        fs::write(root.path().join("big.png"), "x".repeat(4096)).unwrap();
+        let files = StaticFiles::new(root.path());
+        let accept = [("Accept-Encoding", "gzip")];
+
+        let response = serve(&files, "site.css", &accept);
+        assert_eq!(response.headers.get("content-encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("vary"), Some("Accept-Encoding"));
+        let etag = response.headers.get("etag").unwrap().to_string();
+        assert!(etag.starts_with("W/"));
+        assert_eq!(gunzip(&response.body), text);
+        //文件变化之后不能再用缓存的旧副本
+        let changed = "p { margin: 0; }\n".repeat(100);
+        fs::write(root.path().join("site.css"), &changed).unwrap();
+        let response = serve(&files, "site.css", &accept);
+        assert_eq!(gunzip(&response.body), changed);
+        let etag = response.headers.get("etag").unwrap().to_string();
+        //弱 ETag 也能用于条件请求
+        let response = serve(&files, "site.css", &[("If-None-Match", &etag)]);
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers.get("vary"), Some("Accept-Encoding"));
+
+        //不支持压缩的客户端、区间请求和图片都得到原文
+        let response = serve(&files, "site.css", &[]);
+        assert_eq!(response.body, changed.as_bytes());
+        assert!(!response.headers.contains("content-encoding"));
+        let response = serve(&files, "site.css", &[accept[0], ("Range", "bytes=0-3")]);
+        assert_eq!(response.body, b"p { ");
+        let response = serve(&files, "big.png", &accept);
+        assert!(!response.headers.contains("content-encoding"));
+        assert!(!response.headers.contains("vary"));
+
+        let files = files.compression(false);
+        let response = serve(&files, "site.css", &accept);
+        assert_eq!(response.body, changed.as_bytes());
+    }
+
+    #[test]
+    fn honours_compress_min_size() {
+        let root = root();
+        let text = "a { b: c; }\n".repeat(20);
+        fs::write(root.path().join("small.css"), &text).unwrap();
+        let accept = [("Accept-Encoding", "gzip")];
+
+        //默认小于 1024 字节不压缩，server 写出之前也不会再压缩它
+        let files = StaticFiles::new(root.path());
+        let mut response = serve(&files, "small.css", &accept);
+        assert!(!response.headers.contains("content-encoding"));
+        compress::compress_response(&get("/small.css", &accept), &mut response, 16);
+        assert!(!response.headers.contains("content-encoding"));
+
+        let files = files.compress_min_size(Some(16));
+        let response = serve(&files, "small.css", &accept);
+        assert_eq!(gunzip(&response.body), text);
+        let files = files.compress_min_size(None);
+        let response = serve(&files, "small.css", &accept);
+        assert_eq!(response.body, text.as_bytes());
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-0", 10), RangeSpec::Partial(0, 0));
+        assert_eq!(parse_range("bytes=-20", 10), RangeSpec::Partial(0, 9));
+        assert_eq!(parse_range("bytes=-0", 10), RangeSpec::Unsatisfiable);
+        assert_eq!(parse_range("bytes=5-2", 10), RangeSpec::Full);
+        assert_eq!(parse_range("items=0-1", 10), RangeSpec::Full);
+        assert_eq!(parse_range("bytes=0-", 0), RangeSpec::Unsatisfiable);
+    }
+}
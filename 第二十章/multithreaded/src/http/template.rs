@@ -0,0 +1,745 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 01:02:37
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 01:02:37
+ * @Description: 简单的 HTML 模板：变量、循环、条件、包含和自动转义，模板从文档根目录读取并缓存
+ */
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::static_files::escape_html;
+
+//server 只能回复 hello.html 和 404.html 这样内容固定的页面，想在页面里显示请求的路径就只能在代码里拼 HTML，
+//一不小心就把请求里的 `<script>` 原样写进了页面。Templates 读取文档根目录下的模板文件，语法是：
+// 1. {{ user.name }}：输出变量，`.` 取映射的字段或者列表的下标。输出的内容按 HTML 转义，
+//    确实要输出 HTML 时写成 {{ html | raw }}。不存在的变量输出为空；列表和映射不能直接输出，也输出为空；
+// 2. {% if user %}...{% else %}...{% endif %}：false、0、空字符串、空列表、空映射和不存在的变量都是假，
+//    条件前面可以加 not；
+// 3. {% for item in items %}...{% endfor %}：循环体里还可以用 loop.index（从 1 开始）、loop.first 和 loop.last；
+// 4. {% include "header.html" %}：在这里渲染另一个模板，它看到的变量和当前位置一样。模板名都相对于文档根目录；
+// 5. {# 注释 #}：不会输出。
+//
+//{% %} 标签和注释后面紧跟的换行不输出，所以标签可以单独占一行。
+//
+//解析好的模板按文件名缓存，再次渲染时只检查文件的修改时间，文件改过了才重新读取和解析，所以修改模板不用重启 server。
+
+//include 嵌套的最大层数，模板互相包含时报错而不是无限递归
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// 模板中变量的值
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    //if 的条件是否成立
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Int(value) => *value != 0,
+            Value::Str(value) => !value.is_empty(),
+            Value::List(items) => !items.is_empty(),
+            Value::Map(fields) => !fields.is_empty(),
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(fields) => fields.get(key),
+            Value::List(items) => key.parse().ok().and_then(|index: usize| items.get(index)),
+            _ => None,
+        }
+    }
+
+    fn write_to(&self, out: &mut String, raw: bool) {
+        match self {
+            Value::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Value::Int(value) => out.push_str(&value.to_string()),
+            Value::Str(value) if raw => out.push_str(value),
+            Value::Str(value) => out.push_str(&escape_html(value)),
+            Value::Null | Value::List(_) | Value::Map(_) => {}
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Int(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Value {
+        Value::Int(value.into())
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Value {
+        Value::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::Str(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Value {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<Context> for Value {
+    fn from(context: Context) -> Value {
+        Value::Map(context.0)
+    }
+}
+
+/// 渲染模板时可以使用的变量。也可以转成 [`Value::Map`]，作为另一个变量的字段或者列表的元素
+///
+/// ```
+/// use multithreaded::http::Context;
+///
+/// let link = Context::new().with("href", "/").with("title", "Home");
+/// let context = Context::new().with("path", "/missing").with("links", vec![link]);
+/// # let _ = context;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context(BTreeMap<String, Value>);
+
+impl Context {
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// 设置变量 `name`，返回自身以便连续调用
+    pub fn with(mut self, name: &str, value: impl Into<Value>) -> Context {
+        self.insert(name, value);
+        self
+    }
+
+    pub fn insert(&mut self, name: &str, value: impl Into<Value>) {
+        self.0.insert(name.to_string(), value.into());
+    }
+}
+
+/// 渲染模板失败的原因
+#[derive(Debug)]
+pub enum TemplateError {
+    /// 模板名是绝对路径或者含有 `..`，会跳出文档根目录
+    InvalidName(String),
+    /// 读取模板文件失败
+    Io(String, io::Error),
+    /// 模板有语法错误，带有模板名和行号
+    Syntax {
+        name: String,
+        line: usize,
+        message: String,
+    },
+    /// for 循环的对象不是列表
+    NotAList {
+        name: String,
+        line: usize,
+        variable: String,
+    },
+    /// include 嵌套超过了 16 层，多半是模板互相包含
+    TooDeep(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::InvalidName(name) => write!(f, "invalid template name {:?}", name),
+            TemplateError::Io(name, err) => write!(f, "cannot read template {}: {}", name, err),
+            TemplateError::Syntax {
+                name,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", name, line, message),
+            TemplateError::NotAList {
+                name,
+                line,
+                variable,
+            } => write!(f, "{}:{}: `{}` is not a list", name, line, variable),
+            TemplateError::TooDeep(name) => {
+                write!(f, "includes nested too deeply while rendering {}", name)
+            }
+        }
+    }
+}
+
+impl Error for TemplateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TemplateError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// 从文档根目录读取并缓存模板
+///
+/// ```no_run
+/// use multithreaded::http::{Context, Response, Router, StatusCode, Templates};
+///
+/// let templates = Templates::new("public");
+/// let router = Router::new().not_found(move |request| {
+///     let context = Context::new().with("path", request.path());
+///     match templates.render("templates/404.html", &context) {
+///         Ok(html) => Response::html(StatusCode::NOT_FOUND, html),
+///         Err(_) => Response::error_page(StatusCode::INTERNAL_SERVER_ERROR),
+///     }
+/// });
+/// # let _ = router;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Templates {
+    root: PathBuf,
+    //克隆出来的 Templates 共用同一个缓存
+    cache: Arc<Mutex<HashMap<String, Cached>>>,
+}
+
+#[derive(Debug)]
+struct Cached {
+    modified: Option<SystemTime>,
+    template: Arc<Template>,
+}
+
+impl Templates {
+    /// 模板名都相对于 `root`
+    pub fn new(root: impl Into<PathBuf>) -> Templates {
+        Templates {
+            root: root.into(),
+            cache: Arc::default(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// 用 `context` 中的变量渲染模板 `name`
+    pub fn render(&self, name: &str, context: &Context) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        let mut scope = Scope {
+            context,
+            locals: Vec::new(),
+        };
+        self.render_template(name, &mut scope, &mut out, 0)?;
+        Ok(out)
+    }
+
+    fn render_template(
+        &self,
+        name: &str,
+        scope: &mut Scope,
+        out: &mut String,
+        depth: usize,
+    ) -> Result<(), TemplateError> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(TemplateError::TooDeep(name.to_string()));
+        }
+        let template = self.load(name)?;
+        self.render_nodes(&template, &template.nodes, scope, out, depth)
+    }
+
+    fn render_nodes(
+        &self,
+        template: &Template,
+        nodes: &[Node],
+        scope: &mut Scope,
+        out: &mut String,
+        depth: usize,
+    ) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Var { path, raw } => {
+                    if let Some(value) = scope.lookup(path) {
+                        value.write_to(out, *raw);
+                    }
+                }
+                Node::If {
+                    negate,
+                    path,
+                    then,
+                    otherwise,
+                } => {
+                    let truthy = scope.lookup(path).is_some_and(Value::is_truthy);
+                    let branch = if truthy != *negate { then } else { otherwise };
+                    self.render_nodes(template, branch, scope, out, depth)?;
+                }
+                Node::For {
+                    var,
+                    path,
+                    body,
+                    line,
+                } => {
+                    let items = match scope.lookup(path) {
+                        None | Some(Value::Null) => continue,
+                        Some(Value::List(items)) => items.clone(),
+                        Some(_) => {
+                            return Err(TemplateError::NotAList {
+                                name: template.name.clone(),
+                                line: *line,
+                                variable: path.join("."),
+                            })
+                        }
+                    };
+                    let count = items.len();
+                    for (index, item) in items.into_iter().enumerate() {
+                        let info = Context::new()
+                            .with("index", index + 1)
+                            .with("first", index == 0)
+                            .with("last", index + 1 == count);
+                        scope.locals.push(("loop".to_string(), info.into()));
+                        scope.locals.push((var.clone(), item));
+                        let result = self.render_nodes(template, body, scope, out, depth);
+                        scope.locals.truncate(scope.locals.len() - 2);
+                        result?;
+                    }
+                }
+                Node::Include(name) => self.render_template(name, scope, out, depth + 1)?,
+            }
+        }
+        Ok(())
+    }
+
+    //从缓存中取出模板；文件改过了（或者还没有缓存）就重新读取和解析
+    fn load(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        let inside_root = Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if name.is_empty() || !inside_root {
+            return Err(TemplateError::InvalidName(name.to_string()));
+        }
+        let path = self.root.join(name);
+        let io_error = |err| TemplateError::Io(name.to_string(), err);
+        let modified = fs::metadata(&path).map_err(io_error)?.modified().ok();
+        if let Some(cached) = self.cache.lock().unwrap().get(name) {
+            if modified.is_some() && cached.modified == modified {
+                return Ok(Arc::clone(&cached.template));
+            }
+        }
+        //读文件和解析的时候不持有锁，其他模板照常渲染
+        let source = fs::read_to_string(&path).map_err(io_error)?;
+        let template = Arc::new(Template::parse(name, &source)?);
+        self.cache.lock().unwrap().insert(
+            name.to_string(),
+            Cached {
+                modified,
+                template: Arc::clone(&template),
+            },
+        );
+        Ok(template)
+    }
+}
+
+//渲染时可见的变量：循环变量在前面，遮住同名的 context 变量
+struct Scope<'a> {
+    context: &'a Context,
+    locals: Vec<(String, Value)>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, path: &[String]) -> Option<&Value> {
+        let (first, rest) = path.split_first()?;
+        let value = self
+            .locals
+            .iter()
+            .rev()
+            .find(|(name, _)| name == first)
+            .map(|(_, value)| value)
+            .or_else(|| self.context.0.get(first))?;
+        rest.iter().try_fold(value, |value, key| value.field(key))
+    }
+}
+
+#[derive(Debug)]
+struct Template {
+    name: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var {
+        path: Vec<String>,
+        raw: bool,
+    },
+    If {
+        negate: bool,
+        path: Vec<String>,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        var: String,
+        path: Vec<String>,
+        body: Vec<Node>,
+        line: usize,
+    },
+    Include(String),
+}
+
+//模板源码切成的片段：普通文本、{{ }} 和 {% %}，后两者带有所在的行号。注释在切分时就丢掉了
+enum Token<'a> {
+    Text(&'a str),
+    Var(&'a str, usize),
+    Tag(&'a str, usize),
+}
+
+impl Template {
+    fn parse(name: &str, source: &str) -> Result<Template, TemplateError> {
+        let syntax = |line, message: String| TemplateError::Syntax {
+            name: name.to_string(),
+            line,
+            message,
+        };
+        let tokens = tokenize(source).map_err(|(line, message)| syntax(line, message))?;
+        let mut tokens = tokens.into_iter();
+        let (nodes, _) =
+            parse_block(&mut tokens, None).map_err(|(line, message)| syntax(line, message))?;
+        Ok(Template {
+            name: name.to_string(),
+            nodes,
+        })
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+    while let Some(start) = find_open(rest) {
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+        line += rest[..start].matches('\n').count();
+        let close = match &rest[start + 1..start + 2] {
+            "{" => "}}",
+            "%" => "%}",
+            _ => "#}",
+        };
+        let inner = &rest[start + 2..];
+        let Some(end) = inner.find(close) else {
+            return Err((line, format!("missing closing {}", close)));
+        };
+        let content = inner[..end].trim();
+        match close {
+            "}}" => tokens.push(Token::Var(content, line)),
+            "%}" => tokens.push(Token::Tag(content, line)),
+            _ => {}
+        }
+        line += inner[..end].matches('\n').count();
+        rest = &inner[end + 2..];
+        //标签和注释后面紧跟的一个换行不输出，单独占一行的标签不会在页面里留下空行
+        if close != "}}" {
+            if let Some(after) = rest
+                .strip_prefix('\n')
+                .or_else(|| rest.strip_prefix("\r\n"))
+            {
+                line += 1;
+                rest = after;
+            }
+        }
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+    Ok(tokens)
+}
+
+//下一个 {{、{% 或 {# 的位置
+fn find_open(text: &str) -> Option<usize> {
+    text.match_indices('{')
+        .map(|(index, _)| index)
+        .find(|&index| matches!(text.as_bytes().get(index + 1), Some(b'{' | b'%' | b'#')))
+}
+
+//解析到 `until` 中的某个结束标签为止，返回解析出的节点和遇到的结束标签。
+//`until` 为 None 时解析到模板末尾；否则它是开始标签的名字、行号和可以结束它的标签
+type Until<'a> = (&'a str, usize, &'a [&'a str]);
+
+fn parse_block<'a>(
+    tokens: &mut impl Iterator<Item = Token<'a>>,
+    until: Option<Until>,
+) -> Result<(Vec<Node>, &'a str), (usize, String)> {
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        let (content, line) = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text.to_string()));
+                continue;
+            }
+            Token::Var(content, line) => {
+                let (path, filter) = match content.split_once('|') {
+                    Some((path, filter)) => (path.trim(), Some(filter.trim())),
+                    None => (content, None),
+                };
+                let raw = match filter {
+                    None => false,
+                    Some("raw") => true,
+                    Some(filter) => return Err((line, format!("unknown filter `{}`", filter))),
+                };
+                let path = parse_path(path).ok_or_else(|| bad_expression(line, content))?;
+                nodes.push(Node::Var { path, raw });
+                continue;
+            }
+            Token::Tag(content, line) => (content, line),
+        };
+        let words: Vec<&str> = content.split_whitespace().collect();
+        match words.as_slice() {
+            [word] if until.is_some_and(|(_, _, ends)| ends.contains(word)) => {
+                return Ok((nodes, word));
+            }
+            ["if", condition @ ..] => {
+                let (negate, path) = match condition {
+                    ["not", path] => (true, *path),
+                    [path] => (false, *path),
+                    _ => return Err(bad_expression(line, content)),
+                };
+                let path = parse_path(path).ok_or_else(|| bad_expression(line, content))?;
+                let (then, end) = parse_block(tokens, Some(("if", line, &["else", "endif"])))?;
+                let otherwise = if end == "else" {
+                    parse_block(tokens, Some(("if", line, &["endif"])))?.0
+                } else {
+                    Vec::new()
+                };
+                nodes.push(Node::If {
+                    negate,
+                    path,
+                    then,
+                    otherwise,
+                });
+            }
+            ["for", var, "in", path] => {
+                if parse_path(var).is_none_or(|path| path.len() != 1) {
+                    return Err(bad_expression(line, content));
+                }
+                let path = parse_path(path).ok_or_else(|| bad_expression(line, content))?;
+                let (body, _) = parse_block(tokens, Some(("for", line, &["endfor"])))?;
+                nodes.push(Node::For {
+                    var: var.to_string(),
+                    path,
+                    body,
+                    line,
+                });
+            }
+            ["include", name] => {
+                let name = name
+                    .strip_prefix('"')
+                    .and_then(|name| name.strip_suffix('"'))
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| bad_expression(line, content))?;
+                nodes.push(Node::Include(name.to_string()));
+            }
+            _ => return Err((line, format!("unexpected {{% {} %}}", content))),
+        }
+    }
+    match until {
+        None => Ok((nodes, "")),
+        Some((tag, line, ends)) => Err((
+            line,
+            format!(
+                "{{% {} %}} is missing {{% {} %}}",
+                tag,
+                ends[ends.len() - 1]
+            ),
+        )),
+    }
+}
+
+//user.name 这样用 `.` 分隔的变量名，每一段由字母、数字和下划线组成
+fn parse_path(text: &str) -> Option<Vec<String>> {
+    let path: Vec<String> = text.trim().split('.').map(str::to_string).collect();
+    let valid = path.iter().all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    });
+    valid.then_some(path)
+}
+
+fn bad_expression(line: usize, content: &str) -> (usize, String) {
+    (line, format!("invalid expression `{}`", content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    //每个测试一个独立的临时模板目录，TempDir 在 drop 时（包括测试 panic 时）删除它
+    struct Root(TempDir);
+
+    impl Root {
+        fn new(files: &[(&str, &str)]) -> Root {
+            let dir = tempfile::tempdir().unwrap();
+            fs::create_dir_all(dir.path().join("partials")).unwrap();
+            for (name, source) in files {
+                fs::write(dir.path().join(name), source).unwrap();
+            }
+            Root(dir)
+        }
+
+        fn path(&self) -> &Path {
+            self.0.path()
+        }
+
+        fn render(&self, name: &str, context: &Context) -> Result<String, TemplateError> {
+            Templates::new(self.path()).render(name, context)
+        }
+    }
+
+    #[test]
+    fn variables_are_escaped_unless_raw() {
+        let root = Root::new(&[(
+            "page.html",
+            "<p>{{ path }}</p>{{ html | raw }}{{ user.name }} {{ count }} {{ missing }}{# hidden #}",
+        )]);
+        let context = Context::new()
+            .with("path", "/<script>alert('x')</script>&")
+            .with("html", "<b>bold</b>")
+            .with("user", Context::new().with("name", "ferris"))
+            .with("count", 3);
+        assert_eq!(
+            root.render("page.html", &context).unwrap(),
+            "<p>/&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;&amp;</p><b>bold</b>ferris 3 "
+        );
+    }
+
+    #[test]
+    fn loops_and_conditionals() {
+        let root = Root::new(&[(
+            "list.html",
+            "{% for item in items %}\n{% if not loop.first %}, {% endif %}{{ loop.index }}.{{ item.name }}\
+             {% if item.done %}✓{% else %}✗{% endif %}{% endfor %}\
+             {% if empty %}never{% else %}|{% endif %}{% for x in missing %}never{% endfor %}{{ items.1.name }}",
+        )]);
+        let item = |name: &str, done| Context::new().with("name", name).with("done", done);
+        let context = Context::new()
+            .with("items", vec![item("a", true), item("b", false)])
+            .with("empty", Vec::<String>::new());
+        assert_eq!(root.render("list.html", &context).unwrap(), "1.a✓, 2.b✗|b");
+    }
+
+    #[test]
+    fn includes_see_the_same_variables() {
+        let root = Root::new(&[
+            (
+                "page.html",
+                "{% for name in names %}{% include \"partials/item.html\" %}{% endfor %}",
+            ),
+            ("partials/item.html", "<li>{{ name }} of {{ total }}</li>"),
+            ("loop.html", "{% include \"loop.html\" %}"),
+            ("escape.html", "{% include \"../secret\" %}"),
+        ]);
+        let context = Context::new()
+            .with("names", vec!["a", "<b>"])
+            .with("total", 2);
+        assert_eq!(
+            root.render("page.html", &context).unwrap(),
+            "<li>a of 2</li><li>&lt;b&gt; of 2</li>"
+        );
+        assert!(matches!(
+            root.render("loop.html", &context),
+            Err(TemplateError::TooDeep(_))
+        ));
+        assert!(matches!(
+            root.render("escape.html", &context),
+            Err(TemplateError::InvalidName(name)) if name == "../secret"
+        ));
+        assert!(matches!(
+            root.render("missing.html", &context),
+            Err(TemplateError::Io(..))
+        ));
+    }
+
+    #[test]
+    fn syntax_errors_report_the_line() {
+        let cases = [
+            (
+                "<p>\n{% if x %}\nopen",
+                "bad.html:2: {% if %} is missing {% endif %}",
+            ),
+            ("\n\n{{ a b }}", "bad.html:3: invalid expression `a b`"),
+            ("{{ x | upper }}", "bad.html:1: unknown filter `upper`"),
+            ("{% endfor %}", "bad.html:1: unexpected {% endfor %}"),
+            ("a\n{{ x", "bad.html:2: missing closing }}"),
+        ];
+        for (source, message) in cases {
+            let root = Root::new(&[("bad.html", source)]);
+            let err = root.render("bad.html", &Context::new()).unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+        let root = Root::new(&[("for.html", "\n{% for x in name %}{% endfor %}")]);
+        let err = root
+            .render("for.html", &Context::new().with("name", "ferris"))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "for.html:2: `name` is not a list");
+    }
+
+    #[test]
+    fn templates_are_cached_until_the_file_changes() {
+        let root = Root::new(&[("page.html", "one")]);
+        let templates = Templates::new(root.path());
+        assert_eq!(
+            templates.render("page.html", &Context::new()).unwrap(),
+            "one"
+        );
+        let first = templates.load("page.html").unwrap();
+        assert!(Arc::ptr_eq(&first, &templates.load("page.html").unwrap()));
+
+        //修改时间的精度可能不够区分两次写入，直接把它设成另一个时间
+        let file = fs::File::options()
+            .write(true)
+            .truncate(true)
+            .open(root.path().join("page.html"))
+            .unwrap();
+        io::Write::write_all(&mut &file, b"two").unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(
+            templates.render("page.html", &Context::new()).unwrap(),
+            "two"
+        );
+        assert!(!Arc::ptr_eq(&first, &templates.load("page.html").unwrap()));
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 22:14:05
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 22:14:05
+ * @Description: 用 rustls 直接提供 HTTPS：读取 PEM 证书和私钥，把接受的连接包装成 TLS 会话
+ */
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConnection, StreamOwned};
+
+//配置了证书和私钥时，server 接受的每个 TcpStream 先包装成 rustls 的会话再交给解析器：
+//解析器读到的是解密后的明文，写出的响应由 rustls 加密后再写进套接字。握手在第一次读的时候进行。
+//ALPN 只提供 http/1.1，所以浏览器不会尝试 HTTP/2。
+
+/// HTTPS 的配置，由证书链和私钥创建，可以在多个连接之间共享
+#[derive(Clone)]
+pub struct TlsConfig(Arc<rustls::ServerConfig>);
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("alpn_protocols", &self.0.alpn_protocols)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 加载证书或者私钥时的错误
+#[derive(Debug)]
+pub enum TlsError {
+    /// 读取文件失败
+    Io(PathBuf, io::Error),
+    /// PEM 中没有证书或者私钥，或者格式错误
+    Pem(String),
+    /// rustls 不接受这对证书和私钥，例如私钥与证书不匹配
+    Rustls(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            TlsError::Pem(message) => f.write_str(message),
+            TlsError::Rustls(err) => write!(f, "invalid certificate or key: {}", err),
+        }
+    }
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TlsError::Io(_, err) => Some(err),
+            TlsError::Rustls(err) => Some(err),
+            TlsError::Pem(_) => None,
+        }
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(err: rustls::Error) -> TlsError {
+        TlsError::Rustls(err)
+    }
+}
+
+impl TlsConfig {
+    /// 从 PEM 文件读取证书链（叶子证书在前）和私钥（PKCS#8、PKCS#1 或 SEC1）
+    pub fn from_pem_files(
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<TlsConfig, TlsError> {
+        let read = |path: &Path| fs::read(path).map_err(|err| TlsError::Io(path.into(), err));
+        TlsConfig::from_pem(&read(cert.as_ref())?, &read(key.as_ref())?)
+    }
+
+    /// 从 PEM 格式的证书链和私钥创建
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> Result<TlsConfig, TlsError> {
+        let certs = CertificateDer::pem_slice_iter(cert)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| TlsError::Pem(format!("invalid certificate PEM: {}", err)))?;
+        if certs.is_empty() {
+            return Err(TlsError::Pem("no certificate found in PEM".to_string()));
+        }
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|err| TlsError::Pem(format!("invalid private key PEM: {}", err)))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsConfig(Arc::new(config)))
+    }
+}
+
+/// 一个连接：明文的套接字，或者套接字上的 TLS 会话
+pub(crate) enum Stream<S: Read + Write> {
+    Plain(S),
+    Tls(Box<StreamOwned<ServerConnection, S>>),
+}
+
+impl<S: Read + Write> Stream<S> {
+    pub(crate) fn new(sock: S, tls: Option<&TlsConfig>) -> io::Result<Stream<S>> {
+        match tls {
+            None => Ok(Stream::Plain(sock)),
+            Some(config) => {
+                let conn =
+                    ServerConnection::new(Arc::clone(&config.0)).map_err(io::Error::other)?;
+                Ok(Stream::Tls(Box::new(StreamOwned::new(conn, sock))))
+            }
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &S {
+        match self {
+            Stream::Plain(sock) => sock,
+            Stream::Tls(tls) => tls.get_ref(),
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut S {
+        match self {
+            Stream::Plain(sock) => sock,
+            Stream::Tls(tls) => tls.get_mut(),
+        }
+    }
+
+    /// TLS 会话里是否还有已经收到、没有读走的明文。这些数据已经不在套接字里，看套接字是看不出来的。
+    pub(crate) fn has_buffered(&mut self) -> bool {
+        match self {
+            Stream::Plain(_) => false,
+            Stream::Tls(tls) => tls
+                .conn
+                .process_new_packets()
+                .is_ok_and(|state| state.plaintext_bytes_to_read() > 0),
+        }
+    }
+}
+
+impl<S: Read + Write> Read for Stream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(sock) => sock.read(buf),
+            Stream::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl<S: Read + Write> Write for Stream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(sock) => sock.write(buf),
+            Stream::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    //对于 TLS，还要把 rustls 缓冲的密文写进套接字
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(sock) => sock.flush(),
+            Stream::Tls(tls) => tls.flush(),
+        }
+    }
+}
+
+impl<S: Read + Write> Drop for Stream<S> {
+    //关闭连接前发送 close_notify，客户端据此知道响应没有被截断。写不出去就算了，反正连接就要关闭
+    fn drop(&mut self) {
+        if let Stream::Tls(tls) = self {
+            if tls.conn.is_handshaking() {
+                return;
+            }
+            tls.conn.send_close_notify();
+            let StreamOwned { conn, sock } = &mut **tls;
+            while conn.wants_write() {
+                if !matches!(conn.write_tls(sock), Ok(n) if n > 0) {
+                    break;
+                }
+            }
+        }
+    }
+}
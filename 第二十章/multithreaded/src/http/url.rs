@@ -0,0 +1,51 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 18:05:37
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 18:05:37
+ * @Description: URL 中的百分号编码
+ */
+
+/// 解码 `%XX` 形式的百分号编码。`%` 后面不是两位十六进制数，或者解码的结果不是合法的 UTF-8 时返回 `None`。
+///
+/// `+` 保持原样，表单编码中 `+` 表示空格的规则由表单的解析负责。
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            let hex = std::str::from_utf8(hex).ok()?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("a%20b").as_deref(), Some("a b"));
+        assert_eq!(percent_decode("j%C3%B6rg").as_deref(), Some("jörg"));
+        assert_eq!(percent_decode("1+1").as_deref(), Some("1+1"));
+    }
+
+    #[test]
+    fn rejects_broken_escapes() {
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+}
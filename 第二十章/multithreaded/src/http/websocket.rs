@@ -0,0 +1,950 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 23:37:14
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 03:52:36
+ * @Description: WebSocket（RFC 6455）：握手、帧的编解码、分片、ping/pong 和关闭
+ */
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+use super::server::Stop;
+use super::tls::Stream;
+use super::{ConnectionError, Request, Response, StatusCode};
+
+//HTTP 的一问一答只能由客户端发起，服务端想推送数据只能让客户端不停地轮询。
+//客户端在 GET 请求里带上 `Upgrade: websocket`，服务端回复 101 之后，这个 TCP 连接就不再说 HTTP，
+//而是双方都可以随时发送的 WebSocket 帧：
+// 1. 握手：用 Sec-WebSocket-Key 拼上固定的 GUID 做 SHA-1，再 base64 编码作为 Sec-WebSocket-Accept 回复；
+// 2. 帧：FIN 位、操作码、长度（7 位，或者 126/127 之后跟 16/64 位）、掩码和载荷。客户端发来的帧必须加掩码，服务端发出的不加；
+// 3. 一条消息可以分成多个帧（第一帧是 Text/Binary，后面是 Continuation，最后一帧 FIN=1），控制帧可以插在分片之间；
+// 4. 收到 Ping 自动回复 Pong；收到 Close 回复 Close 后结束；违反协议时发送带状态码（1002、1007、1009）的 Close。
+//
+//升级之后的连接交给 Router::websocket 注册的处理函数，在线程池上运行直到处理函数返回，这期间一直占用一个 worker。
+//读和写分成两半：recv 直接在复制出来的套接字上阻塞地读原始字节，不持有任何锁，读到之后才短暂地锁住连接把它们换成明文
+//（TLS 会话的状态读写共用）；发送只在写的时候锁住连接，所以 WebSocketSender 不会等着一个没有数据的读。
+//读的时候每隔 POLL 醒来一次，server 关闭时发送 1001 结束会话。
+
+/// 握手时与 `Sec-WebSocket-Key` 拼接的固定 GUID
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// 读操作最多阻塞多久就检查一次 server 是否正在关闭
+const POLL: Duration = Duration::from_millis(100);
+
+/// 一条消息默认的大小上限
+pub const DEFAULT_MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+/// WebSocket 路由的处理函数，参数是升级请求（带有路径参数）和升级后的连接
+pub type WebSocketHandler = dyn Fn(&Request, WebSocket) + Send + Sync;
+
+/// 可以承载 WebSocket 的连接：TcpStream，或者 TLS 会话。
+/// 读不经过它，而是在另一份套接字上读出原始字节，再交给 [`decode`](Socket::decode) 变成明文
+pub(crate) trait Socket: Write + Send {
+    /// 把从套接字上读到的原始字节解码成明文追加到 `plain`。`raw` 为空时只取出连接里已经缓冲的明文
+    fn decode(&mut self, raw: &[u8], plain: &mut Vec<u8>) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn decode(&mut self, raw: &[u8], plain: &mut Vec<u8>) -> io::Result<()> {
+        plain.extend_from_slice(raw);
+        Ok(())
+    }
+}
+
+impl<S: Read + Write + Send> Socket for Stream<S> {
+    fn decode(&mut self, mut raw: &[u8], plain: &mut Vec<u8>) -> io::Result<()> {
+        let tls = match self {
+            Stream::Plain(_) => {
+                plain.extend_from_slice(raw);
+                return Ok(());
+            }
+            Stream::Tls(tls) => tls,
+        };
+        loop {
+            if !raw.is_empty() {
+                tls.conn.read_tls(&mut raw)?;
+            }
+            let state = match tls.conn.process_new_packets() {
+                Ok(state) => state,
+                Err(err) => {
+                    //把 rustls 准备好的告警发给对方
+                    let _ = tls.flush();
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+            };
+            let start = plain.len();
+            plain.resize(start + state.plaintext_bytes_to_read(), 0);
+            tls.conn.reader().read_exact(&mut plain[start..])?;
+            if raw.is_empty() {
+                break;
+            }
+        }
+        //会话可能需要回复（例如 KeyUpdate）
+        tls.flush()
+    }
+}
+
+/// 一条完整的消息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// 常用的关闭状态码
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED: u16 = 1003;
+    pub const INVALID_DATA: u16 = 1007;
+    pub const POLICY: u16 = 1008;
+    pub const TOO_LARGE: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+}
+
+/// WebSocket 连接上的错误
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(io::Error),
+    /// 对方违反了协议，已经向它发送了带有这个状态码的关闭帧
+    Protocol(u16, &'static str),
+    /// 连接已经关闭，不能再发送
+    Closed,
+    /// 握手失败（客户端）
+    Handshake(String),
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Io(err) => write!(f, "websocket I/O error: {}", err),
+            WebSocketError::Protocol(code, message) => {
+                write!(f, "websocket protocol error ({}): {}", code, message)
+            }
+            WebSocketError::Closed => f.write_str("websocket is closed"),
+            WebSocketError::Handshake(message) => {
+                write!(f, "websocket handshake failed: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for WebSocketError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WebSocketError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(err: io::Error) -> WebSocketError {
+        WebSocketError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Opcode> {
+        Some(match value {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xa => Opcode::Pong,
+            _ => return None,
+        })
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    fin: bool,
+    opcode: Opcode,
+    payload: Vec<u8>,
+}
+
+//编码一个帧，`mask` 不为 None 时给载荷加掩码（客户端发出的帧）
+fn encode_frame(fin: bool, opcode: Opcode, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(if fin { 0x80 } else { 0 } | opcode.as_u8());
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => frame.push(mask_bit | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(key) => {
+            frame.extend_from_slice(&key);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+//从 buf 开头解码一个帧，返回帧和它占用的字节数；数据还不够一个帧时返回 None。
+//`masked` 是对方的帧是否应当带掩码，`max` 是载荷的上限
+fn decode_frame(
+    buf: &[u8],
+    masked: bool,
+    max: usize,
+) -> Result<Option<(Frame, usize)>, WebSocketError> {
+    use close_code::{PROTOCOL_ERROR, TOO_LARGE};
+
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    if buf[0] & 0x70 != 0 {
+        return Err(WebSocketError::Protocol(
+            PROTOCOL_ERROR,
+            "reserved bits set",
+        ));
+    }
+    let opcode = Opcode::from_u8(buf[0] & 0x0f)
+        .ok_or(WebSocketError::Protocol(PROTOCOL_ERROR, "unknown opcode"))?;
+    if (buf[1] & 0x80 != 0) != masked {
+        let message = if masked {
+            "client frames must be masked"
+        } else {
+            "server frames must not be masked"
+        };
+        return Err(WebSocketError::Protocol(PROTOCOL_ERROR, message));
+    }
+    let (len, mut pos) = match buf[1] & 0x7f {
+        126 => match buf.get(2..4) {
+            Some(bytes) => (u16::from_be_bytes([bytes[0], bytes[1]]) as u64, 4),
+            None => return Ok(None),
+        },
+        127 => match buf.get(2..10) {
+            Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap()), 10),
+            None => return Ok(None),
+        },
+        len => (len as u64, 2),
+    };
+    if opcode.is_control() && (len > 125 || !fin) {
+        return Err(WebSocketError::Protocol(
+            PROTOCOL_ERROR,
+            "control frames must be short and unfragmented",
+        ));
+    }
+    if len > max as u64 {
+        return Err(WebSocketError::Protocol(TOO_LARGE, "frame too large"));
+    }
+    let len = len as usize;
+    let key = if masked {
+        let Some(key) = buf.get(pos..pos + 4) else {
+            return Ok(None);
+        };
+        pos += 4;
+        Some([key[0], key[1], key[2], key[3]])
+    } else {
+        None
+    };
+    let Some(payload) = buf.get(pos..pos + len) else {
+        return Ok(None);
+    };
+    let payload = match key {
+        Some(key) => payload
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ key[i % 4])
+            .collect(),
+        None => payload.to_vec(),
+    };
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        pos + len,
+    )))
+}
+
+/// 由 `Sec-WebSocket-Key` 计算 `Sec-WebSocket-Accept`
+///
+/// ```
+/// use multithreaded::http::websocket_accept_key;
+///
+/// //RFC 6455 第 1.3 节的例子
+/// assert_eq!(
+///     websocket_accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+///     "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+/// );
+/// ```
+pub fn websocket_accept_key(key: &str) -> String {
+    let digest = Sha1::new()
+        .chain_update(key.as_bytes())
+        .chain_update(GUID.as_bytes())
+        .finalize();
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+/// 检查升级请求，合法时回复 101，否则回复 400 或者 426
+pub(crate) fn handshake(request: &Request) -> Response {
+    let headers = &request.headers;
+    if !headers.has_token("upgrade", "websocket") || !headers.has_token("connection", "upgrade") {
+        return Response::text(
+            StatusCode::UPGRADE_REQUIRED,
+            "426 Upgrade Required: this resource only speaks WebSocket\n",
+        )
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade");
+    }
+    if headers.get("sec-websocket-version").map(str::trim) != Some("13") {
+        return Response::text(
+            StatusCode::UPGRADE_REQUIRED,
+            "426 Upgrade Required: unsupported WebSocket version\n",
+        )
+        .with_header("Sec-WebSocket-Version", "13");
+    }
+    let key = headers
+        .get("sec-websocket-key")
+        .map(str::trim)
+        .unwrap_or("");
+    let valid_key = base64::engine::general_purpose::STANDARD
+        .decode(key)
+        .is_ok_and(|nonce| nonce.len() == 16);
+    if request.method != super::Method::Get
+        || request.version != super::Version::Http11
+        || !valid_key
+    {
+        return Response::text(
+            StatusCode::BAD_REQUEST,
+            "400 Bad Request: invalid WebSocket handshake\n",
+        );
+    }
+    Response::new(StatusCode::SWITCHING_PROTOCOLS)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", websocket_accept_key(key))
+}
+
+//读写两端共用的连接状态
+struct Shared {
+    //只在写和解码时短暂地锁住，读的时候不持有
+    socket: Mutex<Box<dyn Socket>>,
+    //已经发送了关闭帧，之后不能再发送任何帧
+    close_sent: AtomicBool,
+    //客户端发出的帧要加掩码
+    client: bool,
+}
+
+impl Shared {
+    fn send_frame(&self, fin: bool, opcode: Opcode, payload: &[u8]) -> Result<(), WebSocketError> {
+        let mask = self.client.then(|| {
+            let [a, b, c, d, ..] = random_u64().to_le_bytes();
+            [a, b, c, d]
+        });
+        let frame = encode_frame(fin, opcode, payload, mask);
+        let mut socket = self.socket.lock().unwrap();
+        //检查和写都在锁里，关闭帧之后不会再有别的帧
+        if self.close_sent.load(Ordering::SeqCst) {
+            return Err(WebSocketError::Closed);
+        }
+        if opcode == Opcode::Close {
+            self.close_sent.store(true, Ordering::SeqCst);
+        }
+        socket.write_all(&frame)?;
+        socket.flush()?;
+        Ok(())
+    }
+
+    fn send_close(&self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        let mut payload = code.to_be_bytes().to_vec();
+        //控制帧的载荷最多 125 字节
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.send_frame(true, Opcode::Close, &payload)
+    }
+}
+
+/// 一个 WebSocket 连接。[`recv`](WebSocket::recv) 读取消息，`send_*` 发送消息；
+/// 需要在其他线程上发送时用 [`sender`](WebSocket::sender) 得到一个发送端。
+pub struct WebSocket {
+    shared: Arc<Shared>,
+    //读的一半：与 socket 是同一个套接字，设置了读超时
+    control: TcpStream,
+    //读到但还没有解码的数据
+    buf: Vec<u8>,
+    //正在接收的分片消息：第一帧的类型和已经收到的数据
+    fragments: Option<(Opcode, Vec<u8>)>,
+    max_message: usize,
+    //对方发来的关闭状态码和原因
+    close_frame: Option<(u16, String)>,
+    //server 关闭时结束会话；客户端没有
+    stop: Option<Arc<Stop>>,
+}
+
+/// 在其他线程上向 [`WebSocket`] 发送消息的句柄，可以克隆
+#[derive(Clone)]
+pub struct WebSocketSender(Arc<Shared>);
+
+impl WebSocketSender {
+    pub fn send(&self, message: &Message) -> Result<(), WebSocketError> {
+        match message {
+            Message::Text(text) => self.0.send_frame(true, Opcode::Text, text.as_bytes()),
+            Message::Binary(data) => self.0.send_frame(true, Opcode::Binary, data),
+        }
+    }
+
+    pub fn send_text(&self, text: &str) -> Result<(), WebSocketError> {
+        self.0.send_frame(true, Opcode::Text, text.as_bytes())
+    }
+
+    pub fn send_binary(&self, data: &[u8]) -> Result<(), WebSocketError> {
+        self.0.send_frame(true, Opcode::Binary, data)
+    }
+
+    /// 连接是否已经关闭（发送过关闭帧）
+    pub fn is_closed(&self) -> bool {
+        self.0.close_sent.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("peer", &self.control.peer_addr().ok())
+            .field("client", &self.shared.client)
+            .field("closed", &self.shared.close_sent.load(Ordering::SeqCst))
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebSocket {
+    //`buffered` 是读 HTTP 请求（或者响应）时多读到的数据
+    fn new(
+        mut socket: Box<dyn Socket>,
+        control: TcpStream,
+        mut buffered: Vec<u8>,
+        client: bool,
+        stop: Option<Arc<Stop>>,
+    ) -> io::Result<WebSocket> {
+        control.set_read_timeout(Some(POLL))?;
+        //TLS 会话里可能还有解析器没有读走的数据，之后就只从 control 上读了
+        socket.decode(&[], &mut buffered)?;
+        Ok(WebSocket {
+            shared: Arc::new(Shared {
+                socket: Mutex::new(socket),
+                close_sent: AtomicBool::new(false),
+                client,
+            }),
+            control,
+            buf: buffered,
+            fragments: None,
+            max_message: DEFAULT_MAX_MESSAGE,
+            close_frame: None,
+            stop,
+        })
+    }
+
+    /// 作为客户端在 `stream` 上发起握手，请求的路径是 `path`（可以带查询字符串）
+    ///
+    /// # Errors
+    ///
+    /// 服务端没有回复 101 或者 `Sec-WebSocket-Accept` 不对时返回 [`WebSocketError::Handshake`]
+    pub fn connect(
+        mut stream: TcpStream,
+        host: &str,
+        path: &str,
+    ) -> Result<WebSocket, WebSocketError> {
+        let nonce: Vec<u8> = random_u64()
+            .to_le_bytes()
+            .into_iter()
+            .chain(random_u64().to_le_bytes())
+            .collect();
+        let key = base64::engine::general_purpose::STANDARD.encode(nonce);
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path, host, key
+        )?;
+        //一个字节一个字节地读，不会读到头部后面的帧
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if stream.read(&mut byte)? == 0 {
+                return Err(WebSocketError::Handshake("connection closed".to_string()));
+            }
+            head.push(byte[0]);
+            if head.len() > 16 * 1024 {
+                return Err(WebSocketError::Handshake(
+                    "response head too large".to_string(),
+                ));
+            }
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status = head.lines().next().unwrap_or("");
+        if !status.starts_with("HTTP/1.1 101") {
+            return Err(WebSocketError::Handshake(format!(
+                "unexpected status {:?}",
+                status
+            )));
+        }
+        let accept = head.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("sec-websocket-accept")
+                .then(|| value.trim().to_string())
+        });
+        if accept.as_deref() != Some(websocket_accept_key(&key).as_str()) {
+            return Err(WebSocketError::Handshake(
+                "wrong Sec-WebSocket-Accept".to_string(),
+            ));
+        }
+        let control = stream.try_clone()?;
+        Ok(WebSocket::new(
+            Box::new(stream),
+            control,
+            Vec::new(),
+            true,
+            None,
+        )?)
+    }
+
+    /// 设置一条消息（所有分片加起来）的大小上限，超过时以 1009 关闭连接。默认是 [`DEFAULT_MAX_MESSAGE`]。
+    pub fn set_max_message_size(&mut self, max: usize) {
+        self.max_message = max;
+    }
+
+    /// 得到一个可以在其他线程上发送消息的句柄
+    pub fn sender(&self) -> WebSocketSender {
+        WebSocketSender(Arc::clone(&self.shared))
+    }
+
+    pub fn send(&self, message: &Message) -> Result<(), WebSocketError> {
+        self.sender().send(message)
+    }
+
+    pub fn send_text(&self, text: &str) -> Result<(), WebSocketError> {
+        self.shared.send_frame(true, Opcode::Text, text.as_bytes())
+    }
+
+    pub fn send_binary(&self, data: &[u8]) -> Result<(), WebSocketError> {
+        self.shared.send_frame(true, Opcode::Binary, data)
+    }
+
+    /// 把一条消息分成若干帧发送，每帧最多 `chunk` 字节
+    pub fn send_fragmented(&self, message: &Message, chunk: usize) -> Result<(), WebSocketError> {
+        let (opcode, data) = match message {
+            Message::Text(text) => (Opcode::Text, text.as_bytes()),
+            Message::Binary(data) => (Opcode::Binary, &data[..]),
+        };
+        let chunks: Vec<&[u8]> = data.chunks(chunk.max(1)).collect();
+        if chunks.is_empty() {
+            return self.shared.send_frame(true, opcode, &[]);
+        }
+        for (i, part) in chunks.iter().enumerate() {
+            let opcode = if i == 0 { opcode } else { Opcode::Continuation };
+            self.shared
+                .send_frame(i + 1 == chunks.len(), opcode, part)?;
+        }
+        Ok(())
+    }
+
+    /// 发送 Ping，对方会回复同样载荷的 Pong。`payload` 最多 125 字节。
+    pub fn ping(&self, payload: &[u8]) -> Result<(), WebSocketError> {
+        self.shared
+            .send_frame(true, Opcode::Ping, &payload[..payload.len().min(125)])
+    }
+
+    /// 发送关闭帧。之后 [`recv`](WebSocket::recv) 会在收到对方的关闭帧时返回 `Ok(None)`。
+    pub fn close(&self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.shared.send_close(code, reason)
+    }
+
+    /// 对方关闭连接时给出的状态码和原因
+    pub fn close_frame(&self) -> Option<(u16, &str)> {
+        self.close_frame
+            .as_ref()
+            .map(|(code, reason)| (*code, reason.as_str()))
+    }
+
+    /// 读取下一条消息。Ping、Pong 和分片在这里处理，调用者只会看到完整的消息。
+    ///
+    /// 对方关闭了连接（或者 server 正在关闭）时返回 `Ok(None)`。
+    ///
+    /// # Errors
+    ///
+    /// 对方违反了协议时已经发送了关闭帧，返回 [`WebSocketError::Protocol`]；读写失败时返回 [`WebSocketError::Io`]
+    pub fn recv(&mut self) -> Result<Option<Message>, WebSocketError> {
+        loop {
+            let decoded = decode_frame(&self.buf, !self.shared.client, self.max_message);
+            let frame = match decoded {
+                Ok(Some((frame, used))) => {
+                    self.buf.drain(..used);
+                    frame
+                }
+                Ok(None) => {
+                    if !self.fill()? {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                Err(err) => return Err(self.fail(err)),
+            };
+            match self.on_frame(frame) {
+                Ok(Some(message)) => return Ok(Some(message)),
+                Ok(None) if self.close_frame.is_some() => return Ok(None),
+                Ok(None) => {}
+                Err(err) => return Err(self.fail(err)),
+            }
+        }
+    }
+
+    //读更多的数据，返回 false 表示会话结束（server 正在关闭，或者关闭帧已经交换完）
+    fn fill(&mut self) -> Result<bool, WebSocketError> {
+        let mut chunk = [0; 8192];
+        loop {
+            if self
+                .stop
+                .as_ref()
+                .is_some_and(|stop| stop.stopped.load(Ordering::SeqCst))
+            {
+                let _ = self
+                    .shared
+                    .send_close(close_code::GOING_AWAY, "server shutting down");
+                return Ok(false);
+            }
+            match self.control.read(&mut chunk) {
+                Ok(0) => {
+                    //我们先发了关闭帧，对方直接断开也算正常结束
+                    if self.shared.close_sent.load(Ordering::SeqCst) {
+                        return Ok(false);
+                    }
+                    return Err(WebSocketError::Io(io::ErrorKind::UnexpectedEof.into()));
+                }
+                Ok(n) => {
+                    let mut socket = self.shared.socket.lock().unwrap();
+                    socket.decode(&chunk[..n], &mut self.buf)?;
+                    return Ok(true);
+                }
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) => {}
+                Err(err) => return Err(WebSocketError::Io(err)),
+            }
+        }
+    }
+
+    //处理一个帧，拼出完整的消息时返回它
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Message>, WebSocketError> {
+        use close_code::{PROTOCOL_ERROR, TOO_LARGE};
+
+        match frame.opcode {
+            Opcode::Ping => {
+                //已经发送了关闭帧时不再回复
+                match self.shared.send_frame(true, Opcode::Pong, &frame.payload) {
+                    Ok(()) | Err(WebSocketError::Closed) => Ok(None),
+                    Err(err) => Err(err),
+                }
+            }
+            Opcode::Pong => Ok(None),
+            Opcode::Close => {
+                let (code, reason) = parse_close(&frame.payload)?;
+                self.close_frame = Some((code.unwrap_or(1005), reason));
+                //对方先发起的关闭：用同样的状态码回复
+                if !self.shared.close_sent.load(Ordering::SeqCst) {
+                    let _ = self
+                        .shared
+                        .send_close(code.unwrap_or(close_code::NORMAL), "");
+                }
+                Ok(None)
+            }
+            Opcode::Text | Opcode::Binary => {
+                if self.fragments.is_some() {
+                    return Err(WebSocketError::Protocol(
+                        PROTOCOL_ERROR,
+                        "new message before the fragmented one finished",
+                    ));
+                }
+                if frame.fin {
+                    return message(frame.opcode, frame.payload).map(Some);
+                }
+                self.fragments = Some((frame.opcode, frame.payload));
+                Ok(None)
+            }
+            Opcode::Continuation => {
+                let Some((_, data)) = &mut self.fragments else {
+                    return Err(WebSocketError::Protocol(
+                        PROTOCOL_ERROR,
+                        "continuation without a message",
+                    ));
+                };
+                if data.len() + frame.payload.len() > self.max_message {
+                    return Err(WebSocketError::Protocol(TOO_LARGE, "message too large"));
+                }
+                data.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    return Ok(None);
+                }
+                let (opcode, data) = self.fragments.take().unwrap();
+                message(opcode, data).map(Some)
+            }
+        }
+    }
+
+    //违反协议时先发送关闭帧再返回错误
+    fn fail(&mut self, err: WebSocketError) -> WebSocketError {
+        if let WebSocketError::Protocol(code, message) = &err {
+            let _ = self.shared.send_close(*code, message);
+        }
+        err
+    }
+}
+
+fn message(opcode: Opcode, data: Vec<u8>) -> Result<Message, WebSocketError> {
+    match opcode {
+        Opcode::Text => String::from_utf8(data).map(Message::Text).map_err(|_| {
+            WebSocketError::Protocol(close_code::INVALID_DATA, "text message is not UTF-8")
+        }),
+        _ => Ok(Message::Binary(data)),
+    }
+}
+
+//关闭帧的载荷：可选的两字节状态码，后面跟 UTF-8 的原因
+fn parse_close(payload: &[u8]) -> Result<(Option<u16>, String), WebSocketError> {
+    use close_code::{INVALID_DATA, PROTOCOL_ERROR};
+
+    match payload {
+        [] => Ok((None, String::new())),
+        [_] => Err(WebSocketError::Protocol(
+            PROTOCOL_ERROR,
+            "truncated close code",
+        )),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            //1005、1006 和 1015 只在本地表示状态，不能出现在帧里
+            let valid = matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999);
+            if !valid {
+                return Err(WebSocketError::Protocol(
+                    PROTOCOL_ERROR,
+                    "invalid close code",
+                ));
+            }
+            let reason = String::from_utf8(reason.to_vec())
+                .map_err(|_| WebSocketError::Protocol(INVALID_DATA, "close reason is not UTF-8"))?;
+            Ok((Some(code), reason))
+        }
+    }
+}
+
+//掩码和握手的随机数。RandomState 每次创建时密钥都不同，足够用来防止缓存投毒，不用于加密
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(std::process::id() as u64);
+    hasher.finish()
+}
+
+/// 运行升级后的会话：处理函数返回（或者 panic）之后发送关闭帧，然后关闭连接
+pub(super) fn run_session(
+    handler: &WebSocketHandler,
+    request: &Request,
+    socket: Box<dyn Socket>,
+    control: TcpStream,
+    buffered: Vec<u8>,
+    stop: Arc<Stop>,
+) -> Result<(), ConnectionError> {
+    let ws = WebSocket::new(socket, control, buffered, false, Some(stop))
+        .map_err(ConnectionError::Read)?;
+    let shared = Arc::clone(&ws.shared);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| handler(request, ws)));
+    let (code, reason) = match &result {
+        Ok(()) => (close_code::NORMAL, ""),
+        Err(_) => (close_code::INTERNAL_ERROR, "internal error"),
+    };
+    let _ = shared.send_close(code, reason);
+    result.map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        ConnectionError::Handler(message)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Method, Version};
+
+    fn upgrade(headers: &[(&str, &str)]) -> Request {
+        let mut request = Request {
+            method: Method::Get,
+            target: "/ws".to_string(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: Vec::new(),
+        };
+        for (name, value) in headers {
+            request.headers.append(name, *value);
+        }
+        request
+    }
+
+    #[test]
+    fn handshake_accepts_valid_upgrades() {
+        let valid = [
+            ("Upgrade", "WebSocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Version", "13"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        ];
+        let response = handshake(&upgrade(&valid));
+        assert_eq!(response.status, StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            response.headers.get("sec-websocket-accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+
+        assert_eq!(
+            handshake(&upgrade(&[])).status,
+            StatusCode::UPGRADE_REQUIRED
+        );
+        let response = handshake(&upgrade(&[
+            valid[0],
+            valid[1],
+            ("Sec-WebSocket-Version", "8"),
+            valid[3],
+        ]));
+        assert_eq!(response.status, StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(response.headers.get("sec-websocket-version"), Some("13"));
+        let response = handshake(&upgrade(&[
+            valid[0],
+            valid[1],
+            valid[2],
+            ("Sec-WebSocket-Key", "c2hvcnQ="),
+        ]));
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn frames_round_trip_with_every_length_encoding() {
+        for len in [0, 125, 126, 0xffff, 0x10000] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let encoded = encode_frame(true, Opcode::Binary, &payload, mask);
+                let (frame, used) = decode_frame(&encoded, mask.is_some(), usize::MAX)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(used, encoded.len());
+                assert_eq!(frame.payload, payload);
+                //少一个字节就不是完整的帧
+                assert!(
+                    decode_frame(&encoded[..encoded.len() - 1], mask.is_some(), usize::MAX)
+                        .unwrap()
+                        .is_none()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn masks_payload_bytes() {
+        //RFC 6455 第 5.7 节：加了掩码的 "Hello"
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let (frame, _) = decode_frame(&frame, true, 1024).unwrap().unwrap();
+        assert_eq!(frame.payload, b"Hello");
+        assert_eq!(
+            encode_frame(true, Opcode::Text, b"Hello", Some([0x37, 0xfa, 0x21, 0x3d])),
+            [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+        );
+    }
+
+    #[test]
+    fn rejects_protocol_violations() {
+        let error = |frame: &[u8], masked| match decode_frame(frame, masked, 100) {
+            Err(WebSocketError::Protocol(code, _)) => code,
+            other => panic!("{:?}", other.map(|frame| frame.map(|(frame, _)| frame))),
+        };
+        //没有掩码的客户端帧
+        assert_eq!(error(&[0x81, 0x00], true), close_code::PROTOCOL_ERROR);
+        //保留位
+        assert_eq!(
+            error(&[0xc1, 0x80, 0, 0, 0, 0], true),
+            close_code::PROTOCOL_ERROR
+        );
+        //未知的操作码
+        assert_eq!(
+            error(&[0x83, 0x80, 0, 0, 0, 0], true),
+            close_code::PROTOCOL_ERROR
+        );
+        //分片的控制帧
+        assert_eq!(
+            error(&[0x09, 0x80, 0, 0, 0, 0], true),
+            close_code::PROTOCOL_ERROR
+        );
+        //超过上限
+        assert_eq!(
+            error(&[0x82, 0xfe, 0x01, 0x00], true),
+            close_code::TOO_LARGE
+        );
+
+        assert!(parse_close(&[0x03]).is_err());
+        assert!(parse_close(&1005u16.to_be_bytes()).is_err());
+        assert_eq!(
+            parse_close(&[0x03, 0xe8, b'b', b'y', b'e']).unwrap(),
+            (Some(1000), "bye".to_string())
+        );
+    }
+}
@@ -2,68 +2,105 @@
  * @Author: wulongjiang
  * @Date: 2022-12-26 21:22:39
  * @LastEditors: wlj
- * @LastEditTime: 2022-12-27 08:53:56
+ * @LastEditTime: 2026-10-20 05:48:20
  * @Description: 线程池库
  * @FilePath: \multithreaded\src\lib.rs
  */
-use std::sync::mpsc;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
-use std::sync::Mutex;
 use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod http;
+mod par;
+mod priority;
+mod queue;
+mod scope;
+mod stats;
+mod timer;
+
+pub use priority::{Priority, DEFAULT_QUEUE};
+use queue::{Shared, Slot, Task};
+pub use scope::Scope;
+pub use stats::{EventHook, Histogram, HistogramSnapshot, PoolStats};
+pub use timer::ScheduleHandle;
+use timer::Timer;
 pub struct ThreadPool {
     //spawn 返回 JoinHandle<T>，其中 T 是闭包返回的类型。尝试使用 JoinHandle 来看看会发生什么。
     //在我们的情况中，传递给线程池的闭包会处理链接并不返回任何值，所以T将会时单元类型()。
     //改变了 ThreadPool 的定义来存放一个 thread::JoinHandle<()> 的 vector 实例
     // threads: Vec<thread::JoinHandle<()>>,
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    //worker 的数量会在运行时变化（积压时扩容、空闲超时后退出、resize），
+    //而且定时器线程提交任务时也可能需要扩容，所以 worker 列表放在了 Shared 里
+    //最初这里存放的是 mpsc::Sender<Message>，现在换成了与所有 worker 共享的工作窃取队列，见 queue.rs
+    shared: Arc<Shared>,
+    //schedule_after 和 schedule_every 使用的定时器，见 timer.rs
+    timer: Timer,
 }
 //首先，让我们做出如此创建 ThreadPool 时所需的修改。
 // 定义 Worker 结构体存放 id 和 JoinHandle<()>
 // 修改 ThreadPool 存放一个 Worker 实例的 vector
 // 定义 Worker::new 函数，它获取一个 id 数字并返回一个带有 id 和用空闭包分配的线程的 Worker 实例
 // 在 ThreadPool::new 中，使用 for 循环计数生成 id，使用这个 id 新建 Worker，并储存进 vector 中
-struct Worker {
+pub(crate) struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
 }
 
-//信道将发送这个枚举的两个成员之一而不是 Job 实例 为了停机
-enum Message {
-    NewJob(Job),
-    Terminate,
-}
-
+//最初信道会发送 Message::NewJob(job) 或 Message::Terminate 两种消息，每个 worker 收到 Terminate 就退出。
+//换成工作窃取队列之后，停机改由 Shared 中的 shutdown 标志完成：worker 把队列中剩余的任务处理完、并且看到这个标志后退出循环。
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
+    //调用者需要先通过 Shared::reserve_worker 为这个 worker 预留名额
+    fn new(id: usize, shared: Arc<Shared>) -> Worker {
         Worker {
             id,
-            thread: Some(thread::spawn(move || loop {
-                // let job = receiver.lock().unwrap().recv().unwrap();
-                //这里，首先在 receiver 上调用了 lock 来获取互斥器，接着 unwrap 在出现任何错误时 panic。
-                //如果互斥器处于一种叫做 被污染（poisoned）的状态时获取锁可能会失败，这可能发生于其他线程在持有锁时 panic 了且没有释放锁.
-                //在这种情况下，调用 unwrap 使其 panic 是正确的行为。请随意将 unwrap 改为包含有意义错误信息的 expect。
-                //如果锁定了互斥器，接着调用 recv 从信道中接收 Job。
-                //最后的 unwrap 也绕过了一些错误，这可能发生于持有信道发送端的线程停止的情况，类似于如果接收端关闭时 send 方法如何返回 Err 一样。
-                //调用 recv 会阻塞当前线程，所以如果还没有任务，其会等待直到有可用的任务。Mutex<T> 确保一次只有一个 Worker 线程尝试请求任务。
-                // println!("Worker {} got a job; executing.", id);
-                // job();
-                //成功了！现在我们有了一个可以异步执行连接的线程池！它绝不会创建超过四个线程，所以当 server 收到大量请求时系统也不会负担过重。
-                //如果请求 /sleep，server 也能够通过另外一个线程处理其他请求。
-
-                //注意如果同时在多个浏览器窗口打开 /sleep，它们可能会彼此间隔地加载 5 秒
-                //，因为一些浏览器处于缓存的原因会顺序执行相同请求的多个实例。这些限制并不是由于我们的 web server 造成的。
-
-                //
-                let messgae = receiver.lock().unwrap().recv().unwrap();
-
-                match messgae {
-                    Message::NewJob(job) => {
-                        println!("Worker {} got a job; executing.", id);
-                        job();
+            thread: Some(thread::spawn(move || {
+                let (slot, local) = shared.acquire_slot();
+                shared.register_current(id, slot, &local);
+                //无论以什么方式退出，都要归还槽位并告诉线程池这个 worker 已经结束了
+                let mut exit = ExitGuard {
+                    shared: &shared,
+                    id,
+                    slot,
+                    local: &local,
+                    retired: false,
+                };
+                let mut idle_rounds = 0;
+                loop {
+                    if shared.is_stopped() {
+                        break;
+                    }
+                    //之前这里是 receiver.lock().unwrap().recv().unwrap()：在持有互斥器的同时阻塞在 recv 上，
+                    //任何时刻只有一个 worker 能等待任务，其余的 worker 都在排队等锁。
+                    //现在 worker 依次查看自己的本地队列、全局注入队列和其他 worker 的队列，只在真的没有任务时才睡眠。
+                    //最初这里会打印 "Worker {} got a job; executing."，现在改为通过 EventHook 通知，见 stats.rs。
+                    if let Some(task) = shared.find_job(slot, &local) {
+                        idle_rounds = 0;
+                        shared.begin_active();
+                        run_task(&shared, id, task);
+                        shared.end_active();
+                        continue;
+                    }
+                    if shared.is_shutdown() && shared.pending() == 0 {
+                        break;
+                    }
+                    //resize 缩小了线程池，多出来的 worker 一空闲下来就退出
+                    if shared.try_retire(shared.max()) {
+                        exit.retired = true;
+                        break;
+                    }
+                    //先让出几次时间片再睡眠：任务往往是一批一批提交的，马上睡下又被唤醒的代价（系统调用加上下文切换）比多找几轮要大得多。
+                    if idle_rounds < SPIN_ROUNDS {
+                        idle_rounds += 1;
+                        thread::yield_now();
+                        continue;
                     }
-                    Message::Terminate => {
-                        println!("Worker {} was told to terminate.", id);
+                    //空闲了 keep_alive 还没有等到任务，超过 min 的那部分 worker 退出
+                    if !shared.wait_for_job(slot, &local) && shared.try_retire(shared.min()) {
+                        exit.retired = true;
                         break;
                     }
                 }
@@ -72,6 +109,107 @@ impl Worker {
     }
 }
 
+struct ExitGuard<'a> {
+    shared: &'a Shared,
+    id: usize,
+    slot: usize,
+    local: &'a Slot,
+    //通过 try_retire 退出的 worker 已经把 current 减过一了
+    retired: bool,
+}
+
+impl Drop for ExitGuard<'_> {
+    fn drop(&mut self) {
+        if !self.retired {
+            self.shared.worker_gone();
+        }
+        self.shared.release_slot(self.slot, self.local);
+        self.shared.instrumentation.worker_exited(self.id);
+        self.shared.mark_exited(self.id);
+    }
+}
+
+//执行一个任务并记录它的等待时间和执行时间。
+//任务 panic 时捕获 panic，这样一个出错的任务不会带走整个 worker 线程。
+pub(crate) fn run_task(shared: &Shared, worker: usize, task: Task) {
+    let started = Instant::now();
+    let instrumentation = &shared.instrumentation;
+    instrumentation.job_started(worker, started.saturating_duration_since(task.queued_at));
+    let result = panic::catch_unwind(AssertUnwindSafe(task.job));
+    let run_time = started.elapsed();
+    match result {
+        Ok(()) => instrumentation.job_finished(worker, run_time),
+        Err(_) => instrumentation.job_panicked(worker, run_time),
+    }
+}
+
+//启动一个新 worker，worker 总数不超过 limit。返回是否真的启动了。
+fn spawn_worker(shared: &Arc<Shared>, limit: usize) -> bool {
+    if !shared.reserve_worker(limit) {
+        return false;
+    }
+    // create some threads and store them in the vector
+    //如何实际创建线程呢？这是一个难题。标准库提供的创建线程的方法，thread::spawn,它期望获取一些一旦创建线程就应该执行的代码。
+    //然而我们希望开始线程并使其等待稍后传递的代码。标准库的线程实现并没有包含这么做的方法；我们必须自己实现。
+    //我们将要实现的行为是创建线程并稍后发送代码，这会在 ThreadPool 和线程间引入一个新数据类型来管理这种新行为。
+    //这个数据结构称为 Worker：这是一个池实现中的常见概念。想象一下在餐馆厨房工作的员工：员工等待来自客户的订单，他们负责接受这些订单并完成它们。
+    //不同于在线程池中储存一个 JoinHandle<()> 实例的 vector，我们会储存 Worker 结构体的实例。每一个 Worker 会储存一个单独的 JoinHandle<()> 实例。
+    //我们还会赋予每一个 worker id，这样就可以在日志和调试中区别线程池中的不同 worker。
+    //回忆一下第十六章讨论的线程安全智能指针，为了在多个线程间共享所有权并允许线程修改其值，需要使用 Arc<Mutex<T>>。
+    //最初共享的是信道的接收端，现在共享的是整个 Shared，而 Mutex 只保护各个队列本身，不再包住整个接收端。
+    let id = shared.next_worker_id();
+    let mut workers = shared.workers.lock().unwrap();
+    //顺便回收已经因为空闲超时而退出的 worker
+    workers.retain_mut(|worker| match worker.thread.take() {
+        Some(thread) if thread.is_finished() => {
+            let _ = thread.join();
+            shared.forget_exited(worker.id);
+            false
+        }
+        thread => {
+            worker.thread = thread;
+            true
+        }
+    });
+    workers.push(Worker::new(id, Arc::clone(shared)));
+    true
+}
+
+//把任务放进队列。定时器线程也通过它提交到期的任务。
+//线程池已经关闭时（只可能发生在线程池外部的线程上，见 Shared::admit）不接受任务，原样交还给调用者
+pub(crate) fn submit(shared: &Arc<Shared>, task: Task) -> Result<(), Task> {
+    if !shared.admit() {
+        return Err(task);
+    }
+    shared.instrumentation.job_queued();
+    shared.push(task);
+    //等待的任务比空闲的 worker 多而且还没到上限，就再启动一个 worker
+    if shared.should_grow() {
+        spawn_worker(shared, shared.max());
+    }
+    Ok(())
+}
+
+//不持有 ThreadPool 也能向它提交任务的句柄。http::server 中管理空闲连接的线程用它把有数据可读的连接交还给线程池，
+//而 ThreadPool 本身仍然由 Server 独占，可以被 shutdown。
+#[derive(Clone)]
+pub(crate) struct Spawner(Arc<Shared>);
+
+impl Spawner {
+    //线程池已经开始关闭时不再接受任务，返回 Err 交还任务，由调用者决定怎么收尾（例如关闭连接）
+    pub(crate) fn execute<F>(&self, f: F) -> Result<(), Job>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        submit(&self.0, Task::new(Box::new(f))).map_err(|task| task.job)
+    }
+
+    //http::metrics 用它在 /metrics 中报告线程池的状态
+    pub(crate) fn stats(&self) -> PoolStats {
+        self.0.instrumentation.snapshot(pool_metrics(&self.0))
+    }
+}
+
 //使用信道向线程发送请求
 // 下一个需要解决的问题是传递给 thread::spawn 的闭包完全没有做任何工作。
 // 目前，我们在 execute 方法中获得期望执行的闭包，不过在创建 ThreadPool 的过程中创建每一个 Worker 时需要向 thread::spawn 传递一个闭包。
@@ -84,7 +222,167 @@ impl Worker {
 // execute 方法会在信道发送端发出期望执行的任务。
 // 在线程中，Worker 会遍历信道的接收端并执行任何接收到的任务。
 // Job 将是一个有着 execute 接收到的闭包类型的 trait 对象的类型别名。
-type Job = Box<dyn FnOnce() + Send + 'static>;
+//shutdown_now 会把还没开始执行的任务交还给调用者，所以 Job 需要是公有的。
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+//worker 找不到任务时，在睡眠之前最多再让出多少次时间片
+const SPIN_ROUNDS: u32 = 64;
+
+//超过 min 的 worker 默认空闲多久之后退出
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+//任务默认每在队列中等待多久提升一级优先级
+const DEFAULT_AGING: Duration = Duration::from_secs(1);
+
+/// 用来创建大小可以变化的线程池。
+///
+/// ```
+/// use std::time::Duration;
+/// use multithreaded::ThreadPool;
+///
+/// let pool = ThreadPool::builder()
+///     .min_threads(2)
+///     .max_threads(16)
+///     .keep_alive(Duration::from_secs(30))
+///     .build();
+/// pool.execute(|| println!("hello from the pool"));
+/// ```
+#[derive(Clone)]
+pub struct Builder {
+    min: usize,
+    max: usize,
+    keep_alive: Duration,
+    hook: Option<Arc<dyn EventHook>>,
+    //命名队列和它们的权重，第一个总是默认队列
+    queues: Vec<(String, u32)>,
+    aging: Duration,
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("min", &self.min)
+            .field("max", &self.max)
+            .field("keep_alive", &self.keep_alive)
+            .field("hook", &self.hook.is_some())
+            .field("queues", &self.queues)
+            .field("aging", &self.aging)
+            .finish()
+    }
+}
+
+impl Builder {
+    /// 始终保留的 worker 数，线程池创建时就会启动这么多个 worker。默认为 1。
+    pub fn min_threads(mut self, min: usize) -> Builder {
+        self.min = min;
+        self
+    }
+
+    /// 队列积压时最多扩容到的 worker 数。默认为 1。
+    pub fn max_threads(mut self, max: usize) -> Builder {
+        self.max = max;
+        self
+    }
+
+    /// 超过 `min_threads` 的 worker 空闲多久之后退出。默认为 60 秒。
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Builder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// 安装事件钩子，线程池会在任务入队、开始、结束、panic 以及 worker 退出时调用它
+    pub fn event_hook(mut self, hook: Arc<dyn EventHook>) -> Builder {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// 添加一个命名队列，或者修改已有队列（包括 [`DEFAULT_QUEUE`]）的权重。默认队列的权重默认为 1。
+    ///
+    /// 各个队列都有任务时，从每个队列取出的任务数与它的权重成正比。
+    ///
+    /// ```
+    /// use multithreaded::{Priority, ThreadPool};
+    ///
+    /// let pool = ThreadPool::builder()
+    ///     .max_threads(4)
+    ///     .queue("uploads", 1)
+    ///     .queue("api", 4)
+    ///     .build();
+    /// pool.execute_in("uploads", Priority::Low, || { /* 保存上传的文件 */ });
+    /// pool.execute_in("api", Priority::High, || { /* 健康检查 */ });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// `weight` 为 0 时 panic
+    pub fn queue(mut self, name: &str, weight: u32) -> Builder {
+        assert!(weight > 0);
+        match self.queues.iter_mut().find(|(queue, _)| queue == name) {
+            Some(queue) => queue.1 = weight,
+            None => self.queues.push((name.to_string(), weight)),
+        }
+        self
+    }
+
+    /// 任务每在队列中等待多久提升一级优先级，用来保证低优先级的任务不会被饿死。默认为 1 秒。
+    pub fn aging(mut self, aging: Duration) -> Builder {
+        self.aging = aging;
+        self
+    }
+
+    /// 创建线程池
+    ///
+    /// # Panics
+    ///
+    /// `max_threads` 为 0 或者 `min_threads` 大于 `max_threads` 时 panic
+    pub fn build(self) -> ThreadPool {
+        assert!(self.max > 0);
+        assert!(self.min <= self.max);
+        let shared = Shared::new(
+            self.min,
+            self.max,
+            self.keep_alive,
+            self.hook,
+            self.queues,
+            self.aging,
+        );
+        let pool = ThreadPool {
+            shared: Arc::new(shared),
+            timer: Timer::new(),
+        };
+        for _ in 0..self.min {
+            spawn_worker(&pool.shared, self.min);
+        }
+        pool
+    }
+}
+
+fn pool_metrics(shared: &Shared) -> PoolMetrics {
+    let current = shared.current();
+    PoolMetrics {
+        current_workers: current,
+        peak_workers: shared.peak(),
+        idle_workers: current.saturating_sub(shared.active()),
+        queued_jobs: shared.pending(),
+        min_threads: shared.min(),
+        max_threads: shared.max(),
+    }
+}
+
+/// 线程池当前的规模
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// 当前存活的 worker 数
+    pub current_workers: usize,
+    /// 线程池创建以来同时存活的 worker 数的最大值
+    pub peak_workers: usize,
+    /// 没有在执行任务的 worker 数
+    pub idle_workers: usize,
+    /// 已经提交、还没有开始执行的任务数
+    pub queued_jobs: usize,
+    pub min_threads: usize,
+    pub max_threads: usize,
+}
 
 impl ThreadPool {
     //在 new 中验证池中线程数量
@@ -100,34 +398,25 @@ impl ThreadPool {
         //然而，线程数为零的线程池同样没有意义，不过零是一个完全有效的 u32 值。
         //让我们增加在返回 ThreadPool 实例之前检查 size 是否大于零的代码，并使用 assert! 宏在得到零时 panic
         assert!(size > 0);
-        //使用 size 容量来初始化，并设置一个 for 循环了来运行创建线程的代码，并返回包含这些线程的 ThreadPool 实例
-        let mut workers = Vec::with_capacity(size);
-        //with_capacity ,它与Vec::new 做了同样的工作，不过有一个重要的区别：它为vector 预先分配空间。
-        //因为已经知道了vector中需要size个元素，预先进行分配比仅仅Vec::new 要稍微有效率一点，因为Vec::new 随着插入元素而重新改变大小。
-
-        let (sender, reciver) = mpsc::channel(); //新建了一个信道，并接着让线程池在接收端等待。
-        let reciver = Arc::new(Mutex::new(reciver));
-        for id in 0..size {
-            // create some threads and store them in the vector
-            //如何实际创建线程呢？这是一个难题。标准库提供的创建线程的方法，thread::spawn,它期望获取一些一旦创建线程就应该执行的代码。
-            //然而我们希望开始线程并使其等待稍后传递的代码。标准库的线程实现并没有包含这么做的方法；我们必须自己实现。
-            //我们将要实现的行为是创建线程并稍后发送代码，这会在 ThreadPool 和线程间引入一个新数据类型来管理这种新行为。
-            //这个数据结构称为 Worker：这是一个池实现中的常见概念。想象一下在餐馆厨房工作的员工：员工等待来自客户的订单，他们负责接受这些订单并完成它们。
-            //不同于在线程池中储存一个 JoinHandle<()> 实例的 vector，我们会储存 Worker 结构体的实例。每一个 Worker 会储存一个单独的 JoinHandle<()> 实例。
-            //接着会在Worker上实现一个方法，它会获取需要允许代码的闭包并将其发送给已经允许的线程执行。
-            //我们还会赋予每一个 worker id，这样就可以在日志和调试中区别线程池中的不同 worker。
-            //这段代码尝试将 receiver 传递给多个 Worker 实例。这是不行的，回忆第十六章：Rust 所提供的信道实现是多 生产者，单 消费者 的。
-            //这意味着不能简单的克隆信道的消费端来解决问题。即便可以，那也不是我们希望使用的技术；我们希望通过在所有的 worker 中共享单一 receiver，在线程间分发任务。
-            //另外，从信道队列中取出任务涉及到修改 receiver，所以这些线程需要一个能安全的共享和修改 receiver 的方式，否则可能导致竞争状态（参考第十六章）。
-            // workers.push(Worker::new(id, reciver));
-            //回忆一下第十六章讨论的线程安全智能指针，为了在多个线程间共享所有权并允许线程修改其值，需要使用 Arc<Mutex<T>>。
-            //Arc 使得多个 worker 拥有接收端，而 Mutex 则确保一次只有一个 worker 能从接收端得到任务。
-            workers.push(Worker::new(id, Arc::clone(&reciver)));
-            //在 ThreadPool::new 中，将信道的接收端放入一个 Arc 和一个 Mutex 中。对于每一个新 worker，克隆 Arc 来增加引用计数，如此这些 worker 就可以共享接收端的所有权了。
-        }
-
-        ThreadPool { workers, sender }
+        //固定大小的线程池就是 min 和 max 相等的线程池
+        ThreadPool::builder()
+            .min_threads(size)
+            .max_threads(size)
+            .build()
     }
+
+    /// 创建一个 [`Builder`]，用来配置 worker 数量的上下限和空闲超时
+    pub fn builder() -> Builder {
+        Builder {
+            min: 1,
+            max: 1,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            hook: None,
+            queues: vec![(DEFAULT_QUEUE.to_string(), 1)],
+            aging: DEFAULT_AGING,
+        }
+    }
+
     //在 ThreadPool 上定义 execute 函数来获取一个闭包参数。
     //回忆第十三章的 “使用带有泛型和 Fn trait 的闭包” 部分，闭包作为参数时可以使用三个不同的 trait：Fn、FnMut 和 FnOnce。
     //我们需要决定这里应该使用哪种闭包。最终需要实现的类似于标准库的 thread::spawn，所以我们可以观察 thread::spawn 的签名在其参数中使用了何种 bound。
@@ -145,12 +434,132 @@ impl ThreadPool {
         F: FnOnce() + Send + 'static, //FnOnce trait仍然需要后面的(),因为这里的FnOnce代表一个没有参数也没有返回值的闭包。正如函数的定义，返回值类型可以从签名中省略，不过即便没有参数也需要括号。
     {
         let job = Box::new(f);
-        self.sender.send(Message::NewJob(job)).unwrap();
-        //在使用 execute 得到的闭包新建 Job 实例之后，将这些任务从信道的发送端发出。
-        //这里调用 send 上的 unwrap，因为发送可能会失败，这可能发生于例如停止了所有线程执行的情况，这意味着接收端停止接收新消息了。
-        //不过目前我们无法停止线程执行；只要线程池存在他们就会一直执行。使用 unwrap 是因为我们知道失败不可能发生，即便编译器不这么认为。
-        //不过到此事情还没有结束！在 worker 中，传递给 thread::spawn 的闭包仍然还只是 引用 了信道的接收端
-        //相反我们需要闭包一直循环，向信道的接收端请求任务，并在得到任务时执行他们。
+        //在使用 execute 得到的闭包新建 Job 实例之后，把任务放进队列：
+        //在线程池外部调用时进入全局注入队列，在某个 worker 执行的任务里调用时直接进入这个 worker 的本地队列。
+        self.submit(Task::new(job));
+    }
+
+    /// 以指定的优先级在默认队列中执行任务。
+    ///
+    /// 优先级高的任务先执行；等待时间长的任务会逐渐提升优先级（见 [`Builder::aging`]），所以低优先级的任务不会被饿死。
+    pub fn execute_with<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit(Task::with(Box::new(f), 0, priority));
+    }
+
+    /// 以指定的优先级在命名队列中执行任务，队列需要先通过 [`Builder::queue`] 创建。
+    ///
+    /// # Panics
+    ///
+    /// 没有名为 `queue` 的队列时 panic
+    pub fn execute_in<F>(&self, queue: &str, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let index = match self.shared.queue_index(queue) {
+            Some(index) => index,
+            None => panic!("no queue named {:?} in this pool", queue),
+        };
+        self.submit(Task::with(Box::new(f), index, priority));
+    }
+
+    //关闭线程池需要拿走 ThreadPool 本身，所以通过 ThreadPool 提交的任务不会被拒绝
+    fn submit(&self, task: Task) {
+        let _ = submit(&self.shared, task);
+    }
+
+    pub(crate) fn spawner(&self) -> Spawner {
+        Spawner(Arc::clone(&self.shared))
+    }
+
+    /// 在运行时把线程池调整为固定的 `size` 个 worker。
+    ///
+    /// 变大时立即启动新的 worker；变小时多出来的 worker 在执行完手头的任务后退出。
+    ///
+    /// # Panics
+    ///
+    /// `size` 为 0 时 panic
+    pub fn resize(&self, size: usize) {
+        assert!(size > 0);
+        self.shared.set_bounds(size, size);
+        while spawn_worker(&self.shared, size) {}
+    }
+
+    /// 任务计数器、等待时间和执行时间直方图以及线程池规模的快照
+    pub fn stats(&self) -> PoolStats {
+        self.shared.instrumentation.snapshot(self.metrics())
+    }
+
+    /// 线程池当前的规模
+    pub fn metrics(&self) -> PoolMetrics {
+        pool_metrics(&self.shared)
+    }
+
+    /// 优雅地关闭线程池：不再接收新任务，等待队列中所有任务执行完，再等待所有 worker 退出。
+    ///
+    /// 与直接 drop 线程池的效果相同。如果某个任务永远不会结束，这个方法也永远不会返回，
+    /// 需要限定时间时请使用 [`ThreadPool::shutdown_timeout`]。
+    pub fn shutdown(mut self) {
+        self.timer.stop();
+        self.shared.shutdown();
+        self.join_workers(None);
+    }
+
+    /// 立即关闭线程池：正在执行的任务会执行完，还没有开始的任务不再执行，而是作为返回值交还给调用者。
+    pub fn shutdown_now(mut self) -> Vec<Job> {
+        self.timer.stop();
+        let mut jobs = self.shared.stop();
+        self.join_workers(None);
+        //worker 退出时还给注入队列的任务（见 Shared::stop），以及在 stop 之前通过了检查、还没放进队列的任务
+        loop {
+            jobs.extend(self.shared.take_pending());
+            if self.shared.pending() == 0 {
+                return jobs;
+            }
+            thread::yield_now();
+        }
+    }
+
+    /// 带期限地优雅关闭线程池。
+    ///
+    /// 在 `timeout` 之内与 [`ThreadPool::shutdown`] 相同；到期之后放弃剩余的任务，
+    /// 不再等待还没有退出的 worker（它们的线程会被分离，在手头的任务结束后自行退出）。
+    ///
+    /// # Errors
+    ///
+    /// 如果到期时还有 worker 没有退出，返回 [`ShutdownTimeout`]，其中包含这些 worker 的 id 和没来得及执行的任务。
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Result<(), ShutdownTimeout> {
+        //timeout 大到超出 Instant 的范围时一直等下去，与 shutdown 相同
+        let deadline = Instant::now().checked_add(timeout);
+        self.timer.stop();
+        self.shared.shutdown();
+        let workers = self.join_workers(deadline);
+        if workers.is_empty() {
+            return Ok(());
+        }
+        //到期了：让还在运行的 worker 执行完手头的任务就退出，剩下的任务交还给调用者
+        let pending = self.shared.stop();
+        Err(ShutdownTimeout { workers, pending })
+    }
+
+    //等待所有 worker 退出并 join 它们的线程，返回在 deadline 之前没有退出的 worker 的 id。
+    //没有退出的 worker 的 JoinHandle 会被丢弃，线程因此被分离，而不是让调用者一直阻塞下去。
+    //worker 列表会被整个取出来，等待期间不持有它的锁。
+    fn join_workers(&mut self, deadline: Option<Instant>) -> Vec<usize> {
+        let mut stuck = Vec::new();
+        let mut workers = mem::take(&mut *self.shared.workers.lock().unwrap());
+        for worker in workers.iter_mut() {
+            if let Some(thread) = worker.thread.take() {
+                if self.shared.wait_exited(worker.id, deadline) {
+                    thread.join().unwrap();
+                } else {
+                    stuck.push(worker.id);
+                }
+            }
+        }
+        stuck
     }
 }
 
@@ -159,21 +568,23 @@ impl ThreadPool {
 //接着会为 ThreadPool 实现一个告诉线程他们应该停止接收新请求并结束的方式。
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        //向每个 worker 发送一个 Terminate 消息 如果尝试在同一循环中发送消息并立即 join 线程，则无法保证当前迭代的 worker 是从信道收到终止消息的 worker。
-        //为了更好的理解为什么需要两个分开的循环，想象一下只有两个 worker 的场景。如果在一个单独的循环中遍历每个 worker，在第一次迭代中向信道发出终止消息并对第一个 worker 线程调用 join。
-        //如果此时第一个 worker 正忙于处理请求，那么第二个 worker 会收到终止消息并停止。我们会一直等待第一个 worker 结束，不过它永远也不会结束因为第二个线程接收了终止消息。死锁！
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+        //最初这里向每个 worker 发送一个 Terminate 消息，再在第二个循环里 join。
+        //必须分成两个循环：如果在同一循环中发送消息并立即 join，无法保证当前迭代的 worker 就是收到终止消息的那个 worker，可能会死锁。
+        //现在只需设置一次 shutdown 标志并唤醒所有 worker，它们会先处理完队列中剩余的任务再退出。
+        //通过 shutdown、shutdown_now 或 shutdown_timeout 关闭过的线程池不需要再做任何事
+        //先停掉定时器线程，之后就不会再有到期的任务被提交进来了，还没到期的任务直接丢弃
+        self.timer.stop();
+        let mut workers = mem::take(&mut *self.shared.workers.lock().unwrap());
+        if workers.iter().all(|worker| worker.thread.is_none()) {
+            return;
         }
+        self.shared.shutdown();
 
-        println!("Shutting down all workers.");
-
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-
+        //最初这里会打印 "Shutting down all workers." 和 "Shutting down worker {}"，现在 worker 退出时会通过 EventHook::worker_exited 通知
+        for worker in workers.iter_mut() {
             //worker.thread.join().unwrap(); //此时会报错因为join需要的是线程的所有权而不是 可变引用 （所以需要将拿到线程的所有权可以使用Option可以在 Option 上调用 take 方法将值从 Some 成员中移动出来而对 None 成员不做处理。
             //这里遍历线程中的每个workers。 这里使用了&mut 因为self本身是一个可变引用而且也需要能够修改worker
-            //对于每一个线程，会打印出说明信息并表明worker正在关闭，接着在 worker 线程上调用 join。如果 join 调用失败，通过 unwrap 使得 panic 并进行不优雅的关闭。
+            //对于每一个线程，在 worker 线程上调用 join。如果 join 调用失败，通过 unwrap 使得 panic 并进行不优雅的关闭。
 
             //如第十七章我们见过的，Option 上的 take 方法会取出 Some 而留下 None。
             //使用if let 解构some并得到了线程，接着在线程上调用join。如果 worker 的线程已然是 None，就知道此时这个 worker 已经清理了其线程所以无需做任何操作。
@@ -186,5 +597,424 @@ impl Drop for ThreadPool {
             //如果采用这个实现来尝试丢弃ThreadPool，则主线程永远阻塞在等待第一个线程结束上。
             //为了修复这个问题，修改线程既监听是否有 Job 运行也要监听一个应该停止监听并退出无限循环的信号。
         }
+        //drop 会一直等到所有任务都结束，如果某个任务永远不会结束，drop 也会永远阻塞。需要限定时间时先调用 shutdown_timeout。
+    }
+}
+
+/// [`ThreadPool::shutdown_timeout`] 到期时仍有 worker 没有退出。
+pub struct ShutdownTimeout {
+    /// 没有在期限内退出的 worker 的 id
+    pub workers: Vec<usize>,
+    /// 到期时还没有开始执行的任务
+    pub pending: Vec<Job>,
+}
+
+impl fmt::Debug for ShutdownTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownTimeout")
+            .field("workers", &self.workers)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl fmt::Display for ShutdownTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "workers {:?} did not stop in time ({} pending jobs abandoned)",
+            self.workers,
+            self.pending.len()
+        )
+    }
+}
+
+impl Error for ShutdownTimeout {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Mutex};
+
+    #[test]
+    fn runs_every_job_before_drop_returns() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(4);
+        for _ in 0..1000 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(counter.load(Ordering::SeqCst), 1000);
+    }
+
+    #[test]
+    fn jobs_spawned_inside_a_worker_are_stolen_by_others() {
+        //一个任务在 worker 内部提交大量子任务，它们先进入这个 worker 的本地队列，其他 worker 应该能把它们偷走。
+        let pool = Arc::new(ThreadPool::new(4));
+        let (tx, rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        let inner = Arc::clone(&pool);
+        pool.execute(move || {
+            for _ in 0..64 {
+                let tx = tx.clone();
+                inner.execute(move || {
+                    thread::sleep(Duration::from_millis(5));
+                    tx.send(thread::current().id()).unwrap();
+                });
+            }
+            //保证最后一个 Arc 在测试线程里释放，否则 worker 会在 drop 中 join 自己
+            drop(inner);
+            done_tx.send(()).unwrap();
+        });
+        done_rx.recv().unwrap();
+        let ids: std::collections::HashSet<_> = rx.iter().take(64).collect();
+        assert!(ids.len() > 1, "all nested jobs ran on a single worker");
+    }
+
+    #[test]
+    fn external_jobs_run_in_submission_order() {
+        //只有一个 worker 时，线程池外部提交的任务应当按提交的顺序执行，即使它们被成批搬进了本地队列
+        let pool = ThreadPool::new(1);
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        pool.execute(move || gate_rx.recv().unwrap());
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..100 {
+            let order = Arc::clone(&order);
+            pool.execute(move || order.lock().unwrap().push(i));
+        }
+        gate_tx.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(*order.lock().unwrap(), (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shutdown_runs_queued_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(2);
+        for _ in 0..100 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                thread::sleep(Duration::from_micros(100));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.shutdown();
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn shutdown_now_returns_jobs_that_never_started() {
+        let pool = ThreadPool::new(1);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            release_tx.send(()).unwrap();
+        });
+        let jobs = pool.shutdown_now();
+        assert_eq!(jobs.len(), 10);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        //交还的任务仍然可以由调用者自己执行
+        for job in jobs {
+            job();
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn spawner_refuses_jobs_after_the_pool_stops() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        for stop_now in [false, true] {
+            let pool = ThreadPool::new(2);
+            let spawner = pool.spawner();
+            let c = Arc::clone(&counter);
+            assert!(spawner
+                .execute(move || {
+                    c.fetch_add(1, Ordering::SeqCst);
+                })
+                .is_ok());
+            if stop_now {
+                for job in pool.shutdown_now() {
+                    job();
+                }
+            } else {
+                pool.shutdown();
+            }
+            //任务被原样交还，而不是进了没人处理的队列
+            let c = Arc::clone(&counter);
+            let job = spawner
+                .execute(move || {
+                    c.fetch_add(1, Ordering::SeqCst);
+                })
+                .unwrap_err();
+            job();
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn shutdown_now_accounts_for_every_job() {
+        //任务在执行时继续提交子任务，worker 之间不停地成批搬运和窃取任务，这时调用 shutdown_now 也不能丢掉任何任务：
+        //每个提交过的任务要么已经执行了，要么出现在返回值里
+        #[derive(Clone)]
+        struct Tree {
+            spawner: Spawner,
+            submitted: Arc<AtomicUsize>,
+            executed: Arc<AtomicUsize>,
+        }
+
+        impl Tree {
+            fn submit(&self, depth: u32) {
+                let tree = self.clone();
+                if self.spawner.execute(move || tree.run(depth)).is_ok() {
+                    self.submitted.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+
+            fn run(&self, depth: u32) {
+                self.executed.fetch_add(1, Ordering::SeqCst);
+                if depth > 0 {
+                    self.submit(depth - 1);
+                    self.submit(depth - 1);
+                }
+            }
+        }
+
+        for _ in 0..50 {
+            let pool = ThreadPool::new(4);
+            let tree = Tree {
+                spawner: pool.spawner(),
+                submitted: Arc::new(AtomicUsize::new(0)),
+                executed: Arc::new(AtomicUsize::new(0)),
+            };
+            for _ in 0..200 {
+                tree.submit(6);
+            }
+            let returned = pool.shutdown_now().len();
+            assert_eq!(
+                tree.executed.load(Ordering::SeqCst) + returned,
+                tree.submitted.load(Ordering::SeqCst)
+            );
+        }
+    }
+
+    #[test]
+    fn shutdown_timeout_reports_stuck_workers() {
+        let pool = ThreadPool::new(2);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+        let start = Instant::now();
+        let err = pool
+            .shutdown_timeout(Duration::from_millis(100))
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(err.workers.len(), 1);
+        assert!(err.to_string().contains("did not stop in time"));
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn shutdown_timeout_succeeds_when_jobs_finish() {
+        let pool = ThreadPool::new(2);
+        pool.execute(|| thread::sleep(Duration::from_millis(10)));
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    //等待某个条件成立，最多等 5 秒
+    fn eventually(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    #[test]
+    fn grows_when_queue_backs_up_and_shrinks_after_keep_alive() {
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(4)
+            .keep_alive(Duration::from_millis(50))
+            .build();
+        assert_eq!(pool.metrics().current_workers, 1);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        for _ in 0..8 {
+            let release_rx = Arc::clone(&release_rx);
+            pool.execute(move || {
+                let _ = release_rx.lock().unwrap().recv();
+            });
+        }
+        assert!(eventually(|| pool.metrics().current_workers == 4));
+        drop(release_tx);
+        assert!(eventually(|| pool.metrics().current_workers == 1));
+        let metrics = pool.metrics();
+        assert_eq!(metrics.peak_workers, 4);
+        assert_eq!(metrics.idle_workers, 1);
+    }
+
+    #[test]
+    fn resize_changes_worker_count_at_runtime() {
+        let pool = ThreadPool::new(2);
+        pool.resize(5);
+        assert_eq!(pool.metrics().current_workers, 5);
+        pool.resize(1);
+        assert!(eventually(|| pool.metrics().current_workers == 1));
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.shutdown();
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn fixed_pool_does_not_grow() {
+        let pool = ThreadPool::new(2);
+        for _ in 0..10 {
+            pool.execute(|| thread::sleep(Duration::from_millis(10)));
+        }
+        assert_eq!(pool.metrics().current_workers, 2);
+        assert_eq!(pool.metrics().peak_workers, 2);
+    }
+
+    #[derive(Default)]
+    struct CountingHook {
+        queued: AtomicUsize,
+        started: AtomicUsize,
+        finished: AtomicUsize,
+        panicked: AtomicUsize,
+        exited: AtomicUsize,
+    }
+
+    impl EventHook for CountingHook {
+        fn job_queued(&self) {
+            self.queued.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn job_started(&self, _worker: usize, _queue_wait: Duration) {
+            self.started.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn job_finished(&self, _worker: usize, _run_time: Duration) {
+            self.finished.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn job_panicked(&self, _worker: usize, _run_time: Duration) {
+            self.panicked.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn worker_exited(&self, _worker: usize) {
+            self.exited.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn event_hook_sees_every_job_and_worker_exit() {
+        let hook = Arc::new(CountingHook::default());
+        let pool = ThreadPool::builder()
+            .min_threads(2)
+            .max_threads(2)
+            .event_hook(hook.clone())
+            .build();
+        for i in 0..20 {
+            pool.execute(move || {
+                if i == 7 {
+                    panic!("job {} failed", i);
+                }
+            });
+        }
+        pool.shutdown();
+        assert_eq!(hook.queued.load(Ordering::SeqCst), 20);
+        assert_eq!(hook.started.load(Ordering::SeqCst), 20);
+        assert_eq!(hook.finished.load(Ordering::SeqCst), 19);
+        assert_eq!(hook.panicked.load(Ordering::SeqCst), 1);
+        assert_eq!(hook.exited.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn panicking_job_does_not_kill_the_worker() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("boom"));
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let stats = pool.stats();
+        assert_eq!(stats.jobs_panicked, 1);
+        assert_eq!(stats.workers.current_workers, 1);
+    }
+
+    #[test]
+    fn stats_record_counts_and_latencies() {
+        let pool = ThreadPool::new(2);
+        for _ in 0..10 {
+            pool.execute(|| thread::sleep(Duration::from_millis(2)));
+        }
+        assert!(eventually(|| pool.stats().jobs_completed == 10));
+        let stats = pool.stats();
+        assert_eq!(stats.jobs_queued, 10);
+        assert_eq!(stats.run_time.count, 10);
+        assert_eq!(stats.queue_wait.count, 10);
+        assert!(stats.run_time.quantile(0.5) >= Duration::from_millis(2));
+    }
+
+    #[test]
+    fn high_priority_jobs_overtake_queued_ones() {
+        let pool = ThreadPool::builder().queue("bulk", 1).build();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        //先用一个任务占住唯一的 worker，让后面的任务都在队列里排队
+        pool.execute(move || gate_rx.recv().unwrap());
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for (name, priority) in [
+            ("low", Priority::Low),
+            ("normal", Priority::Normal),
+            ("high", Priority::High),
+        ] {
+            let order = Arc::clone(&order);
+            pool.execute_with(priority, move || order.lock().unwrap().push(name));
+        }
+        gate_tx.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(*order.lock().unwrap(), vec!["high", "normal", "low"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "no queue named")]
+    fn unknown_queue_panics() {
+        let pool = ThreadPool::new(1);
+        pool.execute_in("missing", Priority::Normal, || {});
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_sized_pool_panics() {
+        ThreadPool::new(0);
     }
 }
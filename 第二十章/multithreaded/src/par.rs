@@ -0,0 +1,186 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 16:48:05
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 16:48:05
+ * @Description: 并行迭代的辅助方法：par_map、par_for_each、par_chunks
+ */
+use crate::ThreadPool;
+
+//以前在线程池上做并行计算都要手写一遍：把 Vec 切成几块，每块 execute 一个任务，再通过信道把结果收回来按顺序拼好。
+//这几个方法在 scope 的基础上把这套流程固定下来：
+// 1. 输入切成若干块，每块作为一个作用域任务执行，任务可以直接借用 f 和输出缓冲区，不需要 'static，也不需要信道；
+// 2. 每块把结果写进属于自己的输出缓冲区，最后按块的顺序拼起来，所以结果的顺序与输入的顺序一致；
+// 3. 某个元素上的 f panic 时，scope 会等所有块都结束之后在调用者的线程上重新 panic。
+
+//每个 worker 分到多少块。块太少时，某一块特别慢就会让其他 worker 干等；块太多则调度的开销变大。
+const CHUNKS_PER_WORKER: usize = 4;
+
+impl ThreadPool {
+    //把 len 个元素平均分成若干块时每块的大小
+    fn chunk_len(&self, len: usize) -> usize {
+        let chunks = self.metrics().max_threads * CHUNKS_PER_WORKER;
+        len.div_ceil(chunks).max(1)
+    }
+
+    /// 并行地对每个元素调用 `f`，按输入的顺序返回结果，相当于并行版本的 `iter.map(f).collect()`。
+    ///
+    /// ```
+    /// use multithreaded::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let squares = pool.par_map(1..=5, |x| x * x);
+    /// assert_eq!(squares, vec![1, 4, 9, 16, 25]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// 如果 `f` 在某个元素上 panic，所有元素处理完之后 `par_map` 会带着第一个 panic 的信息 panic。
+    pub fn par_map<I, F, R>(&self, iter: I, f: F) -> Vec<R>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> R + Sync,
+        R: Send,
+    {
+        let mut items: Vec<I::Item> = iter.into_iter().collect();
+        let chunk_len = self.chunk_len(items.len());
+        //把输入切成若干个拥有所有权的块，每块在一个任务里被消费掉。
+        //从尾部往前切，每次 split_off 只需要搬动最后一块，而不是剩下的全部元素。
+        let mut inputs = Vec::new();
+        while !items.is_empty() {
+            let start = (items.len() - 1) / chunk_len * chunk_len;
+            inputs.push(items.split_off(start));
+        }
+        inputs.reverse();
+
+        let len = inputs.iter().map(Vec::len).sum();
+        let mut outputs: Vec<Vec<R>> = inputs
+            .iter()
+            .map(|input| Vec::with_capacity(input.len()))
+            .collect();
+        let f = &f;
+        self.scope(|s| {
+            for (input, output) in inputs.into_iter().zip(outputs.iter_mut()) {
+                s.spawn(move || output.extend(input.into_iter().map(f)));
+            }
+        });
+        //scope 正常返回说明每一块都执行完了，按块的顺序拼起来就是按输入顺序排列的结果
+        let mut results = Vec::with_capacity(len);
+        for output in outputs {
+            results.extend(output);
+        }
+        results
+    }
+
+    /// 并行地对每个元素调用 `f`，所有元素处理完之后返回。元素之间的执行顺序不确定。
+    ///
+    /// # Panics
+    ///
+    /// 与 [`ThreadPool::par_map`] 相同
+    pub fn par_for_each<I, F>(&self, iter: I, f: F)
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) + Sync,
+    {
+        self.par_map(iter, f);
+    }
+
+    /// 把 `slice` 切成每块 `chunk_size` 个元素（最后一块可能不足），并行地对每一块调用 `f`，按块的顺序返回结果。
+    ///
+    /// 切分的方式与 [`slice::chunks`] 相同。
+    ///
+    /// ```
+    /// use multithreaded::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let numbers: Vec<u64> = (1..=10).collect();
+    /// let sums = pool.par_chunks(&numbers, 4, |chunk| chunk.iter().sum::<u64>());
+    /// assert_eq!(sums, vec![10, 26, 19]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// `chunk_size` 为 0 时 panic；`f` panic 时与 [`ThreadPool::par_map`] 相同
+    pub fn par_chunks<T, F, R>(&self, slice: &[T], chunk_size: usize, f: F) -> Vec<R>
+    where
+        T: Sync,
+        F: Fn(&[T]) -> R + Sync,
+        R: Send,
+    {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        self.par_map(slice.chunks(chunk_size), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn par_map_preserves_input_order() {
+        let pool = ThreadPool::new(4);
+        let words: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        let lengths = pool.par_map(words.iter(), |word| word.len());
+        assert_eq!(
+            lengths,
+            words.iter().map(|word| word.len()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn par_map_handles_empty_and_short_inputs() {
+        let pool = ThreadPool::new(4);
+        assert_eq!(
+            pool.par_map(Vec::<u32>::new(), |x| x + 1),
+            Vec::<u32>::new()
+        );
+        assert_eq!(pool.par_map(vec![41], |x| x + 1), vec![42]);
+    }
+
+    #[test]
+    fn par_for_each_visits_every_element() {
+        let pool = ThreadPool::new(3);
+        let total = AtomicUsize::new(0);
+        pool.par_for_each(1..=100, |x| {
+            total.fetch_add(x, Ordering::SeqCst);
+        });
+        assert_eq!(total.into_inner(), 5050);
+    }
+
+    #[test]
+    fn par_chunks_matches_slice_chunks() {
+        let pool = ThreadPool::new(4);
+        let numbers: Vec<u32> = (0..103).collect();
+        let expected: Vec<u32> = numbers.chunks(10).map(|chunk| chunk.iter().sum()).collect();
+        let sums = pool.par_chunks(&numbers, 10, |chunk| chunk.iter().sum::<u32>());
+        assert_eq!(sums, expected);
+    }
+
+    #[test]
+    fn panics_propagate_after_every_element_is_processed() {
+        let pool = ThreadPool::new(4);
+        let processed = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.par_map(0..100, |x| {
+                if x == 13 {
+                    panic!("unlucky");
+                }
+                processed.fetch_add(1, Ordering::SeqCst);
+            })
+        }));
+        assert_eq!(result.unwrap_err().downcast_ref::<&str>(), Some(&"unlucky"));
+        //出错的那一块里排在 13 后面的元素不会再处理，其他块都会处理完
+        assert!(processed.load(Ordering::SeqCst) >= 100 - pool.chunk_len(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "chunk size must be non-zero")]
+    fn zero_chunk_size_panics() {
+        let pool = ThreadPool::new(1);
+        pool.par_chunks(&[1, 2, 3], 0, |chunk| chunk.len());
+    }
+}
@@ -0,0 +1,305 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 15:10:26
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 02:31:08
+ * @Description: 任务优先级与命名队列：队列之间按权重公平调度，队列内部按优先级调度，并通过老化避免饿死
+ */
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::queue::Task;
+
+//最初所有任务都通过同一个 mpsc 信道以 Message::NewJob 的形式先进先出地排队，
+//健康检查这样很轻、但要求及时响应的任务只能排在一大批上传任务后面。
+//现在线程池外部提交的任务进入的注入队列分成了若干个命名队列，每个命名队列里又按优先级分成几条通道（lane）：
+// 1. 队列之间按权重做步幅调度（stride scheduling）：每个队列有一个“行程”（pass），每次从行程最小的非空队列里取任务，
+//    取完之后它的行程增加 STRIDE / weight，于是长期来看各个队列被取到的次数与权重成正比；
+// 2. 同一个队列里优先取优先级高的任务；
+// 3. 老化（aging）：任务每在队列里多等待一个 aging 间隔，优先级就提升一级，最多提升到 High。
+//    优先级相同时先取等得久的，所以低优先级任务最多等待 2 个 aging 间隔就会和新来的 High 任务平起平坐，不会被无限期饿死。
+
+/// 任务的优先级
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// 批量任务，例如大文件上传
+    Low,
+    /// [`ThreadPool::execute`](crate::ThreadPool::execute) 使用的默认优先级
+    #[default]
+    Normal,
+    /// 需要尽快响应的任务，例如健康检查
+    High,
+}
+
+impl Priority {
+    const LEVELS: usize = 3;
+
+    fn level(self) -> usize {
+        self as usize
+    }
+}
+
+/// 总是存在的默认队列的名字，[`ThreadPool::execute`](crate::ThreadPool::execute) 和
+/// [`ThreadPool::execute_with`](crate::ThreadPool::execute_with) 提交的任务都进入这个队列
+pub const DEFAULT_QUEUE: &str = "default";
+
+//步幅调度中的常数，权重为 w 的队列每被调度一次，行程增加 STRIDE / w
+const STRIDE: u64 = 1 << 20;
+
+struct NamedQueue {
+    stride: u64,
+    pass: u64,
+    lanes: [VecDeque<Task>; Priority::LEVELS],
+}
+
+impl NamedQueue {
+    fn is_empty(&self) -> bool {
+        self.lanes.iter().all(VecDeque::is_empty)
+    }
+}
+
+//注入队列。本身不加锁，由 Shared 放在 Mutex 里使用。
+pub(crate) struct Injector {
+    queues: Vec<NamedQueue>,
+    //最近一次被调度的队列的行程，相当于整个调度器的“虚拟时间”
+    now_pass: u64,
+    aging: Duration,
+    len: usize,
+    //队列里优先级为 High 的任务数（不包括老化之后才变成 High 的任务）
+    high: usize,
+}
+
+impl Injector {
+    //weights 的下标就是队列的下标
+    pub(crate) fn new(weights: &[u32], aging: Duration) -> Injector {
+        Injector {
+            queues: weights
+                .iter()
+                .map(|&weight| NamedQueue {
+                    stride: STRIDE / u64::from(weight),
+                    pass: 0,
+                    lanes: Default::default(),
+                })
+                .collect(),
+            now_pass: 0,
+            aging,
+            len: 0,
+            high: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn high(&self) -> usize {
+        self.high
+    }
+
+    pub(crate) fn push(&mut self, task: Task) {
+        let queue = &mut self.queues[task.queue];
+        //空了一段时间的队列重新有任务时，行程至少从当前的虚拟时间开始，
+        //否则它会攒下一大笔“没用完的份额”，回来之后长时间独占 worker
+        if queue.is_empty() {
+            queue.pass = queue.pass.max(self.now_pass);
+        }
+        if task.priority == Priority::High {
+            self.high += 1;
+        }
+        queue.lanes[task.priority.level()].push_back(task);
+        self.len += 1;
+    }
+
+    //按照调度策略下一个会被取出的任务入队的时间
+    pub(crate) fn next_queued_at(&self) -> Option<Instant> {
+        let (index, lane) = self.next_lane()?;
+        self.queues[index].lanes[lane]
+            .front()
+            .map(|task| task.queued_at)
+    }
+
+    //按照调度策略取出下一个任务
+    pub(crate) fn pop(&mut self) -> Option<Task> {
+        let (index, lane) = self.next_lane()?;
+        let queue = &mut self.queues[index];
+        let task = queue.lanes[lane].pop_front()?;
+        self.now_pass = queue.pass;
+        queue.pass += queue.stride;
+        self.taken(&task);
+        Some(task)
+    }
+
+    //取出下一个任务；如果注入队列里所有的任务都在同一条通道里，顺便再取出最多 max 个同一通道的任务。
+    //此时不存在调度顺序的问题，成批搬到 worker 的本地队列可以减少对注入队列的锁的争用。
+    pub(crate) fn pop_batch(&mut self, max: usize) -> Option<(Task, Vec<Task>)> {
+        let (index, lane) = self.next_lane()?;
+        let first = self.pop()?;
+        let lane = &mut self.queues[index].lanes[lane];
+        if lane.len() != self.len {
+            return Some((first, Vec::new()));
+        }
+        let count = lane.len().min(max);
+        let batch: Vec<Task> = lane.drain(..count).collect();
+        for task in &batch {
+            self.taken(task);
+        }
+        Some((first, batch))
+    }
+
+    pub(crate) fn drain(&mut self) -> Vec<Task> {
+        let tasks: Vec<Task> = self
+            .queues
+            .iter_mut()
+            .flat_map(|queue| queue.lanes.iter_mut().flat_map(|lane| lane.drain(..)))
+            .collect();
+        self.len = 0;
+        self.high = 0;
+        tasks
+    }
+
+    fn taken(&mut self, task: &Task) {
+        self.len -= 1;
+        if task.priority == Priority::High {
+            self.high -= 1;
+        }
+    }
+
+    //行程最小的非空队列，以及这个队列里应该先取的通道
+    fn next_lane(&self) -> Option<(usize, usize)> {
+        let (index, queue) = self
+            .queues
+            .iter()
+            .enumerate()
+            .filter(|(_, queue)| !queue.is_empty())
+            .min_by_key(|(_, queue)| queue.pass)?;
+        let now = Instant::now();
+        //每条通道只需要看队头：同一条通道里越靠前的任务等得越久，老化后的优先级也越高
+        let lane = queue
+            .lanes
+            .iter()
+            .enumerate()
+            .filter_map(|(lane, tasks)| tasks.front().map(|task| (lane, task)))
+            .max_by_key(|(_, task)| {
+                let waited = now.saturating_duration_since(task.queued_at);
+                (self.effective_level(task.priority, waited), waited)
+            })
+            .map(|(lane, _)| lane)?;
+        Some((index, lane))
+    }
+
+    fn effective_level(&self, priority: Priority, waited: Duration) -> usize {
+        let boost = if self.aging.is_zero() {
+            Priority::LEVELS
+        } else {
+            usize::try_from(waited.as_nanos() / self.aging.as_nanos()).unwrap_or(usize::MAX)
+        };
+        priority
+            .level()
+            .saturating_add(boost)
+            .min(Priority::High.level())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn task(queue: usize, priority: Priority, id: usize, log: &Arc<Mutex<Vec<usize>>>) -> Task {
+        let log = Arc::clone(log);
+        Task::with(
+            Box::new(move || log.lock().unwrap().push(id)),
+            queue,
+            priority,
+        )
+    }
+
+    fn run_all(injector: &mut Injector) {
+        while let Some(task) = injector.pop() {
+            (task.job)();
+        }
+    }
+
+    #[test]
+    fn higher_priority_runs_first_within_a_queue() {
+        let log = Default::default();
+        let mut injector = Injector::new(&[1], Duration::from_secs(60));
+        injector.push(task(0, Priority::Low, 1, &log));
+        injector.push(task(0, Priority::Normal, 2, &log));
+        injector.push(task(0, Priority::High, 3, &log));
+        injector.push(task(0, Priority::Normal, 4, &log));
+        assert_eq!(injector.high(), 1);
+        run_all(&mut injector);
+        assert_eq!(*log.lock().unwrap(), vec![3, 2, 4, 1]);
+        assert_eq!(injector.len(), 0);
+        assert_eq!(injector.high(), 0);
+    }
+
+    #[test]
+    fn queues_are_served_in_proportion_to_their_weights() {
+        let log = Default::default();
+        let mut injector = Injector::new(&[1, 3], Duration::from_secs(60));
+        for _ in 0..40 {
+            injector.push(task(0, Priority::Normal, 0, &log));
+            injector.push(task(1, Priority::Normal, 1, &log));
+        }
+        for _ in 0..40 {
+            (injector.pop().unwrap().job)();
+        }
+        let served = log.lock().unwrap();
+        let heavy = served.iter().filter(|&&queue| queue == 1).count();
+        assert_eq!(heavy, 30);
+    }
+
+    #[test]
+    fn aged_low_priority_jobs_overtake_fresh_high_priority_jobs() {
+        let log = Default::default();
+        let mut injector = Injector::new(&[1], Duration::from_millis(10));
+        injector.push(task(0, Priority::Low, 1, &log));
+        thread::sleep(Duration::from_millis(25));
+        for id in 2..5 {
+            injector.push(task(0, Priority::High, id, &log));
+        }
+        run_all(&mut injector);
+        assert_eq!(*log.lock().unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn an_idle_queue_does_not_bank_credit() {
+        let log = Default::default();
+        let mut injector = Injector::new(&[1, 1], Duration::from_secs(60));
+        for _ in 0..100 {
+            injector.push(task(0, Priority::Normal, 0, &log));
+        }
+        for _ in 0..90 {
+            (injector.pop().unwrap().job)();
+        }
+        log.lock().unwrap().clear();
+        for _ in 0..10 {
+            injector.push(task(1, Priority::Normal, 1, &log));
+        }
+        for _ in 0..10 {
+            (injector.pop().unwrap().job)();
+        }
+        //两个队列权重相同，回来的队列不应该因为之前空闲而连续被调度
+        let served = log.lock().unwrap();
+        assert_eq!(served.iter().filter(|&&queue| queue == 1).count(), 5);
+    }
+
+    #[test]
+    fn batches_only_when_every_task_shares_a_lane() {
+        let log = Default::default();
+        let mut injector = Injector::new(&[1], Duration::from_secs(60));
+        for id in 0..10 {
+            injector.push(task(0, Priority::Normal, id, &log));
+        }
+        let (_, batch) = injector.pop_batch(4).unwrap();
+        assert_eq!(batch.len(), 4);
+        injector.push(task(0, Priority::High, 99, &log));
+        let (first, batch) = injector.pop_batch(4).unwrap();
+        assert_eq!(first.priority, Priority::High);
+        assert!(batch.is_empty());
+        assert_eq!(injector.len(), 5);
+    }
+}
@@ -0,0 +1,588 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 09:12:40
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 03:41:19
+ * @Description: 线程池的任务队列：每个 worker 一个本地双端队列 + 全局注入队列 + 工作窃取
+ */
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::priority::{Injector, Priority};
+use crate::stats::{EventHook, Instrumentation};
+use crate::{Job, Worker};
+
+//一次从全局队列或其他 worker 那里最多搬走多少个任务。
+//批量搬运可以减少对同一把锁的争用：拿到一批之后，后面的任务都从自己的本地队列里取。
+const BATCH: usize = 32;
+
+//worker 每从队列里取这么多次任务，就优先看一次注入队列。
+//否则所有 worker 都忙着执行自己本地队列里的任务（例如任务里不断提交子任务）时，注入队列里的任务会一直没人管。
+const INJECTOR_INTERVAL: u32 = 61;
+
+thread_local! {
+    //记录当前线程是哪个线程池的哪个 worker。为 None 表示当前线程不是任何线程池的 worker。
+    static CURRENT: RefCell<Option<Current>> = const { RefCell::new(None) };
+}
+
+struct Current {
+    //Shared 的地址
+    pool: usize,
+    worker: usize,
+    index: usize,
+    //直接保存槽位的 Arc，worker 内部提交任务时就不需要再去读 slots 的锁了
+    slot: Arc<Slot>,
+}
+
+//队列中的任务，记录了入队的时间，用来统计任务在队列中等待了多久，也用来计算老化之后的优先级
+pub(crate) struct Task {
+    pub(crate) job: Job,
+    pub(crate) queued_at: Instant,
+    //所属的命名队列的下标和优先级，见 priority.rs
+    pub(crate) queue: usize,
+    pub(crate) priority: Priority,
+}
+
+impl Task {
+    //默认队列中的普通优先级任务
+    pub(crate) fn new(job: Job) -> Task {
+        Task::with(job, 0, Priority::Normal)
+    }
+
+    pub(crate) fn with(job: Job, queue: usize, priority: Priority) -> Task {
+        Task {
+            job,
+            queued_at: Instant::now(),
+            queue,
+            priority,
+        }
+    }
+}
+
+//最初的实现里所有 worker 共享一个 Arc<Mutex<mpsc::Receiver<Message>>>，
+//而且是在持有锁的情况下调用 recv 的，于是同一时刻只有一个 worker 能够等待任务，任务的分发被完全串行化了。
+//现在换成工作窃取（work stealing）的设计：
+// 1. 每个 worker 都有自己的本地双端队列，worker 自己从队尾取（后进先出，缓存更友好），
+//    其他 worker 从队头偷（先进先出，偷走的是最老的任务）；
+// 2. 线程池外部提交的任务先进入全局的注入队列（injector），空闲的 worker 成批地把它们搬回本地；
+// 3. 本地队列和注入队列都空了，就去其他 worker 的本地队列里偷一半过来；
+// 4. 什么都找不到时才在条件变量上睡眠，直到有新任务或者线程池关闭。
+//每把锁只在 push/pop 的一瞬间被持有，从来不会在持锁期间阻塞等待。
+//
+//worker 的数量可以在 min 和 max 之间变化，所以本地队列放在可以增长的槽位（Slot）里：
+//新 worker 占用一个空闲槽位（没有就新建一个），退出时把本地队列里剩下的任务还给注入队列，再把槽位放回空闲列表。
+pub(crate) struct Shared {
+    injector: Mutex<Injector>,
+    //注入队列里优先级为 High 的任务数，worker 不用加锁就能知道是否应该先看注入队列
+    urgent: AtomicUsize,
+    //命名队列的名字，下标就是 Task::queue
+    queue_names: Vec<String>,
+    slots: RwLock<Vec<Arc<Slot>>>,
+    free_slots: Mutex<Vec<usize>>,
+    //已经入队、但还没有被 worker 取走执行的任务数
+    pending: AtomicUsize,
+    //正在睡眠、而且还没有人去唤醒的 worker 所在的槽位
+    idle: Mutex<Vec<usize>>,
+    idle_count: AtomicUsize,
+    //当前存活的 worker 数、曾经达到的最大值、正在执行任务的 worker 数
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    active: AtomicUsize,
+    min: AtomicUsize,
+    max: AtomicUsize,
+    keep_alive: Duration,
+    //不再接收新任务，处理完剩余任务后退出
+    shutdown: AtomicBool,
+    //放弃剩余任务，当前任务执行完就退出
+    stop: AtomicBool,
+    //已经退出的 worker，用来在关闭时带超时地等待
+    exited: Mutex<HashSet<usize>>,
+    exit_event: Condvar,
+    pub(crate) instrumentation: Instrumentation,
+    //所有 worker 的线程句柄，以及下一个 worker 的 id
+    pub(crate) workers: Mutex<Vec<Worker>>,
+    next_worker_id: AtomicUsize,
+}
+
+pub(crate) struct Slot {
+    local: Mutex<VecDeque<Task>>,
+    sleeper: Sleeper,
+    //这个槽位的 worker 取过多少次任务，只有它自己会修改
+    ticks: AtomicU32,
+}
+
+//每个 worker 单独睡眠在自己的条件变量上，唤醒时把它从 idle 列表里取出来。
+//这样连续提交很多任务时只会唤醒真正在睡的那几个 worker，
+//而不是每次提交都去 notify 一个已经被唤醒、只是还没来得及运行的 worker。
+struct Sleeper {
+    notified: Mutex<bool>,
+    wakeup: Condvar,
+}
+
+impl Shared {
+    pub(crate) fn new(
+        min: usize,
+        max: usize,
+        keep_alive: Duration,
+        hook: Option<Arc<dyn EventHook>>,
+        queues: Vec<(String, u32)>,
+        aging: Duration,
+    ) -> Shared {
+        let weights: Vec<u32> = queues.iter().map(|&(_, weight)| weight).collect();
+        Shared {
+            injector: Mutex::new(Injector::new(&weights, aging)),
+            urgent: AtomicUsize::new(0),
+            queue_names: queues.into_iter().map(|(name, _)| name).collect(),
+            slots: RwLock::new(Vec::new()),
+            free_slots: Mutex::new(Vec::new()),
+            pending: AtomicUsize::new(0),
+            idle: Mutex::new(Vec::new()),
+            idle_count: AtomicUsize::new(0),
+            current: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            min: AtomicUsize::new(min),
+            max: AtomicUsize::new(max),
+            keep_alive,
+            shutdown: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+            exited: Mutex::new(HashSet::new()),
+            exit_event: Condvar::new(),
+            instrumentation: Instrumentation::new(hook),
+            workers: Mutex::new(Vec::with_capacity(max)),
+            next_worker_id: AtomicUsize::new(0),
+        }
+    }
+
+    fn id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    //命名队列的下标
+    pub(crate) fn queue_index(&self, name: &str) -> Option<usize> {
+        self.queue_names.iter().position(|queue| queue == name)
+    }
+
+    pub(crate) fn next_worker_id(&self) -> usize {
+        self.next_worker_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn slot(&self, index: usize) -> Arc<Slot> {
+        Arc::clone(&self.slots.read().unwrap()[index])
+    }
+
+    //为新 worker 分配一个槽位，返回槽位下标和槽位本身
+    pub(crate) fn acquire_slot(&self) -> (usize, Arc<Slot>) {
+        if let Some(index) = self.free_slots.lock().unwrap().pop() {
+            return (index, self.slot(index));
+        }
+        let slot = Arc::new(Slot {
+            local: Mutex::new(VecDeque::new()),
+            sleeper: Sleeper {
+                notified: Mutex::new(false),
+                wakeup: Condvar::new(),
+            },
+            ticks: AtomicU32::new(0),
+        });
+        let mut slots = self.slots.write().unwrap();
+        slots.push(Arc::clone(&slot));
+        (slots.len() - 1, slot)
+    }
+
+    //worker 退出时归还槽位，本地队列里还没执行的任务交给其他 worker
+    pub(crate) fn release_slot(&self, index: usize, slot: &Slot) {
+        self.unidle(index);
+        let tasks: Vec<Task> = slot.local.lock().unwrap().drain(..).collect();
+        *slot.sleeper.notified.lock().unwrap() = false;
+        if !tasks.is_empty() {
+            let mut injector = self.injector.lock().unwrap();
+            for task in tasks {
+                injector.push(task);
+            }
+            self.urgent.store(injector.high(), Ordering::SeqCst);
+            drop(injector);
+            self.wake_one();
+        }
+        self.free_slots.lock().unwrap().push(index);
+    }
+
+    //在 worker 线程启动时调用，之后这个线程提交的任务会直接进入它自己的本地队列。
+    pub(crate) fn register_current(&self, worker: usize, index: usize, slot: &Arc<Slot>) {
+        CURRENT.with(|current| {
+            *current.borrow_mut() = Some(Current {
+                pool: self.id(),
+                worker,
+                index,
+                slot: Arc::clone(slot),
+            })
+        });
+    }
+
+    //当前线程是否是本线程池的 worker
+    pub(crate) fn is_worker_thread(&self) -> bool {
+        CURRENT.with(
+            |current| matches!(&*current.borrow(), Some(current) if current.pool == self.id()),
+        )
+    }
+
+    //如果当前线程是本线程池的 worker，从队列里取一个任务出来。
+    //在 worker 里等待别的任务完成时（例如嵌套的 scope）用它来帮忙干活，而不是干等着占住一个 worker。
+    //返回 worker 的 id 和取到的任务
+    pub(crate) fn find_job_for_current(&self) -> Option<(usize, Task)> {
+        let (worker, index, slot) = CURRENT.with(|current| match &*current.borrow() {
+            Some(current) if current.pool == self.id() => {
+                Some((current.worker, current.index, Arc::clone(&current.slot)))
+            }
+            _ => None,
+        })?;
+        self.find_job(index, &slot).map(|task| (worker, task))
+    }
+
+    //提交任务之前先为它登记 pending，返回是否接受这个任务。
+    //线程池 shutdown 或 stop 之后，线程池外部的线程不能再提交任务：worker 处理完剩下的任务就会退出，之后入队的任务永远没人执行。
+    //先登记再检查标志（都是 SeqCst）：要么 worker 在退出之前看到了这个 pending、会等它入队，要么这里看到了关闭的标志。
+    //worker 上正在执行的任务提交的子任务总是接受的：shutdown 时 worker 会执行它们，stop 时它们会被交还给调用者。
+    pub(crate) fn admit(&self) -> bool {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        if (self.is_shutdown() || self.is_stopped()) && !self.is_worker_thread() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+
+    //提交一个已经 admit 过的任务。worker 线程内部提交的默认队列、普通优先级的任务进入它自己的本地队列，其余的进入注入队列，
+    //由注入队列按照命名队列的权重和任务的优先级决定先后。
+    pub(crate) fn push(&self, task: Task) {
+        let plain = task.queue == 0 && task.priority == Priority::Normal;
+        let task = CURRENT.with(|current| match &*current.borrow() {
+            Some(current) if plain && current.pool == self.id() => {
+                current.slot.local.lock().unwrap().push_back(task);
+                None
+            }
+            _ => Some(task),
+        });
+        if let Some(task) = task {
+            let mut injector = self.injector.lock().unwrap();
+            injector.push(task);
+            self.urgent.store(injector.high(), Ordering::SeqCst);
+        }
+        self.wake_one();
+    }
+
+    //只有确实有 worker 在睡眠时才去碰 idle 的锁，忙碌时提交任务不需要任何额外的同步。
+    //pending 和 idle_count 都使用 SeqCst：要么 worker 在睡眠前看到了新的 pending，要么这里看到了它登记的 idle。
+    fn wake_one(&self) {
+        if self.idle_count.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let index = {
+            let mut idle = self.idle.lock().unwrap();
+            let index = idle.pop();
+            if index.is_some() {
+                self.idle_count.fetch_sub(1, Ordering::SeqCst);
+            }
+            index
+        };
+        if let Some(index) = index {
+            let slot = self.slot(index);
+            *slot.sleeper.notified.lock().unwrap() = true;
+            slot.sleeper.wakeup.notify_one();
+        }
+    }
+
+    //把 worker 从 idle 列表中移除（如果它还在里面的话）
+    fn unidle(&self, index: usize) {
+        let mut idle = self.idle.lock().unwrap();
+        if let Some(position) = idle.iter().position(|&i| i == index) {
+            idle.swap_remove(position);
+            self.idle_count.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    //按照 本地队列 -> 注入队列 -> 其他 worker 的顺序寻找下一个任务。
+    //注入队列里有 High 任务时，或者每隔 INJECTOR_INTERVAL 次，先看注入队列再看本地队列。
+    //只有去偷其他 worker 的任务时才需要读 slots 的锁
+    pub(crate) fn find_job(&self, index: usize, own: &Slot) -> Option<Task> {
+        let tick = own.ticks.fetch_add(1, Ordering::Relaxed);
+        let local = || own.local.lock().unwrap().pop_back();
+        let job = if self.urgent.load(Ordering::SeqCst) > 0 {
+            self.steal_injector(own, None).or_else(local)
+        } else if tick.is_multiple_of(INJECTOR_INTERVAL) {
+            //本地队列里最新的任务都比注入队列里的任务等得久时（例如它们是上一批从注入队列搬来的），仍然先执行本地的，
+            //这样外部提交的任务保持先进先出；不断产生的子任务总是更新，不会因此把注入队列饿死
+            let newest = own.local.lock().unwrap().back().map(|task| task.queued_at);
+            self.steal_injector(own, newest).or_else(local)
+        } else {
+            local().or_else(|| self.steal_injector(own, None))
+        };
+        let job = job.or_else(|| self.steal_other(index, own));
+        if job.is_some() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+        job
+    }
+
+    //before 不为 None 时，只在注入队列的下一个任务比它更早入队时才取
+    fn steal_injector(&self, own: &Slot, before: Option<Instant>) -> Option<Task> {
+        let mut injector = self.injector.lock().unwrap();
+        if injector.len() == 0 {
+            return None;
+        }
+        if let Some(before) = before {
+            if injector.next_queued_at()? >= before {
+                return None;
+            }
+        }
+        let (job, batch) = injector.pop_batch(BATCH)?;
+        self.urgent.store(injector.high(), Ordering::SeqCst);
+        drop(injector);
+        //worker 从本地队列的队尾取任务，所以倒着放进去，这样外部提交的任务仍然按提交的顺序执行（先进先出）。
+        //否则一批里最后提交的任务最先执行，负载高时最早接受的连接反而可能一直等下去
+        if !batch.is_empty() {
+            own.local.lock().unwrap().extend(batch.into_iter().rev());
+        }
+        Some(job)
+    }
+
+    fn steal_other(&self, index: usize, own: &Slot) -> Option<Task> {
+        let slots = self.slots.read().unwrap();
+        let size = slots.len();
+        for offset in 1..size {
+            let victim = (index + offset) % size;
+            //用 try_lock：别人正在操作这个队列就换下一个，没必要排队。
+            //就算因此错过了任务，pending 仍然大于 0，worker 不会去睡眠，下一轮还会再找。
+            let mut queue = match slots[victim].local.try_lock() {
+                Ok(queue) => queue,
+                Err(_) => continue,
+            };
+            let job = match queue.pop_front() {
+                Some(job) => job,
+                None => continue,
+            };
+            //偷走剩下任务的一半
+            let count = (queue.len() / 2).min(BATCH);
+            if count > 0 {
+                let batch: Vec<Task> = queue.drain(..count).collect();
+                drop(queue);
+                own.local.lock().unwrap().extend(batch);
+            }
+            return Some(job);
+        }
+        None
+    }
+
+    //找不到任务时调用。如果确实没有待执行的任务，就睡眠到被 push 或 shutdown 唤醒。
+    //最多睡 keep_alive 这么久，超时没有等到任务返回 false，由调用者决定这个 worker 是否退出。
+    pub(crate) fn wait_for_job(&self, index: usize, slot: &Slot) -> bool {
+        if self.pending.load(Ordering::SeqCst) > 0 {
+            //有任务但是暂时没找到（例如正在被别的 worker 成批搬运），让出时间片再试
+            thread::yield_now();
+            return true;
+        }
+        self.idle.lock().unwrap().push(index);
+        self.idle_count.fetch_add(1, Ordering::SeqCst);
+        //登记之后再检查一次，避免在登记之前提交的任务没有人来唤醒
+        if self.pending.load(Ordering::SeqCst) > 0 || self.is_shutdown() || self.is_stopped() {
+            self.unidle(index);
+            return true;
+        }
+        let deadline = Instant::now() + self.keep_alive;
+        let mut notified = slot.sleeper.notified.lock().unwrap();
+        let mut timed_out = false;
+        while !*notified && !self.is_shutdown() && !self.is_stopped() && !self.over_max() {
+            let now = Instant::now();
+            if now >= deadline {
+                timed_out = true;
+                break;
+            }
+            notified = slot
+                .sleeper
+                .wakeup
+                .wait_timeout(notified, deadline - now)
+                .unwrap()
+                .0;
+        }
+        *notified = false;
+        drop(notified);
+        //被 push 唤醒时已经从 idle 中移除了，因为超时或 shutdown 醒来时需要自己移除
+        self.unidle(index);
+        !timed_out
+    }
+
+    pub(crate) fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn min(&self) -> usize {
+        self.min.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn max(&self) -> usize {
+        self.max.load(Ordering::SeqCst)
+    }
+
+    fn over_max(&self) -> bool {
+        self.current() > self.max()
+    }
+
+    pub(crate) fn set_bounds(&self, min: usize, max: usize) {
+        self.min.store(min, Ordering::SeqCst);
+        self.max.store(max, Ordering::SeqCst);
+        //让多余的空闲 worker 醒来检查自己是否应该退出
+        self.wake_all();
+    }
+
+    pub(crate) fn begin_active(&self) {
+        self.active.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn end_active(&self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    //为一个新 worker 预留名额：不超过 limit 时把 current 加一并返回 true
+    pub(crate) fn reserve_worker(&self, limit: usize) -> bool {
+        let reserved = self
+            .current
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                if current < limit {
+                    Some(current + 1)
+                } else {
+                    None
+                }
+            });
+        match reserved {
+            Ok(previous) => {
+                self.peak.fetch_max(previous + 1, Ordering::SeqCst);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    //队列积压了：等待的任务比空闲的 worker 还多，并且还没有达到上限
+    pub(crate) fn should_grow(&self) -> bool {
+        let current = self.current();
+        let idle = current.saturating_sub(self.active());
+        self.pending() > idle && current < self.max()
+    }
+
+    //worker 尝试退出：current 大于 floor 时减一并返回 true。
+    //用 fetch_update 保证多个 worker 同时退出时也不会低于 floor。
+    pub(crate) fn try_retire(&self, floor: usize) -> bool {
+        self.current
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                if current > floor {
+                    Some(current - 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+
+    //worker 因为其他原因退出（关闭或 panic）时，由它自己把 current 减一
+    pub(crate) fn worker_gone(&self) {
+        self.current.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    //通知所有 worker：不会再有新任务了，处理完队列里剩下的任务就退出
+    pub(crate) fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.wake_all();
+    }
+
+    //通知所有 worker：执行完手头的任务就退出，并取出所有还没开始的任务。
+    //worker 在取任务之前会检查 stop，所以每个任务要么已经被 worker 取走执行，要么出现在返回值里，不会两边都有。
+    //但是 worker 可能正拿着一批刚从注入队列或者别的 worker 那里搬出来、还没放进自己本地队列的任务，
+    //它看到 stop 退出时会由 release_slot 把这些任务放回注入队列。所以等所有 worker 都退出之后，
+    //还要再调用一次 take_pending 把它们取出来，见 ThreadPool::shutdown_now。
+    pub(crate) fn stop(&self) -> Vec<Job> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.wake_all();
+        self.take_pending()
+    }
+
+    //取出注入队列和所有本地队列里还没开始的任务。
+    //pending 包括已经 admit、但还没来得及放进队列的任务，所以取完之后 pending 仍然可能大于 0
+    pub(crate) fn take_pending(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self
+            .injector
+            .lock()
+            .unwrap()
+            .drain()
+            .into_iter()
+            .map(|task| task.job)
+            .collect();
+        self.urgent.store(0, Ordering::SeqCst);
+        for slot in self.slots.read().unwrap().iter() {
+            jobs.extend(slot.local.lock().unwrap().drain(..).map(|task| task.job));
+        }
+        self.pending.fetch_sub(jobs.len(), Ordering::SeqCst);
+        jobs
+    }
+
+    fn wake_all(&self) {
+        for slot in self.slots.read().unwrap().iter() {
+            let _guard = slot.sleeper.notified.lock().unwrap();
+            slot.sleeper.wakeup.notify_one();
+        }
+    }
+
+    //worker 线程退出时调用
+    pub(crate) fn mark_exited(&self, id: usize) {
+        self.exited.lock().unwrap().insert(id);
+        self.exit_event.notify_all();
+    }
+
+    //worker 被 join 之后就不需要再记录它了
+    pub(crate) fn forget_exited(&self, id: usize) {
+        self.exited.lock().unwrap().remove(&id);
+    }
+
+    //等待某个 worker 退出，deadline 为 None 时一直等。返回它是否已经退出。
+    pub(crate) fn wait_exited(&self, id: usize, deadline: Option<Instant>) -> bool {
+        let mut exited = self.exited.lock().unwrap();
+        while !exited.contains(&id) {
+            exited = match deadline {
+                None => self.exit_event.wait(exited).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.exit_event
+                        .wait_timeout(exited, deadline - now)
+                        .unwrap()
+                        .0
+                }
+            };
+        }
+        true
+    }
+}
@@ -0,0 +1,249 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 13:20:44
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-19 13:20:44
+ * @Description: 作用域任务：可以借用调用者栈上数据的任务，仿照 std::thread::scope
+ */
+use std::any::Any;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::queue::Task;
+use crate::{run_task, Job, ThreadPool};
+
+//execute 要求闭包是 'static 的，因为线程池并不知道任务什么时候才会执行完。
+//如果能保证在某个函数返回之前，它提交的所有任务都已经执行完，那这些任务借用这个函数栈上的数据就是安全的。
+//这正是 std::thread::scope 的思路：scope 在返回之前等待所有在其中创建的线程结束。
+//这里把同样的思路用在线程池上：Scope::spawn 接受只活到 'scope 的闭包，ThreadPool::scope 在返回之前等待它们全部执行完。
+
+/// 作用域，通过 [`ThreadPool::scope`] 得到。
+///
+/// 在作用域里用 [`Scope::spawn`] 提交的任务可以借用作用域外面（`'env`）的数据。
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    //与 std::thread::Scope 相同：让 'scope 和 'env 都是不变（invariant）的，
+    //防止编译器把它们缩短或者延长，从而让借用检查器认为不安全的借用是合法的。
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+struct ScopeState {
+    //还没有执行完的任务数
+    running: Mutex<usize>,
+    all_done: Condvar,
+    //第一个 panic 的任务的 panic 信息
+    panic: Mutex<Option<Box<dyn Any + Send + 'static>>>,
+}
+
+impl ScopeState {
+    fn complete(&self, result: Result<(), Box<dyn Any + Send + 'static>>) {
+        if let Err(payload) = result {
+            self.panic.lock().unwrap().get_or_insert(payload);
+        }
+        let mut running = self.running.lock().unwrap();
+        *running -= 1;
+        if *running == 0 {
+            self.all_done.notify_all();
+        }
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// 在线程池中执行一个可以借用作用域外数据的任务。
+    ///
+    /// 任务 panic 不会影响同一作用域里的其他任务，[`ThreadPool::scope`] 会在所有任务结束之后再把 panic 传播出来。
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.running.lock().unwrap() += 1;
+        let state = Arc::clone(&self.state);
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            //先执行完 f（f 和它捕获的借用在这里就被释放了），再通知作用域这个任务已经结束
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            state.complete(result);
+        });
+        //SAFETY: ThreadPool::scope 在返回之前（包括作用域内的闭包 panic 的情况）会等待 running 变为 0，
+        //所以这个任务借用的数据一定比任务活得更久。这里只是把这个保证告诉编译器。
+        let job: Job = unsafe {
+            std::mem::transmute::<
+                Box<dyn FnOnce() + Send + 'scope>,
+                Box<dyn FnOnce() + Send + 'static>,
+            >(job)
+        };
+        self.pool.submit(Task::new(job));
+    }
+}
+
+impl ThreadPool {
+    /// 创建一个作用域，在其中提交的任务可以借用非 `'static` 的数据。
+    ///
+    /// 与 [`std::thread::scope`] 相同，`scope` 在返回之前会等待作用域里提交的所有任务执行完，
+    /// 即使其中有任务 panic 或者 `f` 本身 panic 也是如此。
+    ///
+    /// ```
+    /// use multithreaded::ThreadPool;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let numbers = vec![1, 2, 3, 4, 5, 6];
+    /// let sum = AtomicUsize::new(0);
+    /// pool.scope(|s| {
+    ///     for chunk in numbers.chunks(2) {
+    ///         let sum = &sum;
+    ///         s.spawn(move || {
+    ///             sum.fetch_add(chunk.iter().sum(), Ordering::SeqCst);
+    ///         });
+    ///     }
+    /// });
+    /// assert_eq!(sum.into_inner(), 21);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// 如果作用域里的某个任务 panic 了，`scope` 会在所有任务结束之后带着第一个 panic 的信息 panic。
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                running: Mutex::new(0),
+                all_done: Condvar::new(),
+                panic: Mutex::new(None),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        self.wait_scope(&scope.state);
+        //先传播 f 自己的 panic，其次是任务的 panic
+        let value = match result {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        if let Some(payload) = scope.state.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+        value
+    }
+
+    //等待作用域内的任务全部结束。
+    //如果当前线程本身就是这个线程池的 worker（在任务里嵌套使用 scope），就一边等一边帮忙执行队列里的任务，
+    //否则所有 worker 都在等待各自的作用域时，就再也没有 worker 去执行它们等待的任务了。
+    //帮忙执行的任务和 worker 执行的任务一样通过 run_task 执行，它会捕获 panic，
+    //所以不会有 panic 从这里提前传播出去，让 scope 在任务还借用着调用者栈上的数据时就返回。
+    fn wait_scope(&self, state: &ScopeState) {
+        let mut running = state.running.lock().unwrap();
+        while *running > 0 {
+            drop(running);
+            match self.shared.find_job_for_current() {
+                Some((worker, task)) => {
+                    run_task(&self.shared, worker, task);
+                    running = state.running.lock().unwrap();
+                }
+                None => {
+                    running = state.running.lock().unwrap();
+                    if *running > 0 && self.shared.is_worker_thread() {
+                        //worker 线程隔一会儿再看看有没有可以帮忙的任务
+                        running = state
+                            .all_done
+                            .wait_timeout(running, Duration::from_millis(1))
+                            .unwrap()
+                            .0;
+                    } else if *running > 0 {
+                        running = state.all_done.wait(running).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn jobs_can_borrow_from_the_stack() {
+        let pool = ThreadPool::new(4);
+        let mut numbers: Vec<usize> = (0..1000).collect();
+        pool.scope(|s| {
+            for chunk in numbers.chunks_mut(100) {
+                s.spawn(move || {
+                    for n in chunk {
+                        *n *= 2;
+                    }
+                });
+            }
+        });
+        assert_eq!(numbers, (0..1000).map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn scope_returns_the_closure_result() {
+        let pool = ThreadPool::new(2);
+        let total = AtomicUsize::new(0);
+        let spawned = pool.scope(|s| {
+            for i in 0..10 {
+                let total = &total;
+                s.spawn(move || {
+                    total.fetch_add(i, Ordering::SeqCst);
+                });
+            }
+            10
+        });
+        assert_eq!(spawned, 10);
+        assert_eq!(total.load(Ordering::SeqCst), 45);
+    }
+
+    #[test]
+    fn waits_for_every_job_even_when_one_panics() {
+        let pool = ThreadPool::new(4);
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.spawn(|| panic!("boom"));
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        thread::sleep(Duration::from_millis(20));
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+        assert_eq!(finished.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn nested_scopes_inside_workers_do_not_deadlock() {
+        //每个 worker 都在等待自己的内层作用域，内层任务只能靠等待的 worker 帮忙执行
+        let pool = ThreadPool::new(2);
+        let total = AtomicUsize::new(0);
+        pool.scope(|outer| {
+            for _ in 0..4 {
+                let pool = &pool;
+                let total = &total;
+                outer.spawn(move || {
+                    pool.scope(|inner| {
+                        for _ in 0..4 {
+                            inner.spawn(|| {
+                                total.fetch_add(1, Ordering::SeqCst);
+                            });
+                        }
+                    });
+                });
+            }
+        });
+        assert_eq!(total.load(Ordering::SeqCst), 16);
+    }
+}
@@ -0,0 +1,320 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 14:02:31
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 02:51:37
+ * @Description: 线程池的观测：事件钩子、计数器和延迟直方图
+ */
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::PoolMetrics;
+
+//最初 worker 每执行一个任务都会 println!("Worker {} got a job; executing.", id)，
+//关闭时也会打印 "Shutting down worker {}"，这些输出会混进使用线程池的程序（比如 web server）自己的输出里。
+//现在线程池自己什么都不打印，而是：
+// 1. 在关键的时间点调用使用者提供的 EventHook，想打印日志、上报监控都可以在钩子里做；
+// 2. 始终维护一组计数器和延迟直方图，随时可以通过 ThreadPool::stats 取一份快照。
+
+/// 线程池事件的钩子，通过 [`Builder::event_hook`](crate::Builder::event_hook) 安装。
+///
+/// 所有方法都有空的默认实现，只需要实现关心的事件。钩子在 worker 线程（或提交任务的线程）上同步调用，应当尽快返回。
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use multithreaded::{EventHook, ThreadPool};
+///
+/// struct PrintSlowJobs;
+///
+/// impl EventHook for PrintSlowJobs {
+///     fn job_finished(&self, worker: usize, run_time: Duration) {
+///         if run_time > Duration::from_secs(1) {
+///             eprintln!("worker {} spent {:?} on a job", worker, run_time);
+///         }
+///     }
+/// }
+///
+/// let pool = ThreadPool::builder()
+///     .max_threads(4)
+///     .event_hook(Arc::new(PrintSlowJobs))
+///     .build();
+/// pool.execute(|| {});
+/// ```
+pub trait EventHook: Send + Sync {
+    /// 一个任务被提交到了队列中
+    fn job_queued(&self) {}
+
+    /// worker 开始执行一个任务，`queue_wait` 是这个任务在队列中等待的时间
+    fn job_started(&self, _worker: usize, _queue_wait: Duration) {}
+
+    /// worker 执行完了一个任务
+    fn job_finished(&self, _worker: usize, _run_time: Duration) {}
+
+    /// 任务 panic 了。worker 会捕获 panic 并继续执行下一个任务。
+    fn job_panicked(&self, _worker: usize, _run_time: Duration) {}
+
+    /// worker 线程退出了（线程池关闭、空闲超时或者 resize 缩小）
+    fn worker_exited(&self, _worker: usize) {}
+}
+
+//直方图的桶：第 i 个桶统计 (2^(i-1), 2^i] 微秒的样本，第 0 个桶统计不超过 1 微秒的样本，
+//最后一个桶统计超过 2^(BUCKETS-2) 微秒（约 67 秒）的所有样本。
+const BUCKETS: usize = 28;
+
+/// 以 2 的幂为边界的延迟直方图，可以在多个线程中无锁地记录。
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// 记录一个样本
+    pub fn record(&self, value: Duration) {
+        let micros = u64::try_from(value.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// 当前的统计结果
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            counts: self
+                .buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+fn bucket_index(micros: u64) -> usize {
+    if micros <= 1 {
+        return 0;
+    }
+    //不小于 micros 的最小的 2 的幂是 2^i，样本就落在第 i 个桶
+    let index = (64 - (micros - 1).leading_zeros()) as usize;
+    index.min(BUCKETS - 1)
+}
+
+/// [`Histogram`] 某一时刻的快照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    counts: Vec<u64>,
+    /// 样本总数
+    pub count: u64,
+    /// 所有样本之和
+    pub sum: Duration,
+}
+
+impl HistogramSnapshot {
+    /// 每个桶的上界和落在这个桶里的样本数，最后一个桶的上界为 `None`（没有上界）
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.counts.iter().enumerate().map(|(index, &count)| {
+            let bound = if index == BUCKETS - 1 {
+                None
+            } else {
+                Some(Duration::from_micros(1 << index))
+            };
+            (bound, count)
+        })
+    }
+
+    /// 平均值，没有样本时为 0
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            //count 可能超过 u32::MAX，所以用纳秒和 u128 来算，而不是 Duration / u32
+            let nanos = self.sum.as_nanos() / u128::from(self.count);
+            Duration::new(
+                (nanos / 1_000_000_000) as u64,
+                (nanos % 1_000_000_000) as u32,
+            )
+        }
+    }
+
+    /// 分位数（`q` 取 0.0 到 1.0）的估计值：返回包含这个分位数的桶的上界。
+    /// 没有样本时为 0，落在最后一个桶时为 `Duration::MAX`。
+    pub fn quantile(&self, q: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((self.count as f64) * q.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bound, count) in self.buckets() {
+            seen += count;
+            if seen >= rank {
+                return bound.unwrap_or(Duration::MAX);
+            }
+        }
+        Duration::MAX
+    }
+}
+
+//线程池内部的计数器和直方图，与钩子放在一起，由 worker 在各个时间点调用
+pub(crate) struct Instrumentation {
+    hook: Option<Arc<dyn EventHook>>,
+    queued: AtomicU64,
+    started: AtomicU64,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    workers_exited: AtomicU64,
+    queue_wait: Histogram,
+    run_time: Histogram,
+}
+
+impl Instrumentation {
+    pub(crate) fn new(hook: Option<Arc<dyn EventHook>>) -> Instrumentation {
+        Instrumentation {
+            hook,
+            queued: AtomicU64::new(0),
+            started: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            workers_exited: AtomicU64::new(0),
+            queue_wait: Histogram::new(),
+            run_time: Histogram::new(),
+        }
+    }
+
+    pub(crate) fn job_queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if let Some(hook) = &self.hook {
+            hook.job_queued();
+        }
+    }
+
+    pub(crate) fn job_started(&self, worker: usize, queue_wait: Duration) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+        self.queue_wait.record(queue_wait);
+        if let Some(hook) = &self.hook {
+            hook.job_started(worker, queue_wait);
+        }
+    }
+
+    pub(crate) fn job_finished(&self, worker: usize, run_time: Duration) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        self.run_time.record(run_time);
+        if let Some(hook) = &self.hook {
+            hook.job_finished(worker, run_time);
+        }
+    }
+
+    pub(crate) fn job_panicked(&self, worker: usize, run_time: Duration) {
+        self.panicked.fetch_add(1, Ordering::Relaxed);
+        self.run_time.record(run_time);
+        if let Some(hook) = &self.hook {
+            hook.job_panicked(worker, run_time);
+        }
+    }
+
+    pub(crate) fn worker_exited(&self, worker: usize) {
+        self.workers_exited.fetch_add(1, Ordering::Relaxed);
+        if let Some(hook) = &self.hook {
+            hook.worker_exited(worker);
+        }
+    }
+
+    pub(crate) fn snapshot(&self, workers: PoolMetrics) -> PoolStats {
+        PoolStats {
+            jobs_queued: self.queued.load(Ordering::Relaxed),
+            jobs_started: self.started.load(Ordering::Relaxed),
+            jobs_completed: self.completed.load(Ordering::Relaxed),
+            jobs_panicked: self.panicked.load(Ordering::Relaxed),
+            workers_exited: self.workers_exited.load(Ordering::Relaxed),
+            queue_wait: self.queue_wait.snapshot(),
+            run_time: self.run_time.snapshot(),
+            workers,
+        }
+    }
+}
+
+/// [`ThreadPool::stats`](crate::ThreadPool::stats) 返回的快照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolStats {
+    /// 提交过的任务数
+    pub jobs_queued: u64,
+    /// 开始执行过的任务数
+    pub jobs_started: u64,
+    /// 正常执行完的任务数
+    pub jobs_completed: u64,
+    /// panic 的任务数
+    pub jobs_panicked: u64,
+    /// 已经退出的 worker 数
+    pub workers_exited: u64,
+    /// 任务从提交到开始执行的等待时间
+    pub queue_wait: HistogramSnapshot,
+    /// 任务的执行时间（包括 panic 的任务）
+    pub run_time: HistogramSnapshot,
+    /// 线程池当前的规模
+    pub workers: PoolMetrics,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_land_in_power_of_two_buckets() {
+        assert_eq!(bucket_index(0), 0);
+        assert_eq!(bucket_index(1), 0);
+        assert_eq!(bucket_index(2), 1);
+        assert_eq!(bucket_index(3), 2);
+        assert_eq!(bucket_index(4), 2);
+        assert_eq!(bucket_index(1000), 10);
+        assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn quantiles_return_bucket_upper_bounds() {
+        let histogram = Histogram::new();
+        for _ in 0..90 {
+            histogram.record(Duration::from_micros(3));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_millis(1));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 100);
+        assert_eq!(snapshot.quantile(0.5), Duration::from_micros(4));
+        assert_eq!(snapshot.quantile(0.99), Duration::from_micros(1024));
+        assert_eq!(snapshot.mean(), Duration::from_nanos(102_700));
+    }
+
+    #[test]
+    fn mean_handles_more_than_u32_max_samples() {
+        let snapshot = HistogramSnapshot {
+            counts: vec![0; BUCKETS],
+            count: 1 << 32,
+            sum: Duration::from_secs(3 << 32),
+        };
+        assert_eq!(snapshot.mean(), Duration::from_secs(3));
+        let snapshot = HistogramSnapshot {
+            count: u64::MAX,
+            sum: Duration::MAX,
+            ..snapshot
+        };
+        assert_eq!(snapshot.mean(), Duration::from_nanos(1_000_000_000));
+    }
+}
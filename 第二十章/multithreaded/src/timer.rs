@@ -0,0 +1,430 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 16:02:48
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 03:41:19
+ * @Description: 延迟任务和周期任务：由线程池内部的一个定时器线程按到期时间提交到 worker
+ */
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::queue::{Shared, Task};
+use crate::{submit, Job, Priority, ThreadPool};
+
+//在任务里调用 thread::sleep 来实现延迟（就像 web server 的 /sleep 路由那样）会在等待期间一直占着一个 worker。
+//现在由一个专门的定时器线程来等待：所有还没到期的任务按到期时间放在一个最小堆里，
+//定时器线程睡到堆顶的任务到期，再把它作为 High 优先级的任务提交给 worker 执行。
+//定时器线程本身从不执行任务，所以任务执行得再慢也不会让其他定时任务晚点。
+//定时器线程在第一次调用 schedule_after 或 schedule_every 时才启动，线程池关闭或 drop 时停止，还没到期的任务直接丢弃。
+
+//到期时间最远只算到一百年以后。很大的 delay（例如用 Duration::MAX 表示“永远不会到期”）直接加到 Instant 上会溢出 panic
+const FAR_FUTURE: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+fn deadline_after(from: Instant, delay: Duration) -> Instant {
+    from + delay.min(FAR_FUTURE)
+}
+
+/// [`ThreadPool::schedule_after`] 和 [`ThreadPool::schedule_every`] 返回的句柄，用来取消定时任务。
+///
+/// 丢弃句柄不会取消任务。
+#[derive(Debug, Clone)]
+pub struct ScheduleHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScheduleHandle {
+    /// 取消任务：还没有到期的延迟任务不再执行，周期任务不再开始新的一轮。已经开始执行的那一次不受影响。
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// 是否已经取消
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+enum Kind {
+    Once(Job),
+    Every {
+        period: Duration,
+        job: Arc<dyn Fn() + Send + Sync>,
+        //上一轮是否还在执行（或者还在队列里排队）
+        running: Arc<AtomicBool>,
+    },
+}
+
+struct Entry {
+    deadline: Instant,
+    //到期时间相同的任务按提交顺序执行
+    seq: u64,
+    cancelled: Arc<AtomicBool>,
+    kind: Kind,
+}
+
+//BinaryHeap 是最大堆，这里把顺序反过来，让最早到期的任务在堆顶
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> CmpOrdering {
+        other
+            .deadline
+            .cmp(&self.deadline)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+struct TimerQueue {
+    heap: BinaryHeap<Entry>,
+    next_seq: u64,
+    stopped: bool,
+}
+
+struct TimerState {
+    queue: Mutex<TimerQueue>,
+    //有新的任务加入或者定时器要停止了
+    changed: Condvar,
+}
+
+pub(crate) struct Timer {
+    state: Arc<TimerState>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl Timer {
+    pub(crate) fn new() -> Timer {
+        Timer {
+            state: Arc::new(TimerState {
+                queue: Mutex::new(TimerQueue {
+                    heap: BinaryHeap::new(),
+                    next_seq: 0,
+                    stopped: false,
+                }),
+                changed: Condvar::new(),
+            }),
+            thread: Mutex::new(None),
+        }
+    }
+
+    fn schedule(&self, shared: &Arc<Shared>, deadline: Instant, kind: Kind) -> ScheduleHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        {
+            let mut queue = self.state.queue.lock().unwrap();
+            let seq = queue.next_seq;
+            queue.next_seq += 1;
+            queue.heap.push(Entry {
+                deadline,
+                seq,
+                cancelled: Arc::clone(&cancelled),
+                kind,
+            });
+        }
+        //新任务可能比原来的堆顶更早到期，让定时器线程重新计算要睡多久
+        self.state.changed.notify_one();
+        let mut thread = self.thread.lock().unwrap();
+        if thread.is_none() {
+            let state = Arc::clone(&self.state);
+            let shared = Arc::clone(shared);
+            *thread = Some(thread::spawn(move || run(&state, &shared)));
+        }
+        ScheduleHandle { cancelled }
+    }
+
+    //停止定时器线程并等待它退出，可以重复调用
+    pub(crate) fn stop(&self) {
+        self.state.queue.lock().unwrap().stopped = true;
+        self.state.changed.notify_one();
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+//定时器线程的主循环
+fn run(state: &TimerState, shared: &Arc<Shared>) {
+    let mut queue = state.queue.lock().unwrap();
+    loop {
+        if queue.stopped {
+            return;
+        }
+        let now = Instant::now();
+        let deadline = match queue.heap.peek() {
+            None => {
+                queue = state.changed.wait(queue).unwrap();
+                continue;
+            }
+            Some(entry) => entry.deadline,
+        };
+        if deadline > now {
+            queue = state.changed.wait_timeout(queue, deadline - now).unwrap().0;
+            continue;
+        }
+        let entry = queue.heap.pop().unwrap();
+        if entry.cancelled.load(Ordering::SeqCst) {
+            continue;
+        }
+        match entry.kind {
+            Kind::Once(job) => {
+                //提交任务时可能需要启动新的 worker，不必为此一直持有定时器的锁
+                drop(queue);
+                //线程池已经关闭时任务被丢弃，和还没到期的任务一样
+                let _ = submit(shared, Task::with(job, 0, Priority::High));
+                queue = state.queue.lock().unwrap();
+            }
+            Kind::Every {
+                period,
+                job,
+                running,
+            } => {
+                //下一次的到期时间从这一次的到期时间算起，而不是从现在算起，这样误差不会一轮一轮地累积。
+                //如果定时器线程因为系统繁忙晚了不止一个周期，错过的那几轮直接跳过，不会事后一口气补上。
+                let mut next = deadline_after(entry.deadline, period);
+                if next <= now {
+                    let missed = (now - entry.deadline).as_nanos() / period.as_nanos();
+                    next = entry.deadline + period * u32::try_from(missed).unwrap_or(u32::MAX);
+                    next = deadline_after(next, period);
+                }
+                let seq = queue.next_seq;
+                queue.next_seq += 1;
+                queue.heap.push(Entry {
+                    deadline: next,
+                    seq,
+                    cancelled: Arc::clone(&entry.cancelled),
+                    kind: Kind::Every {
+                        period,
+                        job: Arc::clone(&job),
+                        running: Arc::clone(&running),
+                    },
+                });
+                //上一轮还没执行完就跳过这一轮，避免 worker 都很忙时同一个周期任务在队列里越积越多
+                if running.swap(true, Ordering::SeqCst) {
+                    continue;
+                }
+                drop(queue);
+                let cancelled = entry.cancelled;
+                let tick: Job = Box::new(move || {
+                    //定时器把它提交出去之后才取消的，也不再执行
+                    if !cancelled.load(Ordering::SeqCst) {
+                        //job panic 时也要清掉 running，否则这个周期任务再也不会执行了
+                        let _running = Running(running);
+                        job();
+                    } else {
+                        running.store(false, Ordering::SeqCst);
+                    }
+                });
+                let _ = submit(shared, Task::with(tick, 0, Priority::High));
+                queue = state.queue.lock().unwrap();
+            }
+        }
+    }
+}
+
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl ThreadPool {
+    /// 在 `delay` 之后执行一次任务。
+    ///
+    /// 等待期间不会占用 worker。线程池关闭或者被 drop 时，还没有到期的任务会被丢弃。
+    ///
+    /// ```
+    /// use std::sync::mpsc;
+    /// use std::time::Duration;
+    /// use multithreaded::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// let (tx, rx) = mpsc::channel();
+    /// pool.schedule_after(Duration::from_millis(10), move || tx.send("done").unwrap());
+    /// assert_eq!(rx.recv().unwrap(), "done");
+    /// ```
+    pub fn schedule_after<F>(&self, delay: Duration, f: F) -> ScheduleHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.timer.schedule(
+            &self.shared,
+            deadline_after(Instant::now(), delay),
+            Kind::Once(Box::new(f)),
+        )
+    }
+
+    /// 每隔 `period` 执行一次任务，第一次在 `period` 之后执行，直到通过返回的句柄取消或者线程池关闭。
+    ///
+    /// 到期时间按固定的频率计算，不会因为任务本身的执行时间而漂移。
+    /// 如果到期时上一次还没有执行完，这一次会被跳过。
+    ///
+    /// # Panics
+    ///
+    /// `period` 为 0 时 panic
+    pub fn schedule_every<F>(&self, period: Duration, f: F) -> ScheduleHandle
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(!period.is_zero());
+        let kind = Kind::Every {
+            period,
+            job: Arc::new(f),
+            running: Arc::new(AtomicBool::new(false)),
+        };
+        self.timer
+            .schedule(&self.shared, deadline_after(Instant::now(), period), kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+
+    #[test]
+    fn delayed_job_runs_after_the_delay() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+        pool.schedule_after(Duration::from_millis(50), move || {
+            tx.send(Instant::now()).unwrap()
+        });
+        let ran_at = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(ran_at - start >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn waiting_does_not_occupy_a_worker() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel();
+        let later = tx.clone();
+        pool.schedule_after(Duration::from_millis(200), move || {
+            later.send("later").unwrap()
+        });
+        pool.execute(move || tx.send("now").unwrap());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "now");
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "later");
+    }
+
+    #[test]
+    fn jobs_fire_in_deadline_order() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel();
+        for delay in [60, 20, 40] {
+            let tx = tx.clone();
+            pool.schedule_after(Duration::from_millis(delay), move || {
+                tx.send(delay).unwrap()
+            });
+        }
+        let order: Vec<u64> = (0..3)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        assert_eq!(order, vec![20, 40, 60]);
+    }
+
+    #[test]
+    fn cancelled_job_never_runs() {
+        let pool = ThreadPool::new(1);
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&ran);
+        let handle = pool.schedule_after(Duration::from_millis(30), move || {
+            flag.store(true, Ordering::SeqCst);
+        });
+        handle.cancel();
+        assert!(handle.is_cancelled());
+        thread::sleep(Duration::from_millis(100));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn periodic_job_repeats_until_cancelled() {
+        let pool = ThreadPool::new(2);
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&count);
+        let handle = pool.schedule_every(Duration::from_millis(10), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let start = Instant::now();
+        while count.load(Ordering::SeqCst) < 3 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        handle.cancel();
+        //取消时可能已经有一轮提交给了 worker，等它执行完再看
+        thread::sleep(Duration::from_millis(30));
+        let stopped_at = count.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(count.load(Ordering::SeqCst), stopped_at);
+    }
+
+    #[test]
+    fn slow_periodic_job_does_not_pile_up() {
+        let pool = ThreadPool::new(4);
+        let running = Arc::new(AtomicUsize::new(0));
+        let overlapped = Arc::new(AtomicBool::new(false));
+        let (r, o) = (Arc::clone(&running), Arc::clone(&overlapped));
+        let handle = pool.schedule_every(Duration::from_millis(5), move || {
+            if r.fetch_add(1, Ordering::SeqCst) > 0 {
+                o.store(true, Ordering::SeqCst);
+            }
+            thread::sleep(Duration::from_millis(30));
+            r.fetch_sub(1, Ordering::SeqCst);
+        });
+        thread::sleep(Duration::from_millis(150));
+        handle.cancel();
+        assert!(!overlapped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn drop_discards_pending_timers_without_waiting() {
+        let pool = ThreadPool::new(1);
+        pool.schedule_after(Duration::from_secs(3600), || {});
+        pool.schedule_every(Duration::from_secs(3600), || {});
+        let start = Instant::now();
+        drop(pool);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn huge_delays_never_fire_instead_of_overflowing() {
+        let pool = ThreadPool::new(1);
+        let ran = Arc::new(AtomicBool::new(false));
+        let (once, every) = (Arc::clone(&ran), Arc::clone(&ran));
+        pool.schedule_after(Duration::MAX, move || once.store(true, Ordering::SeqCst));
+        pool.schedule_every(Duration::MAX, move || every.store(true, Ordering::SeqCst));
+        //其他定时任务照常执行
+        let (tx, rx) = mpsc::channel();
+        pool.schedule_after(Duration::from_millis(10), move || tx.send(()).unwrap());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!ran.load(Ordering::SeqCst));
+        let start = Instant::now();
+        drop(pool);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn timers_start_workers_in_a_pool_without_idle_threads() {
+        //min_threads 为 0 时一开始没有 worker，定时器提交任务时要负责扩容
+        let pool = ThreadPool::builder().min_threads(0).max_threads(2).build();
+        let (tx, rx) = mpsc::channel();
+        pool.schedule_after(Duration::from_millis(10), move || tx.send(()).unwrap());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
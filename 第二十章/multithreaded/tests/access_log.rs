@@ -0,0 +1,80 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 20:21:44
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: 访问日志的集成测试
+ */
+mod common;
+
+use std::fs;
+use std::io::Write;
+
+use multithreaded::http::{AccessLog, LogFormat, Response, Rotation, Router, Server, StatusCode};
+use multithreaded::ThreadPool;
+
+#[test]
+fn requests_are_logged_as_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("access.log");
+    let log = AccessLog::open(&path, LogFormat::Json, Rotation::default()).unwrap();
+    let router = Router::new().get("/hello", |_| Response::text(StatusCode::OK, "hello"));
+    let server = Server::builder(router)
+        .pool(ThreadPool::new(2))
+        .access_log(log)
+        .build();
+    let (addr, stop, serving) = common::serve_until_shutdown(server);
+
+    let client = common::client();
+    let response = client
+        .get(&common::url(addr, "/hello?x=1"))
+        .header("Referer", "http://example.com/")
+        .header("User-Agent", "test \"agent\"")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "hello");
+    let response = client.head(&common::url(addr, "/missing")).send().unwrap();
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    //无法解析的请求只能直接写进套接字
+    let mut stream = common::connect(addr);
+    stream.write_all(b"BROKEN\r\n\r\n").unwrap();
+    let response = common::read_all(&mut stream);
+    assert!(
+        response.contains("HTTP/1.1 400 Bad Request"),
+        "{}",
+        response
+    );
+
+    drop(client);
+    stop.shutdown();
+    //shutdown 返回、server 被 drop 之后日志已经写完
+    serving.join().unwrap().unwrap();
+    let text = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", text);
+    //不合法的请求走的是另一个连接，三条记录的先后不一定
+    let find = |method: &str| {
+        let field = format!("\"method\":\"{}\"", method);
+        *lines.iter().find(|line| line.contains(&field)).unwrap()
+    };
+
+    let get = find("GET");
+    assert!(get.contains("\"client\":\"127.0.0.1\""), "{}", get);
+    assert!(get.contains("\"method\":\"GET\",\"path\":\"/hello?x=1\",\"version\":\"HTTP/1.1\""));
+    assert!(get.contains("\"status\":200,\"bytes\":5,"));
+    assert!(get.contains("\"referer\":\"http://example.com/\""));
+    assert!(get.contains("\"user_agent\":\"test \\\"agent\\\"\""));
+    assert!(get.contains("\"latency_us\":"));
+    //HEAD 请求没有发送响应体
+    let head = find("HEAD");
+    assert!(head.contains("\"status\":404,\"bytes\":0,"), "{}", head);
+    assert!(head.contains("\"referer\":null"));
+    //无法解析的请求也有一条记录
+    let broken = find("");
+    assert!(
+        broken.contains("\"method\":\"\",\"path\":\"\""),
+        "{}",
+        broken
+    );
+    assert!(broken.contains("\"status\":400"));
+}
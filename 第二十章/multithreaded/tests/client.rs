@@ -0,0 +1,198 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 01:31:45
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: 用 Client 测试 server：两种模式、持久连接的复用、分块的请求体和响应体，以及超时
+ */
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+
+use multithreaded::http::{
+    Client, ClientError, Middleware, Mode, Request, Response, Router, Server, ServerConfig,
+    StatusCode, Version,
+};
+use multithreaded::ThreadPool;
+
+//把客户端的端口放进请求头，/peer 据此判断两个请求是不是走的同一个连接
+struct PeerPort;
+
+impl Middleware for PeerPort {
+    fn before(&self, request: &mut Request, peer: Option<SocketAddr>) -> Option<Response> {
+        if let Some(peer) = peer {
+            request
+                .headers
+                .insert("X-Peer-Port", peer.port().to_string());
+        }
+        None
+    }
+}
+
+//在随机端口上启动 server，返回它的根地址，例如 http://127.0.0.1:12345
+fn start(mode: Mode, keep_alive: Duration) -> String {
+    let router = Router::new()
+        .get("/", |_| Response::text(StatusCode::OK, "hello"))
+        .post("/echo", |request| {
+            Response::new(StatusCode::OK).with_body(request.body.clone())
+        })
+        .get("/peer", |request| {
+            let port = request.headers.get("x-peer-port").unwrap_or("");
+            Response::text(StatusCode::OK, port.to_string())
+        })
+        .get("/close", |_| {
+            Response::text(StatusCode::OK, "bye").with_header("Connection", "close")
+        })
+        .get("/sleep", |_| {
+            thread::sleep(Duration::from_secs(1));
+            Response::text(StatusCode::OK, "late")
+        })
+        .blocking();
+    let config = ServerConfig {
+        mode,
+        keep_alive,
+        ..ServerConfig::default()
+    };
+    let addr = common::serve(
+        Server::builder(router)
+            .config(config)
+            .pool(ThreadPool::new(2))
+            .on_error(|_, _| {})
+            .middleware(PeerPort)
+            .build(),
+    );
+    common::url(addr, "")
+}
+
+fn talks_to_the_server(mode: Mode) {
+    let base = start(mode, Duration::from_secs(5));
+    let client = common::client();
+
+    let response = client.get(&format!("{}/", base)).send().unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.version, Version::Http11);
+    assert_eq!(response.headers.get("content-length"), Some("5"));
+    assert_eq!(response.text(), "hello");
+
+    //HEAD 的响应没有响应体，连接照样可以复用
+    let response = client.head(&format!("{}/", base)).send().unwrap();
+    assert_eq!(response.headers.get("content-length"), Some("5"));
+    assert!(response.body.is_empty());
+
+    let response = client.get(&format!("{}/missing", base)).send().unwrap();
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    //请求体可以带长度发送，也可以分块发送
+    let body: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let url = format!("{}/echo", base);
+    let response = client.post(&url).body(body.clone()).send().unwrap();
+    assert_eq!(response.body, body);
+    let response = client
+        .post(&url)
+        .body("chunked")
+        .chunked(true)
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "chunked");
+    let response = client.post(&url).send().unwrap();
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn talks_to_the_server_in_thread_mode() {
+    talks_to_the_server(Mode::Threads);
+}
+
+#[test]
+fn talks_to_the_server_in_event_loop_mode() {
+    talks_to_the_server(Mode::EventLoop);
+}
+
+#[test]
+fn reuses_keep_alive_connections() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let base = start(mode, Duration::from_millis(300));
+        let client = Client::new();
+        let url = format!("{}/peer", base);
+        let first = client.get(&url).send().unwrap().text().into_owned();
+        assert_eq!(client.idle_connections(), 1);
+        let second = client.get(&url).send().unwrap().text().into_owned();
+        assert_eq!(first, second, "{:?}", mode);
+
+        //server 要求关闭的连接不放回连接池
+        let response = client.get(&format!("{}/close", base)).send().unwrap();
+        assert_eq!(response.headers.get("connection"), Some("close"));
+        assert_eq!(client.idle_connections(), 0);
+
+        //空闲的连接被 server 关闭之后，下一个请求自动换一个新连接
+        let third = client.get(&url).send().unwrap().text().into_owned();
+        assert_ne!(first, third, "{:?}", mode);
+        thread::sleep(Duration::from_millis(600));
+        let fourth = client.get(&url).send().unwrap().text().into_owned();
+        assert_ne!(third, fourth, "{:?}", mode);
+    }
+}
+
+#[test]
+fn slow_responses_time_out() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let base = start(mode, Duration::from_secs(5));
+        let client = Client::new().timeout(Duration::from_millis(200));
+        let err = client.get(&format!("{}/sleep", base)).send().unwrap_err();
+        assert!(matches!(err, ClientError::Timeout), "{:?}", err);
+        //超时的连接不会放回连接池，后面的请求照常发送
+        assert_eq!(client.idle_connections(), 0);
+        let response = client.get(&format!("{}/", base)).send().unwrap();
+        assert_eq!(response.text(), "hello");
+    }
+}
+
+#[test]
+fn reads_chunked_and_close_delimited_bodies() {
+    //server 总是带 Content-Length，这两种响应由一个简易的服务给出
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let stream = reader.get_mut();
+            if request_line.starts_with("GET /chunked ") {
+                stream
+                    .write_all(
+                        b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                          4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nX-Trailer: 1\r\n\r\n",
+                    )
+                    .unwrap();
+            } else {
+                stream
+                    .write_all(b"HTTP/1.0 200 OK\r\n\r\nuntil the connection closes")
+                    .unwrap();
+            }
+        }
+    });
+    let client = Client::new();
+    let response = client
+        .get(&format!("http://{}/chunked", addr))
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "Wikipedia");
+    let response = client.get(&format!("http://{}/eof", addr)).send().unwrap();
+    assert_eq!(response.version, Version::Http10);
+    assert_eq!(response.text(), "until the connection closes");
+    assert_eq!(client.idle_connections(), 0);
+
+    let err = client.get("https://localhost/").send().unwrap_err();
+    assert!(matches!(err, ClientError::InvalidUrl(_)), "{:?}", err);
+}
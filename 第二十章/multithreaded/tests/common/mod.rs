@@ -0,0 +1,104 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 04:03:27
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: 集成测试共用的辅助函数：在随机端口上启动 server、发请求用的 Client，以及直接读写套接字时读取响应
+ */
+//每个 tests/*.rs 是单独的 crate，只会用到其中一部分
+#![allow(dead_code)]
+
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use multithreaded::http::{Client, Router, Server, ServerConfig, ShutdownHandle};
+use multithreaded::{ShutdownTimeout, ThreadPool};
+
+//在随机端口上运行 server，返回它的地址。接受连接的线程随测试进程一起结束
+pub fn serve(server: Server) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || server.serve(&listener));
+    addr
+}
+
+//和 serve 一样，但 ShutdownHandle::shutdown 让 serve 返回之后接着调用 Server::shutdown，
+//join 返回的线程就能等到 server 完全停下来
+pub fn serve_until_shutdown(
+    server: Server,
+) -> (
+    SocketAddr,
+    ShutdownHandle,
+    JoinHandle<Result<(), ShutdownTimeout>>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stop = server.shutdown_handle();
+    let serving = thread::spawn(move || {
+        server.serve(&listener);
+        server.shutdown(Duration::from_secs(5))
+    });
+    (addr, stop, serving)
+}
+
+//最常见的情况：`workers` 个 worker 的线程池，不打印连接上的错误
+pub fn start(router: Router, config: ServerConfig, workers: usize) -> SocketAddr {
+    serve(
+        Server::builder(router)
+            .config(config)
+            .pool(ThreadPool::new(workers))
+            .on_error(|_, _| {})
+            .build(),
+    )
+}
+
+pub fn url(addr: SocketAddr, path: &str) -> String {
+    format!("http://{}{}", addr, path)
+}
+
+//读写都有超时，server 没有回应时测试失败而不是卡住
+pub fn client() -> Client {
+    Client::new().timeout(Duration::from_secs(5))
+}
+
+//下面两个给需要控制连接本身的测试用：流水线、HTTP/1.0、不合法的请求，以及连接什么时候关闭
+pub fn connect(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+//读出一个带 Content-Length 的响应，返回头部和响应体
+pub fn read_response(stream: &mut impl Read) -> (String, String) {
+    let mut data = Vec::new();
+    let mut byte = [0; 1];
+    while !data.ends_with(b"\r\n\r\n") {
+        assert_eq!(
+            stream.read(&mut byte).unwrap(),
+            1,
+            "connection closed early"
+        );
+        data.push(byte[0]);
+    }
+    let head = String::from_utf8(data).unwrap();
+    let len: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).unwrap();
+    (head, String::from_utf8(body).unwrap())
+}
+
+//读到连接关闭为止
+pub fn read_all(stream: &mut TcpStream) -> String {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
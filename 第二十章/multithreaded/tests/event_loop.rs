@@ -0,0 +1,154 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 21:36:52
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: 事件循环模式的集成测试，以及与线程模式在慢客户端下的对比
+ */
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{connect, read_response};
+use multithreaded::http::{
+    Mode, Response, Router, Server, ServerConfig, ShutdownHandle, StatusCode,
+};
+use multithreaded::{ShutdownTimeout, ThreadPool};
+
+fn router() -> Router {
+    Router::new()
+        .get("/slow", |_| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(StatusCode::OK, "slow")
+        })
+        .blocking()
+        .get("/:name", |request| {
+            Response::text(StatusCode::OK, request.param("name").unwrap().to_string())
+        })
+}
+
+//在随机端口上启动 server，返回它的地址和停止用的句柄，以及 serve 所在的线程
+fn start(
+    config: ServerConfig,
+    workers: usize,
+) -> (
+    SocketAddr,
+    ShutdownHandle,
+    thread::JoinHandle<Result<(), ShutdownTimeout>>,
+) {
+    common::serve_until_shutdown(Server::new(router(), config, ThreadPool::new(workers)))
+}
+
+fn event_loop() -> ServerConfig {
+    ServerConfig {
+        mode: Mode::EventLoop,
+        ..ServerConfig::default()
+    }
+}
+
+fn get(path: &str) -> String {
+    format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path)
+}
+
+#[test]
+fn pipelined_requests_mixing_inline_and_blocking_handlers() {
+    let (addr, _, _) = start(event_loop(), 2);
+    let mut stream = connect(addr);
+    let requests = format!("{}{}{}", get("/a"), get("/slow"), get("/b"));
+    //一个字节一个字节地发送，解析器要能拼出完整的请求
+    for byte in requests.as_bytes() {
+        stream.write_all(&[*byte]).unwrap();
+    }
+    for expected in ["a", "slow", "b"] {
+        let (head, body) = read_response(&mut stream);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert_eq!(body, expected);
+    }
+}
+
+#[test]
+fn idle_connections_time_out_and_bad_requests_get_400() {
+    let config = ServerConfig {
+        keep_alive: Duration::from_millis(200),
+        max_requests: 2,
+        ..event_loop()
+    };
+    let (addr, _, _) = start(config, 1);
+    let mut stream = connect(addr);
+    stream.write_all(get("/x").as_bytes()).unwrap();
+    read_response(&mut stream);
+    let start = Instant::now();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    assert!(start.elapsed() >= Duration::from_millis(150));
+
+    let mut stream = connect(addr);
+    stream
+        .write_all(format!("{}{}{}", get("/a"), get("/b"), get("/c")).as_bytes())
+        .unwrap();
+    read_response(&mut stream);
+    let (head, _) = read_response(&mut stream);
+    assert!(head.contains("Connection: close"));
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+    let mut stream = connect(addr);
+    stream.write_all(b"NOT HTTP\r\n\r\n").unwrap();
+    let response = common::read_all(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[test]
+fn shutdown_waits_for_blocking_handlers() {
+    let (addr, stop, serving) = start(event_loop(), 2);
+    let mut slow = connect(addr);
+    slow.write_all(get("/slow").as_bytes()).unwrap();
+    let mut idle = connect(addr);
+    idle.write_all(get("/idle").as_bytes()).unwrap();
+    read_response(&mut idle);
+    thread::sleep(Duration::from_millis(100));
+
+    stop.shutdown();
+    serving.join().unwrap().unwrap();
+    let (head, body) = read_response(&mut slow);
+    assert!(head.contains("Connection: close"));
+    assert_eq!(body, "slow");
+    assert_eq!(idle.read(&mut [0; 1]).unwrap(), 0);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+//开 slow 个只发送了半个请求就停住的客户端，再测一个正常请求要多久
+fn fast_request_behind_slow_clients(config: ServerConfig, slow: usize) -> Duration {
+    let config = ServerConfig {
+        request_timeout: Duration::from_millis(500),
+        ..config
+    };
+    let (addr, _, _) = start(config, 2);
+    let stalled: Vec<TcpStream> = (0..slow)
+        .map(|_| {
+            let mut stream = connect(addr);
+            stream.write_all(b"GET /stalled HTTP/1.1\r\nHo").unwrap();
+            stream
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(50));
+    let start = Instant::now();
+    let response = common::client()
+        .get(&common::url(addr, "/fast"))
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "fast");
+    drop(stalled);
+    start.elapsed()
+}
+
+#[test]
+fn slow_clients_do_not_block_the_event_loop() {
+    //线程模式下两个 worker 都阻塞在停住的客户端上，直到 request_timeout 才空出来
+    let threads = fast_request_behind_slow_clients(ServerConfig::default(), 4);
+    assert!(threads >= Duration::from_millis(400), "{:?}", threads);
+
+    let events = fast_request_behind_slow_clients(event_loop(), 200);
+    assert!(events < Duration::from_millis(300), "{:?}", events);
+}
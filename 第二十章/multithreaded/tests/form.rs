@@ -0,0 +1,241 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 02:05:26
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 06:24:10
+ * @Description: 通过 server 提交 urlencoded 和 multipart 表单，上传文件、流式上传大文件以及各种错误的状态码
+ */
+mod common;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use multithreaded::http::{
+    Form, FormError, FormParser, Limits, Method, Mode, Response, Router, ServerConfig, StatusCode,
+};
+
+//在随机端口上启动 server，/upload 把解析出的字段和文件列出来，/stream 也一样，但请求体是流式读取的
+fn start(config: ServerConfig, temp_dir: &Path) -> SocketAddr {
+    let forms = FormParser::new()
+        .temp_dir(temp_dir)
+        .max_file_size(64 * 1024);
+    let streamed = forms.clone();
+    let router = Router::new()
+        .post("/upload", move |request| describe(forms.parse(request)))
+        .blocking()
+        .streaming(Method::Post, "/stream", move |request, body| {
+            describe(streamed.parse_stream(request, body))
+        });
+    common::start(router, config, 2)
+}
+
+fn describe(form: Result<Form, FormError>) -> Response {
+    let form = match form {
+        Ok(form) => form,
+        Err(err) => return err.to_response(),
+    };
+    let mut text = String::new();
+    for (name, value) in form.fields() {
+        text.push_str(&format!("{}={}\n", name, value));
+    }
+    for file in form.files() {
+        let content = fs::read(file.path()).unwrap();
+        text.push_str(&format!(
+            "{}: {} {} {} {}\n",
+            file.name,
+            file.filename,
+            file.content_type,
+            file.size,
+            content == vec![b'x'; file.size as usize]
+        ));
+    }
+    Response::text(StatusCode::OK, text)
+}
+
+fn multipart(boundary: &str, file: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi there\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"doc\"; filename=\"notes.txt\"\r\n\
+         Content-Type: text/plain\r\n\r\n",
+        b = boundary
+    )
+    .into_bytes();
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+fn uploads_forms(mode: Mode) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = ServerConfig {
+        mode,
+        ..ServerConfig::default()
+    };
+    let url = common::url(start(config, dir.path()), "/upload");
+    let client = common::client();
+
+    let response = client
+        .post(&url)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=J%C3%B6rg+M&tag=a&tag=b")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "name=Jörg M\ntag=a\ntag=b\n");
+
+    let response = client
+        .post(&url)
+        .header("Content-Type", "multipart/form-data; boundary=----form123")
+        .body(multipart("----form123", &[b'x'; 50_000]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.text(),
+        "note=hi there\ndoc: notes.txt text/plain 50000 true\n"
+    );
+
+    //超过 max_file_size 的文件是 413
+    let response = client
+        .post(&url)
+        .header("Content-Type", "multipart/form-data; boundary=----form123")
+        .body(multipart("----form123", &[b'x'; 70_000]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    //boundary 不合法，或者请求体里的 boundary 对不上，都是 400
+    for content_type in [
+        "multipart/form-data",
+        "multipart/form-data; boundary=bad{boundary}",
+        "multipart/form-data; boundary=other",
+    ] {
+        let response = client
+            .post(&url)
+            .header("Content-Type", content_type)
+            .body(multipart("----form123", b"xx"))
+            .send()
+            .unwrap();
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", content_type);
+    }
+
+    let response = client
+        .post(&url)
+        .header("Content-Type", "application/json")
+        .body("{}")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    //处理完请求之后临时文件都删掉了
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn uploads_forms_in_thread_mode() {
+    uploads_forms(Mode::Threads);
+}
+
+#[test]
+fn uploads_forms_in_event_loop_mode() {
+    uploads_forms(Mode::EventLoop);
+}
+
+#[test]
+fn max_body_limits_uploads() {
+    //普通路由的请求体在调用处理函数之前已经整个读进内存，FormParser 的上限（64 KiB）比 max_body 大也没有用
+    let dir = tempfile::tempdir().unwrap();
+    let config = ServerConfig {
+        limits: Limits {
+            max_body: 16 * 1024,
+            ..Limits::default()
+        },
+        ..ServerConfig::default()
+    };
+    let url = common::url(start(config, dir.path()), "/upload");
+    let client = common::client();
+    let response = client
+        .post(&url)
+        .header("Content-Type", "multipart/form-data; boundary=----form123")
+        .body(multipart("----form123", &[b'x'; 20_000]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let response = client
+        .post(&url)
+        .header("Content-Type", "multipart/form-data; boundary=----form123")
+        .body(multipart("----form123", &[b'x'; 10_000]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response
+        .text()
+        .ends_with("doc: notes.txt text/plain 10000 true\n"));
+}
+
+fn streams_uploads(mode: Mode) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = ServerConfig {
+        mode,
+        limits: Limits {
+            max_body: 16 * 1024,
+            ..Limits::default()
+        },
+        ..ServerConfig::default()
+    };
+    let addr = start(config, dir.path());
+    let client = common::client();
+
+    //流式读取的请求体不受 max_body 限制，只受 FormParser 的上限限制
+    let response = client
+        .post(&common::url(addr, "/stream"))
+        .header("Content-Type", "multipart/form-data; boundary=----form123")
+        .body(multipart("----form123", &[b'x'; 50_000]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.text(),
+        "note=hi there\ndoc: notes.txt text/plain 50000 true\n"
+    );
+    let response = client
+        .post(&common::url(addr, "/stream"))
+        .header("Content-Type", "multipart/form-data; boundary=----form123")
+        .body(multipart("----form123", &[b'x'; 70_000]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    //分块编码的请求体，先等 100 Continue 再发送
+    let mut stream = common::connect(addr);
+    stream
+        .write_all(
+            b"POST /stream HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\n\
+              Content-Type: application/x-www-form-urlencoded\r\nTransfer-Encoding: chunked\r\n\r\n",
+        )
+        .unwrap();
+    let mut interim = [0; 25];
+    stream.read_exact(&mut interim).unwrap();
+    assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream
+        .write_all(b"5\r\na=1&b\r\n3\r\n=22\r\n0\r\n\r\n")
+        .unwrap();
+    let (head, body) = common::read_response(&mut stream);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert_eq!(body, "a=1\nb=22\n");
+
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn streams_uploads_in_thread_mode() {
+    streams_uploads(Mode::Threads);
+}
+
+#[test]
+fn streams_uploads_in_event_loop_mode() {
+    streams_uploads(Mode::EventLoop);
+}
@@ -0,0 +1,189 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 19:12:08
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 05:48:20
+ * @Description: 持久连接、流水线请求和空闲连接的集成测试
+ */
+mod common;
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{connect, read_response};
+use multithreaded::http::{Mode, Response, Router, ServerConfig, StatusCode};
+
+//这里测的是连接本身的行为（流水线、HTTP/1.0、连接什么时候关闭），所以直接读写套接字
+fn start(config: ServerConfig, workers: usize) -> SocketAddr {
+    let router = Router::new().get("/:name", |request| {
+        Response::text(StatusCode::OK, request.param("name").unwrap().to_string())
+    });
+    common::start(router, config, workers)
+}
+
+fn get(path: &str) -> String {
+    format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path)
+}
+
+#[test]
+fn serves_several_requests_on_one_connection() {
+    let addr = start(ServerConfig::default(), 2);
+    let mut stream = connect(addr);
+    for name in ["one", "two", "three"] {
+        stream
+            .write_all(get(&format!("/{}", name)).as_bytes())
+            .unwrap();
+        let (head, body) = read_response(&mut stream);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!head.contains("Connection: close"));
+        assert_eq!(body, name);
+    }
+}
+
+#[test]
+fn pipelined_responses_come_back_in_order() {
+    let addr = start(ServerConfig::default(), 4);
+    let mut stream = connect(addr);
+    let mut requests: String = (0..10).map(|i| get(&format!("/r{}", i))).collect();
+    requests.push_str("GET /last HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n");
+    stream.write_all(requests.as_bytes()).unwrap();
+    for i in 0..10 {
+        assert_eq!(read_response(&mut stream).1, format!("r{}", i));
+    }
+    let (head, body) = read_response(&mut stream);
+    assert!(head.contains("Connection: close"));
+    assert_eq!(body, "last");
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn closes_after_max_requests() {
+    let config = ServerConfig {
+        max_requests: 2,
+        ..ServerConfig::default()
+    };
+    let addr = start(config, 2);
+    let mut stream = connect(addr);
+    stream
+        .write_all(format!("{}{}{}", get("/a"), get("/b"), get("/c")).as_bytes())
+        .unwrap();
+    let (head, _) = read_response(&mut stream);
+    assert!(!head.contains("Connection: close"));
+    let (head, body) = read_response(&mut stream);
+    assert!(head.contains("Connection: close"));
+    assert_eq!(body, "b");
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn idle_connections_time_out() {
+    let config = ServerConfig {
+        keep_alive: Duration::from_millis(200),
+        ..ServerConfig::default()
+    };
+    let addr = start(config, 1);
+    let mut stream = connect(addr);
+    stream.write_all(get("/x").as_bytes()).unwrap();
+    read_response(&mut stream);
+    let start = Instant::now();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    let waited = start.elapsed();
+    assert!(waited >= Duration::from_millis(150), "{:?}", waited);
+    assert!(waited < Duration::from_secs(3), "{:?}", waited);
+}
+
+#[test]
+fn http10_closes_unless_asked_to_keep_alive() {
+    let addr = start(ServerConfig::default(), 1);
+    let mut stream = connect(addr);
+    stream.write_all(b"GET /old HTTP/1.0\r\n\r\n").unwrap();
+    let (head, _) = read_response(&mut stream);
+    assert!(head.contains("Connection: close"));
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+    let mut stream = connect(addr);
+    stream
+        .write_all(b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /b HTTP/1.0\r\n\r\n")
+        .unwrap();
+    let (head, _) = read_response(&mut stream);
+    assert!(head.contains("Connection: keep-alive"));
+    assert_eq!(read_response(&mut stream).1, "b");
+}
+
+#[test]
+fn idle_connections_do_not_starve_the_pool() {
+    //只有一个 worker，而且空闲连接要 5 秒才会超时
+    let addr = start(ServerConfig::default(), 1);
+    let mut idle = Vec::new();
+    for i in 0..4 {
+        let mut stream = connect(addr);
+        stream
+            .write_all(get(&format!("/idle{}", i)).as_bytes())
+            .unwrap();
+        read_response(&mut stream);
+        idle.push(stream);
+    }
+    let start = Instant::now();
+    let mut stream = connect(addr);
+    stream.write_all(get("/fresh").as_bytes()).unwrap();
+    assert_eq!(read_response(&mut stream).1, "fresh");
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    //之前空闲的连接也还能继续使用
+    stream = idle.swap_remove(0);
+    stream.write_all(get("/again").as_bytes()).unwrap();
+    assert_eq!(read_response(&mut stream).1, "again");
+}
+
+#[test]
+fn clients_that_stop_reading_do_not_hold_a_worker() {
+    //响应比套接字的缓冲区大得多，客户端不读取的话 worker 就一直阻塞在 write 上
+    let router = Router::new()
+        .get("/big", |_| {
+            Response::text(StatusCode::OK, "x".repeat(64 << 20))
+        })
+        .get("/small", |_| Response::text(StatusCode::OK, "small"));
+    let config = ServerConfig {
+        request_timeout: Duration::from_millis(300),
+        ..ServerConfig::default()
+    };
+    let addr = common::start(router, config, 1);
+    let mut stuck = connect(addr);
+    stuck.write_all(get("/big").as_bytes()).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    let mut stream = connect(addr);
+    stream.write_all(get("/small").as_bytes()).unwrap();
+    assert_eq!(read_response(&mut stream).1, "small");
+    assert!(
+        start.elapsed() < Duration::from_secs(2),
+        "{:?}",
+        start.elapsed()
+    );
+    drop(stuck);
+}
+
+#[test]
+fn huge_timeouts_never_expire() {
+    //Duration::MAX 加到 Instant 上会溢出，这时连接永不超时，而不是让空闲连接线程或者事件循环 panic
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let config = ServerConfig {
+            keep_alive: Duration::MAX,
+            request_timeout: Duration::MAX,
+            mode,
+            ..ServerConfig::default()
+        };
+        let addr = start(config, 1);
+        let mut stream = connect(addr);
+        for name in ["a", "b"] {
+            stream
+                .write_all(get(&format!("/{}", name)).as_bytes())
+                .unwrap();
+            assert_eq!(read_response(&mut stream).1, name);
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
@@ -0,0 +1,113 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 00:34:18
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: 内置的 /healthz、/readyz 和 /metrics 在两种模式下的集成测试
+ */
+mod common;
+
+use std::net::SocketAddr;
+
+use multithreaded::http::{
+    Client, ClientResponse, Mode, Response, Router, ServerConfig, StatusCode,
+};
+
+fn start(mode: Mode, max_connections: usize) -> SocketAddr {
+    let router = Router::new()
+        .get("/", |_| Response::text(StatusCode::OK, "hello"))
+        .get("/healthz", |_| Response::text(StatusCode::OK, "custom"))
+        .get("/fail", |_| {
+            Response::text(StatusCode::INTERNAL_SERVER_ERROR, "no")
+        });
+    let config = ServerConfig {
+        mode,
+        max_connections,
+        ..ServerConfig::default()
+    };
+    common::start(router, config, 2)
+}
+
+fn get(client: &Client, addr: SocketAddr, path: &str) -> ClientResponse {
+    client.get(&common::url(addr, path)).send().unwrap()
+}
+
+fn reports_metrics(mode: Mode) {
+    let addr = start(mode, 64);
+    let client = common::client();
+    assert_eq!(get(&client, addr, "/").status, StatusCode::OK);
+    assert_eq!(
+        get(&client, addr, "/fail").status,
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    assert_eq!(get(&client, addr, "/missing").status, StatusCode::NOT_FOUND);
+    //路由表里的 /healthz 优先于内置的
+    assert_eq!(get(&client, addr, "/healthz").text(), "custom");
+    let ready = get(&client, addr, "/readyz");
+    assert_eq!(ready.status, StatusCode::OK, "{}", ready.text());
+
+    let metrics = get(&client, addr, "/metrics");
+    assert_eq!(metrics.status, StatusCode::OK);
+    assert_eq!(
+        metrics.headers.get("content-type"),
+        Some("text/plain; version=0.0.4; charset=utf-8")
+    );
+    assert_eq!(metrics.headers.get("cache-control"), Some("no-store"));
+    let body = metrics.text();
+    assert!(
+        body.contains("http_requests_total{status=\"200\"} 3\n"),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains("http_requests_total{status=\"404\"} 1\n"),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains("http_requests_total{status=\"500\"} 1\n"),
+        "{}",
+        body
+    );
+    assert!(body.contains("http_request_duration_seconds_count{class=\"2xx\"} 3\n"));
+    assert!(body.contains("http_request_duration_seconds_bucket{class=\"5xx\",le=\"+Inf\"} 1\n"));
+    //抓取 /metrics 的连接本身是打开的
+    let open = body
+        .lines()
+        .find_map(|line| line.strip_prefix("http_connections_open "))
+        .unwrap();
+    assert!(open.parse::<usize>().unwrap() >= 1, "{}", body);
+    assert!(body.contains("threadpool_workers 2\n"), "{}", body);
+    assert!(body.contains("# TYPE threadpool_queue_depth gauge\n"));
+    assert!(body.contains("# TYPE threadpool_utilization gauge\n"));
+}
+
+#[test]
+fn reports_metrics_in_thread_mode() {
+    reports_metrics(Mode::Threads);
+}
+
+#[test]
+fn reports_metrics_in_event_loop_mode() {
+    reports_metrics(Mode::EventLoop);
+}
+
+#[test]
+fn readyz_is_503_at_max_connections() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let addr = start(mode, 2);
+        //先用一个客户端占住一个持久连接，加上发送 /readyz 的连接就达到了上限
+        let held = common::client();
+        assert_eq!(get(&held, addr, "/").status, StatusCode::OK);
+        assert_eq!(held.idle_connections(), 1);
+        let ready = get(&common::client(), addr, "/readyz");
+        assert_eq!(
+            ready.status,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "{:?}: {}",
+            mode,
+            ready.text()
+        );
+        drop(held);
+    }
+}
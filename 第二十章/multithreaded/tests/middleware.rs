@@ -0,0 +1,113 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 23:08:45
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:31:05
+ * @Description: 中间件链在两种模式下的集成测试
+ */
+mod common;
+
+use std::net::SocketAddr;
+
+use multithreaded::http::{
+    BasicAuth, Client, ClientResponse, Cors, Method, Mode, RateLimit, RequestId, Response, Router,
+    Scoped, Server, ServerConfig, StatusCode,
+};
+use multithreaded::ThreadPool;
+
+fn start(mode: Mode) -> SocketAddr {
+    let router = Router::new()
+        .get("/panic", |_| panic!("boom"))
+        .get("/*path", |request| {
+            Response::text(StatusCode::OK, request.param("path").unwrap().to_string())
+        });
+    let config = ServerConfig {
+        mode,
+        ..ServerConfig::default()
+    };
+    common::serve(
+        Server::builder(router)
+            .config(config)
+            .pool(ThreadPool::new(2))
+            .on_error(|_, _| {})
+            .middleware(RequestId::new())
+            .middleware(Cors::new())
+            .middleware(Scoped::new(
+                "/admin",
+                BasicAuth::new("admin").user("root", "hunter2"),
+            ))
+            .middleware(Scoped::new("/limited", RateLimit::new(0.1, 2)))
+            .build(),
+    )
+}
+
+fn get(client: &Client, addr: SocketAddr, path: &str) -> ClientResponse {
+    client.get(&common::url(addr, path)).send().unwrap()
+}
+
+fn chain_runs_around_handlers(mode: Mode) {
+    let addr = start(mode);
+    let client = common::client();
+    let response = client
+        .get(&common::url(addr, "/hello"))
+        .header("Origin", "https://app.example")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert!(response.headers.contains("x-request-id"));
+    assert_eq!(
+        response.headers.get("access-control-allow-origin"),
+        Some("*")
+    );
+
+    let response = client
+        .request(Method::Options, &common::url(addr, "/hello"))
+        .header("Origin", "https://app.example")
+        .header("Access-Control-Request-Method", "DELETE")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::NO_CONTENT, "{:?}", response);
+    assert!(response.headers.contains("access-control-allow-methods"));
+
+    let response = get(&client, addr, "/admin/panel");
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert!(response.headers.contains("x-request-id"));
+    //router 会把这两种写法规范化成 /admin/panel，Scoped 也一样
+    for path in ["//admin/panel", "/%61dmin/panel"] {
+        let response = get(&client, addr, path);
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{}", path);
+    }
+    let response = client
+        .get(&common::url(addr, "/admin/panel"))
+        .header("Authorization", "Basic cm9vdDpodW50ZXIy")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "admin/panel");
+
+    for _ in 0..2 {
+        assert_eq!(get(&client, addr, "/limited").status, StatusCode::OK);
+    }
+    let response = get(&client, addr, "/limited");
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers.get("retry-after"), Some("10"));
+    assert_eq!(get(&client, addr, "/other").status, StatusCode::OK);
+
+    //处理函数 panic 时，500 响应也经过中间件
+    let response = client
+        .get(&common::url(addr, "/panic"))
+        .header("X-Request-Id", "trace-me")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.headers.get("x-request-id"), Some("trace-me"));
+}
+
+#[test]
+fn middleware_in_thread_mode() {
+    chain_runs_around_handlers(Mode::Threads);
+}
+
+#[test]
+fn middleware_in_event_loop_mode() {
+    chain_runs_around_handlers(Mode::EventLoop);
+}
@@ -0,0 +1,212 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-20 00:06:52
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: 反向代理的集成测试，上游是测试里临时监听的一个简易 HTTP 服务
+ */
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use multithreaded::http::{
+    Mode, Proxy, Response, Router, Scoped, Server, ServerConfig, StatusCode,
+};
+use multithreaded::ThreadPool;
+
+//简易的上游：每个连接一个线程，支持持久连接。/api/slow 很慢，/api/close 用关闭连接表示响应体结束，
+//其余的路径用分块编码回显请求的方法、目标、部分头部和请求体。返回地址和接受过的连接数
+fn upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&accepted);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || serve_upstream(stream.unwrap()));
+        }
+    });
+    (addr, accepted)
+}
+
+fn serve_upstream(stream: TcpStream) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let request_line = line.trim_end().to_string();
+        let mut headers = Vec::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end().to_string();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap();
+            let (name, value) = (name.to_ascii_lowercase(), value.trim().to_string());
+            if name == "content-length" {
+                length = value.parse().unwrap();
+            }
+            headers.push((name, value));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        let stream = reader.get_mut();
+        if request_line.contains("/api/slow") {
+            thread::sleep(Duration::from_secs(2));
+        }
+        if request_line.contains("/api/close") {
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nuntil eof");
+            return;
+        }
+        let mut text = format!("{}\n", request_line);
+        for (name, value) in &headers {
+            if name.starts_with("x-") || name == "host" || name == "connection" {
+                text.push_str(&format!("{}: {}\n", name, value));
+            }
+        }
+        text.push_str(&String::from_utf8_lossy(&body));
+        let (first, second) = text.split_at(text.len() / 2);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\
+             Keep-Alive: timeout=5\r\nX-Upstream: yes\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            first.len(),
+            first,
+            second.len(),
+            second
+        );
+        if stream.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+fn start(mode: Mode, upstream: SocketAddr) -> SocketAddr {
+    let router = Router::new().get("/local", |_| Response::text(StatusCode::OK, "local"));
+    let config = ServerConfig {
+        mode,
+        ..ServerConfig::default()
+    };
+    let proxy = Proxy::new(format!("http://{}", upstream)).timeout(Duration::from_millis(300));
+    common::serve(
+        Server::builder(router)
+            .config(config)
+            .pool(ThreadPool::new(2))
+            .on_error(|_, _| {})
+            .middleware(Scoped::new("/api", proxy))
+            .build(),
+    )
+}
+
+fn forwards_requests(mode: Mode) {
+    let (upstream, accepted) = upstream();
+    let addr = start(mode, upstream);
+    let client = common::client();
+
+    let response = client
+        .get(&common::url(addr, "/api/items?page=2"))
+        .header("Host", "example.com")
+        .header("X-Forwarded-For", "10.0.0.1")
+        .header("Connection", "close")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers.get("x-upstream"), Some("yes"));
+    //逐跳的头部不会转给客户端，分块编码已经解开
+    assert!(
+        !response.headers.contains("transfer-encoding"),
+        "{:?}",
+        response.headers
+    );
+    assert!(!response.headers.contains("keep-alive"));
+    let body = response.text();
+    assert!(
+        body.starts_with("GET /api/items?page=2 HTTP/1.1\n"),
+        "{}",
+        body
+    );
+    assert!(body.contains(&format!("host: {}\n", upstream)), "{}", body);
+    assert!(body.contains("x-forwarded-host: example.com\n"), "{}", body);
+    assert!(
+        body.contains("x-forwarded-for: 10.0.0.1, 127.0.0.1\n"),
+        "{}",
+        body
+    );
+    //客户端的 Connection: close 只对客户端的连接有效
+    assert!(!body.contains("connection:"), "{}", body);
+
+    let response = client
+        .post(&common::url(addr, "/api/items"))
+        .body("payload")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    let body = response.text();
+    assert!(body.starts_with("POST /api/items HTTP/1.1\n"), "{}", body);
+    assert!(body.ends_with("\npayload"), "{}", body);
+
+    //连接关闭表示结束的响应体
+    let response = client.get(&common::url(addr, "/api/close")).send().unwrap();
+    assert_eq!(response.text(), "until eof");
+
+    //不在 /api 下的请求由本地的路由处理
+    let response = client.get(&common::url(addr, "/local")).send().unwrap();
+    assert_eq!(response.text(), "local");
+    //前面的三个请求都用同一个到上游的连接，/api/close 之后这个连接关闭了，下一个请求才重新连接
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    let response = client.get(&common::url(addr, "/api/again")).send().unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn forwards_requests_in_thread_mode() {
+    forwards_requests(Mode::Threads);
+}
+
+#[test]
+fn forwards_requests_in_event_loop_mode() {
+    forwards_requests(Mode::EventLoop);
+}
+
+#[test]
+fn slow_upstreams_get_504() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let (upstream, _) = upstream();
+        let addr = start(mode, upstream);
+        let client = common::client();
+        let response = client.get(&common::url(addr, "/api/slow")).send().unwrap();
+        assert_eq!(response.status, StatusCode::GATEWAY_TIMEOUT, "{:?}", mode);
+        //超时的连接不会放回连接池，后面的请求照常转发
+        let response = client.get(&common::url(addr, "/api/fast")).send().unwrap();
+        assert_eq!(response.status, StatusCode::OK, "{:?}", mode);
+    }
+}
+
+#[test]
+fn unreachable_upstreams_get_502() {
+    //先占一个端口再关掉，连接会被拒绝
+    let upstream = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let addr = start(Mode::Threads, upstream);
+    let response = common::client()
+        .get(&common::url(addr, "/api/items"))
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    let body = response.text();
+    assert!(body.contains("cannot connect to upstream"), "{}", body);
+}
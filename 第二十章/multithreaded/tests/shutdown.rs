@@ -0,0 +1,113 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 19:47:31
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: 优雅关闭和连接数上限的集成测试
+ */
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{connect, read_all};
+use multithreaded::http::{Response, Router, Server, ServerConfig, StatusCode};
+use multithreaded::ThreadPool;
+
+fn router() -> Router {
+    Router::new()
+        .get("/", |_| Response::text(StatusCode::OK, "fast"))
+        .get("/slow", |_| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(StatusCode::OK, "slow")
+        })
+}
+
+//关闭时连接上发生了什么（Connection: close、空闲的连接被关闭）要直接看套接字
+#[test]
+fn shutdown_finishes_in_flight_requests() {
+    let server = Server::new(router(), ServerConfig::default(), ThreadPool::new(2));
+    let (addr, stop, serving) = common::serve_until_shutdown(server);
+
+    let mut slow = connect(addr);
+    slow.write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\n\r\n")
+        .unwrap();
+    //再开一个处于空闲状态的持久连接
+    let mut idle = connect(addr);
+    idle.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    stop.shutdown();
+    assert!(stop.is_shutdown());
+    serving.join().unwrap().unwrap();
+
+    //正在处理的请求拿到了完整的响应，而且连接随后被关闭
+    let response = read_all(&mut slow);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Connection: close"));
+    assert!(response.ends_with("slow"));
+    assert!(read_all(&mut idle).ends_with("fast"));
+    //不再接受新连接
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn stopping_closes_idle_connections_without_waiting_for_keep_alive() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(router(), ServerConfig::default(), ThreadPool::new(2));
+    let stop = server.shutdown_handle();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    //serve 返回之后先不调用 Server::shutdown，只有 ShutdownHandle 能让空闲连接线程知道要停止了
+    thread::spawn(move || {
+        server.serve(&listener);
+        let _ = done_rx.recv();
+    });
+
+    let mut idle = connect(addr);
+    idle.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 256];
+    assert!(idle.read(&mut buf).unwrap() > 0);
+    thread::sleep(Duration::from_millis(50));
+
+    let start = Instant::now();
+    stop.shutdown();
+    //keep_alive 是 5 秒，空闲的连接应当马上被关闭
+    assert_eq!(idle.read(&mut buf).unwrap(), 0);
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "{:?}",
+        start.elapsed()
+    );
+    drop(done_tx);
+}
+
+#[test]
+fn connections_over_the_limit_get_503() {
+    let config = ServerConfig {
+        max_connections: 1,
+        ..ServerConfig::default()
+    };
+    let addr = common::start(router(), config, 2);
+    let url = common::url(addr, "/");
+
+    //第一个客户端的持久连接留在它的连接池里
+    let first = common::client();
+    assert_eq!(first.get(&url).send().unwrap().text(), "fast");
+    assert_eq!(first.idle_connections(), 1);
+
+    //第一个连接还开着，第二个连接超出上限
+    let response = common::client().get(&url).send().unwrap();
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+
+    //第一个连接关闭之后又可以接受新连接了
+    drop(first);
+    thread::sleep(Duration::from_millis(100));
+    let response = common::client().get(&url).send().unwrap();
+    assert_eq!(response.text(), "fast");
+}
@@ -0,0 +1,199 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 22:14:05
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: HTTPS 的集成测试，证书在测试时用 rcgen 生成
+ */
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::read_response;
+use multithreaded::http::{
+    Mode, Response, Router, Server, ServerConfig, StatusCode, TlsConfig, TlsError,
+};
+use multithreaded::ThreadPool;
+use rcgen::CertifiedKey;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+fn certificate() -> CertifiedKey<rcgen::KeyPair> {
+    rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap()
+}
+
+fn start(mode: Mode, cert: &CertifiedKey<rcgen::KeyPair>) -> SocketAddr {
+    let tls = TlsConfig::from_pem(
+        cert.cert.pem().as_bytes(),
+        cert.signing_key.serialize_pem().as_bytes(),
+    )
+    .unwrap();
+    let router = Router::new()
+        .get("/:name", |request| {
+            Response::text(
+                StatusCode::OK,
+                format!("secret {}", request.param("name").unwrap()),
+            )
+        })
+        .websocket("/ws/echo", |_, mut ws| {
+            while let Ok(Some(message)) = ws.recv() {
+                if ws.send(&message).is_err() {
+                    break;
+                }
+            }
+        });
+    let config = ServerConfig {
+        mode,
+        ..ServerConfig::default()
+    };
+    common::serve(
+        Server::builder(router)
+            .config(config)
+            .pool(ThreadPool::new(2))
+            .tls(tls)
+            .on_error(|_, _| {})
+            .build(),
+    )
+}
+
+//只信任测试证书的客户端
+fn connect(
+    addr: SocketAddr,
+    cert: &CertifiedKey<rcgen::KeyPair>,
+) -> StreamOwned<ClientConnection, TcpStream> {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.cert.der().clone()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let name = ServerName::try_from("localhost").unwrap();
+    let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+    let tcp = TcpStream::connect(addr).unwrap();
+    tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    StreamOwned::new(conn, tcp)
+}
+
+fn keep_alive_over_tls(mode: Mode) {
+    let cert = certificate();
+    let addr = start(mode, &cert);
+    let mut stream = connect(addr, &cert);
+    stream
+        .write_all(b"GET /one HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let (head, body) = read_response(&mut stream);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert_eq!(body, "secret one");
+    //服务端只提供 http/1.1
+    assert_eq!(stream.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+    stream
+        .write_all(b"GET /two HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let (head, body) = read_response(&mut stream);
+    assert!(head.contains("Connection: close"));
+    assert_eq!(body, "secret two");
+    //服务端发送了 close_notify，连接干净地结束
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn serves_https_in_thread_mode() {
+    keep_alive_over_tls(Mode::Threads);
+}
+
+#[test]
+fn serves_https_in_event_loop_mode() {
+    keep_alive_over_tls(Mode::EventLoop);
+}
+
+//客户端发出的短文本帧，掩码固定为 1 2 3 4
+fn masked_text(text: &str) -> Vec<u8> {
+    let mut frame = vec![0x81, 0x80 | text.len() as u8, 1, 2, 3, 4];
+    frame.extend(text.bytes().enumerate().map(|(i, b)| b ^ (i as u8 % 4 + 1)));
+    frame
+}
+
+#[test]
+fn websocket_over_tls() {
+    let cert = certificate();
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let addr = start(mode, &cert);
+        let mut stream = connect(addr, &cert);
+        //握手请求和第一个帧在同一次写里发出，帧可能还留在服务端的 TLS 会话里
+        let mut data = b"GET /ws/echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+            Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\n\r\n"
+            .to_vec();
+        data.extend_from_slice(&masked_text("first"));
+        stream.write_all(&data).unwrap();
+
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(
+            head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+            "{}",
+            head
+        );
+        for text in ["first", "second", "third"] {
+            if text != "first" {
+                stream.write_all(&masked_text(text)).unwrap();
+            }
+            let mut frame = vec![0; 2 + text.len()];
+            stream.read_exact(&mut frame).unwrap();
+            assert_eq!(frame[..2], [0x81, text.len() as u8], "{:?}", mode);
+            assert_eq!(&frame[2..], text.as_bytes());
+        }
+    }
+}
+
+#[test]
+fn plain_http_gets_no_response() {
+    let cert = certificate();
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let addr = start(mode, &cert);
+        let mut stream = common::connect(addr);
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(!response.starts_with(b"HTTP/1.1"), "{:?}", mode);
+    }
+}
+
+#[test]
+fn rejects_bad_certificates_and_keys() {
+    let cert = certificate();
+    let other = certificate();
+    let pem = cert.cert.pem();
+    assert!(matches!(
+        TlsConfig::from_pem(b"not a certificate", b""),
+        Err(TlsError::Pem(_))
+    ));
+    assert!(matches!(
+        TlsConfig::from_pem(pem.as_bytes(), b"no key here"),
+        Err(TlsError::Pem(_))
+    ));
+    assert!(matches!(
+        TlsConfig::from_pem(pem.as_bytes(), other.signing_key.serialize_pem().as_bytes()),
+        Err(TlsError::Rustls(_))
+    ));
+    assert!(matches!(
+        TlsConfig::from_pem_files("missing-cert.pem", "missing-key.pem"),
+        Err(TlsError::Io(..))
+    ));
+}
@@ -0,0 +1,218 @@
+/*
+ * @Author: wlj
+ * @Date: 2026-10-19 23:37:14
+ * @LastEditors: wlj
+ * @LastEditTime: 2026-10-20 04:03:27
+ * @Description: WebSocket 在两种模式下的集成测试，客户端是同一进程里的 WebSocket::connect
+ */
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use multithreaded::http::{
+    close_code, Message, Mode, Response, Router, Server, ServerConfig, ShutdownHandle, StatusCode,
+    WebSocket,
+};
+use multithreaded::ThreadPool;
+
+fn start(mode: Mode) -> (SocketAddr, ShutdownHandle) {
+    let router = Router::new()
+        .get("/", |_| Response::text(StatusCode::OK, "plain"))
+        .websocket("/ws/echo", |_, mut ws| {
+            while let Ok(Some(message)) = ws.recv() {
+                if ws.send(&message).is_err() {
+                    break;
+                }
+            }
+        })
+        .websocket("/ws/greet/:name", |request, ws| {
+            let name = request.param("name").unwrap().to_string();
+            ws.send_text(&format!("hello {}", name)).unwrap();
+        })
+        .websocket("/ws/count", |_, mut ws| {
+            //数收到的消息，收到 "done" 时回复总数
+            let mut count = 0;
+            while let Ok(Some(message)) = ws.recv() {
+                if message == Message::Text("done".to_string()) {
+                    let _ = ws.send_text(&count.to_string());
+                    break;
+                }
+                count += 1;
+            }
+        })
+        .websocket("/ws/panic", |_, _| panic!("boom"));
+    let config = ServerConfig {
+        mode,
+        ..ServerConfig::default()
+    };
+    let server = Server::builder(router)
+        .config(config)
+        .pool(ThreadPool::new(2))
+        .on_error(|_, _| {})
+        .build();
+    let handle = server.shutdown_handle();
+    (common::serve(server), handle)
+}
+
+fn connect(addr: SocketAddr, path: &str) -> WebSocket {
+    let stream = TcpStream::connect(addr).unwrap();
+    WebSocket::connect(stream, "localhost", path).unwrap()
+}
+
+fn echo(mode: Mode) {
+    let (addr, _handle) = start(mode);
+    let mut ws = connect(addr, "/ws/echo");
+    ws.send_text("hello").unwrap();
+    assert_eq!(ws.recv().unwrap(), Some(Message::Text("hello".to_string())));
+
+    //分成多个帧发送的消息被拼成一条
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    ws.send_fragmented(&Message::Binary(data.clone()), 30_000)
+        .unwrap();
+    assert_eq!(ws.recv().unwrap(), Some(Message::Binary(data)));
+    let text = "分片的文本".repeat(10);
+    ws.send_fragmented(&Message::Text(text.clone()), 7).unwrap();
+    assert_eq!(ws.recv().unwrap(), Some(Message::Text(text)));
+
+    //Pong 由 recv 处理掉，不会当作消息返回
+    ws.ping(b"are you there").unwrap();
+    ws.send_text("after ping").unwrap();
+    assert_eq!(
+        ws.recv().unwrap(),
+        Some(Message::Text("after ping".to_string()))
+    );
+
+    //客户端发起关闭，服务端用同样的状态码回复
+    ws.close(close_code::NORMAL, "bye").unwrap();
+    assert_eq!(ws.recv().unwrap(), None);
+    assert_eq!(ws.close_frame(), Some((close_code::NORMAL, "")));
+}
+
+#[test]
+fn echoes_messages_in_thread_mode() {
+    echo(Mode::Threads);
+}
+
+#[test]
+fn echoes_messages_in_event_loop_mode() {
+    echo(Mode::EventLoop);
+}
+
+#[test]
+fn frames_sent_with_the_handshake_are_not_lost() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let (addr, _handle) = start(mode);
+        let mut stream = common::connect(addr);
+        //握手请求后面紧跟着一个加了掩码的 Ping 帧，载荷是 "x"
+        let mut data = b"GET /ws/echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+            Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\n\r\n"
+            .to_vec();
+        data.extend_from_slice(&[0x89, 0x81, 1, 2, 3, 4, b'x' ^ 1]);
+        stream.write_all(&data).unwrap();
+
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(
+            head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+            "{}",
+            head
+        );
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        //服务端的 Pong 不加掩码
+        let mut pong = [0; 3];
+        stream.read_exact(&mut pong).unwrap();
+        assert_eq!(pong, [0x8a, 0x01, b'x'], "{:?}", mode);
+    }
+}
+
+#[test]
+fn senders_do_not_wait_for_a_blocked_recv() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let (addr, _handle) = start(mode);
+        let mut ws = connect(addr, "/ws/count");
+        let sender = ws.sender();
+        //另一个线程阻塞在 recv 上，服务端在收到 "done" 之前什么也不发
+        let reader = thread::spawn(move || {
+            let reply = ws.recv().unwrap();
+            (reply, ws.recv().unwrap())
+        });
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        for i in 0..50 {
+            sender.send_text(&i.to_string()).unwrap();
+        }
+        //读的时候不占着写的锁，每次发送都不用等 recv 的超时
+        assert!(
+            start.elapsed() < Duration::from_millis(100),
+            "{:?} {:?}",
+            start.elapsed(),
+            mode
+        );
+        sender.send_text("done").unwrap();
+        let (reply, end) = reader.join().unwrap();
+        assert_eq!(reply, Some(Message::Text("50".to_string())));
+        assert_eq!(end, None);
+    }
+}
+
+#[test]
+fn handlers_see_params_and_sessions_end_with_a_close_frame() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let (addr, _handle) = start(mode);
+        let mut ws = connect(addr, "/ws/greet/ferris");
+        assert_eq!(
+            ws.recv().unwrap(),
+            Some(Message::Text("hello ferris".to_string()))
+        );
+        //处理函数返回后服务端发送 1000
+        assert_eq!(ws.recv().unwrap(), None);
+        assert_eq!(ws.close_frame(), Some((close_code::NORMAL, "")));
+
+        //处理函数 panic 时是 1011
+        let mut ws = connect(addr, "/ws/panic");
+        assert_eq!(ws.recv().unwrap(), None);
+        assert_eq!(
+            ws.close_frame(),
+            Some((close_code::INTERNAL_ERROR, "internal error"))
+        );
+    }
+}
+
+#[test]
+fn plain_requests_to_websocket_routes_get_426() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let (addr, _handle) = start(mode);
+        let response = common::client()
+            .get(&common::url(addr, "/ws/echo"))
+            .send()
+            .unwrap();
+        assert_eq!(response.status, StatusCode::UPGRADE_REQUIRED, "{:?}", mode);
+        assert_eq!(response.headers.get("upgrade"), Some("websocket"));
+    }
+}
+
+#[test]
+fn shutdown_closes_sessions_with_1001() {
+    for mode in [Mode::Threads, Mode::EventLoop] {
+        let (addr, handle) = start(mode);
+        let mut ws = connect(addr, "/ws/echo");
+        ws.send_text("ready").unwrap();
+        assert!(ws.recv().unwrap().is_some());
+        handle.shutdown();
+        assert_eq!(ws.recv().unwrap(), None);
+        assert_eq!(
+            ws.close_frame(),
+            Some((close_code::GOING_AWAY, "server shutting down"))
+        );
+    }
+}